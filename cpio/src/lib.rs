@@ -0,0 +1,350 @@
+//! Newc cpio archives of WASI virtual file system trees, as used by initramfs
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use flate2::read::GzDecoder;
+use wasi_common::file::FileType;
+use wasi_common::{Error, ErrorExt, SystemTimeSpec};
+use wasmtime_vfs_dir::{Directory, Symlink};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_memory::{Node, Permissions};
+
+const MAGIC: &[u8] = b"07070";
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+enum Kind {
+    Dir,
+    File,
+    Symlink,
+}
+
+struct Item {
+    path: Vec<String>,
+    kind: Kind,
+    ino: (u32, u32, u32),
+    nlink: u32,
+    permissions: Permissions,
+    mtime: SystemTime,
+    data: Vec<u8>,
+}
+
+// Parses a header field of eight hex digits.
+fn field(header: &[u8], index: usize) -> Result<u32, Error> {
+    let digits = &header[6 + index * 8..][..8];
+    let digits = std::str::from_utf8(digits).map_err(|_| Error::invalid_argument())?;
+    u32::from_str_radix(digits, 16).map_err(|_| Error::invalid_argument())
+}
+
+// Reads `len` bytes and then the padding to a multiple of four after `at`.
+fn read(reader: &mut impl Read, len: usize, at: usize) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+
+    let mut pad = [0; 3];
+    reader.read_exact(&mut pad[..(4 - (at + len) % 4) % 4])?;
+    Ok(data)
+}
+
+// Reads the supported entries of an archive, before any await point.
+fn items(mut reader: impl Read) -> Result<Vec<Item>, Error> {
+    let mut items = Vec::new();
+
+    loop {
+        let mut header = [0; 110];
+        reader.read_exact(&mut header)?;
+        if !header.starts_with(MAGIC) || !matches!(header[5], b'1' | b'2') {
+            return Err(Error::invalid_argument());
+        }
+
+        let namesize = field(&header, 11)? as usize;
+        let mut name = read(&mut reader, namesize, header.len())?;
+        if name.pop() != Some(0) {
+            return Err(Error::invalid_argument());
+        }
+
+        let name = String::from_utf8(name).map_err(|_| Error::illegal_byte_sequence())?;
+        if name == TRAILER {
+            break;
+        }
+
+        let data = read(&mut reader, field(&header, 6)? as usize, 0)?;
+        let mode = field(&header, 1)?;
+        let kind = match mode & S_IFMT {
+            S_IFDIR => Kind::Dir,
+            S_IFREG => Kind::File,
+            S_IFLNK => Kind::Symlink,
+            _ => continue,
+        };
+
+        let mut path = Vec::new();
+        for seg in name.split('/') {
+            match seg {
+                "" | "." => continue,
+                ".." => return Err(Error::invalid_argument()),
+                seg => path.push(seg.to_string()),
+            }
+        }
+
+        items.push(Item {
+            path,
+            kind,
+            ino: (field(&header, 7)?, field(&header, 8)?, field(&header, 0)?),
+            nlink: field(&header, 4)?,
+            permissions: Permissions {
+                mode: mode & 0o7777,
+                uid: field(&header, 2)?,
+                gid: field(&header, 3)?,
+            },
+            mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(field(&header, 5)?.into()),
+            data,
+        });
+    }
+
+    Ok(items)
+}
+
+// Gets the directory at `path` below `root`, creating any that are missing.
+async fn mkdirs(root: &Arc<Directory>, path: &[String]) -> Result<Arc<Directory>, Error> {
+    let mut dir = root.clone();
+
+    for name in path {
+        dir = match dir.get(name).await {
+            Ok(node) => node
+                .to_any()
+                .downcast::<Directory>()
+                .map_err(|_| Error::not_dir())?,
+            Err(..) => {
+                let child = Directory::new(dir.clone(), dir.factory());
+                dir.attach(name, child.clone()).await?;
+                child
+            }
+        };
+    }
+
+    Ok(dir)
+}
+
+/// Unpacks a newc cpio archive, as used by initramfs, into `root`.
+///
+/// Directories, regular files and symbolic links are created with their
+/// archived content, permissions and modification times, and files sharing
+/// an inode become hard links. Existing directories are merged into and
+/// other existing nodes replaced. Other kinds of entry, such as devices, are
+/// skipped. Entries climbing out of `root` with `..` fail with `EINVAL`, as
+/// do malformed headers.
+///
+/// The archive is read in full before the tree is touched, so `reader` may
+/// block without stalling other tasks partway through.
+pub async fn import(root: &Arc<Directory>, reader: impl Read) -> Result<(), Error> {
+    let items = items(reader)?;
+
+    // The content of a hard linked file comes with the last of its links.
+    let mut data = BTreeMap::new();
+    for item in items.iter().filter(|item| item.nlink > 1) {
+        if let Kind::File = item.kind {
+            if !item.data.is_empty() {
+                data.insert(item.ino, item.data.clone());
+            }
+        }
+    }
+
+    let mut links: BTreeMap<_, Arc<dyn Node>> = BTreeMap::new();
+    let mut metas: Vec<(Arc<dyn Node>, _, _)> = Vec::new();
+
+    for item in items {
+        let (name, parent) = match item.path.split_last() {
+            Some(split) => split,
+            None if matches!(item.kind, Kind::Dir) => {
+                metas.push((root.clone() as Arc<dyn Node>, item.permissions, item.mtime));
+                continue;
+            }
+            None => return Err(Error::invalid_argument()),
+        };
+
+        let parent = mkdirs(root, parent).await?;
+        let node: Arc<dyn Node> = match item.kind {
+            Kind::Dir => {
+                let dir = mkdirs(&parent, std::slice::from_ref(name)).await?;
+                metas.push((dir, item.permissions, item.mtime));
+                continue;
+            }
+            Kind::Symlink => {
+                let target = String::from_utf8(item.data);
+                let target = target.map_err(|_| Error::illegal_byte_sequence())?;
+                Symlink::new(parent.clone(), target)
+            }
+            Kind::File if item.nlink > 1 => match links.get(&item.ino) {
+                Some(node) => node.clone(),
+                None => {
+                    let data = data.remove(&item.ino).unwrap_or_default();
+                    let node = File::with_data(parent.clone(), data);
+                    links.insert(item.ino, node.clone());
+                    node
+                }
+            },
+            Kind::File => File::with_data(parent.clone(), item.data),
+        };
+
+        // A later entry for the same path replaces the earlier one.
+        if let Ok(old) = parent.get(name).await {
+            if old.filetype() == FileType::Directory {
+                return Err(Error::exist());
+            }
+
+            let mut ilock = parent.inode().data().write().await;
+            if let Some(old) = ilock.content_mut().remove(name) {
+                old.id().unlink();
+            }
+        }
+
+        parent.attach(name, node.clone()).await?;
+        metas.push((node, item.permissions, item.mtime));
+    }
+
+    // Adding entries touches their directories, so set the metadata last.
+    for (node, permissions, mtime) in metas {
+        let mtime = SystemTimeSpec::Absolute(cap_std::time::SystemTime::from_std(mtime));
+        node.clone().set_times(None, Some(mtime)).await?;
+
+        if node.filetype() != FileType::SymbolicLink {
+            node.set_permissions(permissions).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpacks a gzip-compressed newc cpio archive into `root`, as with
+/// [`import`].
+pub async fn import_gz(root: &Arc<Directory>, reader: impl Read) -> Result<(), Error> {
+    import(root, GzDecoder::new(reader)).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    // Writes a newc archive of `(path, mode, ino, nlink, data)` entries.
+    fn archive(entries: &[(&str, u32, u32, u32, &str)]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let trailer = (TRAILER, 0, 0, 1, "");
+        for (path, mode, ino, nlink, data) in entries.iter().chain([&trailer]) {
+            let fields = [
+                *ino,
+                *mode,
+                1000,
+                100,
+                *nlink,
+                1_000_000,
+                data.len() as u32,
+                0,
+                0,
+                0,
+                0,
+                path.len() as u32 + 1,
+                0,
+            ];
+
+            out.extend_from_slice(b"070701");
+            for field in fields {
+                out.extend_from_slice(format!("{:08x}", field).as_bytes());
+            }
+
+            out.extend_from_slice(path.as_bytes());
+            out.push(0);
+            out.resize(out.len().div_ceil(4) * 4, 0);
+            out.extend_from_slice(data.as_bytes());
+            out.resize(out.len().div_ceil(4) * 4, 0);
+        }
+
+        out
+    }
+
+    async fn content(root: &Arc<Directory>, path: &str) -> Vec<u8> {
+        let node = root.get(path).await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let data = file.inode().data().read().await.content().to_vec();
+        data
+    }
+
+    #[tokio::test]
+    async fn import() {
+        let root = Directory::root(Ledger::new(), None);
+        let cpio = archive(&[
+            (".", 0o040755, 1, 2, ""),
+            ("bin", 0o040700, 2, 2, ""),
+            ("bin/sh", 0o100755, 3, 1, "#!"),
+            ("init", 0o120777, 4, 1, "bin/sh"),
+            ("dev/console", 0o020600, 5, 1, ""),
+            ("etc/a", 0o100600, 6, 2, ""),
+            ("etc/b", 0o100600, 6, 2, "linked"),
+        ]);
+        super::import(&root, &cpio[..]).await.unwrap();
+
+        // Files keep their content, permissions and times.
+        assert_eq!(content(&root, "bin/sh").await, b"#!");
+        let sh = root.get("bin/sh").await.unwrap();
+        let permissions = sh.permissions().await.unwrap();
+        assert_eq!(permissions.mode, 0o755);
+        assert_eq!((permissions.uid, permissions.gid), (1000, 100));
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(sh.filestat().await.unwrap().mtim, Some(mtime));
+        let bin = root.get("bin").await.unwrap();
+        assert_eq!(bin.permissions().await.unwrap().mode, 0o700);
+        assert_eq!(root.permissions().await.unwrap().mode, 0o755);
+
+        // Symbolic links are kept and devices are skipped.
+        let open = root.clone().open_dir().await.unwrap();
+        let target = open.read_link("init").await.unwrap();
+        assert_eq!(target.to_str(), Some("bin/sh"));
+        root.get("dev/console").await.err().unwrap();
+
+        // Entries sharing an inode are hard links.
+        assert_eq!(content(&root, "etc/a").await, b"linked");
+        let a = root.get("etc/a").await.unwrap();
+        let b = root.get("etc/b").await.unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.id().links(), 2);
+
+        // A compressed archive merges into the tree.
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&archive(&[("bin/ls", 0o100755, 7, 1, "ls")]))
+            .unwrap();
+        import_gz(&root, &gz.finish().unwrap()[..]).await.unwrap();
+        assert_eq!(content(&root, "bin/ls").await, b"ls");
+        assert_eq!(content(&root, "bin/sh").await, b"#!");
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let root = Directory::root(Ledger::new(), None);
+
+        // Entries climbing out of the tree are refused.
+        let cpio = archive(&[("../x", 0o100644, 1, 1, "")]);
+        super::import(&root, &cpio[..]).await.err().unwrap();
+
+        // So are archives cut short or in other formats.
+        let cpio = archive(&[("x", 0o100644, 1, 1, "data")]);
+        super::import(&root, &cpio[..120]).await.err().unwrap();
+        let mut odc = cpio.clone();
+        odc[5] = b'7';
+        super::import(&root, &odc[..]).await.err().unwrap();
+
+        root.get("x").await.err().unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+//! Character devices for WASI virtual file system trees
+
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::sync::{Arc, Weak};
+
+use rand::RngCore;
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node, Open};
+
+mod stdio;
+
+pub use stdio::{populate_stdio, Stdio};
+
+/// A source of random bytes supplied by the host
+///
+/// It fills the whole buffer, or fails the read with its error.
+pub type Random = Arc<dyn Fn(&mut [u8]) -> Result<(), Error> + Send + Sync>;
+
+#[derive(Clone)]
+enum Kind {
+    Null,
+    Zero,
+    Full,
+    Urandom(Option<Random>),
+}
+
+/// A character device
+///
+/// Writes to `full` fail with `ENOSPC`, and writes to any other device
+/// succeed and are discarded. Reads from `null` are at end of file, reads
+/// from `zero` and `full` fill the buffers with zeros and reads from
+/// `urandom` with random bytes from the host.
+pub struct Device(Link<Kind>);
+
+#[async_trait::async_trait]
+impl Node for Device {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::CharacterDevice
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let inode = self.0.inode();
+        let ilock = inode.read().await?;
+
+        Ok(Filestat {
+            device_id: **inode.id().device(),
+            inode: **inode.id(),
+            filetype: FileType::CharacterDevice,
+            nlink: inode.id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        let kind = self.0.inode().data().read().await.content().clone();
+        Ok(Box::new(OpenDevice {
+            open: Open::new(self, read, write, flags),
+            kind,
+        }))
+    }
+}
+
+impl Device {
+    fn new(parent: Arc<dyn Node>, kind: Kind) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, kind)))
+    }
+
+    /// Creates a `/dev/null` device.
+    pub fn null(parent: Arc<dyn Node>) -> Arc<Self> {
+        Self::new(parent, Kind::Null)
+    }
+
+    /// Creates a `/dev/zero` device.
+    pub fn zero(parent: Arc<dyn Node>) -> Arc<Self> {
+        Self::new(parent, Kind::Zero)
+    }
+
+    /// Creates a `/dev/full` device.
+    pub fn full(parent: Arc<dyn Node>) -> Arc<Self> {
+        Self::new(parent, Kind::Full)
+    }
+
+    /// Creates a `/dev/urandom` device reading from the thread RNG.
+    pub fn urandom(parent: Arc<dyn Node>) -> Arc<Self> {
+        Self::new(parent, Kind::Urandom(None))
+    }
+
+    /// Creates a `/dev/urandom` device reading from `random`.
+    pub fn urandom_with(parent: Arc<dyn Node>, random: Random) -> Arc<Self> {
+        Self::new(parent, Kind::Urandom(Some(random)))
+    }
+}
+
+/// Attaches `null`, `zero`, `full` and `urandom` devices to `dir`.
+pub async fn populate(dir: &Arc<Directory>) -> Result<(), Error> {
+    populate_devices(dir, Device::urandom(dir.clone())).await
+}
+
+/// Attaches `null`, `zero`, `full` and `urandom` devices to `dir`, with
+/// `urandom` reading from `random`.
+pub async fn populate_with(dir: &Arc<Directory>, random: Random) -> Result<(), Error> {
+    populate_devices(dir, Device::urandom_with(dir.clone(), random)).await
+}
+
+// Attaches the devices, given the `urandom` device to attach.
+async fn populate_devices(dir: &Arc<Directory>, urandom: Arc<Device>) -> Result<(), Error> {
+    dir.attach("null", Device::null(dir.clone())).await?;
+    dir.attach("zero", Device::zero(dir.clone())).await?;
+    dir.attach("full", Device::full(dir.clone())).await?;
+    dir.attach("urandom", urandom).await
+}
+
+struct OpenDevice {
+    open: Open<Device>,
+    kind: Kind,
+}
+
+impl OpenDevice {
+    fn fill(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<u64, Error> {
+        if !self.open.can_read() {
+            return Err(Error::badf());
+        }
+
+        let mut total = 0;
+        for buf in bufs {
+            match &self.kind {
+                Kind::Null => return Ok(0),
+                Kind::Zero | Kind::Full => buf.fill(0),
+                Kind::Urandom(None) => rand::thread_rng().fill_bytes(buf),
+                Kind::Urandom(Some(random)) => random(buf)?,
+            }
+
+            total += buf.len() as u64;
+        }
+
+        Ok(total)
+    }
+
+    fn discard(&self, bufs: &[IoSlice<'_>]) -> Result<u64, Error> {
+        if !self.open.can_write() {
+            return Err(Error::badf());
+        }
+
+        if let Kind::Full = self.kind {
+            return Err(Error::no_space());
+        }
+
+        Ok(bufs.iter().map(|b| b.len() as u64).sum())
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenDevice {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.open.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.open.link().clone().filestat().await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.fill(bufs)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _offset: u64,
+    ) -> Result<u64, Error> {
+        self.fill(bufs)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        self.discard(bufs)
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        _offset: u64,
+    ) -> Result<u64, Error> {
+        self.discard(bufs)
+    }
+
+    // As on Linux, seeking a character device succeeds and goes nowhere.
+    async fn seek(&mut self, _pos: SeekFrom) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.fill(&mut [IoSliceMut::new(buf)])
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasi_common::file::OFlags;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    async fn read(dir: &dyn WasiDir, path: &str) -> (u64, [u8; 64]) {
+        let mut file = dir
+            .open_file(false, path, OFlags::empty(), true, true, FdFlags::empty())
+            .await
+            .unwrap();
+
+        let n = file.write_vectored(&[IoSlice::new(b"abc")]).await.unwrap();
+        assert_eq!(n, 3);
+
+        let mut buf = [1u8; 64];
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        (n, buf)
+    }
+
+    #[tokio::test]
+    async fn devices() {
+        let root = Directory::root(Ledger::new(), None);
+        populate(&root).await.unwrap();
+        let null = root.get("null").await.unwrap();
+        assert_eq!(null.filetype(), FileType::CharacterDevice);
+
+        let dir = root.open_dir().await.unwrap();
+        assert_eq!(read(&*dir, "null").await.0, 0);
+        assert_eq!(read(&*dir, "zero").await, (64, [0u8; 64]));
+
+        let (n, buf) = read(&*dir, "urandom").await;
+        assert_eq!(n, 64);
+        assert_ne!(buf, [1u8; 64]);
+        assert_ne!(buf, read(&*dir, "urandom").await.1);
+
+        // Writes to `full` fail, but reads are zeros.
+        let mut full = dir
+            .open_file(false, "full", OFlags::empty(), true, true, FdFlags::empty())
+            .await
+            .unwrap();
+        let error = full.write_vectored(&[IoSlice::new(b"abc")]).await;
+        let errno = |e: Error| e.downcast::<std::io::Error>().unwrap().raw_os_error();
+        assert_eq!(errno(error.unwrap_err()), errno(Error::no_space()));
+        let mut buf = [1u8; 64];
+        let n = full.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!((n.unwrap(), buf), (64, [0u8; 64]));
+    }
+
+    #[tokio::test]
+    async fn random() {
+        let root = Directory::root(Ledger::new(), None);
+        let random: Random = Arc::new(|buf: &mut [u8]| {
+            buf.fill(7);
+            Ok(())
+        });
+        populate_with(&root, random).await.unwrap();
+
+        let dir = root.open_dir().await.unwrap();
+        assert_eq!(read(&*dir, "urandom").await, (64, [7u8; 64]));
+
+        // The host can fail reads.
+        let root = Directory::root(Ledger::new(), None);
+        populate_with(&root, Arc::new(|_: &mut [u8]| Err(Error::io())))
+            .await
+            .unwrap();
+        let dir = root.open_dir().await.unwrap();
+        let mut urandom = dir
+            .open_file(
+                false,
+                "urandom",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let n = urandom
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await;
+        n.unwrap_err();
+    }
+}
@@ -0,0 +1,228 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use tokio::sync::Mutex;
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node, Open};
+
+type Shared = Arc<Mutex<Box<dyn WasiFile>>>;
+
+/// A character device bridging to a host file, such as a standard stream
+///
+/// All open handles share the file, so a guest opening `/dev/stdout` by
+/// path writes where its stdout goes. Each handle can only read or write
+/// if it was opened to, and the file may refuse either.
+pub struct Stdio(Link<Shared>);
+
+#[async_trait::async_trait]
+impl Node for Stdio {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::CharacterDevice
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let inode = self.0.inode();
+        let ilock = inode.read().await?;
+
+        Ok(Filestat {
+            device_id: **inode.id().device(),
+            inode: **inode.id(),
+            filetype: FileType::CharacterDevice,
+            nlink: inode.id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        let file = self.0.inode().data().read().await.content().clone();
+        Ok(Box::new(OpenStdio {
+            open: Open::new(self, read, write, flags),
+            file,
+        }))
+    }
+}
+
+impl Stdio {
+    pub fn new(parent: Arc<dyn Node>, file: Box<dyn WasiFile>) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, Arc::new(Mutex::new(file)))))
+    }
+}
+
+/// Attaches `stdin`, `stdout` and `stderr` devices bridging to the given
+/// files to `dir`.
+pub async fn populate_stdio(
+    dir: &Arc<Directory>,
+    stdin: Box<dyn WasiFile>,
+    stdout: Box<dyn WasiFile>,
+    stderr: Box<dyn WasiFile>,
+) -> Result<(), Error> {
+    dir.attach("stdin", Stdio::new(dir.clone(), stdin)).await?;
+    dir.attach("stdout", Stdio::new(dir.clone(), stdout))
+        .await?;
+    dir.attach("stderr", Stdio::new(dir.clone(), stderr)).await
+}
+
+struct OpenStdio {
+    open: Open<Stdio>,
+    file: Shared,
+}
+
+impl OpenStdio {
+    fn reader(&self) -> Result<&Shared, Error> {
+        match self.open.can_read() {
+            true => Ok(&self.file),
+            false => Err(Error::badf()),
+        }
+    }
+
+    fn writer(&self) -> Result<&Shared, Error> {
+        match self.open.can_write() {
+            true => Ok(&self.file),
+            false => Err(Error::badf()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenStdio {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    // A handle blocked on the file holds the lock, and we cannot wait here.
+    fn isatty(&mut self) -> bool {
+        match self.file.try_lock() {
+            Ok(mut file) => file.isatty(),
+            Err(..) => false,
+        }
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.open.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.open.link().clone().filestat().await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.reader()?.lock().await.read_vectored(bufs).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        self.writer()?.lock().await.write_vectored(bufs).await
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.reader()?.lock().await.peek(buf).await
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.reader()?.lock().await.num_ready_bytes().await
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.reader()?.lock().await.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        self.writer()?.lock().await.writable().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::RwLock;
+
+    use wasi_common::file::OFlags;
+    use wasi_common::pipe::{ReadPipe, WritePipe};
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stdio() {
+        let root = Directory::root(Ledger::new(), None);
+        let stdout = Arc::new(RwLock::new(Vec::new()));
+        populate_stdio(
+            &root,
+            Box::new(ReadPipe::from("input")),
+            Box::new(WritePipe::from_shared(stdout.clone())),
+            Box::new(WritePipe::new_in_memory()),
+        )
+        .await
+        .unwrap();
+
+        let dir = root.open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut stdin = dir
+            .open_file(false, "stdin", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        assert_eq!(
+            stdin.get_filetype().await.unwrap(),
+            FileType::CharacterDevice
+        );
+
+        // Reads from stdin drain its file, but stdin was opened read-only.
+        let mut buf = [0u8; 8];
+        let n = stdin.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!(&buf[..n.unwrap() as usize], b"input");
+        stdin
+            .write_vectored(&[IoSlice::new(b"x")])
+            .await
+            .unwrap_err();
+
+        // Every handle to stdout writes to its file.
+        for _ in 0..2 {
+            let mut out = dir
+                .open_file(false, "stdout", OFlags::empty(), false, true, flags)
+                .await
+                .unwrap();
+            out.write_vectored(&[IoSlice::new(b"out")]).await.unwrap();
+        }
+        assert_eq!(&*stdout.read().unwrap(), b"outout");
+    }
+}
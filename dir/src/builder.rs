@@ -0,0 +1,541 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::IoSlice;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use wasi_common::file::{FdFlags, FileType, OFlags};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec};
+use wasmtime_vfs_memory::{normalize, ErrnoExt, Node, Permissions};
+
+use crate::{Directory, NodeFactory, Symlink};
+
+type Constructor = Arc<dyn Fn(Arc<dyn Node>) -> Arc<dyn Node> + Send + Sync>;
+
+enum Item {
+    Dir,
+    DirWith(Option<Arc<dyn NodeFactory>>),
+    File(Vec<u8>),
+    Node(Constructor),
+    Attach(Arc<dyn Node>),
+}
+
+/// The metadata of an entry of a [`TreeBuilder`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The access time, or `None` to keep the time of creation
+    pub atime: Option<SystemTime>,
+
+    /// The modification time, or `None` to keep the time of creation
+    pub mtime: Option<SystemTime>,
+
+    /// The permissions, or `None` to keep those of creation
+    pub permissions: Option<Permissions>,
+
+    /// Whether to clear the write bits of the permissions
+    pub readonly: bool,
+}
+
+/// A directory or file for [`TreeBuilder::entries`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeEntry {
+    /// The path, relative to the directory the tree is below
+    pub path: String,
+
+    /// The content of a file, or `None` for a directory
+    pub data: Option<Vec<u8>>,
+
+    /// The metadata to set once the tree is built
+    pub meta: Metadata,
+}
+
+/// Builds a tree below a [`Directory`] in one chained expression
+///
+/// Paths are relative to the directory and [`normalize`]d. Missing parents
+/// are created as by `mkdir -p`, with the [`NodeFactory`] of their own
+/// parent. Nothing is attached until [`TreeBuilder::build`], which applies
+/// the entries in the order they were added, then their metadata.
+pub struct TreeBuilder {
+    root: Arc<Directory>,
+    items: Vec<(String, Item)>,
+    metas: Vec<(String, Metadata)>,
+}
+
+impl TreeBuilder {
+    /// Starts a tree below `root`.
+    pub fn new(root: Arc<Directory>) -> Self {
+        Self {
+            root,
+            items: Vec::new(),
+            metas: Vec::new(),
+        }
+    }
+
+    fn push(mut self, path: impl Into<String>, item: Item) -> Self {
+        self.items.push((path.into(), item));
+        self
+    }
+
+    /// Adds a directory, unless there is one at `path` already.
+    pub fn dir(self, path: impl Into<String>) -> Self {
+        self.push(path, Item::Dir)
+    }
+
+    /// Adds a directory whose new nodes are made by `factory`.
+    pub fn dir_with(self, path: impl Into<String>, factory: Option<Arc<dyn NodeFactory>>) -> Self {
+        self.push(path, Item::DirWith(factory))
+    }
+
+    /// Adds a file made by the factory of its directory and holding `data`.
+    pub fn file(self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.push(path, Item::File(data.into()))
+    }
+
+    /// Adds the node made by `factory`, which is given the parent.
+    pub fn node<F>(self, path: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(Arc<dyn Node>) -> Arc<dyn Node> + Send + Sync + 'static,
+    {
+        self.push(path, Item::Node(Arc::new(factory)))
+    }
+
+    /// Adds a node made elsewhere, such as the root of another device.
+    ///
+    /// The node is pointed at its new parent when attached.
+    pub fn attach(self, path: impl Into<String>, node: Arc<dyn Node>) -> Self {
+        self.push(path, Item::Attach(node))
+    }
+
+    /// Adds copies of the directories, files and symbolic links below the
+    /// host directory `host`, with their times and read-only bits.
+    ///
+    /// The host tree is read now, in name order. Other kinds of file, such as
+    /// sockets, are left out, and names which are not UTF-8 fail with
+    /// `EILSEQ`. A `path` of `.` copies into the directory itself.
+    pub fn host_dir(
+        mut self,
+        path: impl Into<String>,
+        host: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+
+        let mut entries = std::fs::read_dir(host)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = utf8(entry.file_name())?;
+            let path = match path.as_str() {
+                "" | "." => name,
+                path => format!("{}/{}", path, name),
+            };
+
+            // Entries do not follow symbolic links, so neither does this.
+            let stat = entry.metadata()?;
+            let kind = stat.file_type();
+            if kind.is_symlink() {
+                let target = utf8(std::fs::read_link(entry.path())?.into_os_string())?;
+                self = self.node(path, move |parent| Symlink::new(parent, target.clone()));
+                continue;
+            }
+
+            self = match kind {
+                kind if kind.is_dir() => self
+                    .dir(path.clone())
+                    .host_dir(path.clone(), entry.path())?,
+                kind if kind.is_file() => self.file(path.clone(), std::fs::read(entry.path())?),
+                _ => continue,
+            };
+
+            let meta = Metadata {
+                atime: stat.accessed().ok(),
+                mtime: stat.modified().ok(),
+                readonly: stat.permissions().readonly(),
+                ..Default::default()
+            };
+            self = self.meta(path, meta);
+        }
+
+        Ok(self)
+    }
+
+    /// Sets the metadata of the node at `path`, once the tree is built.
+    ///
+    /// The node may be any in the tree, including parents made on the way.
+    pub fn meta(mut self, path: impl Into<String>, meta: Metadata) -> Self {
+        self.metas.push((path.into(), meta));
+        self
+    }
+
+    /// Adds each of `entries`, as by [`TreeBuilder::dir`] or
+    /// [`TreeBuilder::file`] and [`TreeBuilder::meta`].
+    pub fn entries(mut self, entries: impl IntoIterator<Item = TreeEntry>) -> Self {
+        for entry in entries {
+            self = match entry.data {
+                Some(data) => self.file(entry.path.clone(), data),
+                None => self.dir(entry.path.clone()),
+            };
+
+            if entry.meta != Metadata::default() {
+                self = self.meta(entry.path, entry.meta);
+            }
+        }
+
+        self
+    }
+
+    /// Checks the entries against each other without attaching any.
+    ///
+    /// Fails if a path is not valid or leaves the tree, if two entries other
+    /// than directories share a path, or if a file is the parent of another
+    /// entry. Entries can still conflict with the existing tree when built.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut seen = BTreeMap::new();
+
+        for (path, item) in &self.items {
+            let path = normalize(path)?;
+            let (_, name) = path.rsplit_once('/').unwrap_or(("", &path));
+            if name == "." || path.split('/').any(|s| s == "..") {
+                return Err(Error::invalid_argument());
+            }
+
+            match seen.insert(path, item) {
+                Some(Item::Dir) if matches!(item, Item::Dir) => (),
+                Some(..) => return Err(Error::exist()),
+                None => (),
+            }
+        }
+
+        for path in seen.keys() {
+            let mut parent: &str = path;
+            while let Some((lhs, _)) = parent.rsplit_once('/') {
+                if let Some(Item::File(..)) = seen.get(lhs) {
+                    return Err(Error::not_dir());
+                }
+
+                parent = lhs;
+            }
+        }
+
+        for (path, _) in &self.metas {
+            normalize(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Attaches every entry, returning the directory the tree is below.
+    ///
+    /// The entries are [validated](TreeBuilder::validate) first.
+    pub async fn build(self) -> Result<Arc<Directory>, Error> {
+        self.validate()?;
+
+        for (path, item) in self.items {
+            let path = normalize(&path)?;
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((lhs, rhs)) => (mkdirs(&self.root, lhs).await?, rhs),
+                None => (self.root.clone(), &*path),
+            };
+
+            let node: Arc<dyn Node> = match item {
+                Item::Dir => match parent.get(name).await {
+                    Ok(child) if child.filetype() == FileType::Directory => continue,
+                    _ => Directory::new(parent.clone(), parent.factory()),
+                },
+                Item::DirWith(factory) => Directory::new(parent.clone(), factory),
+                Item::File(data) => {
+                    let factory = parent.factory().ok_or_else(Error::not_supported)?;
+                    let flags = FdFlags::empty();
+                    let node = factory.create_file(&parent, name, OFlags::CREATE, flags)?;
+                    write(&node, &data).await?;
+                    node
+                }
+                Item::Node(factory) => factory(parent.clone()),
+                Item::Attach(node) => {
+                    let weak = Arc::downgrade(&(parent.clone() as Arc<dyn Node>));
+                    node.set_parent(weak);
+                    node
+                }
+            };
+
+            parent.attach(name, node).await?;
+        }
+
+        for (path, meta) in self.metas {
+            let node = self.root.get(&normalize(&path)?).await?;
+            apply(node, meta).await?;
+        }
+
+        Ok(self.root)
+    }
+}
+
+// Sets the metadata of `node`, its times first in case it becomes read-only.
+async fn apply(node: Arc<dyn Node>, meta: Metadata) -> Result<(), Error> {
+    let spec = |time: Option<SystemTime>| {
+        time.map(|t| SystemTimeSpec::Absolute(cap_std::time::SystemTime::from_std(t)))
+    };
+
+    if meta.atime.is_some() || meta.mtime.is_some() {
+        node.clone()
+            .set_times(spec(meta.atime), spec(meta.mtime))
+            .await?;
+    }
+
+    if let Some(permissions) = meta.permissions {
+        node.set_permissions(permissions).await?;
+    }
+
+    if meta.readonly {
+        let mut permissions = node.permissions().await.unwrap_or_default();
+        permissions.mode &= !0o222;
+        node.set_permissions(permissions).await?;
+    }
+
+    Ok(())
+}
+
+// Finds the directory at the normal `path` below `root`, creating any
+// missing on the way.
+async fn mkdirs(root: &Arc<Directory>, path: &str) -> Result<Arc<Directory>, Error> {
+    let mut dir = root.clone();
+
+    for seg in path.split('/') {
+        dir = match seg {
+            "." => continue,
+            ".." => return Err(Error::invalid_argument()),
+            seg => match dir.get(seg).await {
+                Ok(child) => child
+                    .to_any()
+                    .downcast::<Directory>()
+                    .map_err(|_| Error::not_dir())?,
+                Err(..) => {
+                    let child = Directory::new(dir.clone(), dir.factory());
+                    dir.attach(seg, child.clone()).await?;
+                    child
+                }
+            },
+        };
+    }
+
+    Ok(dir)
+}
+
+// Converts a host name to UTF-8, failing with `EILSEQ`.
+fn utf8(name: OsString) -> Result<String, Error> {
+    name.into_string()
+        .map_err(|_| Error::illegal_byte_sequence())
+}
+
+// Writes `data` into the new file `node`.
+async fn write(node: &Arc<dyn Node>, data: &[u8]) -> Result<(), Error> {
+    let flags = FdFlags::empty();
+    let mut file = node
+        .clone()
+        .open_file("", false, false, true, flags)
+        .await?;
+
+    let mut done = 0;
+    while done < data.len() {
+        match file.write_vectored(&[IoSlice::new(&data[done..])]).await? {
+            0 => return Err(Error::file_too_big()),
+            n => done += n as usize,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use wasmtime_vfs_file::File;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn build() {
+        let other = Directory::root(Ledger::new(), None);
+        let keys = Directory::device(other.clone(), None);
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let root = TreeBuilder::new(root)
+            .file("etc/hosts", "127.0.0.1 localhost\n")
+            .dir("etc")
+            .dir("var/log")
+            .dir_with("ro", None)
+            .node("etc/motd", |parent| File::with_data(parent, "hi"))
+            .attach("keys", keys.clone())
+            .build()
+            .await
+            .unwrap();
+
+        // Files hold their data and directories are made on the way.
+        let hosts = root.get("etc/hosts").await.unwrap();
+        assert_eq!(hosts.filestat().await.unwrap().size, 20);
+        assert_eq!(
+            root.get("var/log").await.unwrap().filetype(),
+            FileType::Directory
+        );
+        assert_eq!(
+            root.get("etc/motd")
+                .await
+                .unwrap()
+                .filestat()
+                .await
+                .unwrap()
+                .size,
+            2
+        );
+
+        // Each directory keeps its own factory.
+        let ro = root
+            .get("ro")
+            .await
+            .unwrap()
+            .to_any()
+            .downcast::<Directory>();
+        assert!(ro.unwrap().factory().is_none());
+
+        // Attached nodes are moved under their new parent.
+        let parent = keys.parent().unwrap();
+        assert!(Arc::ptr_eq(&parent, &(root.clone() as Arc<dyn Node>)));
+
+        // Entries cannot replace each other.
+        let root = TreeBuilder::new(root).file("etc/hosts", "").build().await;
+        root.err().unwrap();
+    }
+
+    #[tokio::test]
+    async fn metadata() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let then = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let meta = Metadata {
+            atime: Some(then),
+            mtime: Some(then),
+            readonly: true,
+            ..Default::default()
+        };
+
+        let entries = [
+            TreeEntry {
+                path: "etc/hosts".into(),
+                data: Some(b"127.0.0.1 localhost\n".to_vec()),
+                meta,
+            },
+            TreeEntry {
+                path: "tmp".into(),
+                ..Default::default()
+            },
+        ];
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let root = TreeBuilder::new(root)
+            .entries(entries.clone())
+            .meta("etc", meta)
+            .dir("home/user")
+            .meta(
+                "home/user",
+                Metadata {
+                    permissions: Some(Permissions {
+                        mode: 0o750,
+                        uid: 1000,
+                        gid: 100,
+                    }),
+                    readonly: true,
+                    ..Default::default()
+                },
+            )
+            .build()
+            .await
+            .unwrap();
+
+        // Metadata is set once the entries below are in place.
+        for path in ["etc", "etc/hosts"] {
+            let node = root.get(path).await.unwrap();
+            let stat = node.clone().filestat().await.unwrap();
+            assert_eq!(stat.atim, Some(then));
+            assert_eq!(stat.mtim, Some(then));
+            assert_eq!(node.permissions().await.unwrap().mode, 0o555);
+        }
+        let tmp = root.get("tmp").await.unwrap();
+        assert_eq!(tmp.permissions().await.unwrap().mode, 0o777);
+
+        // Permissions are set before the write bits are cleared.
+        let user = root.get("home/user").await.unwrap();
+        let permissions = user.permissions().await.unwrap();
+        assert_eq!(permissions.mode, 0o550);
+        assert_eq!((permissions.uid, permissions.gid), (1000, 100));
+
+        // Conflicting entries are refused before any is attached.
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let builder = TreeBuilder::new(root.clone()).entries(entries);
+        builder.validate().unwrap();
+        for builder in [
+            builder.file("a/../../b", ""),
+            TreeBuilder::new(root.clone()).file("a", "").dir("a"),
+            TreeBuilder::new(root.clone()).dir("a").file("./a/", ""),
+            TreeBuilder::new(root.clone())
+                .file("a", "")
+                .file("a/b/c", ""),
+        ] {
+            builder.validate().err().unwrap();
+            builder.build().await.err().unwrap();
+        }
+        root.get("etc").await.err().unwrap();
+        root.get("a").await.err().unwrap();
+    }
+
+    #[tokio::test]
+    async fn host_dir() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let host = tempfile::tempdir().unwrap();
+        std::fs::create_dir(host.path().join("etc")).unwrap();
+        std::fs::write(host.path().join("etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+        std::fs::write(host.path().join("motd"), "hi").unwrap();
+        std::os::unix::fs::symlink("etc/hosts", host.path().join("hosts")).unwrap();
+
+        let motd = std::fs::File::options()
+            .write(true)
+            .open(host.path().join("motd"))
+            .unwrap();
+        let then = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        motd.set_modified(then).unwrap();
+        let mut permissions = motd.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        motd.set_permissions(permissions).unwrap();
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let root = TreeBuilder::new(root)
+            .host_dir(".", host.path())
+            .unwrap()
+            .host_dir("copy", host.path().join("etc"))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        // Files keep their content, times and read-only bits.
+        for (path, size) in [("etc/hosts", 20), ("copy/hosts", 20), ("motd", 2)] {
+            let stat = root.get(path).await.unwrap().filestat().await.unwrap();
+            assert_eq!(stat.size, size);
+        }
+        let motd = root.get("motd").await.unwrap();
+        assert_eq!(motd.clone().filestat().await.unwrap().mtim, Some(then));
+        assert_eq!(motd.permissions().await.unwrap().mode & 0o222, 0);
+
+        // Symbolic links are copied as links.
+        let open = root.open_dir().await.unwrap();
+        assert_eq!(
+            open.read_link("hosts").await.unwrap().to_str(),
+            Some("etc/hosts")
+        );
+
+        // Missing host directories fail.
+        let root = Directory::root(Ledger::new(), None);
+        let missing = host.path().join("missing");
+        TreeBuilder::new(root).host_dir(".", missing).err().unwrap();
+    }
+}
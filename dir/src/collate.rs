@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use unicode_normalization::UnicodeNormalization;
+use wasmtime_vfs_ledger::Collation;
+
+/// Finding directory entries by name under a [`Collation`]
+pub(crate) trait Names {
+    /// The name under which `name` is stored, if any.
+    ///
+    /// Insensitive lookups of names not stored as given scan the entries.
+    fn key(&self, collation: Collation, name: &str) -> Option<String>;
+}
+
+impl<T> Names for BTreeMap<String, T> {
+    fn key(&self, collation: Collation, name: &str) -> Option<String> {
+        if let Some((key, _)) = self.get_key_value(name) {
+            return Some(key.clone());
+        }
+
+        match collation {
+            Collation::Exact => None,
+            Collation::Insensitive => {
+                let folded = fold(name);
+                self.keys().find(|key| fold(key) == folded).cloned()
+            }
+        }
+    }
+}
+
+// Folds the case of `name`, then composes it, so that names differing only
+// in either match.
+fn fold(name: &str) -> String {
+    name.to_lowercase().nfc().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let mut names = BTreeMap::new();
+        names.insert("Config.TOML".to_string(), ());
+        names.insert("caf\u{e9}".to_string(), ());
+
+        let exact = Collation::Exact;
+        assert_eq!(names.key(exact, "Config.TOML").unwrap(), "Config.TOML");
+        assert_eq!(names.key(exact, "config.toml"), None);
+
+        let insensitive = Collation::Insensitive;
+        let key = names.key(insensitive, "config.toml").unwrap();
+        assert_eq!(key, "Config.TOML");
+        let key = names.key(insensitive, "CAFE\u{301}").unwrap();
+        assert_eq!(key, "caf\u{e9}");
+        assert_eq!(names.key(insensitive, "cafe"), None);
+    }
+}
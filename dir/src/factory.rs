@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use wasi_common::file::{FdFlags, OFlags};
+use wasi_common::Error;
+use wasmtime_vfs_memory::Node;
+
+use crate::Directory;
+
+/// What `O_CREAT` and `mkdir` make in a directory
+///
+/// Each directory made by the default [`create_dir`](Self::create_dir)
+/// shares the factory of its parent. A factory can refuse a name, for
+/// example with `EACCES`, and nothing is created. It is also given the
+/// flags of the open creating the node; `mkdir` creates as an open with
+/// `O_CREAT | O_DIRECTORY | O_EXCL` and no descriptor flags would.
+///
+/// Any `Fn(Arc<dyn Node>) -> Arc<dyn Node>`, such as `File::new`, is a
+/// factory making that kind of file under every name.
+pub trait NodeFactory: Send + Sync {
+    /// Makes the regular file `name` in `parent`, for an open with `oflags`
+    /// and `flags`.
+    fn create_file(
+        &self,
+        parent: &Arc<Directory>,
+        name: &str,
+        oflags: OFlags,
+        flags: FdFlags,
+    ) -> Result<Arc<dyn Node>, Error>;
+
+    /// Makes the directory `name` in `parent`, for an open with `oflags` and
+    /// `flags`.
+    fn create_dir(
+        &self,
+        parent: &Arc<Directory>,
+        _name: &str,
+        _oflags: OFlags,
+        _flags: FdFlags,
+    ) -> Result<Arc<dyn Node>, Error> {
+        Ok(Directory::new(parent.clone(), parent.factory()))
+    }
+}
+
+impl<F> NodeFactory for F
+where
+    F: Fn(Arc<dyn Node>) -> Arc<dyn Node> + Send + Sync,
+{
+    fn create_file(
+        &self,
+        parent: &Arc<Directory>,
+        _name: &str,
+        _oflags: OFlags,
+        _flags: FdFlags,
+    ) -> Result<Arc<dyn Node>, Error> {
+        Ok(self(parent.clone()))
+    }
+}
@@ -0,0 +1,217 @@
+use std::any::Any;
+use std::io::{IoSliceMut, SeekFrom};
+use std::mem::take;
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node, Open};
+
+use crate::Fetcher;
+
+/// The response to a fetch, streamed to the guest chunk by chunk
+#[async_trait::async_trait]
+pub trait Body: Send + Sync {
+    /// Waits for the next chunk, or `None` at the end of the response.
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// A response which has arrived in full
+#[async_trait::async_trait]
+impl Body for Vec<u8> {
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(Some(take(self)).filter(|chunk| !chunk.is_empty()))
+    }
+}
+
+/// A read-only file whose contents the embedder fetches on demand
+///
+/// The first read of each handle calls the [`Fetcher`], for example to GET
+/// a certificate chain over HTTPS, and reads return the response as it
+/// arrives. Handles fetch independently, so reopening the file fetches it
+/// again. A failed fetch fails the read, and the next read tries again.
+pub struct Fetched(Link<Fetcher>);
+
+#[async_trait::async_trait]
+impl Node for Fetched {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::RegularFile
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let ilock = self.0.inode().read().await?;
+
+        // The size is unknown until the file is fetched.
+        Ok(Filestat {
+            device_id: **self.0.inode().id().device(),
+            inode: **self.0.inode().id(),
+            filetype: FileType::RegularFile,
+            nlink: self.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if write {
+            return Err(Error::access());
+        }
+
+        Ok(Box::new(OpenFetched {
+            open: Open::new(self, read, write, flags),
+            response: Response::Pending,
+            chunk: Vec::new(),
+            offset: 0,
+            pos: 0,
+        }))
+    }
+}
+
+impl Fetched {
+    /// Creates a file in `parent` reading what `fetcher` fetches.
+    pub fn new(parent: Arc<dyn Node>, fetcher: Fetcher) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, fetcher)))
+    }
+}
+
+// How far the fetch of a handle has got
+enum Response {
+    Pending,
+    Reading(Box<dyn Body>),
+    Done,
+}
+
+struct OpenFetched {
+    open: Open<Fetched>,
+    response: Response,
+
+    // The chunk being read and how much of it has been.
+    chunk: Vec<u8>,
+    offset: usize,
+
+    // How much of the response has been read.
+    pos: u64,
+}
+
+impl OpenFetched {
+    // Waits for unread bytes, fetching the file on the first call. Returns
+    // an empty slice at the end of the response.
+    async fn fill(&mut self) -> Result<&[u8], Error> {
+        while self.offset == self.chunk.len() {
+            let body = match &mut self.response {
+                Response::Pending => {
+                    let fetcher = self
+                        .open
+                        .link()
+                        .0
+                        .inode()
+                        .data()
+                        .read()
+                        .await
+                        .content()
+                        .clone();
+                    self.response = Response::Reading(fetcher().await?);
+                    continue;
+                }
+                Response::Reading(body) => body,
+                Response::Done => return Ok(&[]),
+            };
+
+            match body.chunk().await? {
+                Some(chunk) => (self.chunk, self.offset) = (chunk, 0),
+                None => self.response = Response::Done,
+            }
+        }
+
+        Ok(&self.chunk[self.offset..])
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenFetched {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.open.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.open.link().clone().filestat().await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        if !self.open.can_read() {
+            return Err(Error::badf());
+        }
+
+        let data = self.fill().await?;
+
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), data.len() - total);
+            buf[..len].copy_from_slice(&data[total..][..len]);
+            total += len;
+        }
+
+        self.offset += total;
+        self.pos += total as u64;
+        Ok(total as u64)
+    }
+
+    // The response can only be read in order.
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.pos),
+            SeekFrom::Start(pos) if pos == self.pos => Ok(self.pos),
+            _ => Err(Error::not_supported()),
+        }
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok((self.chunk.len() - self.offset) as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
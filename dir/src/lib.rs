@@ -1,24 +1,81 @@
 use std::any::Any;
 use std::collections::BTreeMap;
+use std::future::Future;
 use std::io::{IoSlice, IoSliceMut, SeekFrom};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
-use std::sync::{Arc, Weak};
+use std::pin::Pin;
+use std::sync::{Arc, PoisonError, RwLock as SyncRwLock, Weak};
 
 use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
 use wasi_common::file::{Advice, FdFlags, FileType, Filestat, OFlags};
 use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
-use wasmtime_vfs_ledger::{DeviceId, InodeId, Ledger};
-use wasmtime_vfs_memory::{Link, Node, Open, State};
-
-type NodeConstructor = Arc<dyn Fn(Arc<dyn Node>) -> Arc<dyn Node> + Send + Sync>;
-
-/// A directory generic in file [`Node`] constructor
+use wasmtime_vfs_ledger::{Collation, DeviceId, InodeId, Ledger, Statvfs, Strictness};
+use wasmtime_vfs_memory::{
+    access, normalize, walk_path, walk_path_as_guest, Data, ErrnoExt, Event, Link, Node, Open,
+    Permissions, Watch, MAX_LINKS,
+};
+
+use collate::Names;
+use readdir::Entries;
+
+pub use builder::{Metadata, TreeBuilder, TreeEntry};
+pub use factory::NodeFactory;
+pub use fetch::{Body, Fetched};
+pub use mount::Mounts;
+pub use symlink::Symlink;
+pub use template::Templates;
+pub use trash::{Trash, Trashed};
+pub use walk::{walk, walk_with, Entry, Links};
+pub use watch::Watcher;
+
+mod builder;
+mod collate;
+mod factory;
+mod fetch;
+mod mount;
+mod readdir;
+mod symlink;
+mod template;
+mod trash;
+mod walk;
+mod watch;
+
+/// The size a directory reports for each entry, as tmpfs does on Linux
+pub const DIRENT_SIZE: u64 = 20;
+
+/// A callback starting the fetch behind a [`Fetched`] file
+///
+/// It is called by the first read of each handle, and resolves to the
+/// [`Body`] of the response.
+pub type Fetcher = Arc<
+    dyn Fn() -> Pin<Box<dyn Future<Output = Result<Box<dyn Body>, Error>> + Send>> + Send + Sync,
+>;
+
+/// A callback synthesizing the child of a directory named by a lookup
+///
+/// It is given the directory and the missing name, and returns the node to
+/// use for that lookup alone, or `None` to report `ENOENT`.
+pub type Resolver = Arc<dyn Fn(Arc<Directory>, &str) -> Option<Arc<dyn Node>> + Send + Sync>;
+
+/// A directory generic in the [`NodeFactory`] making its new nodes
 pub struct Directory {
     nodes: Link<BTreeMap<String, Arc<dyn Node>>>,
-    create_file: Option<NodeConstructor>,
+    factory: Option<Arc<dyn NodeFactory>>,
+    trash: SyncRwLock<Option<Arc<Trash>>>,
+    templates: SyncRwLock<Option<Arc<Templates>>>,
+    resolver: SyncRwLock<Option<Resolver>>,
 }
 
+// The locks of directories are taken in one order, so that no two tasks wait
+// for each other. A directory is locked before its children, and two
+// directories neither of which holds the other are locked in address order.
+// Any other lock is only tried, failing with `EBUSY` if it is held, and no
+// lock is held while opening a child, since opening may wait.
+//
+// Reads take the lock only long enough to copy what they return: `filestat`
+// copies the metadata and `readdir` the entries from its cursor on.
+
 impl Deref for Directory {
     type Target = Link<BTreeMap<String, Arc<dyn Node>>>;
 
@@ -33,83 +90,388 @@ impl DerefMut for Directory {
     }
 }
 
+// The children lose the entries naming them along with the directory.
+impl Drop for Directory {
+    fn drop(&mut self) {
+        if let Ok(ilock) = self.inode().data().try_read() {
+            for child in ilock.content().values() {
+                child.id().unlink();
+            }
+        }
+    }
+}
+
+/// Reports the usage of the device holding a directory handle of a guest,
+/// as `fstatvfs` does.
+///
+/// WASI has no such call, so hosts offering one answer it with this. It
+/// fails with `EBADF` for handles not opened on a [`Directory`].
+pub fn statvfs(dir: &dyn WasiDir) -> Result<Statvfs, Error> {
+    let dir = dir.as_any().downcast_ref::<OpenDir>();
+    let dir = dir.ok_or_else(Error::badf)?;
+    Ok(dir.link().id().device().statvfs())
+}
+
+// Counts the removal of an entry naming `node`, revoking it with the last,
+// along with everything below it which no other entry names.
+async fn unlink(node: &dyn Node) {
+    node.id().unlink();
+    if node.id().links() == 0 {
+        node.revoke().await;
+    }
+}
+
 impl Directory {
     fn new_at(
         parent: Weak<dyn Node>,
         device_id: Arc<DeviceId>,
-        create_file: Option<NodeConstructor>,
+        factory: Option<Arc<dyn NodeFactory>>,
     ) -> Arc<Self> {
-        let nodes = Link {
-            parent,
-            inode: Arc::new(device_id.create_inode().into()),
-        };
-        Self { nodes, create_file }.into()
+        let nodes = Link::with_id(parent, device_id.create_inode());
+        Self {
+            nodes,
+            factory,
+            trash: Default::default(),
+            templates: Default::default(),
+            resolver: Default::default(),
+        }
+        .into()
+    }
+
+    // The topmost directory on the same device as this one.
+    fn top(self: &Arc<Self>) -> Arc<Self> {
+        let mut this = self.clone();
+
+        while let Some(parent) = this.parent() {
+            if parent.id().device() != this.id().device() {
+                break;
+            }
+
+            match parent.to_any().downcast::<Directory>() {
+                Ok(parent) => this = parent,
+                Err(..) => break,
+            }
+        }
+
+        this
     }
 
     fn prev(self: &Arc<Self>) -> Arc<dyn Node> {
-        match self.parent.upgrade() {
+        match self.nodes.parent() {
             Some(parent) => parent,
             None => self.clone(),
         }
     }
 
-    pub fn device(parent: Arc<dyn Node>, create_file: Option<NodeConstructor>) -> Arc<Self> {
+    pub fn device(parent: Arc<dyn Node>, factory: Option<Arc<dyn NodeFactory>>) -> Arc<Self> {
         Self::new_at(
             Arc::downgrade(&parent),
             parent.id().device().ledger().create_device(),
-            create_file,
+            factory,
         )
     }
 
-    pub fn root(ledger: Arc<Ledger>, create_file: Option<NodeConstructor>) -> Arc<Self> {
-        Self::new_at(Weak::<Self>::new(), ledger.create_device(), create_file)
+    pub fn root(ledger: Arc<Ledger>, factory: Option<Arc<dyn NodeFactory>>) -> Arc<Self> {
+        Self::new_at(Weak::<Self>::new(), ledger.create_device(), factory)
     }
 
-    pub fn new(parent: Arc<dyn Node>, create_file: Option<NodeConstructor>) -> Arc<Self> {
-        Self::new_at(Arc::downgrade(&parent), parent.id().device(), create_file)
+    pub fn new(parent: Arc<dyn Node>, factory: Option<Arc<dyn NodeFactory>>) -> Arc<Self> {
+        Self::new_at(Arc::downgrade(&parent), parent.id().device(), factory)
     }
 
-    pub async fn get(self: &Arc<Self>, path: &str) -> Result<Arc<dyn Node>, Error> {
-        let mut this: Arc<dyn Node> = self.clone();
-
-        for seg in path.trim_end_matches('/').split('/') {
-            this = match seg {
-                "" | "." => continue,
-                ".." => self.prev(),
-                seg => {
-                    let any = this.to_any();
-                    let dir = any.downcast::<Directory>().map_err(|_| Error::not_dir())?;
-                    let ilock = dir.inode.data.read().await;
-                    ilock.content.get(seg).ok_or_else(Error::not_found)?.clone()
-                }
-            };
+    /// Gets the factory for nodes created in this directory, if any.
+    pub fn factory(&self) -> Option<Arc<dyn NodeFactory>> {
+        self.factory.clone()
+    }
+
+    /// Sets the [`Trash`] for the device of this directory.
+    ///
+    /// While set, files unlinked or replaced by a rename anywhere on the
+    /// device are moved to the trash instead of being dropped.
+    pub fn set_trash(self: &Arc<Self>, trash: Option<Arc<Trash>>) {
+        let top = self.top();
+        *top.trash.write().unwrap_or_else(PoisonError::into_inner) = trash;
+    }
+
+    /// Gets the [`Trash`] for the device of this directory, if any.
+    pub fn trash(self: &Arc<Self>) -> Option<Arc<Trash>> {
+        let top = self.top();
+        let trash = top.trash.read().unwrap_or_else(PoisonError::into_inner);
+        trash.clone()
+    }
+
+    /// Sets the [`Templates`] for the device of this directory.
+    ///
+    /// While set, regular files created anywhere on the device start with
+    /// the contents of the first matching template.
+    pub fn set_templates(self: &Arc<Self>, templates: Option<Arc<Templates>>) {
+        let top = self.top();
+        *top.templates
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = templates;
+    }
+
+    /// Gets the [`Templates`] for the device of this directory, if any.
+    pub fn templates(self: &Arc<Self>) -> Option<Arc<Templates>> {
+        let top = self.top();
+        let templates = top.templates.read().unwrap_or_else(PoisonError::into_inner);
+        templates.clone()
+    }
+
+    /// Sets the [`Resolver`] consulted when a name is missing from this
+    /// directory.
+    ///
+    /// Synthesized nodes are not attached, so they are not listed by
+    /// `readdir` and each lookup may return a different node. Creating,
+    /// removing and renaming only see the attached children.
+    pub fn set_resolver(&self, resolver: Option<Resolver>) {
+        *self
+            .resolver
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = resolver;
+    }
+
+    // Tells the watches of this directory of a change to its entries.
+    fn notify(&self, event: Event) {
+        self.inode().watchers().send(event)
+    }
+
+    // The collation of the names in this directory.
+    fn collation(&self) -> Collation {
+        self.id().device().collation()
+    }
+
+    // Finds the child `name`, asking the resolver if it is not attached.
+    async fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<dyn Node>, Error> {
+        let ilock = self.inode().data().read().await;
+        if let Some(key) = ilock.content().key(self.collation(), name) {
+            return Ok(ilock.content()[&key].clone());
         }
+        drop(ilock);
 
-        Ok(this)
+        self.resolve(name).ok_or_else(Error::not_found)
     }
 
-    pub async fn attach(self: &Arc<Self>, path: &str, node: Arc<dyn Node>) -> Result<(), Error> {
-        let path = path.trim_end_matches('/');
-        let (this, name) = match path.rsplit_once('/') {
+    // Asks the resolver, if any, for the missing child `name`.
+    fn resolve(self: &Arc<Self>, name: &str) -> Option<Arc<dyn Node>> {
+        let resolver = self.resolver.read().unwrap_or_else(PoisonError::into_inner);
+        let resolver = resolver.clone()?;
+        resolver(self.clone(), name)
+    }
+
+    // Splits `path` into the node it starts from, the root of the tree if it
+    // is absolute, and the path from there. A `..` above the root fails with
+    // `ENOENT` rather than staying there.
+    fn start<'a>(self: &Arc<Self>, path: &'a str) -> Result<(Arc<dyn Node>, &'a str), Error> {
+        let (start, path): (Arc<dyn Node>, _) = match path.strip_prefix('/') {
+            Some(path) => (Node::root(self), path.trim_start_matches('/')),
             None => (self.clone(), path),
+        };
+
+        let mut depth = std::iter::successors(start.parent(), |node| node.parent()).count();
+        for segment in path.split('/') {
+            depth = match segment {
+                "" | "." => depth,
+                ".." => depth.checked_sub(1).ok_or_else(Error::not_found)?,
+                _ => depth + 1,
+            };
+        }
+
+        Ok((start, path))
+    }
+
+    // Finds the directory holding the node at `path`, resolved as by `get`,
+    // and the name of the node in it.
+    async fn locate(self: &Arc<Self>, path: &str) -> Result<(Arc<Self>, String), Error> {
+        let (start, path) = self.start(path)?;
+        let path = match path {
+            "" => return Err(Error::invalid_argument()),
+            path => normalize(path)?,
+        };
+
+        let start = start.to_any().downcast::<Directory>();
+        let start = start.map_err(|_| Error::not_dir())?;
+
+        match path.rsplit_once('/') {
+            None => Ok((start, path.to_string())),
             Some((lhs, rhs)) => {
-                let any = self.get(lhs).await?.to_any();
+                let any = start.get(lhs).await?.to_any();
                 let dir = any.downcast::<Directory>().map_err(|_| Error::not_dir())?;
-                (dir, rhs)
+                Ok((dir, rhs.to_owned()))
             }
+        }
+    }
+
+    /// Finds the node at `path`, relative to this directory.
+    ///
+    /// The path is [`normalize`]d first, but an absolute path starts from the
+    /// root of the tree and an empty path is this directory. A `..` above the
+    /// root fails with `ENOENT`.
+    pub async fn get(self: &Arc<Self>, path: &str) -> Result<Arc<dyn Node>, Error> {
+        let (start, path) = self.start(path)?;
+        let path = match path {
+            "" => ".",
+            path => path,
         };
 
-        let mut ilock = this.inode.data.write().await;
+        let path = normalize(path)?;
+        let (node, last) = walk_path(start, &path).await?;
+        match node.child(&last).await {
+            Some(child) => child,
+            None => Err(Error::not_dir()),
+        }
+    }
+
+    /// Attaches `node` at `path`, relative to this directory, which is
+    /// resolved as by [`Directory::get`].
+    pub async fn attach(self: &Arc<Self>, path: &str, node: Arc<dyn Node>) -> Result<(), Error> {
+        let (this, name) = self.locate(path).await?;
+
+        let mut ilock = this.inode().data().write().await;
 
-        match name {
+        match &*name {
             "" | "." | ".." => Err(Error::invalid_argument()),
-            name if ilock.content.contains_key(name) => Err(Error::exist()),
+            name if ilock.content().key(this.collation(), name).is_some() => Err(Error::exist()),
             name => {
-                ilock.content.insert(name.to_owned(), node);
+                node.id().link();
+                ilock.content_mut().insert(name.to_owned(), node);
+                ilock.touch();
+                this.notify(Event::Create(name.into()));
                 Ok(())
             }
         }
     }
+
+    /// Removes the node at `path` and everything below it, as `rm -r` does.
+    ///
+    /// The path is resolved as by [`Directory::attach`]. As for `rmdir`,
+    /// removal stays on the device of the directory holding the node: if
+    /// anything below it, such as a mount, is on another device, this fails
+    /// with `EXDEV` and removes nothing. Handles still open keep their nodes.
+    pub async fn remove_all(self: &Arc<Self>, path: &str) -> Result<(), Error> {
+        let (this, name) = self.locate(path).await?;
+
+        if let "." | ".." = &*name {
+            return Err(Error::invalid_argument());
+        }
+
+        let ilock = this.inode().read().await?;
+        let key = ilock.content().key(this.collation(), &name);
+        let node = ilock.content()[&key.ok_or_else(Error::not_found)?].clone();
+        drop(ilock);
+
+        // Walked before locking, since the walk locks the directories below.
+        let device = this.id().device();
+        for entry in walk(node.clone()).await? {
+            if entry.node.id().device() != device {
+                return Err(Error::cross_device());
+            }
+        }
+
+        // Remove the node unless it was replaced meanwhile.
+        let mut ilock = this.inode().write().await?;
+        match ilock.content().key(this.collation(), &name) {
+            Some(key) if Arc::ptr_eq(&ilock.content()[&key], &node) => {
+                ilock.content_mut().remove(&key);
+                ilock.touch();
+                this.notify(Event::Remove(key));
+                drop(ilock);
+
+                // Revoking may wait, so no lock is held.
+                unlink(&*node).await;
+                Ok(())
+            }
+            _ => Err(Error::not_found()),
+        }
+    }
+
+    /// Takes a point-in-time copy of the tree below this directory.
+    ///
+    /// The copy is the root of a new device on the same ledger, with the
+    /// same limits, quotas and credentials, ready to be mounted or handed to
+    /// another guest. File contents are shared copy-on-write, so the copy
+    /// costs little until either side writes. Other devices, such as mounts,
+    /// and nodes which cannot be copied, such as sockets, are left out.
+    pub async fn snapshot(self: &Arc<Self>) -> Arc<Self> {
+        let device = self.id().device();
+        let root = Self::root(device.ledger(), self.factory.clone());
+
+        let copy = root.id().device();
+        copy.set_max_file_size(device.max_file_size());
+        copy.set_strictness(device.strictness());
+        copy.set_granularity(device.granularity());
+        copy.set_collation(device.collation());
+        copy.set_clock(device.clock());
+        copy.set_max_bytes(device.max_bytes());
+        copy.set_max_inodes(device.max_inodes());
+        copy.set_credentials(device.credentials());
+
+        self.snapshot_into(&root).await;
+        root
+    }
+
+    /// Replaces the tree below this directory with a copy of `checkpoint`.
+    ///
+    /// The checkpoint is usually a [`snapshot`](Self::snapshot) taken before
+    /// and is left as it is, so it can be restored again. The directory keeps
+    /// its identity and its mounts. The entries on its device are unlinked,
+    /// as if removed, and open handles to them keep working unless their
+    /// nodes are revoked, as the sockets of a key are. Watches on the
+    /// directory see each entry removed and each copied one created.
+    pub async fn restore(self: &Arc<Self>, checkpoint: &Arc<Self>) {
+        if Arc::ptr_eq(self, checkpoint) {
+            return;
+        }
+
+        let device = self.id().device();
+        let mut removed = Vec::new();
+        let mut ilock = self.inode().data().write().await;
+        ilock.content_mut().retain(|name, child| {
+            let mount = child.id().device() != device;
+            if !mount {
+                removed.push((name.clone(), child.clone()));
+            }
+            mount
+        });
+        ilock.touch();
+        for (name, _) in &removed {
+            self.notify(Event::Remove(name.clone()));
+        }
+        drop(ilock);
+
+        for (_, child) in removed {
+            unlink(&*child).await;
+        }
+
+        checkpoint.snapshot_into(self).await;
+    }
+
+    // Copies the children and metadata of this directory into `copy`,
+    // keeping the entries `copy` already has.
+    async fn snapshot_into(&self, copy: &Arc<Self>) {
+        // Neither directory holds the other, so they go in address order.
+        let (ilock, mut clock) = if (self as *const Self) < Arc::as_ptr(copy) {
+            let ilock = self.inode().data().read().await;
+            (ilock, copy.inode().data().write().await)
+        } else {
+            let clock = copy.inode().data().write().await;
+            (self.inode().data().read().await, clock)
+        };
+
+        for (name, child) in ilock.content().iter() {
+            if child.id().device() != self.id().device() || clock.content().contains_key(name) {
+                continue;
+            }
+
+            if let Some(child) = child.snapshot(copy.clone()).await {
+                child.id().link();
+                clock.content_mut().insert(name.clone(), child);
+                copy.notify(Event::Create(name.clone()));
+            }
+        }
+
+        clock.copy_metadata(&ilock);
+    }
 }
 
 #[async_trait::async_trait]
@@ -119,7 +481,11 @@ impl Node for Directory {
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.parent.upgrade()
+        self.nodes.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.nodes.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -127,17 +493,115 @@ impl Node for Directory {
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.inode.id.clone()
+        self.inode().id()
+    }
+
+    fn watch(&self) -> Option<Watch> {
+        Some(self.inode().watchers().subscribe())
+    }
+
+    async fn permissions(&self) -> Option<Permissions> {
+        Some(self.inode().data().read().await.permissions())
+    }
+
+    async fn set_permissions(&self, permissions: Permissions) -> Result<(), Error> {
+        self.inode()
+            .data()
+            .write()
+            .await
+            .set_permissions(permissions);
+        Ok(())
+    }
+
+    async fn xattr(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.inode().data().read().await.xattr(name)
+    }
+
+    async fn xattrs(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .inode()
+            .data()
+            .read()
+            .await
+            .xattrs()
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    async fn set_xattr(&self, name: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inode().data().write().await.set_xattr(name, value)
+    }
+
+    async fn remove_xattr(&self, name: &str) -> Result<(), Error> {
+        self.inode().data().write().await.remove_xattr(name)
+    }
+
+    // The children go with the directory, unless another entry names them.
+    async fn revoke(&self) {
+        let ilock = self.inode().data().read().await;
+        let children: Vec<_> = ilock.content().values().cloned().collect();
+        drop(ilock);
+
+        for child in children {
+            if child.id().device() == self.id().device() && child.id().links() <= 1 {
+                child.revoke().await;
+            }
+        }
+    }
+
+    async fn snapshot(&self, parent: Arc<dyn Node>) -> Option<Arc<dyn Node>> {
+        let copy = Directory::new(parent, self.factory.clone());
+        self.snapshot_into(&copy).await;
+        Some(copy)
+    }
+
+    async fn child(self: Arc<Self>, name: &str) -> Option<Result<Arc<dyn Node>, Error>> {
+        Some(match name {
+            "" => Err(Error::invalid_argument()),
+            "." => Ok(self),
+            ".." => Ok(self.prev()),
+            name => self.lookup(name).await,
+        })
+    }
+
+    // As on Linux, the links of a directory are its name, its `.` and the
+    // `..` of each subdirectory, and its size counts `.` and `..` too.
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let ilock = self.inode().read().await?;
+        let subdirs = ilock
+            .content()
+            .values()
+            .filter(|child| child.filetype() == FileType::Directory)
+            .count();
+
+        Ok(Filestat {
+            device_id: **self.inode().id().device(),
+            inode: **self.inode().id(),
+            filetype: FileType::Directory,
+            nlink: 2 + subdirs as u64,
+            size: (ilock.content().len() as u64 + 2) * DIRENT_SIZE,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn set_times(
+        self: Arc<Self>,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.inode().write().await?.set_times(atime, mtime)
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
-        Ok(Box::new(OpenDir(Open {
-            root: self.root(),
-            link: self,
-            state: State::default().into(),
-            write: false,
-            read: false,
-        })))
+        Ok(Box::new(OpenDir::new(Open::new(
+            self,
+            false,
+            false,
+            FdFlags::empty(),
+        ))))
     }
 
     async fn open_file(
@@ -148,23 +612,179 @@ impl Node for Directory {
         write: bool,
         flags: FdFlags,
     ) -> Result<Box<dyn WasiFile>, Error> {
-        Ok(Box::new(OpenDir(Open {
-            root: self.root(),
-            link: self,
-            state: State::from(flags).into(),
-            write,
-            read,
-        })))
+        // As on Linux, directories cannot be opened for writing.
+        if write {
+            return Err(Error::is_dir());
+        }
+
+        Ok(Box::new(OpenDir::new(Open::new(self, read, write, flags))))
     }
 }
 
-struct OpenDir(Open<Directory>);
+struct OpenDir {
+    open: Open<Directory>,
+
+    // Where the last listing ended, to resume the next one from.
+    last: readdir::Last,
+}
 
 impl Deref for OpenDir {
     type Target = Open<Directory>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.open
+    }
+}
+
+impl OpenDir {
+    fn new(open: Open<Directory>) -> Self {
+        Self {
+            open,
+            last: Default::default(),
+        }
+    }
+
+    // Locks this directory and `dest` for writing, for a change spanning
+    // both. They are locked in address order, so two such changes cannot
+    // deadlock, and there is no second lock if they are the same.
+    async fn lock_pair<'a>(
+        &'a self,
+        dest: &'a OpenDir,
+    ) -> Result<
+        (
+            impl DerefMut<Target = Data<BTreeMap<String, Arc<dyn Node>>>> + 'a,
+            Option<impl DerefMut<Target = Data<BTreeMap<String, Arc<dyn Node>>>> + 'a>,
+        ),
+        Error,
+    > {
+        if Arc::ptr_eq(self.link(), dest.link()) {
+            Ok((self.link().inode().write().await?, None))
+        } else if Arc::as_ptr(self.link()) < Arc::as_ptr(dest.link()) {
+            let slock = self.link().inode().write().await?;
+            Ok((slock, Some(dest.link().inode().write().await?)))
+        } else {
+            let dlock = dest.link().inode().write().await?;
+            Ok((self.link().inode().write().await?, Some(dlock)))
+        }
+    }
+
+    // Walks to the directory holding the last segment of `path`, or `None`
+    // if the path is a normal single segment and so is in this directory.
+    //
+    // Looking up a name needs search permission on each directory holding
+    // it, this one included.
+    async fn walk(&self, path: &str) -> Result<Option<(Box<dyn WasiDir>, String)>, Error> {
+        let normal = normalize(path)?;
+        if normal == path && !path.contains('/') {
+            access(&**self.link(), false, false, true).await?;
+            return Ok(None);
+        }
+
+        let (node, rest) = walk_path_as_guest(self.link().clone(), &normal).await?;
+        Ok(Some((node.open_dir().await?, rest.into_owned())))
+    }
+
+    // Guests only create nodes within the inode quota of the device.
+    fn room(&self) -> Result<(), Error> {
+        match self.link().id().device().inode_room() {
+            true => Ok(()),
+            false => Err(Error::no_space()),
+        }
+    }
+
+    // Follows the chain of links named by the single segment `name`, if it
+    // is one, to the directory holding the last target and its name there.
+    async fn follow(&self, name: &str) -> Result<Option<(Box<dyn WasiDir>, String)>, Error> {
+        let mut dir: Arc<dyn Node> = self.link().clone();
+        let mut name = name.to_owned();
+        let mut links = 0;
+
+        loop {
+            access(&*dir, false, false, true).await?;
+            let target = match dir.clone().child(&name).await {
+                Some(Ok(node)) => node.read_link(),
+                _ => None,
+            };
+
+            let target = match target {
+                Some(target) => target,
+                None if links == 0 => return Ok(None),
+                None => return Ok(Some((dir.open_dir().await?, name))),
+            };
+
+            links += 1;
+            if links > MAX_LINKS {
+                return Err(Error::too_many_links());
+            }
+
+            // Absolute targets start from the root of the tree.
+            if target.starts_with('/') {
+                while let Some(parent) = dir.parent() {
+                    dir = parent;
+                }
+            }
+
+            let target = match target.trim_start_matches('/') {
+                "" => ".".to_owned(),
+                target => normalize(target)?.into_owned(),
+            };
+
+            let (next, last) = walk_path_as_guest(dir, &target).await?;
+            name = last.into_owned();
+            dir = next;
+        }
+    }
+
+    // Whether ambiguous operations should fail as POSIX requires.
+    fn strict(&self) -> bool {
+        self.link().id().device().strictness() == Strictness::Strict
+    }
+
+    // Writes the template matching `name`, if any, into a new file.
+    async fn apply_template(&self, name: &str, child: &Arc<dyn Node>) -> Result<(), Error> {
+        let contents = match self.link().templates().and_then(|t| t.find(name)) {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+
+        let flags = FdFlags::empty();
+        let mut file = child
+            .clone()
+            .open_file(name, false, false, true, flags)
+            .await?;
+        file.write_vectored(&[IoSlice::new(&contents)]).await?;
+        Ok(())
+    }
+
+    // Moves a node removed from this directory to the trash, if enabled.
+    async fn discard(&self, name: &str, node: Arc<dyn Node>) {
+        let trash = match self.link().trash() {
+            Some(trash) => trash,
+            None => return,
+        };
+
+        let flags = FdFlags::empty();
+        let size = match node
+            .clone()
+            .open_file(name, false, false, false, flags)
+            .await
+        {
+            Ok(mut file) => file.get_filestat().await.map(|s| s.size).unwrap_or(0),
+            Err(..) => 0,
+        };
+
+        // A node still open or linked elsewhere can change size after the
+        // trash has counted it, so the trash does not hold it.
+        if Arc::strong_count(&node) > 1 {
+            return;
+        }
+
+        trash.push(Trashed {
+            name: name.into(),
+            size,
+            when: self.link().id().device().now(),
+            node,
+        });
     }
 }
 
@@ -195,13 +815,21 @@ impl WasiDir for OpenDir {
         ];
 
         // Descend into the path.
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(follow, lhs).await?;
-            return child
-                .open_file(follow, rhs, oflags, read, write, flags)
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir
+                .open_file(follow, &rest, oflags, read, write, flags)
                 .await;
         }
 
+        // Follow a link to its target, unless it must be created afresh.
+        if follow && !oflags.contains(OFlags::EXCLUSIVE) {
+            if let Some((dir, rest)) = self.follow(path).await? {
+                return dir
+                    .open_file(false, &rest, oflags, read, write, flags)
+                    .await;
+            }
+        }
+
         // Check the validity of the flags.
         if !VALID_OFLAGS.contains(&oflags.bits()) {
             return Err(Error::invalid_argument());
@@ -217,46 +845,90 @@ impl WasiDir for OpenDir {
         // Find or create the child.
         match path {
             "." if oflags.contains(OFlags::EXCLUSIVE) => Err(Error::exist()),
-            "." if oflags.contains(OFlags::TRUNCATE) => Err(Error::io()), // FIXME
+            "." | ".." if oflags.contains(OFlags::TRUNCATE) => Err(Error::is_dir()),
             "." | "" => {
-                let link = self.link.clone();
+                let link = self.link().clone();
+                access(&*link, read, write, false).await?;
                 link.open_file(path, odir, read, write, flags).await
             }
 
             ".." if oflags.contains(OFlags::EXCLUSIVE) => Err(Error::exist()),
-            ".." if oflags.contains(OFlags::TRUNCATE) => Err(Error::io()), // FIXME
             ".." => {
-                let link = self.link.prev();
+                let link = self.link().prev();
+                access(&*link, read, write, false).await?;
                 link.open_file(path, odir, read, write, flags).await
             }
 
             name => {
-                let mut ilock = self.link.inode.data.write().await;
-                let child = ilock.content.get(name).cloned();
+                // Checked before locking, since the check reads this directory.
+                let creatable = match oflags.contains(OFlags::CREATE) {
+                    true => access(&**self.link(), false, true, true).await,
+                    false => Ok(()),
+                };
+
+                let mut ilock = self.link().inode().write().await?;
+                let key = ilock.content().key(self.link().collation(), name);
+                let child = key.map(|key| ilock.content()[&key].clone());
                 match (child, oflags.contains(OFlags::CREATE)) {
                     // If the file exists and we're creating it, then we have an error.
                     (Some(_), true) if oflags.contains(OFlags::EXCLUSIVE) => Err(Error::exist()),
 
-                    // If the file doesn't exist and we're not creating it, then we have an error.
-                    (None, false) => Err(Error::not_found()),
+                    // If the file doesn't exist and we're not creating it,
+                    // then it must be synthesized or we have an error.
+                    (None, false) => {
+                        drop(ilock);
+                        let child = self.link().resolve(name).ok_or_else(Error::not_found)?;
+                        let truncate = oflags.contains(OFlags::TRUNCATE);
+                        access(&*child, read, write || truncate, false).await?;
+                        let mut open = child.open_file(path, odir, read, write, flags).await?;
+                        if oflags.contains(OFlags::TRUNCATE) {
+                            open.set_filestat_size(0).await?;
+                        }
+
+                        Ok(open)
+                    }
+
+                    // Don't create a directory which cannot then be opened.
+                    (None, true) if odir && write => Err(Error::is_dir()),
 
                     // If the file doesn't exist, create it.
                     (None, true) => {
-                        let link = self.link.clone();
-                        let child: Arc<dyn Node> = if oflags.contains(OFlags::DIRECTORY) {
-                            Directory::new(link, self.link.create_file.clone())
-                        } else if let Some(ref create_file) = self.link.create_file {
-                            create_file(link)
-                        } else {
-                            return Err(Error::not_supported());
+                        creatable?;
+                        self.room()?;
+                        let link = self.link();
+                        let child = match &self.link().factory {
+                            Some(factory) if odir => {
+                                factory.create_dir(link, name, oflags, flags)?
+                            }
+                            None if odir => Directory::new(link.clone(), None),
+                            Some(factory) => {
+                                let child = factory.create_file(link, name, oflags, flags)?;
+                                self.apply_template(name, &child).await?;
+                                child
+                            }
+                            None => return Err(Error::not_supported()),
                         };
 
-                        ilock.content.insert(name.into(), child.clone());
+                        child.id().link();
+                        ilock.content_mut().insert(name.into(), child.clone());
+                        ilock.touch();
+                        self.link().notify(Event::Create(name.into()));
+                        drop(ilock);
                         child.open_file(path, odir, read, write, flags).await
                     }
 
+                    // Directories cannot be truncated.
+                    (Some(child), _)
+                        if oflags.contains(OFlags::TRUNCATE)
+                            && child.filetype() == FileType::Directory =>
+                    {
+                        Err(Error::is_dir())
+                    }
+
                     // Truncate the file.
                     (Some(child), _) if oflags.contains(OFlags::TRUNCATE) => {
+                        drop(ilock);
+                        access(&*child, read, true, false).await?;
                         let mut open = child
                             .open_file(path, odir, false, true, FdFlags::empty())
                             .await?;
@@ -264,48 +936,64 @@ impl WasiDir for OpenDir {
                         Ok(open)
                     }
 
-                    // Open the file.
-                    (Some(child), _) => child.open_file(path, odir, read, write, flags).await,
+                    // Open the file. Opening may wait, as for a FIFO, so
+                    // the directory must not stay locked meanwhile.
+                    (Some(child), _) => {
+                        drop(ilock);
+                        access(&*child, read, write, false).await?;
+                        child.open_file(path, odir, read, write, flags).await
+                    }
                 }
             }
         }
     }
 
     async fn open_dir(&self, follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(follow, lhs).await?;
-            return child.open_dir(follow, rhs).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.open_dir(follow, &rest).await;
+        }
+
+        if follow {
+            if let Some((dir, rest)) = self.follow(path).await? {
+                return dir.open_dir(false, &rest).await;
+            }
         }
 
         match path {
             "" => Err(Error::invalid_argument()),
-            "." => self.link.clone().open_dir().await,
-            ".." => self.link.prev().open_dir().await,
+            "." => self.link().clone().open_dir().await,
+            ".." => self.link().prev().open_dir().await,
 
-            name => {
-                let ilock = self.link.inode.data.read().await;
-                let child = ilock.content.get(name).ok_or_else(Error::not_found)?;
-                child.clone().open_dir().await
-            }
+            name => self.link().lookup(name).await?.open_dir().await,
         }
     }
 
     async fn create_dir(&self, path: &str) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.create_dir(rhs).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.create_dir(&rest).await;
         }
 
         match path {
             "" | "." | ".." => Err(Error::invalid_argument()),
             name => {
-                let mut ilock = self.link.inode.data.write().await;
-                match ilock.content.contains_key(name) {
+                access(&**self.link(), false, true, true).await?;
+                let mut ilock = self.link().inode().write().await?;
+                match ilock.content().key(self.link().collation(), name).is_some() {
                     true => Err(Error::exist()),
                     false => {
-                        let child =
-                            Directory::new(self.link.clone(), self.link.create_file.clone());
-                        ilock.content.insert(name.into(), child);
+                        self.room()?;
+                        let oflags = OFlags::CREATE | OFlags::DIRECTORY | OFlags::EXCLUSIVE;
+                        let child = match &self.link().factory {
+                            Some(factory) => {
+                                let flags = FdFlags::empty();
+                                factory.create_dir(self.link(), name, oflags, flags)?
+                            }
+                            None => Directory::new(self.link().clone(), None),
+                        };
+                        child.id().link();
+                        ilock.content_mut().insert(name.into(), child);
+                        ilock.touch();
+                        self.link().notify(Event::Create(name.into()));
                         Ok(())
                     }
                 }
@@ -317,50 +1005,65 @@ impl WasiDir for OpenDir {
         &self,
         cursor: ReaddirCursor,
     ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
-        let cursor: usize = u64::from(cursor)
-            .try_into()
-            .map_err(|_| Error::invalid_argument())?;
+        let cursor = u64::from(cursor);
 
         // Get the directory reference.
-        let ilock = self.link.inode.data.read().await;
+        let ilock = self.link().inode().read().await?;
 
         // Add the single dot entries.
-        let mut entries = vec![
-            Ok(ReaddirEntity {
+        //
+        // Readdir entries carry no device id. At a device boundary, `..`
+        // reports the inode of the parent in the parent's device, which is
+        // also what `get_path_filestat("..")` returns. Tools detecting mount
+        // points must compare the device ids from stat, as they do on Linux.
+        let prev = self.link().prev();
+        let dots = [
+            ReaddirEntity {
                 name: ".".into(),
                 next: 1.into(),
-                inode: **self.link.id(),
-                filetype: self.link.filetype(),
-            }),
-            Ok(ReaddirEntity {
+                inode: **self.link().id(),
+                filetype: self.link().filetype(),
+            },
+            ReaddirEntity {
                 name: "..".into(),
                 next: 2.into(),
-                inode: **self.link.prev().id(),
-                filetype: self.link.prev().filetype(),
-            }),
+                inode: **prev.id(),
+                filetype: prev.filetype(),
+            },
         ];
 
-        // Add all of the child entries.
-        for (k, v) in ilock.content.iter() {
-            let next = entries.len() as u64 + 1;
-            entries.push(Ok(ReaddirEntity {
-                name: k.into(),
-                next: next.into(),
-                inode: **v.id(),
-                filetype: v.filetype(),
-            }));
-        }
-
-        Ok(Box::new(entries.into_iter().skip(cursor)))
+        // The child entries are copied now, so the listing never waits.
+        let skip = cursor.min(2) as usize;
+        let entries = Entries::new(ilock.content(), cursor.max(2), self.last.clone());
+        drop(ilock);
+        Ok(Box::new(dots.into_iter().skip(skip).map(Ok).chain(entries)))
     }
 
     async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = new_path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.symlink(old_path, rhs).await;
+        if let Some((dir, rest)) = self.walk(new_path).await? {
+            return dir.symlink(old_path, &rest).await;
         }
 
-        Err(Error::not_supported())
+        match (old_path, new_path) {
+            ("", _) | (_, "") => Err(Error::not_found()),
+            (_, "." | "..") => Err(Error::exist()),
+            (target, name) => {
+                access(&**self.link(), false, true, true).await?;
+                let mut ilock = self.link().inode().write().await?;
+                match ilock.content().key(self.link().collation(), name).is_some() {
+                    true => Err(Error::exist()),
+                    false => {
+                        self.room()?;
+                        let child = Symlink::new(self.link().clone(), target);
+                        child.id().link();
+                        ilock.content_mut().insert(name.into(), child);
+                        ilock.touch();
+                        self.link().notify(Event::Create(name.into()));
+                        Ok(())
+                    }
+                }
+            }
+        }
     }
 
     // Some notes on this code are in order.
@@ -371,20 +1074,22 @@ impl WasiDir for OpenDir {
     // behavior would be odd. Therefore, we only remove child directories if
     // the child is also a `Directory` AND has the same device id.
     async fn remove_dir(&self, path: &str) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.remove_dir(rhs).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.remove_dir(&rest).await;
         }
 
         match path {
             "" | "." | ".." => Err(Error::invalid_argument()),
 
             name => {
-                let mut plock = self.link.inode.data.write().await;
-
-                let cnode = plock.content.get(name).ok_or_else(Error::not_found)?;
-                if self.link.id().device() != cnode.id().device() {
-                    return Err(Error::io()); // FIXME: EXDEV?
+                access(&**self.link(), false, true, true).await?;
+                let mut plock = self.link().inode().write().await?;
+
+                let key = plock.content().key(self.link().collation(), name);
+                let key = key.ok_or_else(Error::not_found)?;
+                let cnode = &plock.content()[&key];
+                if self.link().id().device() != cnode.id().device() {
+                    return Err(Error::cross_device());
                 }
 
                 let clink = cnode
@@ -393,12 +1098,17 @@ impl WasiDir for OpenDir {
                     .downcast::<Directory>()
                     .map_err(|_| Error::not_dir())?;
 
-                let clock = clink.inode.data.read().await;
-                if clock.content.is_empty() {
-                    return Err(Error::io()); // FIXME: ENOTEMPTY
+                // A rename may hold the child while waiting for this
+                // directory, so waiting for the child here could deadlock.
+                let clock = clink.inode().data().try_read().map_err(|_| Error::busy())?;
+                if !clock.content().is_empty() {
+                    return Err(Error::not_empty());
                 }
 
-                plock.content.remove(name);
+                plock.content_mut().remove(&key);
+                clink.id().unlink();
+                plock.touch();
+                self.link().notify(Event::Remove(key));
                 Ok(())
             }
         }
@@ -406,74 +1116,89 @@ impl WasiDir for OpenDir {
 
     // The same comments for `remove_dir` apply here.
     async fn unlink_file(&self, path: &str) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.unlink_file(rhs).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.unlink_file(&rest).await;
         }
 
         match path {
             "" | "." | ".." => Err(Error::invalid_argument()),
 
             name => {
-                let mut plock = self.link.inode.data.write().await;
-                let cnode = plock.content.get(name).ok_or_else(Error::not_found)?;
+                access(&**self.link(), false, true, true).await?;
+                let mut plock = self.link().inode().write().await?;
+                let key = plock.content().key(self.link().collation(), name);
+                let key = key.ok_or_else(Error::not_found)?;
+                let cnode = &plock.content()[&key];
 
                 if cnode.filetype() == FileType::Directory {
-                    return Err(Error::io()); // FIXME: ENOTFILE?
+                    return Err(Error::is_dir());
+                }
+
+                if self.link().id().device() != cnode.id().device() {
+                    return Err(Error::cross_device());
+                }
+
+                // Sockets are served by the host, not bound by the guest.
+                let socket = matches!(
+                    cnode.filetype(),
+                    FileType::SocketDgram | FileType::SocketStream
+                );
+                if socket && self.strict() {
+                    return Err(Error::perm());
                 }
 
-                if self.link.id().device() != cnode.id().device() {
-                    return Err(Error::io()); // FIXME: EXDEV?
+                let cnode = plock.content_mut().remove(&key);
+                plock.touch();
+                self.link().notify(Event::Remove(key.clone()));
+                drop(plock);
+
+                // Revoking and trashing may wait, so no lock is held.
+                if let Some(cnode) = cnode {
+                    unlink(&*cnode).await;
+                    self.discard(&key, cnode).await;
                 }
 
-                plock.content.remove(name);
                 Ok(())
             }
         }
     }
 
     async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.read_link(rhs).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.read_link(&rest).await;
         }
 
-        Err(Error::not_supported())
+        match path {
+            "" => Err(Error::not_found()),
+            "." | ".." => Err(Error::invalid_argument()),
+            name => {
+                let node = self.link().lookup(name).await?;
+                let target = node.read_link().ok_or_else(Error::invalid_argument)?;
+                Ok(target.into())
+            }
+        }
     }
 
     async fn get_filestat(&self) -> Result<Filestat, Error> {
-        let ilock = self.link.inode.data.read().await;
-
-        Ok(Filestat {
-            device_id: **self.link.inode.id.device(),
-            inode: **self.link.inode.id,
-            filetype: FileType::Directory,
-            nlink: Arc::strong_count(&self.link.inode) as u64 * 2,
-            size: 0, // FIXME
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
-        })
+        self.link().clone().filestat().await
     }
 
     async fn get_path_filestat(&self, path: &str, follow: bool) -> Result<Filestat, Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.get_path_filestat(rhs, follow).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.get_path_filestat(&rest, follow).await;
+        }
+
+        if follow {
+            if let Some((dir, rest)) = self.follow(path).await? {
+                return dir.get_path_filestat(&rest, false).await;
+            }
         }
 
         match path {
             "." | "" => self.get_filestat().await,
             ".." => self.open_dir(true, "..").await?.get_filestat().await,
 
-            name => {
-                let flags = FdFlags::empty();
-                let ilock = self.link.inode.data.read().await;
-                let child = ilock.content.get(name).ok_or_else(Error::not_found)?;
-                let child = child.clone();
-                let mut file = child.open_file(path, false, false, false, flags).await?;
-                file.get_filestat().await
-            }
+            name => self.link().lookup(name).await?.filestat().await,
         }
     }
 
@@ -483,54 +1208,247 @@ impl WasiDir for OpenDir {
         dest_dir: &dyn WasiDir,
         dest_path: &str,
     ) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.rename(rhs, dest_dir, dest_path).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.rename(&rest, dest_dir, dest_path).await;
         }
 
-        Err(Error::not_supported())
-    }
+        if let Some(dest) = dest_dir.as_any().downcast_ref::<OpenDir>() {
+            if let Some((dir, rest)) = dest.walk(dest_path).await? {
+                return self.rename(path, &*dir, &rest).await;
+            }
+        }
 
-    async fn hard_link(
-        &self,
-        path: &str,
-        target_dir: &dyn WasiDir,
-        target_path: &str,
-    ) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.hard_link(rhs, target_dir, target_path).await;
+        let (src, dst) = match (path, dest_path) {
+            ("" | "." | "..", _) | (_, "" | "." | "..") => return Err(Error::invalid_argument()),
+            names => names,
+        };
+
+        // Only directories of this file system can be renamed between.
+        let dest = dest_dir
+            .as_any()
+            .downcast_ref::<OpenDir>()
+            .ok_or_else(Error::cross_device)?;
+
+        if self.link().id().device() != dest.link().id().device() {
+            return Err(Error::cross_device());
         }
 
-        Err(Error::not_supported())
-    }
+        access(&**self.link(), false, true, true).await?;
+        access(&**dest.link(), false, true, true).await?;
 
-    async fn set_times(
-        &self,
+        let same = Arc::ptr_eq(self.link(), dest.link());
+        let (mut slock, mut dlock) = self.lock_pair(dest).await?;
+
+        let collation = self.link().collation();
+        let skey = slock.content().key(collation, src);
+        let skey = skey.ok_or_else(Error::not_found)?;
+        let snode = slock.content()[&skey].clone();
+
+        // A node on another device is mounted here and cannot be moved.
+        if snode.id().device() != self.link().id().device() {
+            return Err(Error::cross_device());
+        }
+
+        let sdir = snode.filetype() == FileType::Directory;
+
+        // A directory cannot be moved inside itself.
+        if sdir {
+            let mut ancestor: Option<Arc<dyn Node>> = Some(dest.link().clone());
+            while let Some(node) = ancestor {
+                if Arc::as_ptr(&node) as *const () == Arc::as_ptr(&snode) as *const () {
+                    return Err(Error::invalid_argument());
+                }
+
+                ancestor = node.parent();
+            }
+        }
+
+        let dcontent = match dlock.as_mut() {
+            Some(dlock) => dlock.content_mut(),
+            None => slock.content_mut(),
+        };
+
+        // Renaming a node to another case of its name only changes the case.
+        let dkey = dcontent.key(collation, dst);
+        let recase = same && dkey.as_deref() == Some(&skey) && dst != skey;
+
+        // Check that the destination, if any, can be replaced.
+        if let Some(dnode) = dkey.as_ref().filter(|_| !recase).map(|key| &dcontent[key]) {
+            // Renaming a node over itself does nothing.
+            if Arc::as_ptr(dnode) as *const () == Arc::as_ptr(&snode) as *const () {
+                return Ok(());
+            }
+
+            if dnode.id().device() != dest.link().id().device() {
+                return Err(Error::cross_device());
+            }
+
+            match (sdir, dnode.filetype() == FileType::Directory) {
+                (true, false) => return Err(Error::not_dir()),
+                (false, true) => return Err(Error::is_dir()),
+                (false, false) => (),
+                (true, true) => {
+                    // An ancestor of the source is not empty, and is locked above.
+                    let mut ancestor: Option<Arc<dyn Node>> = Some(self.link().clone());
+                    while let Some(node) = ancestor {
+                        if Arc::as_ptr(&node) as *const () == Arc::as_ptr(dnode) as *const () {
+                            return Err(Error::not_empty());
+                        }
+
+                        ancestor = node.parent();
+                    }
+
+                    // Waiting here while holding both locks could deadlock
+                    // against another rename, so give up instead.
+                    let dnode = dnode.clone().to_any().downcast::<Directory>();
+                    let dnode = dnode.map_err(|_| Error::not_dir())?;
+                    let dlock = dnode.inode().data().try_read().map_err(|_| Error::busy())?;
+                    if !dlock.content().is_empty() {
+                        return Err(Error::not_empty());
+                    }
+                }
+            }
+        }
+
+        let replaced = dkey.and_then(|key| dcontent.remove(&key));
+        let replaced = replaced.filter(|_| !recase);
+        dcontent.insert(dst.into(), snode.clone());
+        if !recase {
+            slock.content_mut().remove(&skey);
+        }
+
+        snode.set_parent(Arc::downgrade(&(dest.link().clone() as Arc<dyn Node>)));
+
+        slock.touch();
+        if let Some(dlock) = dlock.as_mut() {
+            dlock.touch();
+        }
+
+        match same {
+            true => self.link().notify(Event::Rename {
+                from: skey,
+                to: dst.into(),
+            }),
+            false => {
+                self.link().notify(Event::Remove(skey));
+                dest.link().notify(Event::Create(dst.into()));
+            }
+        }
+
+        drop(slock);
+        drop(dlock);
+
+        // Revoking and trashing may wait, so no lock is held.
+        if let Some(replaced) = &replaced {
+            unlink(&**replaced).await;
+        }
+
+        if let Some(replaced) = replaced.filter(|n| n.filetype() != FileType::Directory) {
+            dest.discard(dst, replaced).await;
+        }
+
+        Ok(())
+    }
+
+    async fn hard_link(
+        &self,
+        path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.hard_link(&rest, target_dir, target_path).await;
+        }
+
+        if let Some(target) = target_dir.as_any().downcast_ref::<OpenDir>() {
+            if let Some((dir, rest)) = target.walk(target_path).await? {
+                return self.hard_link(path, &*dir, &rest).await;
+            }
+        }
+
+        let (src, dst) = match (path, target_path) {
+            ("" | "." | "..", _) | (_, "" | "." | "..") => return Err(Error::invalid_argument()),
+            names => names,
+        };
+
+        // Only directories of this file system can be linked between.
+        let target = target_dir
+            .as_any()
+            .downcast_ref::<OpenDir>()
+            .ok_or_else(Error::cross_device)?;
+
+        if self.link().id().device() != target.link().id().device() {
+            return Err(Error::cross_device());
+        }
+
+        access(&**target.link(), false, true, true).await?;
+
+        // Hold both directories, so the source cannot be unlinked or renamed
+        // away between finding it and linking it.
+        let (slock, mut tlock) = self.lock_pair(target).await?;
+        let node = match slock.content().key(self.link().collation(), src) {
+            Some(key) => slock.content()[&key].clone(),
+            None => self.link().resolve(src).ok_or_else(Error::not_found)?,
+        };
+
+        if node.id().device() != target.link().id().device() {
+            return Err(Error::cross_device());
+        }
+
+        // As on Linux, directories cannot be linked.
+        if node.filetype() == FileType::Directory {
+            return Err(Error::perm());
+        }
+
+        let mut slock = slock;
+        let tlock: &mut Data<_> = match tlock.as_mut() {
+            Some(tlock) => tlock,
+            None => &mut slock,
+        };
+
+        if tlock
+            .content()
+            .key(target.link().collation(), dst)
+            .is_some()
+        {
+            return Err(Error::exist());
+        }
+
+        node.id().link();
+        tlock.content_mut().insert(dst.into(), node);
+        tlock.touch();
+        target.link().notify(Event::Create(dst.into()));
+        Ok(())
+    }
+
+    async fn set_times(
+        &self,
         path: &str,
         atime: Option<SystemTimeSpec>,
         mtime: Option<SystemTimeSpec>,
         follow: bool,
     ) -> Result<(), Error> {
-        if let Some((lhs, rhs)) = path.split_once('/') {
-            let child = self.open_dir(true, lhs).await?;
-            return child.set_times(rhs, atime, mtime, follow).await;
+        if let Some((dir, rest)) = self.walk(path).await? {
+            return dir.set_times(&rest, atime, mtime, follow).await;
+        }
+
+        if follow {
+            if let Some((dir, rest)) = self.follow(path).await? {
+                return dir.set_times(&rest, atime, mtime, false).await;
+            }
         }
 
         match path {
-            "." | "" => self.link.inode.data.write().await.set_times(atime, mtime),
+            "." | "" => self.link().inode().write().await?.set_times(atime, mtime),
             ".." => {
                 let dir = self.open_dir(true, "..").await?;
                 dir.set_times(".", atime, mtime, follow).await
             }
 
             name => {
-                let flags = FdFlags::empty();
-                let ilock = self.link.inode.data.read().await;
-                let child = ilock.content.get(name).ok_or_else(Error::not_found)?;
-                let child = child.clone();
-                let mut file = child.open_file(path, false, false, false, flags).await?;
-                file.set_times(atime, mtime).await
+                let child = self.link().lookup(name).await?;
+                access(&*child, false, true, false).await?;
+                child.set_times(atime, mtime).await
             }
         }
     }
@@ -563,22 +1481,14 @@ impl WasiFile for OpenDir {
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.inode.data.read().await;
-
-        Ok(Filestat {
-            device_id: **self.link.inode.id.device(),
-            inode: **self.link.inode.id,
-            filetype: FileType::Directory,
-            nlink: Arc::strong_count(&self.link.inode) as u64,
-            size: ilock.content.len() as u64,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
-        })
+        self.link().clone().filestat().await
     }
 
     async fn set_filestat_size(&mut self, _size: u64) -> Result<(), Error> {
-        Err(Error::not_supported())
+        match self.strict() {
+            true => Err(Error::invalid_argument()),
+            false => Err(Error::not_supported()),
+        }
     }
 
     async fn advise(&mut self, _offset: u64, _len: u64, _advice: Advice) -> Result<(), Error> {
@@ -594,7 +1504,7 @@ impl WasiFile for OpenDir {
         atime: Option<SystemTimeSpec>,
         mtime: Option<SystemTimeSpec>,
     ) -> Result<(), Error> {
-        self.link.inode.data.write().await.set_times(atime, mtime)
+        self.link().inode().write().await?.set_times(atime, mtime)
     }
 
     async fn read_vectored<'a>(&mut self, _bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
@@ -613,9 +1523,6 @@ impl WasiFile for OpenDir {
         Err(Error::not_supported())
     }
 
-    // FIXME: we need to decide on a behavior for O_APPEND. WASI doesn't
-    // specify a behavior. POSIX defines one behavior. Linux has a different
-    // one. See: https://linux.die.net/man/2/pwrite
     async fn write_vectored_at<'a>(
         &mut self,
         _bufs: &[IoSlice<'a>],
@@ -649,7 +1556,7 @@ impl WasiFile for OpenDir {
 mod test {
     use super::*;
 
-    use std::io::IoSliceMut;
+    use std::io::{IoSlice, IoSliceMut};
     use std::sync::Arc;
 
     use wasi_common::file::{FdFlags, FileType, OFlags};
@@ -745,4 +1652,1929 @@ mod test {
         assert_eq!(len, 3);
         assert_eq!(&buf, b"abc");
     }
+
+    #[tokio::test]
+    async fn walk() {
+        let dir = Directory::root(Ledger::new(), None);
+        let foo = Directory::new(dir.clone(), None);
+        dir.attach("foo", foo.clone()).await.unwrap();
+        dir.attach("zip", File::with_data(dir.clone(), "abc"))
+            .await
+            .unwrap();
+        foo.attach("bar", File::new(foo.clone())).await.unwrap();
+
+        let entries = super::walk(dir).await.unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| (&e.path[..], e.depth)).collect();
+        assert_eq!(paths, [("/", 0), ("/foo", 1), ("/foo/bar", 2), ("/zip", 1)]);
+    }
+
+    #[tokio::test]
+    async fn symlinks() {
+        use rustix::io::Errno;
+        use wasi_common::ErrorKind;
+
+        let errno = |e: Error| {
+            let e = e
+                .downcast_ref::<std::io::Error>()
+                .and_then(|e| e.raw_os_error());
+            e.map(Errno::from_raw_os_error)
+        };
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = root.clone().open_dir().await.unwrap();
+        open.create_dir("foo").await.unwrap();
+        open.create_dir("foo/bar").await.unwrap();
+        root.attach("foo/bar/file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+
+        open.symlink("foo/bar", "rel").await.unwrap();
+        open.symlink("/foo/bar/file", "foo/abs").await.unwrap();
+        open.symlink("../bar/file", "foo/bar/up").await.unwrap();
+        open.symlink("loop", "loop").await.unwrap();
+        open.symlink("new", "dangling").await.unwrap();
+        let e = open.symlink("x", "rel").await.unwrap_err();
+        assert_eq!(e.downcast_ref::<ErrorKind>(), Some(&ErrorKind::Exist));
+        assert_eq!(
+            open.read_link("foo/abs").await.unwrap(),
+            PathBuf::from("/foo/bar/file")
+        );
+        open.read_link("foo").await.unwrap_err();
+
+        // Links are followed within paths and, if asked, at their ends.
+        let flags = FdFlags::empty();
+        let read = |path: &'static str, follow| {
+            let open = &open;
+            async move {
+                let mut file = open
+                    .open_file(follow, path, OFlags::empty(), true, false, flags)
+                    .await?;
+                let mut buf = [0u8; 8];
+                let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await?;
+                Ok::<_, Error>(buf[..n as usize].to_vec())
+            }
+        };
+        for path in ["rel/file", "foo/abs", "rel/up", "foo/bar/up"] {
+            assert_eq!(read(path, true).await.unwrap(), b"abc");
+        }
+        open.open_dir(true, "rel").await.unwrap();
+        let e = read("foo/abs", false).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::LOOP));
+
+        // Chains too long to follow fail.
+        let e = read("loop", true).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::LOOP));
+        let e = read("loop/file", true).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::LOOP));
+
+        // Stat reports the link or its target.
+        let stat = open.get_path_filestat("foo/abs", false).await.unwrap();
+        assert_eq!(stat.filetype, FileType::SymbolicLink);
+        assert_eq!(stat.size, 13);
+        let stat = open.get_path_filestat("foo/abs", true).await.unwrap();
+        assert_eq!(stat.filetype, FileType::RegularFile);
+
+        // Creating through a dangling link creates its target, but not
+        // exclusively.
+        let e = open
+            .open_file(
+                true,
+                "dangling",
+                OFlags::CREATE | OFlags::EXCLUSIVE,
+                false,
+                true,
+                flags,
+            )
+            .await;
+        let e = e.err().unwrap();
+        assert_eq!(e.downcast_ref::<ErrorKind>(), Some(&ErrorKind::Exist));
+        open.open_file(true, "dangling", OFlags::CREATE, false, true, flags)
+            .await
+            .unwrap();
+        assert_eq!(
+            root.get("new").await.unwrap().filetype(),
+            FileType::RegularFile
+        );
+
+        // Unlinking removes the link and leaves the target.
+        open.unlink_file("rel").await.unwrap();
+        root.get("foo/bar/file").await.unwrap();
+        root.get("rel").await.err().unwrap();
+    }
+
+    #[tokio::test]
+    async fn walk_links() {
+        let dir = Directory::root(Ledger::new(), None);
+        let foo = Directory::new(dir.clone(), None);
+        dir.attach("foo", foo.clone()).await.unwrap();
+        foo.attach("bar", File::with_data(foo.clone(), "abc"))
+            .await
+            .unwrap();
+        foo.attach("up", Symlink::new(foo.clone(), ".."))
+            .await
+            .unwrap();
+        dir.attach("abs", Symlink::new(dir.clone(), "/foo/bar"))
+            .await
+            .unwrap();
+        dir.attach("chain", Symlink::new(dir.clone(), "abs"))
+            .await
+            .unwrap();
+        dir.attach("dangling", Symlink::new(dir.clone(), "nowhere"))
+            .await
+            .unwrap();
+        dir.attach("loop", Symlink::new(dir.clone(), "loop"))
+            .await
+            .unwrap();
+        dir.attach("rel", Symlink::new(dir.clone(), "foo"))
+            .await
+            .unwrap();
+
+        // By default, links are yielded as links.
+        let entries = super::walk(dir.clone()).await.unwrap();
+        let links = entries
+            .iter()
+            .filter(|e| e.node.filetype() == FileType::SymbolicLink)
+            .count();
+        assert_eq!(links, 6);
+
+        // Following them yields their targets, descending into directories once.
+        let entries = super::walk_with(dir, Links::Follow).await.unwrap();
+        let found: Vec<_> = entries
+            .iter()
+            .map(|e| (&e.path[..], e.node.filetype()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("/", FileType::Directory),
+                ("/abs", FileType::RegularFile),
+                ("/chain", FileType::RegularFile),
+                ("/dangling", FileType::SymbolicLink),
+                ("/foo", FileType::Directory),
+                ("/foo/bar", FileType::RegularFile),
+                ("/foo/up", FileType::Directory),
+                ("/loop", FileType::SymbolicLink),
+                ("/rel", FileType::Directory),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dots() {
+        let root = Directory::root(Ledger::new(), None);
+        let sub = Directory::new(root.clone(), None);
+        let dev = Directory::device(sub.clone(), None);
+        root.attach("sub", sub.clone()).await.unwrap();
+        sub.attach("dev", dev.clone()).await.unwrap();
+
+        // Check that `..` resolves relative to the preceding segment.
+        let node = root.get("sub/dev/..").await.unwrap();
+        assert_eq!(**node.id(), **sub.id());
+        let node = root.get("sub/dev/../..").await.unwrap();
+        assert_eq!(**node.id(), **root.id());
+        assert!(root.get("..").await.is_err());
+
+        let open = root.clone().open_dir().await.unwrap();
+        let sstat = open.get_path_filestat("sub", false).await.unwrap();
+        let dstat = open.get_path_filestat("sub/dev", false).await.unwrap();
+        assert_ne!(sstat.device_id, dstat.device_id);
+
+        // At the device boundary, `.` is in the new device...
+        let dopen = open.open_dir(false, "sub/dev").await.unwrap();
+        let entries: Vec<_> = dopen.readdir(0.into()).await.unwrap().collect();
+        let dot = entries[0].as_ref().unwrap();
+        assert_eq!(dot.name, ".");
+        assert_eq!(dot.inode, dstat.inode);
+        assert_eq!(u64::from(dot.next), 1);
+        let stat = dopen.get_path_filestat(".", false).await.unwrap();
+        assert_eq!(stat.device_id, dstat.device_id);
+
+        // ... and `..` is the mount point's parent in the parent's device.
+        let dotdot = entries[1].as_ref().unwrap();
+        assert_eq!(dotdot.name, "..");
+        assert_eq!(dotdot.inode, sstat.inode);
+        assert_eq!(dotdot.filetype, FileType::Directory);
+        assert_eq!(u64::from(dotdot.next), 2);
+        let stat = dopen.get_path_filestat("..", false).await.unwrap();
+        assert_eq!(stat.device_id, sstat.device_id);
+        assert_eq!(stat.inode, sstat.inode);
+
+        // Resuming from a cursor skips the dot entries.
+        let rest: Vec<_> = dopen.readdir(2.into()).await.unwrap().collect();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn readdir() {
+        let dir = Directory::root(Ledger::new(), None);
+        for i in 0..100 {
+            let name = format!("{:03}", i);
+            dir.attach(&name, File::new(dir.clone())).await.unwrap();
+        }
+
+        let open = dir.clone().open_dir().await.unwrap();
+        let names = |entries: Vec<Result<ReaddirEntity, Error>>| -> Vec<String> {
+            entries.into_iter().map(|e| e.unwrap().name).collect()
+        };
+
+        // List in batches, reading one entry past each as wasi-common does.
+        let mut cursor = 0u64;
+        let mut listed = Vec::new();
+        loop {
+            let batch: Vec<_> = open.readdir(cursor.into()).await.unwrap().take(8).collect();
+            let batch: Vec<_> = batch.into_iter().map(Result::unwrap).collect();
+            match batch.len() {
+                8 => {
+                    cursor = batch[6].next.into();
+                    listed.extend(batch.into_iter().take(7).map(|e| e.name));
+                }
+                _ => {
+                    listed.extend(batch.into_iter().map(|e| e.name));
+                    break;
+                }
+            }
+        }
+
+        let mut expected = vec![".".to_string(), "..".to_string()];
+        expected.extend((0..100).map(|i| format!("{:03}", i)));
+        assert_eq!(listed, expected);
+
+        // Entries removed or added while listing are skipped or returned by
+        // name, not by position.
+        let entries: Vec<_> = open.readdir(2.into()).await.unwrap().take(3).collect();
+        let cursor = entries[1].as_ref().unwrap().next;
+        assert_eq!(names(entries), ["000", "001", "002"]);
+        open.unlink_file("000").await.unwrap();
+        open.unlink_file("002").await.unwrap();
+        dir.attach("0015", File::new(dir.clone())).await.unwrap();
+        let entries: Vec<_> = open.readdir(cursor).await.unwrap().take(2).collect();
+        assert_eq!(names(entries), ["0015", "003"]);
+
+        // Any other cursor skips that many entries.
+        let entries: Vec<_> = open.readdir(10.into()).await.unwrap().collect();
+        assert_eq!(entries.len(), 91);
+        assert_eq!(names(entries)[0], "009");
+        let entries: Vec<_> = open.readdir(500.into()).await.unwrap().collect();
+        assert!(entries.is_empty());
+
+        // A listing is read from a copy, so writers neither hold it up nor
+        // fail it.
+        let entries = open.readdir(0.into()).await.unwrap();
+        let guard = dir.inode().data().write().await;
+        let listed: Vec<_> = entries.map(Result::unwrap).collect();
+        drop(guard);
+        assert_eq!(listed.len(), 101);
+
+        let entries = open.readdir(0.into()).await.unwrap();
+        let mut listed = Vec::new();
+        for (i, entry) in entries.enumerate() {
+            listed.push(entry.unwrap().name);
+            open.create_dir(&format!("new{i:03}")).await.unwrap();
+        }
+        assert_eq!(listed.len(), 101);
+        assert_eq!(listed.last().unwrap(), "099");
+    }
+
+    #[tokio::test]
+    async fn truncate_shared() {
+        let dir = Directory::root(Ledger::new(), None);
+        dir.attach("file", File::with_data(dir.clone(), "abcdef"))
+            .await
+            .unwrap();
+
+        let open = dir.open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut one = open
+            .open_file(false, "file", OFlags::empty(), true, true, flags)
+            .await
+            .unwrap();
+        let mut two = open
+            .open_file(false, "file", OFlags::empty(), true, true, flags)
+            .await
+            .unwrap();
+
+        // Leave the second handle's offset near the end, then truncate.
+        assert_eq!(two.seek(SeekFrom::Start(5)).await.unwrap(), 5);
+        one.set_filestat_size(2).await.unwrap();
+
+        // Reads past the end of the file return nothing.
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            two.read_vectored(&mut [IoSliceMut::new(&mut buf)])
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(two.peek(&mut buf).await.unwrap(), 0);
+        assert_eq!(two.num_ready_bytes().await.unwrap(), 0);
+        let mut bufs = [IoSliceMut::new(&mut buf)];
+        assert_eq!(two.read_vectored_at(&mut bufs, 4).await.unwrap(), 0);
+        let mut bufs = [IoSliceMut::new(&mut buf)];
+        assert_eq!(two.read_vectored_at(&mut bufs, u64::MAX).await.unwrap(), 0);
+
+        // Writing past the end fills the gap with zeros.
+        assert_eq!(two.write_vectored(&[IoSlice::new(b"x")]).await.unwrap(), 1);
+        let mut bufs = [IoSliceMut::new(&mut buf)];
+        assert_eq!(one.read_vectored(&mut bufs).await.unwrap(), 4);
+        assert_eq!(&buf, b"ab\0\0");
+
+        // Positions that would overflow are rejected rather than panicking.
+        two.seek(SeekFrom::Start(i64::MAX as u64)).await.unwrap();
+        assert!(two.seek(SeekFrom::Current(1)).await.is_err());
+        let bufs = [IoSlice::new(b"x")];
+        assert!(two
+            .write_vectored_at(&bufs, usize::MAX as u64)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn max_file_size() {
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        dir.id().device().set_max_file_size(4);
+
+        let open = dir.open_dir().await.unwrap();
+        let mut file = open
+            .open_file(false, "file", OFlags::CREATE, true, true, FdFlags::empty())
+            .await
+            .unwrap();
+
+        let efbig = |e: Error| {
+            let e = e.downcast::<std::io::Error>().unwrap();
+            assert_eq!(
+                e.raw_os_error(),
+                Some(rustix::io::Errno::FBIG.raw_os_error())
+            );
+        };
+
+        // Writes up to the limit succeed.
+        file.write_vectored(&[IoSlice::new(b"abc")]).await.unwrap();
+        file.write_vectored_at(&[IoSlice::new(b"d")], 3)
+            .await
+            .unwrap();
+        file.set_filestat_size(4).await.unwrap();
+        file.allocate(0, 4).await.unwrap();
+
+        // Anything that would grow the file beyond it fails with EFBIG.
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        efbig(
+            file.write_vectored(&[IoSlice::new(b"e")])
+                .await
+                .unwrap_err(),
+        );
+        efbig(
+            file.write_vectored_at(&[IoSlice::new(b"e")], 4)
+                .await
+                .unwrap_err(),
+        );
+        efbig(file.set_filestat_size(5).await.unwrap_err());
+        efbig(file.allocate(2, 3).await.unwrap_err());
+        assert_eq!(file.get_filestat().await.unwrap().size, 4);
+
+        // Writes crossing the limit are cut short at it.
+        file.set_filestat_size(1).await.unwrap();
+        let bufs = [IoSlice::new(b"bc"), IoSlice::new(b"de")];
+        assert_eq!(file.write_vectored_at(&bufs, 1).await.unwrap(), 3);
+        file.set_filestat_size(2).await.unwrap();
+        file.seek(SeekFrom::Start(2)).await.unwrap();
+        assert_eq!(file.write_vectored(&bufs).await.unwrap(), 2);
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 4);
+        assert_eq!(file.get_filestat().await.unwrap().size, 4);
+    }
+
+    #[tokio::test]
+    async fn quotas() {
+        use rustix::io::Errno;
+
+        let errno = |e: Error| {
+            let e = e.downcast::<std::io::Error>().unwrap();
+            e.raw_os_error().map(Errno::from_raw_os_error)
+        };
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let device = root.id().device();
+        root.attach("host", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+        assert_eq!(device.bytes(), 3);
+        device.set_max_bytes(8);
+
+        // Growing files past the byte quota fails with ENOSPC.
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut file = open
+            .open_file(false, "file", OFlags::CREATE, true, true, flags)
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"abcd")]).await.unwrap();
+        let e = file
+            .write_vectored(&[IoSlice::new(b"ef")])
+            .await
+            .unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOSPC));
+        let e = file.set_filestat_size(6).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOSPC));
+        let e = file.allocate(0, 6).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOSPC));
+
+        // Overwriting takes no more room, and shrinking gives it back.
+        file.write_vectored_at(&[IoSlice::new(b"xyz")], 1)
+            .await
+            .unwrap();
+        file.set_filestat_size(1).await.unwrap();
+        assert_eq!(device.bytes(), 4);
+        file.allocate(0, 5).await.unwrap();
+        assert_eq!(device.bytes(), 8);
+
+        // Files release their bytes once gone.
+        drop(file);
+        open.unlink_file("file").await.unwrap();
+        assert_eq!(device.bytes(), 3);
+
+        // Guests cannot create inodes past the inode quota.
+        device.set_max_inodes(device.live_inodes());
+        let e = open
+            .open_file(false, "new", OFlags::CREATE, true, true, flags)
+            .await;
+        assert_eq!(errno(e.err().unwrap()), Some(Errno::NOSPC));
+        let e = open.create_dir("dir").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOSPC));
+        let e = open.symlink("host", "link").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOSPC));
+        assert_eq!(device.stats().free, 0);
+
+        // Guest handles report the usage of their device.
+        let usage = super::statvfs(&*open).unwrap();
+        assert_eq!((usage.bytes, usage.free_bytes), (3, 5));
+        assert_eq!(usage.inodes.free, 0);
+    }
+
+    #[tokio::test]
+    async fn sparse() {
+        use wasi_common::file::Advice;
+
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = dir.clone().open_dir().await.unwrap();
+        let mut file = open
+            .open_file(false, "file", OFlags::CREATE, true, true, FdFlags::empty())
+            .await
+            .unwrap();
+
+        // Growing a file, by any means, stores nothing.
+        file.set_filestat_size(1 << 40).await.unwrap();
+        file.allocate(1 << 41, 1).await.unwrap();
+        assert_eq!(file.get_filestat().await.unwrap().size, (1 << 41) + 1);
+        file.write_vectored_at(&[IoSlice::new(b"abc")], 1 << 42)
+            .await
+            .unwrap();
+
+        let node = dir.get("file").await.unwrap();
+        let node = node.to_any().downcast::<File>().unwrap();
+        assert_eq!(node.inode().data().read().await.content().blocks(), 1);
+
+        // Holes read as zeros.
+        let mut buf = [1u8; 4];
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        file.read_vectored_at(&mut slice, (1 << 42) - 1)
+            .await
+            .unwrap();
+        assert_eq!(&buf, b"\0abc");
+
+        // Blocks of zeros are released once not needed.
+        file.write_vectored_at(&[IoSlice::new(&[0; 3])], 1 << 42)
+            .await
+            .unwrap();
+        file.advise(0, 0, Advice::DontNeed).await.unwrap();
+        assert_eq!(node.inode().data().read().await.content().blocks(), 0);
+    }
+
+    #[tokio::test]
+    async fn parent_times() {
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = dir.clone().open_dir().await.unwrap();
+
+        // Ensure that every change to the entries moves the parent times forward.
+        let mut prev = open.get_filestat().await.unwrap();
+        for step in 0..4 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+
+            match step {
+                0 => open.create_dir("foo").await.unwrap(),
+                1 => drop(
+                    open.open_file(false, "bar", OFlags::CREATE, true, true, FdFlags::empty())
+                        .await
+                        .unwrap(),
+                ),
+                2 => open.unlink_file("bar").await.unwrap(),
+                _ => dir.attach("baz", File::new(dir.clone())).await.unwrap(),
+            }
+
+            let next = open.get_filestat().await.unwrap();
+            assert!(next.mtim > prev.mtim, "step {step}");
+            assert!(next.ctim > prev.ctim, "step {step}");
+            assert_eq!(next.atim, prev.atim, "step {step}");
+            prev = next;
+        }
+    }
+
+    #[tokio::test]
+    async fn granularity() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use wasmtime_vfs_ledger::Granularity;
+
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        dir.id().device().set_granularity(Granularity::Second);
+        let open = dir.clone().open_dir().await.unwrap();
+
+        // New inodes get whole seconds.
+        open.create_dir("foo").await.unwrap();
+        let stat = open.get_path_filestat("foo", true).await.unwrap();
+        for time in [stat.atim, stat.mtim, stat.ctim] {
+            let since = time.unwrap().duration_since(UNIX_EPOCH).unwrap();
+            assert_eq!(since.subsec_nanos(), 0);
+        }
+
+        // A change time ahead of the clock, as after the clock was set back,
+        // does not move backwards.
+        let ahead = UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+        dir.id().device().set_clock(Some(Arc::new(move || ahead)));
+        open.create_dir("bar").await.unwrap();
+        dir.id().device().set_clock(None);
+        open.create_dir("baz").await.unwrap();
+        let stat = open.get_filestat().await.unwrap();
+        assert_eq!(stat.ctim, Some(ahead));
+        assert_eq!(stat.mtim, Some(ahead));
+    }
+
+    #[tokio::test]
+    async fn rename() {
+        use rustix::io::Errno;
+        use wasi_common::ErrorKind;
+
+        let errno = |e: Error| {
+            let e = e
+                .downcast_ref::<std::io::Error>()
+                .and_then(|e| e.raw_os_error());
+            e.map(Errno::from_raw_os_error)
+        };
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let dev = Directory::device(root.clone(), None);
+        root.attach("dev", dev.clone()).await.unwrap();
+        root.attach("file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+        root.attach("other", File::with_data(root.clone(), "xyz"))
+            .await
+            .unwrap();
+
+        let open = root.clone().open_dir().await.unwrap();
+        open.create_dir("foo").await.unwrap();
+        open.create_dir("foo/bar").await.unwrap();
+        open.create_dir("empty").await.unwrap();
+
+        // Rename within a directory, then over an existing file.
+        let flags = FdFlags::empty();
+        let mut moved = open
+            .open_file(false, "file", OFlags::empty(), true, true, flags)
+            .await
+            .unwrap();
+        let mut replaced = open
+            .open_file(false, "other", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        open.rename("file", &*open, "moved").await.unwrap();
+        open.rename("moved", &*open, "other").await.unwrap();
+        let node = root.get("other").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        assert_eq!(*file.inode().data().read().await.content(), b"abc");
+        assert!(root.get("file").await.is_err());
+        assert!(root.get("moved").await.is_err());
+
+        // Open handles follow the moved file and keep the replaced one.
+        moved.write_vectored(&[IoSlice::new(b"d")]).await.unwrap();
+        assert_eq!(*file.inode().data().read().await.content(), b"dbc");
+        let mut buf = [0u8; 8];
+        let bufs = &mut [IoSliceMut::new(&mut buf)];
+        assert_eq!(replaced.read_vectored(bufs).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"xyz");
+
+        // Rename into another directory and check its new parent.
+        let foo = open.open_dir(false, "foo").await.unwrap();
+        open.rename("other", &*foo, "bar/file").await.unwrap();
+        let bar = root.get("foo/bar").await.unwrap();
+        let file = root.get("foo/bar/file").await.unwrap();
+        assert_eq!(**file.parent().unwrap().id(), **bar.id());
+
+        // Directories and files only replace their own kind.
+        open.rename("empty", &*open, "foo/bar/file")
+            .await
+            .unwrap_err();
+        let e = open.rename("foo/bar/file", &*open, "empty").await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::ISDIR));
+
+        // Directories replace only empty directories.
+        let e = open.rename("empty", &*open, "foo").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOTEMPTY));
+        open.rename("foo/bar/file", &*open, "file").await.unwrap();
+        foo.rename("bar", &*open, "empty").await.unwrap();
+        let empty = root.get("empty").await.unwrap();
+        assert_eq!(**empty.id(), **bar.id());
+        assert_eq!(**root.get("empty/..").await.unwrap().id(), **root.id());
+
+        // A directory cannot be moved inside itself.
+        open.create_dir("foo/baz").await.unwrap();
+        let e = open.rename("foo", &*open, "foo/baz/foo").await.unwrap_err();
+        assert_eq!(e.downcast_ref::<ErrorKind>(), Some(&ErrorKind::Inval));
+
+        // Nor can it replace one of its own ancestors.
+        open.create_dir("foo/baz/sub").await.unwrap();
+        let e = foo.rename("baz", &*open, "foo").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOTEMPTY));
+        let baz = open.open_dir(false, "foo/baz").await.unwrap();
+        let e = baz.rename("sub", &*open, "foo").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::NOTEMPTY));
+
+        // A destination directory locked elsewhere is busy.
+        let node = root.get("empty").await.unwrap();
+        let empty = node.to_any().downcast::<Directory>().unwrap();
+        let guard = empty.inode().data().write().await;
+        let e = open.rename("foo/baz/sub", &*open, "empty").await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::BUSY));
+        drop(guard);
+        open.rename("foo/baz/sub", &*open, "empty").await.unwrap();
+
+        // Renames across devices fail with EXDEV.
+        let e = open.rename("file", &*open, "dev/file").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+        let e = open.rename("dev", &*open, "mnt").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+    }
+
+    #[tokio::test]
+    async fn hard_link() {
+        use rustix::io::Errno;
+
+        let errno = |e: Error| {
+            let e = e
+                .downcast_ref::<std::io::Error>()
+                .and_then(|e| e.raw_os_error());
+            e.map(Errno::from_raw_os_error)
+        };
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let dev = Directory::device(root.clone(), None);
+        root.attach("dev", dev.clone()).await.unwrap();
+        root.attach("file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+
+        let open = root.clone().open_dir().await.unwrap();
+        open.create_dir("sub").await.unwrap();
+        let nlink = |path| {
+            let open = &open;
+            async move { open.get_path_filestat(path, false).await.unwrap().nlink }
+        };
+        assert_eq!(nlink("file").await, 1);
+
+        // Both names are the same file.
+        let sub = open.open_dir(false, "sub").await.unwrap();
+        open.hard_link("file", &*sub, "link").await.unwrap();
+        assert_eq!(nlink("file").await, 2);
+        assert_eq!(nlink("sub/link").await, 2);
+        let file = root.get("file").await.unwrap();
+        let link = root.get("sub/link").await.unwrap();
+        assert_eq!(**file.id(), **link.id());
+
+        // Removing a name, or the directory holding it, drops its link.
+        open.unlink_file("file").await.unwrap();
+        assert_eq!(nlink("sub/link").await, 1);
+        open.hard_link("sub/link", &*open, "file").await.unwrap();
+        drop(sub);
+        root.remove_all("sub").await.unwrap();
+        assert_eq!(nlink("file").await, 1);
+
+        // Replacing a name by a rename drops its link too.
+        open.hard_link("file", &*open, "copy").await.unwrap();
+        root.attach("other", File::with_data(root.clone(), "xyz"))
+            .await
+            .unwrap();
+        open.rename("other", &*open, "copy").await.unwrap();
+        assert_eq!(nlink("file").await, 1);
+
+        // Links neither replace names nor name directories.
+        open.create_dir("dir").await.unwrap();
+        open.hard_link("file", &*open, "copy").await.unwrap_err();
+        open.hard_link("dir", &*open, "alias").await.unwrap_err();
+        assert!(root.get("alias").await.is_err());
+
+        // Links across devices fail with EXDEV.
+        let e = open
+            .hard_link("file", &*open, "dev/file")
+            .await
+            .unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+
+        // A link racing the removal of its source either fails or keeps the
+        // file alive with the one remaining name.
+        open.create_dir("race").await.unwrap();
+        let race = open.open_dir(false, "race").await.unwrap();
+        for i in 0..32 {
+            let name = format!("race/{i}");
+            open.open_file(false, &name, OFlags::CREATE, false, true, FdFlags::empty())
+                .await
+                .unwrap();
+            let (unlinked, linked) = tokio::join!(
+                open.unlink_file(&name),
+                open.hard_link(&name, &*race, "kept"),
+            );
+            unlinked.unwrap();
+            match linked {
+                Ok(()) => {
+                    assert_eq!(nlink("race/kept").await, 1);
+                    race.unlink_file("kept").await.unwrap();
+                }
+                Err(e) => assert_eq!(
+                    e.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+                    Some(std::io::ErrorKind::NotFound)
+                ),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn templates() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let sub = Directory::new(root.clone(), root.factory());
+        root.attach("sub", sub.clone()).await.unwrap();
+
+        // The templates are shared by the whole device.
+        let templates = Templates::new();
+        templates.add("*.log", "# log\n");
+        sub.set_templates(Some(templates));
+        assert!(root.templates().is_some());
+
+        let open = root.clone().open_dir().await.unwrap();
+        for path in ["a.log", "sub/b.log", "c.txt"] {
+            let mut file = open
+                .open_file(false, path, OFlags::CREATE, false, true, FdFlags::APPEND)
+                .await
+                .unwrap();
+            file.write_vectored(&[IoSlice::new(b"x")]).await.unwrap();
+        }
+
+        for (path, data) in [
+            ("a.log", "# log\nx"),
+            ("sub/b.log", "# log\nx"),
+            ("c.txt", "x"),
+        ] {
+            let node = root.get(path).await.unwrap();
+            let file = node.to_any().downcast::<File>().unwrap();
+            assert_eq!(
+                *file.inode().data().read().await.content(),
+                data.as_bytes(),
+                "{path}"
+            );
+        }
+
+        // Existing files are left alone.
+        let mut file = open
+            .open_file(false, "a.log", OFlags::CREATE, false, true, FdFlags::APPEND)
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"y")]).await.unwrap();
+        let node = root.get("a.log").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        assert_eq!(*file.inode().data().read().await.content(), b"# log\nxy");
+    }
+
+    #[tokio::test]
+    async fn trash() {
+        let root = Directory::root(Ledger::new(), None);
+        let sub = Directory::new(root.clone(), None);
+        root.attach("sub", sub.clone()).await.unwrap();
+        for (name, data) in [("a", "abc"), ("b", "defg"), ("c", "hi")] {
+            let file = File::with_data(sub.clone(), data);
+            sub.attach(name, file).await.unwrap();
+        }
+
+        // The trash is shared by the whole device.
+        let trash = Trash::new(6);
+        sub.set_trash(Some(trash.clone()));
+        assert!(root.trash().is_some());
+
+        let open = root.clone().open_dir().await.unwrap();
+        open.unlink_file("sub/a").await.unwrap();
+        open.rename("sub/c", &*open, "sub/b").await.unwrap();
+        assert!(root.get("sub/a").await.is_err());
+        assert_eq!(trash.size(), 4);
+
+        // The oldest entry was dropped to stay under the capacity.
+        let trashed = trash.take();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].name, "b");
+        assert_eq!(trashed[0].size, 4);
+        let file = trashed[0].node.clone().to_any().downcast::<File>().unwrap();
+        assert_eq!(*file.inode().data().read().await.content(), b"defg");
+        assert_eq!(**trashed[0].node.parent().unwrap().id(), **sub.id());
+        assert_eq!(trash.size(), 0);
+
+        // A file unlinked while open could outgrow the capacity, so it is
+        // not kept.
+        let mut file = open
+            .open_file(
+                false,
+                "sub/b",
+                OFlags::empty(),
+                true,
+                true,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        open.unlink_file("sub/b").await.unwrap();
+        file.write_vectored(&[IoSlice::new(b"0123456789")])
+            .await
+            .unwrap();
+        assert_eq!(trash.size(), 0);
+        assert!(trash.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn errno() {
+        use rustix::io::Errno;
+        use wasi_common::ErrorKind;
+        use wasmtime_vfs_ledger::Strictness;
+
+        fn errno(e: Option<Error>) -> Option<Errno> {
+            let e = e?.downcast::<std::io::Error>().ok()?;
+            e.raw_os_error().map(Errno::from_raw_os_error)
+        }
+
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = dir.clone().open_dir().await.unwrap();
+        open.create_dir("sub").await.unwrap();
+        open.create_dir("sub/sub").await.unwrap();
+        dir.attach("mnt", Directory::device(dir.clone(), None))
+            .await
+            .unwrap();
+
+        // Truncating a directory fails the same way on every device.
+        for strictness in [Strictness::Permissive, Strictness::Strict] {
+            dir.id().device().set_strictness(strictness);
+
+            let trunc = OFlags::TRUNCATE;
+            for path in [".", "..", "sub"] {
+                let flags = FdFlags::empty();
+                let e = open.open_file(false, path, trunc, true, true, flags).await;
+                assert_eq!(errno(e.err()), Some(Errno::ISDIR), "{path}");
+            }
+        }
+
+        // Directories cannot be opened for writing, nor created to be.
+        let flags = FdFlags::empty();
+        for (path, oflags) in [
+            ("sub", OFlags::empty()),
+            ("new", OFlags::CREATE | OFlags::DIRECTORY),
+        ] {
+            let e = open
+                .open_file(false, path, oflags, false, true, flags)
+                .await;
+            assert_eq!(errno(e.err()), Some(Errno::ISDIR), "{path}");
+        }
+        assert!(dir.get("new").await.is_err());
+
+        let e = open.remove_dir("sub").await;
+        assert_eq!(errno(e.err()), Some(Errno::NOTEMPTY));
+        let e = open.unlink_file("sub").await;
+        assert_eq!(errno(e.err()), Some(Errno::ISDIR));
+        let e = open.remove_dir("mnt").await;
+        assert_eq!(errno(e.err()), Some(Errno::XDEV));
+        open.remove_dir("sub/sub").await.unwrap();
+        open.remove_dir("sub").await.unwrap();
+
+        // Handles used against their mode fail with EBADF.
+        let badf = |e: Option<Error>| matches!(e?.downcast().ok()?, ErrorKind::Badf).then_some(());
+        let oflags = OFlags::CREATE;
+        let flags = FdFlags::empty();
+        let mut file = open
+            .open_file(false, "file", oflags, false, true, flags)
+            .await
+            .unwrap();
+        let e = file.read_vectored(&mut [IoSliceMut::new(&mut [0])]).await;
+        assert!(badf(e.err()).is_some());
+
+        let mut file = open
+            .open_file(false, "file", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        let e = file.write_vectored(&[IoSlice::new(b"a")]).await;
+        assert!(badf(e.err()).is_some());
+        let e = file.set_filestat_size(0).await;
+        assert!(badf(e.err()).is_some());
+    }
+
+    #[tokio::test]
+    async fn append() {
+        let dir = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = dir.clone().open_dir().await.unwrap();
+        let mut file = open
+            .open_file(false, "file", OFlags::CREATE, true, true, FdFlags::APPEND)
+            .await
+            .unwrap();
+
+        async fn read(file: &mut Box<dyn WasiFile>) -> Vec<u8> {
+            let mut buf = [0u8; 16];
+            let mut bufs = [IoSliceMut::new(&mut buf)];
+            let n = file.read_vectored_at(&mut bufs, 0).await.unwrap();
+            buf[..n as usize].to_vec()
+        }
+
+        // Positional writes ignore the flag and leave the position alone.
+        file.write_vectored(&[IoSlice::new(b"ab")]).await.unwrap();
+        file.write_vectored_at(&[IoSlice::new(b"X")], 0)
+            .await
+            .unwrap();
+        assert_eq!(read(&mut file).await, b"Xb");
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 2);
+
+        // Sequential writes append, even after seeking or extending.
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.write_vectored(&[IoSlice::new(b"c"), IoSlice::new(b"d")])
+            .await
+            .unwrap();
+        file.write_vectored_at(&[IoSlice::new(b"Y")], 5)
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"e")]).await.unwrap();
+        assert_eq!(read(&mut file).await, b"Xbcd\0Ye");
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 7);
+
+        // Appends land after what other handles wrote meanwhile, while
+        // their positional writes stay where they were aimed.
+        let mut other = open
+            .open_file(false, "file", OFlags::empty(), true, true, FdFlags::empty())
+            .await
+            .unwrap();
+        other
+            .write_vectored_at(&[IoSlice::new(b"fg")], 7)
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"h")]).await.unwrap();
+        other
+            .write_vectored_at(&[IoSlice::new(b"Z")], 1)
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"i")]).await.unwrap();
+        assert_eq!(read(&mut file).await, b"XZcd\0Yefghi");
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 11);
+
+        // Setting the flag later makes sequential writes append too.
+        other.seek(SeekFrom::Start(0)).await.unwrap();
+        other.set_fdflags(FdFlags::APPEND).await.unwrap();
+        other.write_vectored(&[IoSlice::new(b"j")]).await.unwrap();
+        assert_eq!(read(&mut file).await, b"XZcd\0Yefghij");
+    }
+
+    #[tokio::test]
+    async fn digest() {
+        use wasmtime_vfs_file::CHUNK_SIZE;
+
+        let data = vec![7u8; CHUNK_SIZE * 2 + 5];
+        let dir = Directory::root(Ledger::new(), None);
+        dir.attach("file", File::with_data(dir.clone(), data.clone()))
+            .await
+            .unwrap();
+        let node = dir.get("file").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let first = file.digest().await;
+
+        // Writes through a handle are reflected in the digest.
+        let open = dir.open_dir().await.unwrap();
+        let mut handle = open
+            .open_file(false, "file", OFlags::empty(), true, true, FdFlags::empty())
+            .await
+            .unwrap();
+        handle
+            .write_vectored_at(&[IoSlice::new(b"x")], CHUNK_SIZE as u64)
+            .await
+            .unwrap();
+        let second = file.digest().await;
+        assert_ne!(first, second);
+
+        // The incremental digest matches one computed from scratch.
+        file.reset_digest();
+        assert_eq!(file.digest().await, second);
+
+        // Restoring the content restores the digest.
+        handle
+            .write_vectored_at(&[IoSlice::new(&[7])], CHUNK_SIZE as u64)
+            .await
+            .unwrap();
+        assert_eq!(file.digest().await, first);
+
+        handle.set_filestat_size(3).await.unwrap();
+        let shrunk = file.digest().await;
+        file.reset_digest();
+        assert_eq!(file.digest().await, shrunk);
+    }
+
+    #[tokio::test]
+    async fn resolver() {
+        let root = Directory::root(Ledger::new(), None);
+        let wk = Directory::new(root.clone(), None);
+        root.attach(".well-known", wk.clone()).await.unwrap();
+        root.attach(".well-known/real", File::with_data(wk.clone(), "real"))
+            .await
+            .unwrap();
+
+        wk.set_resolver(Some(Arc::new(|dir, name| {
+            let data = name.strip_prefix("echo-")?.to_owned();
+            Some(File::with_data(dir, data) as Arc<dyn Node>)
+        })));
+
+        // Attached children win, and synthesized ones are not attached.
+        let open = root.clone().open_dir().await.unwrap();
+        for (path, data) in [(".well-known/real", "real"), (".well-known/echo-hi", "hi")] {
+            let mut file = open
+                .open_file(false, path, OFlags::empty(), true, false, FdFlags::empty())
+                .await
+                .unwrap();
+            let mut buf = [0u8; 8];
+            let n = file
+                .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+                .await
+                .unwrap();
+            assert_eq!(&buf[..n as usize], data.as_bytes(), "{path}");
+        }
+
+        let names: Vec<_> = open
+            .open_dir(false, ".well-known")
+            .await
+            .unwrap()
+            .readdir(2.into())
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().name)
+            .collect();
+        assert_eq!(names, ["real"]);
+
+        let stat = open
+            .get_path_filestat(".well-known/echo-abc", false)
+            .await
+            .unwrap();
+        assert_eq!(stat.size, 3);
+        assert!(root.get(".well-known/echo-x").await.is_ok());
+
+        // Names the resolver declines are still missing.
+        let err = open
+            .open_file(
+                false,
+                ".well-known/other",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .err()
+            .unwrap();
+        let err = err.downcast::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(root.get("echo-x").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetched() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Chunks(Vec<&'static [u8]>);
+
+        #[async_trait::async_trait]
+        impl Body for Chunks {
+            async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+                Ok(self.0.pop().map(Vec::from))
+            }
+        }
+
+        // The first fetch fails, and the others return the chain in chunks.
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let count = fetches.clone();
+        let fetcher: Fetcher = Arc::new(move || {
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                match n {
+                    0 => Err(Error::io()),
+                    _ => Ok(Box::new(Chunks(vec![b"-chain", b"cert"])) as Box<dyn Body>),
+                }
+            })
+        });
+
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("chain", Fetched::new(root.clone(), fetcher))
+            .await
+            .unwrap();
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+
+        // Opening does not fetch, and the file cannot be written.
+        let mut file = open
+            .open_file(false, "chain", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        open.open_file(false, "chain", OFlags::empty(), true, true, flags)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 0);
+
+        // Reads retry a failed fetch, then stream the response to its end.
+        let mut buf = [0u8; 16];
+        let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        n.unwrap_err();
+        let mut data = Vec::new();
+        loop {
+            let mut buf = [0u8; 3];
+            let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+            match n.unwrap() as usize {
+                0 => break,
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(data, b"cert-chain");
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 10);
+        file.seek(SeekFrom::Start(0)).await.unwrap_err();
+
+        // Each handle fetches afresh.
+        let mut file = open
+            .open_file(false, "chain", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!(&buf[..n.unwrap() as usize], b"cert");
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn factory() {
+        use std::sync::Mutex;
+
+        // Makes files only with a `.txt` name, and read-only directories,
+        // noting the flags each is created with.
+        #[derive(Default)]
+        struct Text(Mutex<Vec<(OFlags, FdFlags)>>);
+
+        impl NodeFactory for Text {
+            fn create_file(
+                &self,
+                parent: &Arc<Directory>,
+                name: &str,
+                oflags: OFlags,
+                flags: FdFlags,
+            ) -> Result<Arc<dyn Node>, Error> {
+                match name.ends_with(".txt") {
+                    true => {
+                        self.0.lock().unwrap().push((oflags, flags));
+                        Ok(File::new(parent.clone()))
+                    }
+                    false => Err(Error::access()),
+                }
+            }
+
+            fn create_dir(
+                &self,
+                parent: &Arc<Directory>,
+                _: &str,
+                oflags: OFlags,
+                flags: FdFlags,
+            ) -> Result<Arc<dyn Node>, Error> {
+                self.0.lock().unwrap().push((oflags, flags));
+                Ok(Directory::new(parent.clone(), None))
+            }
+        }
+
+        let text = Arc::new(Text::default());
+        let root = Directory::root(Ledger::new(), Some(text.clone()));
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+
+        let oflags = OFlags::CREATE | OFlags::EXCLUSIVE;
+        open.open_file(false, "a.txt", oflags, true, true, FdFlags::APPEND)
+            .await
+            .unwrap();
+        let err = open
+            .open_file(false, "a.bin", OFlags::CREATE, true, true, flags)
+            .await
+            .err()
+            .unwrap();
+        let err = err.downcast::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(root.get("a.bin").await.is_err());
+
+        // Directories come from the factory too, here without one of their own.
+        open.create_dir("sub").await.unwrap();
+        open.open_file(false, "sub/b.txt", OFlags::CREATE, true, true, flags)
+            .await
+            .err()
+            .unwrap();
+
+        // The factory sees the flags of the creating open.
+        let mkdir = OFlags::CREATE | OFlags::DIRECTORY | OFlags::EXCLUSIVE;
+        assert_eq!(
+            *text.0.lock().unwrap(),
+            [(oflags, FdFlags::APPEND), (mkdir, FdFlags::empty())]
+        );
+    }
+
+    #[tokio::test]
+    async fn locks() {
+        use wasmtime_vfs_file::{LockKind, OpenFile, Owner};
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = root.clone().open_dir().await.unwrap();
+        let mut handles = Vec::new();
+        for (read, write) in [(true, true), (true, false)] {
+            let file = open
+                .open_file(false, "file", OFlags::CREATE, read, write, FdFlags::empty())
+                .await
+                .unwrap();
+            handles.push(file);
+        }
+
+        fn handle(file: &dyn WasiFile) -> &OpenFile {
+            file.as_any().downcast_ref().unwrap()
+        }
+
+        handle(&*handles[0])
+            .flock(LockKind::Exclusive)
+            .await
+            .unwrap();
+        assert!(handle(&*handles[1])
+            .try_lock(0..1, LockKind::Shared)
+            .is_err());
+        assert!(handle(&*handles[1])
+            .try_lock(0..1, LockKind::Exclusive)
+            .is_err());
+
+        // The host locks alongside the guest.
+        let node = root.get("file").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let host = Owner::new();
+        assert!(file.try_lock(host, 0..1, LockKind::Shared).is_err());
+
+        // Closing a handle releases its locks.
+        handles.remove(0);
+        file.try_lock(host, 0..1, LockKind::Shared).unwrap();
+        handle(&*handles[0])
+            .lock(1..2, LockKind::Shared)
+            .await
+            .unwrap();
+        file.unlock(host, 0..1);
+
+        // A read-only handle can still flock, and LOCK_NB fails with EAGAIN
+        // until the other lock is released.
+        let other = open
+            .open_file(
+                false,
+                "file",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        handle(&*other).try_flock(LockKind::Exclusive).unwrap_err();
+        handle(&*handles[0]).funlock();
+        handle(&*other).try_flock(LockKind::Exclusive).unwrap();
+        assert!(file.try_lock(host, 0..1, LockKind::Shared).is_err());
+
+        // A blocked flock is granted once the holder lets go.
+        tokio::join!(
+            async { handle(&*handles[0]).flock(LockKind::Shared).await.unwrap() },
+            async { handle(&*other).funlock() },
+        );
+        handle(&*other).try_flock(LockKind::Shared).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        use std::time::{Duration, Instant};
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = root.clone().open_dir().await.unwrap();
+        let mut file = open
+            .open_file(false, "file", OFlags::CREATE, true, true, FdFlags::empty())
+            .await
+            .unwrap();
+
+        let interrupted = |e: Error| {
+            let e = e.downcast::<std::io::Error>().unwrap();
+            assert_eq!(e.kind(), std::io::ErrorKind::Interrupted);
+        };
+
+        // Cancelled IO fails on files and directories alike.
+        let device = root.id().device();
+        device.cancel();
+        interrupted(
+            file.write_vectored(&[IoSlice::new(b"x")])
+                .await
+                .unwrap_err(),
+        );
+        interrupted(open.readdir(0.into()).await.err().unwrap());
+        device.resume();
+        file.write_vectored(&[IoSlice::new(b"x")]).await.unwrap();
+
+        // So does IO past a deadline, until it is lifted.
+        device.set_deadline(Some(Instant::now()));
+        interrupted(file.get_filestat().await.unwrap_err());
+        device.set_deadline(Some(Instant::now() + Duration::from_secs(3600)));
+        assert_eq!(file.get_filestat().await.unwrap().size, 1);
+        device.set_deadline(None);
+    }
+
+    #[tokio::test]
+    async fn permissions() {
+        use wasmtime_vfs_ledger::Credentials;
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let keys = Directory::new(root.clone(), root.factory());
+        root.attach("keys", keys.clone()).await.unwrap();
+        root.attach("keys/key", File::with_data(keys.clone(), "k"))
+            .await
+            .unwrap();
+
+        let locked = |mode| Permissions {
+            mode,
+            uid: 0,
+            gid: 0,
+        };
+        keys.set_permissions(locked(0o555)).await.unwrap();
+        let key = keys.get("key").await.unwrap();
+        key.set_permissions(locked(0o444)).await.unwrap();
+
+        let denied = |e: Error| {
+            let e = e.downcast::<std::io::Error>().unwrap();
+            assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+        };
+
+        // Without credentials, nothing is enforced.
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        open.open_file(false, "keys/key", OFlags::empty(), true, true, flags)
+            .await
+            .unwrap();
+
+        // A guest can read the keys, but not change them.
+        let device = root.id().device();
+        device.set_credentials(Some(Credentials {
+            uid: 1000,
+            gid: 1000,
+        }));
+        open.open_file(false, "keys/key", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        let e = open
+            .open_file(false, "keys/key", OFlags::empty(), false, true, flags)
+            .await;
+        denied(e.err().unwrap());
+        let e = open
+            .open_file(false, "keys/new", OFlags::CREATE, false, true, flags)
+            .await;
+        denied(e.err().unwrap());
+        denied(open.unlink_file("keys/key").await.unwrap_err());
+        denied(open.create_dir("keys/dir").await.unwrap_err());
+
+        // Names are only looked up in directories the guest may search.
+        keys.set_permissions(locked(0o444)).await.unwrap();
+        let e = open
+            .open_file(false, "keys/key", OFlags::empty(), true, false, flags)
+            .await;
+        denied(e.err().unwrap());
+        denied(open.get_path_filestat("keys/key", false).await.unwrap_err());
+        let kopen = keys.clone().open_dir().await.unwrap();
+        denied(kopen.get_path_filestat("key", false).await.unwrap_err());
+        assert_eq!(kopen.readdir(0.into()).await.unwrap().count(), 3);
+        keys.set_permissions(locked(0o555)).await.unwrap();
+
+        // The owner has the owner's bits, and root passes every check.
+        key.set_permissions(Permissions {
+            mode: 0o644,
+            uid: 1000,
+            gid: 0,
+        })
+        .await
+        .unwrap();
+        open.open_file(false, "keys/key", OFlags::empty(), false, true, flags)
+            .await
+            .unwrap();
+        device.set_credentials(Some(Credentials { uid: 0, gid: 0 }));
+        open.create_dir("keys/dir").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn follow_search() {
+        use wasmtime_vfs_ledger::Credentials;
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let secret = Directory::new(root.clone(), root.factory());
+        root.attach("secret", secret.clone()).await.unwrap();
+        let sub = Directory::new(secret.clone(), root.factory());
+        root.attach("secret/sub", sub.clone()).await.unwrap();
+        root.attach("secret/sub/file", File::with_data(sub, "s"))
+            .await
+            .unwrap();
+
+        let open = root.clone().open_dir().await.unwrap();
+        open.symlink("secret/sub/file", "link").await.unwrap();
+        let locked = Permissions {
+            mode: 0o600,
+            uid: 0,
+            gid: 0,
+        };
+        secret.set_permissions(locked).await.unwrap();
+        root.id().device().set_credentials(Some(Credentials {
+            uid: 1000,
+            gid: 1000,
+        }));
+
+        // A link does not reach through a directory the guest cannot search.
+        let flags = FdFlags::empty();
+        let e = open
+            .open_file(true, "link", OFlags::empty(), true, false, flags)
+            .await;
+        let e = e.err().unwrap().downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn snapshot() {
+        async fn content(root: &Arc<Directory>, path: &str) -> Vec<u8> {
+            let file = root.get(path).await.unwrap().to_any();
+            let file = file.downcast::<File>().unwrap();
+            let data = file.inode().data().read().await.content().to_vec();
+            data
+        }
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let sub = Directory::new(root.clone(), root.factory());
+        root.attach("sub", sub.clone()).await.unwrap();
+        root.attach("sub/file", File::with_data(sub, "abc"))
+            .await
+            .unwrap();
+        root.attach("mnt", Directory::device(root.clone(), None))
+            .await
+            .unwrap();
+
+        // The copy is a device of its own, without the mounts.
+        let copy = root.snapshot().await;
+        assert!(copy.id().device() != root.id().device());
+        assert_eq!(content(&copy, "sub/file").await, b"abc");
+        copy.get("mnt").await.err().unwrap();
+
+        // Writes to either side diverge.
+        let open = copy.clone().open_dir().await.unwrap();
+        let mut file = open
+            .open_file(
+                false,
+                "sub/file",
+                OFlags::empty(),
+                true,
+                true,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"x")]).await.unwrap();
+        open.create_dir("new").await.unwrap();
+
+        assert_eq!(content(&copy, "sub/file").await, b"xbc");
+        assert_eq!(content(&root, "sub/file").await, b"abc");
+        root.get("new").await.err().unwrap();
+
+        // Restoring rolls the tree back, keeping its mounts and handles.
+        let checkpoint = root.snapshot().await;
+        let open = root.clone().open_dir().await.unwrap();
+        open.unlink_file("sub/file").await.unwrap();
+        open.create_dir("new").await.unwrap();
+        let watch = root.watch().unwrap();
+        root.restore(&checkpoint).await;
+        assert_eq!(content(&root, "sub/file").await, b"abc");
+        assert_eq!(watch.try_next(), Some(Event::Remove("new".into())));
+        assert_eq!(watch.try_next(), Some(Event::Remove("sub".into())));
+        assert_eq!(watch.try_next(), Some(Event::Create("sub".into())));
+        assert_eq!(watch.try_next(), None);
+        root.get("new").await.err().unwrap();
+        root.get("mnt").await.unwrap();
+        open.get_path_filestat("sub/file", false).await.unwrap();
+
+        // The checkpoint is left as it is and can be restored again.
+        root.restore(&copy).await;
+        assert_eq!(content(&root, "sub/file").await, b"xbc");
+        root.restore(&checkpoint).await;
+        assert_eq!(content(&root, "sub/file").await, b"abc");
+        root.get("new").await.err().unwrap();
+        assert_eq!(content(&copy, "sub/file").await, b"xbc");
+
+        // The copy keeps the quotas and the credentials of the device.
+        use wasmtime_vfs_ledger::Credentials;
+
+        let device = root.id().device();
+        device.set_max_bytes(1 << 20);
+        device.set_max_inodes(64);
+        device.set_credentials(Some(Credentials { uid: 1, gid: 2 }));
+        let copy = root.snapshot().await.id().device();
+        assert_eq!(copy.max_bytes(), 1 << 20);
+        assert_eq!(copy.max_inodes(), 64);
+        assert_eq!(copy.credentials(), Some(Credentials { uid: 1, gid: 2 }));
+    }
+
+    #[tokio::test]
+    async fn filestat() {
+        use std::time::UNIX_EPOCH;
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let file = File::with_data(root.clone(), "abc");
+        root.attach("file", file.clone()).await.unwrap();
+        let open = root.clone().open_dir().await.unwrap();
+
+        // Children are statted without opening them.
+        let stat = open.get_path_filestat("file", false).await.unwrap();
+        assert_eq!(stat.filetype, FileType::RegularFile);
+        assert_eq!(stat.size, 3);
+        assert_eq!(stat, file.clone().filestat().await.unwrap());
+
+        // Their times are set the same way.
+        let file = file.to_any().downcast::<File>().unwrap();
+        file.inode()
+            .data()
+            .write()
+            .await
+            .set_modify_time(UNIX_EPOCH);
+        let now = Some(SystemTimeSpec::SymbolicNow);
+        open.set_times("file", None, now, false).await.unwrap();
+        let next = open.get_path_filestat("file", false).await.unwrap();
+        assert!(next.mtim.unwrap() > UNIX_EPOCH);
+        assert_eq!(next.atim, stat.atim);
+    }
+
+    #[tokio::test]
+    async fn xattrs() {
+        use rustix::io::Errno;
+
+        let errno = |e: Error| {
+            let e = e.downcast::<std::io::Error>().unwrap();
+            e.raw_os_error().map(Errno::from_raw_os_error)
+        };
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        root.attach("file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+        root.attach("link", Symlink::new(root.clone(), "file"))
+            .await
+            .unwrap();
+
+        for path in [".", "file", "link"] {
+            let node = root.get(path).await.unwrap();
+            assert_eq!(
+                errno(node.xattr("user.a").await.unwrap_err()),
+                Some(Errno::NODATA)
+            );
+
+            // Setting an attribute is a metadata change.
+            let before = node.clone().filestat().await.unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            node.set_xattr("user.b", b"2".to_vec()).await.unwrap();
+            node.set_xattr("user.a", b"1".to_vec()).await.unwrap();
+            let after = node.clone().filestat().await.unwrap();
+            assert!(after.ctim > before.ctim);
+            assert_eq!(after.mtim, before.mtim);
+
+            assert_eq!(node.xattr("user.a").await.unwrap(), b"1");
+            assert_eq!(node.xattrs().await.unwrap(), ["user.a", "user.b"]);
+            node.set_xattr("", Vec::new()).await.unwrap_err();
+
+            node.remove_xattr("user.b").await.unwrap();
+            let e = node.remove_xattr("user.b").await.unwrap_err();
+            assert_eq!(errno(e), Some(Errno::NODATA));
+            assert_eq!(node.xattrs().await.unwrap(), ["user.a"]);
+        }
+
+        // Snapshots keep the attributes.
+        let copy = root.snapshot().await;
+        assert_eq!(copy.xattr("user.a").await.unwrap(), b"1");
+        let file = copy.get("file").await.unwrap();
+        assert_eq!(file.xattr("user.a").await.unwrap(), b"1");
+    }
+
+    #[tokio::test]
+    async fn dir_filestat() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let open = root.clone().open_dir().await.unwrap();
+        let stat = open.get_filestat().await.unwrap();
+        assert_eq!((stat.nlink, stat.size), (2, 2 * DIRENT_SIZE));
+
+        // Subdirectories link back with their `..`; files only add size.
+        open.create_dir("a").await.unwrap();
+        open.create_dir("b").await.unwrap();
+        open.symlink("a", "c").await.unwrap();
+        let stat = open.get_filestat().await.unwrap();
+        assert_eq!((stat.nlink, stat.size), (4, 5 * DIRENT_SIZE));
+        assert_eq!(stat, root.clone().filestat().await.unwrap());
+        let stat = open.get_path_filestat("a", false).await.unwrap();
+        assert_eq!((stat.nlink, stat.size), (2, 2 * DIRENT_SIZE));
+
+        open.remove_dir("b").await.unwrap();
+        open.unlink_file("c").await.unwrap();
+        open.rename("a", &*open, "d").await.unwrap();
+        let stat = open.get_filestat().await.unwrap();
+        assert_eq!((stat.nlink, stat.size), (3, 3 * DIRENT_SIZE));
+    }
+
+    #[tokio::test]
+    async fn insensitive() {
+        async fn names(open: &dyn WasiDir) -> Vec<String> {
+            let entries = open.readdir(2.into()).await.unwrap();
+            entries.map(|e| e.unwrap().name).collect()
+        }
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        root.id().device().set_collation(Collation::Insensitive);
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+
+        // Names match regardless of case, but keep the case they were created with.
+        open.open_file(false, "Config.TOML", OFlags::CREATE, true, true, flags)
+            .await
+            .unwrap();
+        open.open_file(false, "config.toml", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+        let excl = OFlags::CREATE | OFlags::EXCLUSIVE;
+        open.open_file(false, "CONFIG.toml", excl, true, true, flags)
+            .await
+            .err()
+            .unwrap();
+        open.create_dir("config.TOML").await.unwrap_err();
+        assert_eq!(names(&*open).await, ["Config.TOML"]);
+
+        // Renaming to another case changes the case alone.
+        open.rename("config.toml", &*open, "config.toml")
+            .await
+            .unwrap();
+        assert_eq!(names(&*open).await, ["config.toml"]);
+        open.create_dir("Dir").await.unwrap();
+        open.rename("config.toml", &*open, "DIR/Config.toml")
+            .await
+            .unwrap();
+        open.unlink_file("dir/CONFIG.TOML").await.unwrap();
+        open.remove_dir("dIR").await.unwrap();
+        assert!(names(&*open).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deep() {
+        const DEPTH: usize = 1_000;
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let mut dir = root.clone();
+        for _ in 0..DEPTH {
+            let child = Directory::new(dir.clone(), dir.factory());
+            dir.attach("d", child.clone()).await.unwrap();
+            dir = child;
+        }
+
+        // Long paths are walked without recursing per segment.
+        let path = "d/".repeat(DEPTH) + "file";
+        let open = root.open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        open.open_file(false, &path, OFlags::CREATE, true, true, flags)
+            .await
+            .unwrap();
+        dir.get("file").await.unwrap();
+
+        let path = "d/..//".repeat(2) + "file";
+        open.open_file(false, &path, OFlags::empty(), true, false, flags)
+            .await
+            .err()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn normalized() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let sub = Directory::new(root.clone(), root.factory());
+        root.attach("./sub/", sub.clone()).await.unwrap();
+        root.attach("sub//file/", File::with_data(sub.clone(), "abc"))
+            .await
+            .unwrap();
+
+        // Every entry point resolves the same paths alike.
+        let file = root.get("sub/file").await.unwrap();
+        for path in ["sub//file", "./sub/file/", "sub/./file", "sub/../sub/file"] {
+            let node = root.get(path).await.unwrap();
+            assert!(Arc::ptr_eq(&node, &file), "{path}");
+
+            let open = root.clone().open_dir().await.unwrap();
+            let stat = open.get_path_filestat(path, false).await.unwrap();
+            assert_eq!(stat.inode, **file.id(), "{path}");
+            let flags = FdFlags::empty();
+            open.open_file(false, path, OFlags::empty(), true, false, flags)
+                .await
+                .unwrap();
+        }
+
+        // Each `..` leaves the directory reached so far, an absolute path
+        // starts from the root, and stepping above the root fails.
+        let node = sub.get("/sub/../sub/file").await.unwrap();
+        assert!(Arc::ptr_eq(&node, &file));
+        let node = sub.get("../sub/./file").await.unwrap();
+        assert!(Arc::ptr_eq(&node, &file));
+        let top: Arc<dyn Node> = root.clone();
+        assert!(Arc::ptr_eq(&sub.get("/").await.unwrap(), &top));
+        for path in ["../..", "/..", "../../sub/file", "/sub/../../sub/file"] {
+            let enoent = sub.get(path).await.err().unwrap();
+            let enoent = enoent.downcast::<std::io::Error>().unwrap();
+            assert_eq!(enoent.kind(), std::io::ErrorKind::NotFound, "{path}");
+        }
+
+        // Attaching resolves its path the same way.
+        let dir = Directory::new(root.clone(), root.factory());
+        sub.attach("../../dir", dir.clone()).await.err().unwrap();
+        sub.attach("/dir", dir.clone()).await.unwrap();
+        let dir: Arc<dyn Node> = dir;
+        assert!(Arc::ptr_eq(&root.get("dir").await.unwrap(), &dir));
+
+        // Empty paths name nothing.
+        let open = root.clone().open_dir().await.unwrap();
+        let enoent = open.get_path_filestat("", false).await.unwrap_err();
+        assert_eq!(
+            enoent.downcast::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        open.create_dir("sub//dir/").await.unwrap();
+        sub.get("dir").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clock() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        // A fixed clock gives every inode the same timestamps.
+        let epoch = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let ledger = Ledger::builder().clock(Arc::new(move || epoch)).build();
+        let root = Directory::root(ledger, None);
+        let open = root.clone().open_dir().await.unwrap();
+        open.create_dir("dir").await.unwrap();
+        let stat = open.get_path_filestat("dir", false).await.unwrap();
+        assert_eq!(stat.ctim, Some(epoch));
+        assert_eq!(open.get_filestat().await.unwrap().mtim, Some(epoch));
+
+        // A device can count its own time instead. Creating `a` also ticks
+        // for the change to the directory.
+        let ticks = AtomicU64::new(0);
+        let clock = move || UNIX_EPOCH + Duration::from_secs(ticks.fetch_add(1, Ordering::Relaxed));
+        let dev = Directory::device(root.clone(), None);
+        dev.id().device().set_clock(Some(Arc::new(clock)));
+        let open = dev.clone().open_dir().await.unwrap();
+        open.create_dir("a").await.unwrap();
+        open.create_dir("b").await.unwrap();
+        let a = open.get_path_filestat("a", false).await.unwrap();
+        let b = open.get_path_filestat("b", false).await.unwrap();
+        assert_eq!(a.ctim, Some(UNIX_EPOCH));
+        assert_eq!(b.ctim, Some(UNIX_EPOCH + Duration::from_secs(2)));
+        assert_eq!(root.get("dir").await.unwrap().id().device().now(), epoch);
+    }
+
+    #[tokio::test]
+    async fn remove_all() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let root = TreeBuilder::new(root)
+            .file("work/a/b/file", "abc")
+            .dir("work/c")
+            .file("keep", "")
+            .build()
+            .await
+            .unwrap();
+
+        // Mounts below the node keep it in place.
+        let mnt = Directory::device(root.clone(), None);
+        root.attach("work/c/mnt", mnt).await.unwrap();
+        let e = root.remove_all("work").await.unwrap_err();
+        let e = e.downcast::<std::io::Error>().unwrap();
+        assert_eq!(
+            e.raw_os_error(),
+            Some(rustix::io::Errno::XDEV.raw_os_error())
+        );
+        root.get("work/a/b/file").await.unwrap();
+        root.remove_all("work/c/mnt").await.unwrap_err();
+
+        // Otherwise whole subtrees go at once.
+        root.remove_all("./work/a/").await.unwrap();
+        root.get("work/a").await.err().unwrap();
+        root.get("work/c/mnt").await.unwrap();
+        root.get("keep").await.unwrap();
+        root.remove_all("work/a").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn watcher() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let run = Directory::new(root.clone(), None);
+        root.attach("run", run.clone()).await.unwrap();
+        root.attach("run/root", Watcher::new(run.clone(), root.clone()))
+            .await
+            .unwrap();
+
+        let open = root.clone().open_dir().await.unwrap();
+        let nonblock = FdFlags::NONBLOCK;
+        let mut events = open
+            .open_file(false, "run/root", OFlags::empty(), true, false, nonblock)
+            .await
+            .unwrap();
+        let e = events
+            .read_vectored(&mut [IoSliceMut::new(&mut [0; 64])])
+            .await;
+        let e = e.unwrap_err().downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+
+        let mut file = open
+            .open_file(false, "a", OFlags::CREATE, false, true, FdFlags::empty())
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"x")]).await.unwrap();
+        open.rename("a", &*open, "b").await.unwrap();
+        open.create_dir("sub").await.unwrap();
+        open.rename("b", &*open, "sub/b").await.unwrap();
+
+        // Parses the records as (wd, mask, cookie, name).
+        let mut buf = [0u8; 1024];
+        let n = events.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        let mut data = &buf[..n.unwrap() as usize];
+        let mut records = Vec::new();
+        while !data.is_empty() {
+            let word = |i: usize| u32::from_le_bytes(data[i * 4..][..4].try_into().unwrap());
+            let len = word(3) as usize;
+            let name = data[16..][..len].split(|b| *b == 0).next().unwrap();
+            let name = String::from_utf8(name.to_vec()).unwrap();
+            records.push((word(0) as i32, word(1), word(2), name));
+            data = &data[16 + len..];
+        }
+
+        // Writes to a child are reported on the child, not the directory.
+        let cookie = records[1].2;
+        assert_ne!(cookie, 0);
+        assert_eq!(
+            records,
+            [
+                (1, 0x100, 0, "a".into()),
+                (1, 0x40, cookie, "a".into()),
+                (1, 0x80, cookie, "b".into()),
+                (1, 0x100, 0, "sub".into()),
+                (1, 0x200, 0, "b".into()),
+            ]
+        );
+
+        // A file reports its writes, and a buffer too small for an event fails.
+        let node = root.get("sub/b").await.unwrap();
+        root.attach("run/b", Watcher::new(run.clone(), node))
+            .await
+            .unwrap();
+        let mut events = open
+            .open_file(
+                false,
+                "run/b",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"y")]).await.unwrap();
+        let e = events
+            .read_vectored(&mut [IoSliceMut::new(&mut [0; 8])])
+            .await;
+        let e = e.unwrap_err().downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        let n = events.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!(n.unwrap(), 16);
+        assert_eq!(buf[4..8], 0x2u32.to_le_bytes());
+
+        // Sockets only read.
+        open.open_file(
+            false,
+            "run/b",
+            OFlags::empty(),
+            true,
+            true,
+            FdFlags::empty(),
+        )
+        .await
+        .err()
+        .unwrap();
+    }
 }
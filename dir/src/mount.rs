@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+use wasi_common::file::FileType;
+use wasi_common::{Error, ErrorExt};
+use wasmtime_vfs_memory::{ErrnoExt, Node};
+
+use crate::Directory;
+
+struct Mount {
+    node: Arc<dyn Node>,
+    covered: Option<Arc<dyn Node>>,
+}
+
+/// A table of device trees grafted onto paths of a root directory
+///
+/// A mounted tree hides whatever was at its path until it is unmounted.
+/// Since it is on another device, guests cannot rename, unlink or remove
+/// it, and renames into or out of it fail with `EXDEV`.
+pub struct Mounts {
+    root: Arc<Directory>,
+    table: Mutex<BTreeMap<String, Mount>>,
+}
+
+// Splits an absolute path into its parent and final segment.
+fn split(path: &str) -> Result<(String, String, String), Error> {
+    let mut segments = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => continue,
+            ".." => return Err(Error::invalid_argument()),
+            seg => segments.push(seg),
+        }
+    }
+
+    let name = segments.pop().ok_or_else(Error::invalid_argument)?;
+    let parent = segments.join("/");
+    let path = match parent.as_str() {
+        "" => format!("/{name}"),
+        parent => format!("/{parent}/{name}"),
+    };
+
+    Ok((path, parent, name.into()))
+}
+
+// Gets `node` as a directory, failing with `ENOTDIR` if it is not one.
+fn directory(node: Arc<dyn Node>) -> Result<Arc<Directory>, Error> {
+    node.to_any()
+        .downcast::<Directory>()
+        .map_err(|_| Error::not_dir())
+}
+
+impl Mounts {
+    pub fn new(root: Arc<Directory>) -> Self {
+        Self {
+            root,
+            table: Mutex::default(),
+        }
+    }
+
+    // Gets the deepest directory on the way to `parent` which exists, along
+    // with the names of those missing below it.
+    async fn existing<'a>(&self, parent: &'a str) -> Result<(Arc<Directory>, Vec<&'a str>), Error> {
+        let mut dir = self.root.clone();
+        let mut names = parent.split('/').filter(|name| !name.is_empty());
+
+        while let Some(name) = names.next() {
+            match dir.get(name).await {
+                Ok(node) => dir = directory(node)?,
+                Err(..) => return Ok((dir, std::iter::once(name).chain(names).collect())),
+            }
+        }
+
+        Ok((dir, Vec::new()))
+    }
+
+    // Whether `path` holds a mount or `node` is mounted elsewhere.
+    fn taken(table: &BTreeMap<String, Mount>, path: &str, node: &Arc<dyn Node>) -> bool {
+        let addr = Arc::as_ptr(node) as *const ();
+        let mounted = table
+            .values()
+            .any(|m| Arc::as_ptr(&m.node) as *const () == addr);
+        mounted || table.contains_key(path)
+    }
+
+    /// Mounts `node` at the absolute `path`, on top of any directory there.
+    ///
+    /// The node must be on a device of its own, such as one made by
+    /// [`Directory::device`]. A path can hold only one mount at a time.
+    /// Missing directories on the way to `path` are created, as by
+    /// `mkdir -p`, and `..` from the mounted node leads to the directory
+    /// holding it.
+    pub async fn mount(&self, path: &str, node: Arc<dyn Node>) -> Result<(), Error> {
+        let (path, dirs, name) = split(path)?;
+        let (mut parent, missing) = self.existing(&dirs).await?;
+
+        // Check what can fail before creating any directories. Those
+        // created are on the device of the deepest existing one.
+        if node.id().device() == parent.id().device() {
+            return Err(Error::invalid_argument());
+        }
+
+        let table = self.table.lock().unwrap_or_else(PoisonError::into_inner);
+        let taken = Self::taken(&table, &path, &node);
+        drop(table);
+        if taken {
+            return Err(Error::busy());
+        }
+
+        for name in missing {
+            let sub = Directory::new(parent.clone(), parent.factory());
+            parent = match parent.attach(name, sub.clone()).await {
+                Ok(()) => sub,
+                Err(..) => directory(parent.get(name).await?)?,
+            };
+        }
+
+        let mut ilock = parent.inode().data().write().await;
+        let mut table = self.table.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Another mount may have taken the path meanwhile.
+        if Self::taken(&table, &path, &node) {
+            return Err(Error::busy());
+        }
+
+        if let Some(covered) = ilock.content().get(&name) {
+            if covered.filetype() != FileType::Directory {
+                return Err(Error::not_dir());
+            }
+        }
+
+        node.id().link();
+        let covered = ilock.content_mut().insert(name, node.clone());
+        ilock.touch();
+
+        node.set_parent(Arc::downgrade(&(parent.clone() as Arc<dyn Node>)));
+        table.insert(path, Mount { node, covered });
+        Ok(())
+    }
+
+    /// Unmounts the tree at `path`, uncovering what was there before.
+    ///
+    /// This fails with `EBUSY` while other trees are mounted below it.
+    /// Handles still open in the tree keep working, but `..` no longer
+    /// leads out of it, as after a lazy unmount on Linux.
+    pub async fn unmount(&self, path: &str) -> Result<Arc<dyn Node>, Error> {
+        let (path, dirs, name) = split(path)?;
+        let (parent, missing) = self.existing(&dirs).await?;
+        if !missing.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        let mut ilock = parent.inode().data().write().await;
+        let mut table = self.table.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !table.contains_key(&path) {
+            return Err(Error::invalid_argument());
+        }
+
+        let prefix = format!("{path}/");
+        if table.keys().any(|p| p.starts_with(&prefix)) {
+            return Err(Error::busy());
+        }
+
+        let Mount { node, covered } = table.remove(&path).unwrap();
+        ilock.content_mut().remove(&name);
+        node.id().unlink();
+        if let Some(covered) = covered {
+            ilock.content_mut().insert(name, covered);
+        }
+        ilock.touch();
+
+        node.set_parent(Weak::<Directory>::new());
+        Ok(node)
+    }
+
+    /// The paths of all mounts, sorted.
+    pub fn mounts(&self) -> Vec<String> {
+        let table = self.table.lock().unwrap_or_else(PoisonError::into_inner);
+        table.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rustix::io::Errno;
+    use wasi_common::ErrorKind;
+    use wasmtime_vfs_file::File;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    fn errno(e: Error) -> Option<Errno> {
+        let e = e.downcast_ref::<std::io::Error>()?;
+        e.raw_os_error().map(Errno::from_raw_os_error)
+    }
+
+    #[tokio::test]
+    async fn mounts() {
+        let root = Directory::root(Ledger::new(), None);
+        let keys = Directory::new(root.clone(), None);
+        root.attach("keys", keys.clone()).await.unwrap();
+        keys.attach("old", File::with_data(keys.clone(), "abc"))
+            .await
+            .unwrap();
+
+        let dev = Directory::device(root.clone(), None);
+        dev.attach("new", File::with_data(dev.clone(), "xyz"))
+            .await
+            .unwrap();
+
+        let mounts = Mounts::new(root.clone());
+        mounts.mount("/keys/", dev.clone()).await.unwrap();
+        assert_eq!(mounts.mounts(), ["/keys"]);
+        assert!(root.get("keys/new").await.is_ok());
+        assert!(root.get("keys/old").await.is_err());
+        assert_eq!(**root.get("keys/..").await.unwrap().id(), **root.id());
+
+        // Only devices can be mounted, once each and one per path.
+        let sub = Directory::new(root.clone(), None);
+        let e = mounts.mount("/sub", sub).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<ErrorKind>(), Some(&ErrorKind::Inval));
+        let e = mounts.mount("/keys", Directory::device(root.clone(), None));
+        assert_eq!(errno(e.await.unwrap_err()), Some(Errno::BUSY));
+        let e = mounts.mount("/other", dev.clone()).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::BUSY));
+
+        // Guests cannot move the mount point.
+        let open = root.clone().open_dir().await.unwrap();
+        let e = open.rename("keys", &*open, "moved").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+        open.remove_dir("keys").await.unwrap_err();
+
+        // A mount below another keeps the outer one busy.
+        let inner = Directory::device(dev.clone(), None);
+        mounts.mount("/keys/inner", inner).await.unwrap();
+        let e = mounts.unmount("/keys").await.err().unwrap();
+        assert_eq!(errno(e), Some(Errno::BUSY));
+        mounts.unmount("/keys/inner").await.unwrap();
+
+        // Unmounting uncovers the original directory.
+        let node = mounts.unmount("/keys").await.unwrap();
+        assert_eq!(**node.id(), **dev.id());
+        assert!(root.get("keys/old").await.is_ok());
+        assert!(mounts.mounts().is_empty());
+        assert!(mounts.unmount("/keys").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn boundaries() {
+        let root = Directory::root(Ledger::new(), None);
+        let dev = Directory::device(root.clone(), None);
+        let sub = Directory::new(dev.clone(), None);
+        dev.attach("sub", sub).await.unwrap();
+
+        // Mounting creates the directories on the way.
+        let mounts = Mounts::new(root.clone());
+        mounts.mount("/srv/keys", dev.clone()).await.unwrap();
+        let srv = root.get("srv").await.unwrap();
+        assert!(srv.id().device() == root.id().device());
+        assert_eq!(**root.get("srv/keys/..").await.unwrap().id(), **srv.id());
+        let keys = root.get("srv/keys").await.unwrap();
+        assert!(keys.id().device() == dev.id().device());
+
+        // Failing mounts and unmounts create nothing on the way.
+        let sub = Directory::new(root.clone(), None);
+        mounts.mount("/new/sub", sub).await.unwrap_err();
+        mounts.mount("/other/keys", dev.clone()).await.unwrap_err();
+        mounts.unmount("/gone/keys").await.unwrap_err();
+        for name in ["new", "other", "gone"] {
+            root.get(name).await.err().unwrap();
+        }
+
+        // A file on the way is not a directory.
+        root.attach("file", File::new(root.clone())).await.unwrap();
+        let e = mounts.mount("/file/mnt", Directory::device(root.clone(), None));
+        let e = e.await.unwrap_err();
+        assert_eq!(e.downcast_ref::<ErrorKind>(), Some(&ErrorKind::Notdir));
+
+        // Once unmounted, `..` stays inside the tree.
+        let open = root.clone().open_dir().await.unwrap();
+        let keys = open.open_dir(false, "srv/keys/sub").await.unwrap();
+        mounts.unmount("/srv/keys").await.unwrap();
+        let stat = keys.get_path_filestat("../..", false).await.unwrap();
+        assert_eq!(stat.inode, **dev.id());
+        assert_eq!(stat.device_id, **dev.id().device());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::mem::take;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use wasi_common::dir::ReaddirEntity;
+use wasi_common::Error;
+use wasmtime_vfs_memory::Node;
+
+type Content = BTreeMap<String, Arc<dyn Node>>;
+
+/// Where the last listing of a handle stopped
+///
+/// These are the last two entries returned, by name and the cursor following
+/// each. `wasi-common` reads one entry past what fits in the guest buffer,
+/// so the guest resumes after either of them.
+pub(crate) type Last = Arc<Mutex<Vec<(u64, String)>>>;
+
+/// The child entries of a directory from a cursor on
+///
+/// The entries from the cursor on are copied while `readdir` holds the
+/// directory, so the listing never locks it again and writers meanwhile
+/// neither hold it up nor fail it. Cursors count the entries, starting at 3
+/// after the single dot entries, and a cursor continuing the last listing of
+/// the handle resumes from the name it ended at. Any other cursor is found
+/// by skipping that many entries.
+pub(crate) struct Entries {
+    batch: VecDeque<ReaddirEntity>,
+    read: Vec<(u64, String)>,
+    last: Last,
+}
+
+impl Entries {
+    /// Resumes the listing of `content` at `cursor`, which is at least 2.
+    pub(crate) fn new(content: &Content, cursor: u64, last: Last) -> Self {
+        let read = take(&mut *last.lock().unwrap_or_else(PoisonError::into_inner));
+        let resume = read.iter().find(|(next, _)| *next == cursor);
+
+        let from = match resume {
+            _ if cursor == 2 => Some(Bound::Unbounded),
+            Some((_, name)) => Some(Bound::Excluded(name.as_str())),
+            None => usize::try_from(cursor - 3)
+                .ok()
+                .and_then(|skip| content.keys().nth(skip))
+                .map(|name| Bound::Excluded(name.as_str())),
+        };
+
+        let batch = match from {
+            Some(from) => content
+                .range::<str, _>((from, Bound::Unbounded))
+                .zip(cursor + 1..)
+                .map(|((name, node), next)| ReaddirEntity {
+                    name: name.clone(),
+                    next: next.into(),
+                    inode: **node.id(),
+                    filetype: node.filetype(),
+                })
+                .collect(),
+            None => VecDeque::new(),
+        };
+
+        Self { batch, read, last }
+    }
+}
+
+impl Iterator for Entries {
+    type Item = Result<ReaddirEntity, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.batch.pop_front()?;
+        self.read.push((u64::from(entry.next), entry.name.clone()));
+        if self.read.len() > 2 {
+            self.read.remove(0);
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+impl Drop for Entries {
+    fn drop(&mut self) {
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        *last = take(&mut self.read);
+    }
+}
@@ -0,0 +1,129 @@
+use std::any::Any;
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node};
+
+/// A symbolic link
+///
+/// The target is kept as given and only resolved when a path crosses the
+/// link, so it may dangle. Opening the link itself fails with `ELOOP`, as
+/// opening it with `O_NOFOLLOW` does on Linux.
+pub struct Symlink {
+    link: Link<()>,
+    target: String,
+}
+
+#[async_trait::async_trait]
+impl Node for Symlink {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.link.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.link.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SymbolicLink
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.link.inode().id()
+    }
+
+    fn read_link(&self) -> Option<String> {
+        Some(self.target.clone())
+    }
+
+    async fn xattr(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.link.inode().data().read().await.xattr(name)
+    }
+
+    async fn xattrs(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .link
+            .inode()
+            .data()
+            .read()
+            .await
+            .xattrs()
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    async fn set_xattr(&self, name: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.link
+            .inode()
+            .data()
+            .write()
+            .await
+            .set_xattr(name, value)
+    }
+
+    async fn remove_xattr(&self, name: &str) -> Result<(), Error> {
+        self.link.inode().data().write().await.remove_xattr(name)
+    }
+
+    async fn snapshot(&self, parent: Arc<dyn Node>) -> Option<Arc<dyn Node>> {
+        let copy = Self::new(parent, self.target.clone());
+        let ilock = self.link.inode().data().read().await;
+        copy.link.inode().data().write().await.copy_metadata(&ilock);
+        Some(copy)
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let ilock = self.link.inode().read().await?;
+
+        Ok(Filestat {
+            device_id: **self.link.inode().id().device(),
+            inode: **self.link.inode().id(),
+            filetype: FileType::SymbolicLink,
+            nlink: self.link.inode().id().links(),
+            size: self.target.len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn set_times(
+        self: Arc<Self>,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.link.inode().write().await?.set_times(atime, mtime)
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        _dir: bool,
+        _read: bool,
+        _write: bool,
+        _flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        Err(Error::too_many_links())
+    }
+}
+
+impl Symlink {
+    /// Creates a link to `target` in `parent`.
+    pub fn new(parent: Arc<dyn Node>, target: impl Into<String>) -> Arc<Self> {
+        Arc::new(Self {
+            link: Link::new(&parent, ()),
+            target: target.into(),
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Initial contents for files created on a device, chosen by name
+///
+/// Patterns match the name of the new file, not its path. In a pattern,
+/// `*` matches any run of characters and `?` matches any one character.
+/// The first pattern added which matches wins. Only files created through
+/// the file system get a template; nodes attached directly are untouched.
+#[derive(Default)]
+pub struct Templates(RwLock<Vec<(String, Vec<u8>)>>);
+
+impl Templates {
+    /// Create an empty set of templates.
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Give new files matching `pattern` the initial `contents`.
+    pub fn add(&self, pattern: impl Into<String>, contents: impl Into<Vec<u8>>) {
+        let mut lock = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        lock.push((pattern.into(), contents.into()));
+    }
+
+    /// Removes the template for `pattern`, returning whether there was one.
+    pub fn remove(&self, pattern: &str) -> bool {
+        let mut lock = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        let len = lock.len();
+        lock.retain(|(p, _)| p != pattern);
+        lock.len() < len
+    }
+
+    /// Gets the initial contents of a new file named `name`, if any.
+    pub fn find(&self, name: &str) -> Option<Vec<u8>> {
+        let lock = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        lock.iter()
+            .find(|(pattern, _)| matches(pattern.as_bytes(), name.as_bytes()))
+            .map(|(_, contents)| contents.clone())
+    }
+}
+
+// Whether `name` matches `pattern`, backtracking to the last `*` on failure.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let templates = Templates::new();
+        templates.add("*.log", "# log\n");
+        templates.add("a?c*", "abc");
+
+        assert_eq!(templates.find("foo.log").unwrap(), b"# log\n");
+        assert_eq!(templates.find(".log").unwrap(), b"# log\n");
+        assert_eq!(templates.find("abcd.log").unwrap(), b"# log\n");
+        assert_eq!(templates.find("axc").unwrap(), b"abc");
+        assert!(templates.find("foo.logs").is_none());
+        assert!(templates.find("ac").is_none());
+
+        assert!(templates.remove("*.log"));
+        assert!(!templates.remove("*.log"));
+        assert_eq!(templates.find("abcd.log").unwrap(), b"abc");
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+use wasmtime_vfs_memory::Node;
+
+/// A node removed from a directory while its device had a [`Trash`]
+pub struct Trashed {
+    /// The name the node had in its directory
+    ///
+    /// The directory itself is still available from [`Node::parent`].
+    pub name: String,
+
+    /// The size of the node when it was removed
+    pub size: u64,
+
+    /// When the node was removed
+    pub when: SystemTime,
+
+    /// The node itself
+    pub node: Arc<dyn Node>,
+}
+
+/// A hidden area holding nodes unlinked or replaced on a device
+///
+/// Once the total size of the held nodes exceeds the capacity, the oldest
+/// are dropped for good. Nodes still open or linked elsewhere when removed
+/// are not held, since they could grow past the capacity afterwards. The
+/// trash is not visible inside the file system.
+pub struct Trash {
+    capacity: u64,
+    state: Mutex<(u64, VecDeque<Trashed>)>,
+}
+
+impl Trash {
+    /// Create a trash holding at most `capacity` bytes.
+    pub fn new(capacity: u64) -> Arc<Self> {
+        Arc::new(Self {
+            capacity,
+            state: Mutex::default(),
+        })
+    }
+
+    /// The total size of the held nodes.
+    pub fn size(&self) -> u64 {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).0
+    }
+
+    /// Removes and returns all held nodes, oldest first.
+    pub fn take(&self) -> Vec<Trashed> {
+        let mut lock = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        lock.0 = 0;
+        lock.1.drain(..).collect()
+    }
+
+    pub(crate) fn push(&self, trashed: Trashed) {
+        // A node which could never fit is not kept at all.
+        if trashed.size > self.capacity {
+            return;
+        }
+
+        let mut lock = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (size, items) = &mut *lock;
+        *size += trashed.size;
+        items.push_back(trashed);
+
+        while *size > self.capacity {
+            match items.pop_front() {
+                Some(oldest) => *size -= oldest.size,
+                None => break,
+            }
+        }
+    }
+}
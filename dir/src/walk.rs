@@ -0,0 +1,127 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use wasi_common::Error;
+use wasmtime_vfs_memory::{Node, MAX_LINKS};
+
+use crate::Directory;
+
+/// A node visited while walking a tree
+pub struct Entry {
+    /// The path of the node, relative to the root of the walk
+    pub path: String,
+
+    /// The number of path segments between the root and this node
+    pub depth: usize,
+
+    /// The node itself
+    pub node: Arc<dyn Node>,
+}
+
+/// How a walk treats symbolic links
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Links {
+    /// Yield links as links, leaving their targets alone.
+    #[default]
+    Preserve,
+
+    /// Yield the target of each link in its place, as for a copy.
+    ///
+    /// Links which dangle, or form a chain too long to follow, are yielded
+    /// as links.
+    Follow,
+}
+
+// The directory holding `node`, if it is one.
+fn parent(node: &Arc<dyn Node>) -> Option<Arc<Directory>> {
+    node.parent()?.to_any().downcast::<Directory>().ok()
+}
+
+// Resolves `node`, found in `dir`, through any chain of links.
+async fn resolve(dir: Arc<Directory>, node: Arc<dyn Node>) -> Arc<dyn Node> {
+    let mut dir = dir;
+    let mut this = node.clone();
+
+    for _ in 0..MAX_LINKS {
+        let target = match this.read_link() {
+            Some(target) => target,
+            None => return this,
+        };
+
+        // Absolute targets start from the root of the tree holding the link.
+        let base = match target.starts_with('/') {
+            true => Node::root(&dir)
+                .to_any()
+                .downcast::<Directory>()
+                .unwrap_or(dir),
+            false => dir,
+        };
+
+        this = match base.get(&target).await {
+            Ok(node) => node,
+            Err(..) => return node,
+        };
+
+        // The next link in the chain, if any, is relative to its own directory.
+        dir = parent(&this).unwrap_or(base);
+    }
+
+    node
+}
+
+/// Walks a [`Node`] tree in depth-first order, children sorted by name.
+///
+/// The root is returned first with the path `/`. Only [`Directory`] nodes
+/// are descended into, including directories on other devices. A directory
+/// reachable by more than one path is yielded each time but descended into
+/// only once, so walking a tree containing a cycle terminates. Symbolic
+/// links are yielded as links; see [`walk_with`] to follow them.
+pub async fn walk(root: Arc<dyn Node>) -> Result<Vec<Entry>, Error> {
+    walk_with(root, Links::Preserve).await
+}
+
+/// Walks a [`Node`] tree like [`walk`], treating symbolic links as `links` says.
+///
+/// When following links, each target is yielded at the path of the link.
+/// A followed directory is still descended into only once.
+pub async fn walk_with(root: Arc<dyn Node>, links: Links) -> Result<Vec<Entry>, Error> {
+    // Addresses rather than pointers, so that the future stays `Send`.
+    let mut visited = BTreeSet::new();
+    let mut entries = Vec::new();
+    let mut stack = vec![Entry {
+        path: "/".into(),
+        depth: 0,
+        node: root,
+    }];
+
+    while let Some(mut entry) = stack.pop() {
+        if links == Links::Follow && entry.node.read_link().is_some() {
+            if let Some(dir) = parent(&entry.node) {
+                entry.node = resolve(dir, entry.node).await;
+            }
+        }
+
+        let dir = entry.node.clone().to_any().downcast::<Directory>();
+        if let Some(dir) = dir.ok().filter(|d| visited.insert(Arc::as_ptr(d) as usize)) {
+            let ilock = dir.inode().data().read().await;
+
+            // Push in reverse so that children are visited in order.
+            for (name, node) in ilock.content().iter().rev() {
+                let path = match entry.depth {
+                    0 => format!("/{name}"),
+                    _ => format!("{}/{name}", entry.path),
+                };
+
+                stack.push(Entry {
+                    path,
+                    depth: entry.depth + 1,
+                    node: node.clone(),
+                });
+            }
+        }
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
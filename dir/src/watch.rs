@@ -0,0 +1,233 @@
+use std::any::Any;
+use std::io::IoSliceMut;
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Event, Link, Node, Watch};
+
+// The inotify masks of the events.
+const IN_MODIFY: u32 = 0x2;
+const IN_MOVED_FROM: u32 = 0x40;
+const IN_MOVED_TO: u32 = 0x80;
+const IN_CREATE: u32 = 0x100;
+const IN_DELETE: u32 = 0x200;
+const IN_Q_OVERFLOW: u32 = 0x4000;
+
+/// A socket delivering the changes to a node, as inotify does
+///
+/// Each handle watches the node from when it is opened, and reads return
+/// whole `struct inotify_event` records, all with watch descriptor 1. A
+/// rename within a directory is a `IN_MOVED_FROM` and `IN_MOVED_TO` pair
+/// sharing a cookie, and a move between directories is a `IN_DELETE` and
+/// `IN_CREATE`. Reads wait for an event unless the handle is non-blocking,
+/// and fail with `EINVAL` if the buffer cannot hold the next one.
+pub struct Watcher(Link<Arc<dyn Node>>);
+
+#[async_trait::async_trait]
+impl Node for Watcher {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketStream
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || write || !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::perm());
+        }
+
+        let node = self.0.inode().data().read().await.content().clone();
+        let watch = node.watch().ok_or_else(Error::not_supported)?;
+
+        Ok(Box::new(OpenWatcher {
+            _root: self.root(),
+            link: self,
+            watch,
+            flags,
+            cookie: 0,
+        }))
+    }
+}
+
+impl Watcher {
+    /// Creates a socket in `parent` watching `node`.
+    pub fn new(parent: Arc<dyn Node>, node: Arc<dyn Node>) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, node)))
+    }
+}
+
+struct OpenWatcher {
+    _root: Arc<dyn Node>,
+    link: Arc<Watcher>,
+    watch: Watch,
+    flags: FdFlags,
+
+    // The cookie of the last rename.
+    cookie: u32,
+}
+
+// Appends an inotify record to `out`, padding the name as Linux does.
+fn record(out: &mut Vec<u8>, wd: i32, mask: u32, cookie: u32, name: &str) {
+    let len = match name.len() {
+        0 => 0,
+        n => (n + 1).next_multiple_of(16),
+    };
+
+    out.extend_from_slice(&wd.to_le_bytes());
+    out.extend_from_slice(&mask.to_le_bytes());
+    out.extend_from_slice(&cookie.to_le_bytes());
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+    out.resize(out.len() + len - name.len(), 0);
+}
+
+impl OpenWatcher {
+    // Encodes `event` as the inotify records reporting it.
+    fn encode(&self, event: &Event) -> Vec<u8> {
+        let mut out = Vec::new();
+        match event {
+            Event::Create(name) => record(&mut out, 1, IN_CREATE, 0, name),
+            Event::Write => record(&mut out, 1, IN_MODIFY, 0, ""),
+            Event::Remove(name) => record(&mut out, 1, IN_DELETE, 0, name),
+            Event::Rename { from, to } => {
+                let cookie = self.cookie.wrapping_add(1);
+                record(&mut out, 1, IN_MOVED_FROM, cookie, from);
+                record(&mut out, 1, IN_MOVED_TO, cookie, to);
+            }
+            Event::Overflow => record(&mut out, -1, IN_Q_OVERFLOW, 0, ""),
+        }
+
+        out
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenWatcher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.flags)
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        if !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        self.flags = flags;
+        Ok(())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketStream,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        if self.watch.peek().is_none() {
+            if self.flags.contains(FdFlags::NONBLOCK) {
+                return Err(Error::would_block());
+            }
+
+            self.watch.ready().await;
+        }
+
+        // As many whole events as fit, and at least one.
+        let room = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        let mut data = Vec::new();
+        while let Some(event) = self.watch.peek() {
+            let records = self.encode(&event);
+            if data.len() + records.len() > room {
+                break;
+            }
+
+            if let Event::Rename { .. } = event {
+                self.cookie = self.cookie.wrapping_add(1);
+            }
+
+            self.watch.try_next();
+            data.extend(records);
+        }
+
+        if data.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), data.len() - total);
+            buf[..len].copy_from_slice(&data[total..][..len]);
+            total += len;
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self
+            .watch
+            .peek()
+            .map_or(0, |event| self.encode(&event).len() as u64))
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.watch.ready().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! A concurrency stress test, enabled by the `stress` feature.
+//!
+//! Worker threads run random operations against one tree while a checker
+//! thread validates its invariants. Set `VFS_STRESS_OPS` to change the
+//! number of operations each worker runs.
+
+#![cfg(feature = "stress")]
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::IoSlice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use wasi_common::file::{FdFlags, OFlags};
+use wasi_common::WasiDir;
+use wasmtime_vfs_dir::{walk, Directory};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_ledger::Ledger;
+use wasmtime_vfs_memory::Node;
+
+const WORKERS: u64 = 4;
+const PATHS: &[&str] = &["a", "b", "c", "d", "a/x", "a/y", "b/x", "b/y", "a/x/z"];
+
+// A small xorshift generator, so that runs can be replayed from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+
+    fn path(&mut self) -> &'static str {
+        PATHS[self.next(PATHS.len())]
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+// Runs one random operation, ignoring the expected failures. `other` is
+// another handle to the same directory.
+async fn step(dir: &dyn WasiDir, other: &dyn WasiDir, rng: &mut Rng) {
+    let flags = FdFlags::empty();
+    let _ = match rng.next(7) {
+        0 => {
+            let open = dir.open_file(false, rng.path(), OFlags::CREATE, false, true, flags);
+            if let Ok(mut file) = open.await {
+                let data = vec![0u8; rng.next(64)];
+                let _ = file.write_vectored(&[IoSlice::new(&data)]).await;
+            }
+            Ok(())
+        }
+        1 => dir.create_dir(rng.path()).await,
+        2 => dir.unlink_file(rng.path()).await,
+        3 => dir.remove_dir(rng.path()).await,
+        4 => {
+            let (from, to) = (rng.path(), rng.path());
+            dir.rename(from, dir, to).await
+        }
+        5 => {
+            let (from, to) = (rng.path(), rng.path());
+            dir.rename(from, other, to).await
+        }
+        _ => {
+            // The directories are too small to outgrow the first batch, so
+            // their listings are never held up by the other workers.
+            let path = ["", "a", "b"][rng.next(3)];
+            if let Ok(listed) = dir.open_dir(false, path).await {
+                for entry in listed.readdir(0.into()).await.unwrap() {
+                    entry.unwrap();
+                }
+            }
+            Ok(())
+        }
+    };
+}
+
+// Checks the invariants which hold even while the tree is changing.
+async fn check(root: &Arc<Directory>, mtime: &mut SystemTime) {
+    let device = root.id().device();
+    let mut reachable = HashSet::new();
+
+    // Holding the entries keeps every node counted here alive.
+    let entries = walk(root.clone()).await.unwrap();
+    for entry in &entries {
+        reachable.insert(Arc::as_ptr(&entry.node) as *const () as usize);
+
+        let dir = match entry.node.clone().to_any().downcast::<Directory>() {
+            Ok(dir) => dir,
+            Err(..) => continue,
+        };
+
+        // Names are unique by construction; inodes must be too.
+        let ilock = dir.inode().data().read().await;
+        let mut inodes = BTreeMap::new();
+        for (name, node) in ilock.content().iter() {
+            let ptr = Arc::as_ptr(node) as *const () as usize;
+            let seen = *inodes.entry(**node.id()).or_insert(ptr);
+            assert_eq!(seen, ptr, "{}/{name} shares an inode", entry.path);
+        }
+    }
+
+    // Every reachable node holds an inode, but workers may hold more.
+    // Nodes created since the walk may or may not be counted.
+    assert!(reachable.len() as u64 <= device.live_inodes());
+
+    let modify = root.inode().data().read().await.modify_time();
+    assert!(modify >= *mtime, "the root's mtime went backwards");
+    *mtime = modify;
+}
+
+#[test]
+fn stress() {
+    let ops: u64 = std::env::var("VFS_STRESS_OPS")
+        .ok()
+        .and_then(|ops| ops.parse().ok())
+        .unwrap_or(10_000);
+
+    let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+    let done = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        let checker = s.spawn(|| {
+            block_on(async {
+                let mut mtime = SystemTime::UNIX_EPOCH;
+                while !done.load(Ordering::Relaxed) {
+                    check(&root, &mut mtime).await;
+                }
+            })
+        });
+
+        let workers: Vec<_> = (0..WORKERS)
+            .map(|seed| {
+                let root = root.clone();
+                s.spawn(move || {
+                    block_on(async {
+                        let mut rng = Rng(seed + 1);
+                        let dir = root.clone().open_dir().await.unwrap();
+                        let other = root.open_dir().await.unwrap();
+                        for _ in 0..ops {
+                            step(&*dir, &*other, &mut rng).await;
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        done.store(true, Ordering::Relaxed);
+        checker.join().unwrap();
+    });
+
+    // At rest, every live inode is reachable and every parent is correct.
+    block_on(async {
+        let entries = walk(root.clone()).await.unwrap();
+        let mut nodes = BTreeMap::new();
+        for entry in &entries {
+            let ptr = Arc::as_ptr(&entry.node) as *const () as usize;
+            nodes.insert(ptr, entry.node.clone());
+
+            if entry.depth > 0 {
+                let (parent, _) = entry.path.rsplit_once('/').unwrap();
+                let parent = root.get(parent).await.unwrap();
+                let actual = entry.node.parent().unwrap();
+                assert!(Arc::ptr_eq(&parent, &actual), "{}", entry.path);
+            }
+        }
+
+        assert_eq!(nodes.len() as u64, root.id().device().live_inodes());
+    });
+}
@@ -0,0 +1,45 @@
+use sha2::{Digest, Sha256};
+
+use crate::Sparse;
+
+/// The number of bytes covered by each chunk hash
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// SHA-256 hashes of each chunk of a file, `None` where stale
+#[derive(Default)]
+pub(crate) struct Chunks(Vec<Option<[u8; 32]>>);
+
+impl Chunks {
+    /// Marks the chunks overlapping `start..end` as stale.
+    pub fn invalidate(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+
+        let first = start / CHUNK_SIZE;
+        let last = (end - 1) / CHUNK_SIZE;
+        for hash in self.0.iter_mut().take(last + 1).skip(first) {
+            *hash = None;
+        }
+    }
+
+    /// Rehashes any stale chunks of `data` and returns the digest.
+    ///
+    /// The digest is the SHA-256 of the concatenated chunk hashes.
+    pub fn digest(&mut self, data: &Sparse) -> [u8; 32] {
+        let count = data.len().div_ceil(CHUNK_SIZE);
+        self.0.resize(count, None);
+
+        let mut digest = Sha256::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        for (i, hash) in self.0.iter_mut().enumerate() {
+            let hash = hash.get_or_insert_with(|| {
+                let len = data.read(i * CHUNK_SIZE, &mut chunk);
+                Sha256::digest(&chunk[..len]).into()
+            });
+            digest.update(hash);
+        }
+
+        digest.finalize().into()
+    }
+}
@@ -0,0 +1,284 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node, Open};
+
+/// The records kept by a [`Journal`]
+#[derive(Default)]
+struct Records {
+    // The sequence number of the first record in `records`.
+    first: u64,
+    records: VecDeque<Vec<u8>>,
+    size: u64,
+}
+
+/// An append-only file of records, for guest telemetry and event streams
+///
+/// Each write by the guest appends one record, numbered in sequence. Guests
+/// can only open the journal for writing and cannot seek, truncate or read
+/// it, so nothing they wrote can be changed afterwards. The host reads the
+/// records and drops them up to a checkpoint once they are exported. Until
+/// then, records count towards the byte quota of the device, and writes
+/// past it fail with `ENOSPC`.
+pub struct Journal(Link<Records>);
+
+// The records kept count towards the byte quota of the device for as long
+// as the journal lives.
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if let Ok(ilock) = self.0.inode().data().try_read() {
+            self.0.inode().id().device().release(ilock.content().size);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Node for Journal {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::RegularFile
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if read || !(flags - FdFlags::APPEND).is_empty() {
+            return Err(Error::perm());
+        }
+
+        Ok(Box::new(OpenJournal(Open::new(self, read, write, flags))))
+    }
+}
+
+impl Journal {
+    pub fn new(parent: Arc<dyn Node>) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, Records::default())))
+    }
+
+    /// Returns the records kept, oldest first, with their sequence numbers.
+    pub async fn records(&self) -> Vec<(u64, Vec<u8>)> {
+        let ilock = self.0.inode().data().read().await;
+        let first = ilock.content().first;
+        let records = ilock.content().records.iter().cloned();
+        (first..).zip(records).collect()
+    }
+
+    /// Drops the records numbered below `checkpoint` and returns them framed.
+    ///
+    /// Each record is framed as its sequence number as a big-endian `u64`,
+    /// its length as a big-endian `u32` and then its bytes. Checkpoints at or
+    /// below an earlier one return nothing. The records dropped no longer
+    /// count towards the byte quota.
+    pub async fn checkpoint(&self, checkpoint: u64) -> Vec<u8> {
+        let mut ilock = self.0.inode().data().write().await;
+        let records = ilock.content_mut();
+        let size = records.size;
+
+        let mut framed = Vec::new();
+        while records.first < checkpoint {
+            let record = match records.records.pop_front() {
+                Some(record) => record,
+                None => break,
+            };
+
+            framed.extend_from_slice(&records.first.to_be_bytes());
+            framed.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            framed.extend_from_slice(&record);
+            records.size -= record.len() as u64;
+            records.first += 1;
+        }
+
+        self.0.inode().id().device().release(size - records.size);
+        framed
+    }
+}
+
+struct OpenJournal(Open<Journal>);
+
+#[async_trait::async_trait]
+impl WasiFile for OpenJournal {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.0.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.0.link().0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.0.link().0.inode().id().device(),
+            inode: **self.0.link().0.inode().id(),
+            filetype: FileType::RegularFile,
+            nlink: self.0.link().0.inode().id().links(),
+            size: ilock.content().size,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if !self.0.can_write() {
+            return Err(Error::badf());
+        }
+
+        let record: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        if record.is_empty() {
+            return Ok(0);
+        }
+
+        // Records are framed with a 32-bit length when exported.
+        let len = u32::try_from(record.len()).map_err(|_| Error::too_big())?;
+
+        let mut ilock = self.0.link().0.inode().data().write().await;
+        if !self.0.link().0.inode().id().device().reserve(len as u64) {
+            return Err(Error::no_space());
+        }
+
+        ilock.content_mut().size += len as u64;
+        ilock.content_mut().records.push_back(record);
+        ilock.touch();
+        Ok(len as u64)
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_ledger::Ledger;
+
+    struct Root(Link<()>);
+
+    #[async_trait::async_trait]
+    impl Node for Root {
+        fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+            self
+        }
+
+        fn parent(&self) -> Option<Arc<dyn Node>> {
+            None
+        }
+
+        fn set_parent(&self, _parent: Weak<dyn Node>) {}
+
+        fn filetype(&self) -> FileType {
+            FileType::Directory
+        }
+
+        fn id(&self) -> Arc<InodeId> {
+            self.0.inode().id()
+        }
+
+        async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+            Err(Error::not_supported())
+        }
+
+        async fn open_file(
+            self: Arc<Self>,
+            _path: &str,
+            _dir: bool,
+            _read: bool,
+            _write: bool,
+            _flags: FdFlags,
+        ) -> Result<Box<dyn WasiFile>, Error> {
+            Err(Error::not_supported())
+        }
+    }
+
+    #[tokio::test]
+    async fn journal() {
+        let inode = Ledger::new().create_device().create_inode();
+        let root: Arc<dyn Node> = Arc::new(Root(Link::with_id(
+            Weak::<Root>::new() as Weak<dyn Node>,
+            inode,
+        )));
+        let journal = Journal::new(root);
+
+        // Guests may only append.
+        let open = |read, flags| journal.clone().open_file("", false, read, true, flags);
+        assert!(open(true, FdFlags::empty()).await.is_err());
+        let mut file = open(false, FdFlags::APPEND).await.unwrap();
+        assert!(file.seek(std::io::SeekFrom::Start(0)).await.is_err());
+        assert!(file.set_filestat_size(0).await.is_err());
+        assert!(file
+            .write_vectored_at(&[IoSlice::new(b"x")], 0)
+            .await
+            .is_err());
+
+        for record in [
+            &[IoSlice::new(b"a"), IoSlice::new(b"b")][..],
+            &[IoSlice::new(b"cd")],
+        ] {
+            file.write_vectored(record).await.unwrap();
+        }
+        assert_eq!(file.get_filestat().await.unwrap().size, 4);
+
+        let records = journal.records().await;
+        assert_eq!(records, [(0, b"ab".to_vec()), (1, b"cd".to_vec())]);
+
+        // A checkpoint exports and drops the records before it.
+        let framed = journal.checkpoint(1).await;
+        assert_eq!(framed, b"\0\0\0\0\0\0\0\0\0\0\0\x02ab");
+        assert_eq!(journal.records().await, [(1, b"cd".to_vec())]);
+        assert!(journal.checkpoint(1).await.is_empty());
+        assert_eq!(file.get_filestat().await.unwrap().size, 2);
+
+        // Records kept count towards the quota until a checkpoint drops them.
+        let device = journal.id().device();
+        assert_eq!(device.bytes(), 2);
+        device.set_max_bytes(3);
+        let e = file.write_vectored(&[IoSlice::new(b"ef")]).await;
+        let e = e.unwrap_err().downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::StorageFull);
+        assert_eq!(journal.records().await, [(1, b"cd".to_vec())]);
+        journal.checkpoint(2).await;
+        assert_eq!(device.bytes(), 0);
+        file.write_vectored(&[IoSlice::new(b"ef")]).await.unwrap();
+        assert_eq!(device.bytes(), 2);
+    }
+}
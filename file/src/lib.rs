@@ -1,27 +1,44 @@
 use std::any::Any;
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::io::{IoSlice, IoSliceMut, SeekFrom};
-use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
 
 use wasi_common::file::{Advice, FdFlags, FileType, Filestat};
 use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
-use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node, Open, State};
-
-pub struct File(Link<Vec<u8>>);
+use wasmtime_vfs_ledger::{DeviceId, InodeId};
+use wasmtime_vfs_memory::{interruptible, ErrnoExt, Event, Link, Node, Open, Permissions, Watch};
+
+use digest::Chunks;
+use lock::Locks;
+
+pub use digest::CHUNK_SIZE;
+pub use journal::Journal;
+pub use lock::{LockKind, Owner};
+pub use sparse::{Reader, Slice, Sparse, BLOCK_SIZE};
+
+mod digest;
+mod journal;
+mod lock;
+mod sparse;
+
+pub struct File {
+    link: Link<Sparse>,
+    chunks: Mutex<Option<Chunks>>,
+    locks: Locks,
+}
 
 impl Deref for File {
-    type Target = Link<Vec<u8>>;
+    type Target = Link<Sparse>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.link
     }
 }
 
 impl DerefMut for File {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.link
     }
 }
 
@@ -32,7 +49,11 @@ impl Node for File {
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.parent.upgrade()
+        self.link.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.link.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -40,7 +61,87 @@ impl Node for File {
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.inode.id.clone()
+        self.inode().id()
+    }
+
+    fn watch(&self) -> Option<Watch> {
+        Some(self.inode().watchers().subscribe())
+    }
+
+    async fn permissions(&self) -> Option<Permissions> {
+        Some(self.inode().data().read().await.permissions())
+    }
+
+    async fn set_permissions(&self, permissions: Permissions) -> Result<(), Error> {
+        self.inode()
+            .data()
+            .write()
+            .await
+            .set_permissions(permissions);
+        Ok(())
+    }
+
+    async fn xattr(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.inode().data().read().await.xattr(name)
+    }
+
+    async fn xattrs(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .inode()
+            .data()
+            .read()
+            .await
+            .xattrs()
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    async fn set_xattr(&self, name: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inode().data().write().await.set_xattr(name, value)
+    }
+
+    async fn remove_xattr(&self, name: &str) -> Result<(), Error> {
+        self.inode().data().write().await.remove_xattr(name)
+    }
+
+    async fn snapshot(&self, parent: Arc<dyn Node>) -> Option<Arc<dyn Node>> {
+        let ilock = self.inode().data().read().await;
+        let file = Arc::new(Self {
+            link: Link::new(&parent, ilock.content().clone()),
+            chunks: Mutex::default(),
+            locks: Locks::default(),
+        });
+        file.inode()
+            .id()
+            .device()
+            .charge(ilock.content().len() as u64);
+
+        file.inode().data().write().await.copy_metadata(&ilock);
+        Some(file)
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let ilock = self.inode().read().await?;
+
+        Ok(Filestat {
+            device_id: **self.inode().id().device(),
+            inode: **self.inode().id(),
+            filetype: FileType::RegularFile,
+            nlink: self.inode().id().links(),
+            size: ilock.content().len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn set_times(
+        self: Arc<Self>,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.inode().write().await?.set_times(atime, mtime)
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -59,13 +160,10 @@ impl Node for File {
             return Err(Error::not_dir());
         }
 
-        Ok(Box::new(OpenFile(Open {
-            root: self.root(),
-            link: self,
-            state: State::from(flags).into(),
-            write,
-            read,
-        })))
+        Ok(Box::new(OpenFile {
+            open: Open::new(self, read, write, flags),
+            owner: Owner::new(),
+        }))
     }
 }
 
@@ -75,28 +173,218 @@ impl File {
         Self::with_data(parent, [])
     }
 
+    /// Creates a file holding `data`.
+    ///
+    /// The data counts towards the byte quota of the device, even past it.
+    /// Changes made directly to the inode content are not counted.
     pub fn with_data(parent: Arc<dyn Node>, data: impl Into<Vec<u8>>) -> Arc<dyn Node> {
-        let id = parent.id().device().create_inode();
+        let data = data.into();
+        parent.id().device().charge(data.len() as u64);
+        Arc::new(Self {
+            link: Link::new(&parent, data.into()),
+            chunks: Mutex::default(),
+            locks: Locks::default(),
+        })
+    }
 
-        let inode = Inode {
-            data: Data::from(data.into()).into(),
-            id,
-        };
+    /// Takes an advisory lock on `range` for `owner`, failing with `EAGAIN`
+    /// if another owner holds a conflicting lock.
+    ///
+    /// Locks are advisory: they only exclude other lockers, never reads or
+    /// writes. A range ending at `u64::MAX` extends past the end of the file.
+    pub fn try_lock(&self, owner: Owner, range: Range<u64>, kind: LockKind) -> Result<(), Error> {
+        match self.locks.try_lock(owner, range, kind) {
+            true => Ok(()),
+            false => Err(Error::would_block()),
+        }
+    }
 
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+    /// Takes an advisory lock on `range` for `owner`, waiting for any
+    /// conflicting lock to be released.
+    pub async fn lock(&self, owner: Owner, range: Range<u64>, kind: LockKind) {
+        self.locks.lock(owner, range, kind).await
+    }
+
+    /// Releases the advisory locks of `owner` on `range`.
+    pub fn unlock(&self, owner: Owner, range: Range<u64>) {
+        self.locks.unlock(owner, range)
+    }
+
+    /// Returns the SHA-256 of the hashes of each [`CHUNK_SIZE`] chunk of content.
+    ///
+    /// The first call hashes the whole file. After that, writes through open
+    /// handles mark the chunks they touch as stale and only those are hashed
+    /// again. Changes made directly to the inode content are not seen; call
+    /// [`File::reset_digest`] after making them.
+    pub async fn digest(&self) -> [u8; 32] {
+        let ilock = self.inode().data().read().await;
+        let mut chunks = self.chunks.lock().unwrap_or_else(PoisonError::into_inner);
+        chunks
+            .get_or_insert_with(Chunks::default)
+            .digest(ilock.content())
+    }
+
+    /// Discards all chunk hashes, so the next digest hashes the whole file.
+    pub fn reset_digest(&self) {
+        *self.chunks.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    // Marks the content in `start..end` as changed, telling any watches.
+    fn changed(&self, start: usize, end: usize) {
+        let mut chunks = self.chunks.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(chunks) = chunks.as_mut() {
+            chunks.invalidate(start, end);
+        }
+        drop(chunks);
+
+        self.inode().watchers().send(Event::Write);
     }
 }
 
-struct OpenFile(Open<File>);
+// The content of a file counts towards the byte quota of its device for as
+// long as the file lives.
+impl Drop for File {
+    fn drop(&mut self) {
+        if let Ok(ilock) = self.inode().data().try_read() {
+            self.inode()
+                .id()
+                .device()
+                .release(ilock.content().len() as u64);
+        }
+    }
+}
+
+// Copies as much of `buf` as fits below `max` into `data` at `pos`,
+// leaving a hole in any gap past the end, and charges the growth to the
+// quota of `device`. Fails only if nothing fits.
+fn copy_in(
+    device: &DeviceId,
+    data: &mut Sparse,
+    pos: usize,
+    buf: &[u8],
+    max: u64,
+) -> Result<usize, Error> {
+    let room = max.saturating_sub(pos as u64);
+    let len = min(buf.len() as u64, room) as usize;
+    if len == 0 && !buf.is_empty() {
+        return Err(Error::file_too_big());
+    }
+
+    let end = pos.checked_add(len).ok_or_else(Error::invalid_argument)?;
+    if !device.reserve(end.saturating_sub(data.len()) as u64) {
+        return Err(Error::no_space());
+    }
+
+    data.write(pos, &buf[..len]);
+    Ok(len)
+}
+
+// Resizes `data` to `len`, charging growth to the quota of `device` and
+// releasing what shrinks.
+fn resize(device: &DeviceId, data: &mut Sparse, len: usize) -> Result<(), Error> {
+    let old = data.len();
+    if len > old && !device.reserve((len - old) as u64) {
+        return Err(Error::no_space());
+    }
+
+    device.release(old.saturating_sub(len) as u64);
+    data.resize(len);
+    Ok(())
+}
+
+/// An open handle to a [`File`]
+///
+/// Each handle owns its advisory locks, like an open file description on
+/// Linux, and releases them when closed. The host reaches the handle of a
+/// guest through [`WasiFile::as_any`].
+pub struct OpenFile {
+    open: Open<File>,
+    owner: Owner,
+}
 
 impl Deref for OpenFile {
     type Target = Open<File>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.open
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        self.link().unlock(self.owner, 0..u64::MAX);
+    }
+}
+
+impl OpenFile {
+    // The maximum file size allowed by the device.
+    fn max_size(&self) -> u64 {
+        self.link().inode().id().device().max_file_size()
+    }
+
+    // As with `fcntl`, shared locks need reading and exclusive ones writing.
+    fn check_lock(&self, kind: LockKind) -> Result<(), Error> {
+        match kind {
+            LockKind::Shared if !self.can_read() => Err(Error::badf()),
+            LockKind::Exclusive if !self.can_write() => Err(Error::badf()),
+            _ => Ok(()),
+        }
+    }
+
+    // Copies from the content at `pos` into `bufs`. Only taking the slice
+    // holds the inode lock, so the copy does not stall writers. Positions
+    // past the end read nothing, since another handle may have truncated
+    // the file under our offset.
+    async fn read_at(&self, pos: usize, bufs: &mut [IoSliceMut<'_>]) -> Result<usize, Error> {
+        let len = bufs.iter().map(|b| b.len()).sum();
+        let slice = self.link().inode().read().await?.content().slice(pos, len);
+        Ok(slice.copy_to(bufs))
+    }
+
+    /// The owner of the locks taken through this handle.
+    pub fn owner(&self) -> Owner {
+        self.owner
+    }
+
+    /// Takes an advisory lock on `range`, failing with `EAGAIN` if it
+    /// conflicts with a lock held elsewhere.
+    pub fn try_lock(&self, range: Range<u64>, kind: LockKind) -> Result<(), Error> {
+        self.check_lock(kind)?;
+        self.link().try_lock(self.owner, range, kind)
+    }
+
+    /// Takes an advisory lock on `range`, waiting out any conflict unless IO
+    /// on the device is cancelled.
+    pub async fn lock(&self, range: Range<u64>, kind: LockKind) -> Result<(), Error> {
+        self.check_lock(kind)?;
+        let device = self.link().inode().id().device();
+        interruptible(&device, self.link().lock(self.owner, range, kind)).await
+    }
+
+    /// Releases the advisory locks of this handle on `range`.
+    pub fn unlock(&self, range: Range<u64>) {
+        self.link().unlock(self.owner, range)
+    }
+
+    /// Locks the whole file, as `flock` does, waiting out any conflict
+    /// unless IO on the device is cancelled.
+    ///
+    /// Unlike byte-range locks, either kind may be taken through any handle.
+    pub async fn flock(&self, kind: LockKind) -> Result<(), Error> {
+        let device = self.link().inode().id().device();
+        let lock = self.link().lock(self.owner, 0..u64::MAX, kind);
+        interruptible(&device, lock).await
+    }
+
+    /// Locks the whole file, as `flock` with `LOCK_NB` does, failing with
+    /// `EAGAIN` if another owner holds a conflicting lock.
+    pub fn try_flock(&self, kind: LockKind) -> Result<(), Error> {
+        self.link().try_lock(self.owner, 0..u64::MAX, kind)
+    }
+
+    /// Releases the whole-file lock of this handle, as `LOCK_UN` does.
+    pub fn funlock(&self) {
+        self.unlock(0..u64::MAX)
     }
 }
 
@@ -119,58 +407,81 @@ impl WasiFile for OpenFile {
     }
 
     async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
-        Ok(self.state.read().await.flags)
+        Ok(self.state().read().await.flags())
     }
 
     async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::badf());
         }
 
-        self.state.write().await.flags = flags;
+        self.state().write().await.set_flags(flags);
         Ok(())
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.inode.data.read().await;
-
-        Ok(Filestat {
-            device_id: **self.link.inode.id.device(),
-            inode: **self.link.inode.id,
-            filetype: FileType::RegularFile,
-            nlink: Arc::strong_count(&self.link.inode) as u64,
-            size: ilock.content.len() as u64,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
-        })
+        self.link().clone().filestat().await
     }
 
     async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
         let size: usize = size.try_into().map_err(|_| Error::invalid_argument())?;
 
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::badf());
+        }
+
+        if size as u64 > self.max_size() {
+            return Err(Error::file_too_big());
         }
 
-        self.link.inode.data.write().await.content.resize(size, 0);
+        let device = self.link().inode().id().device();
+        let mut ilock = self.link().inode().write().await?;
+        let old = ilock.content().len();
+        resize(&device, ilock.content_mut(), size)?;
+        self.link().changed(min(old, size), max(old, size));
         Ok(())
     }
 
-    async fn advise(&mut self, _offset: u64, _len: u64, _advice: Advice) -> Result<(), Error> {
+    // Dropping data which is not needed only releases the blocks of zeros.
+    async fn advise(&mut self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        if let Advice::DontNeed = advice {
+            let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+            let len = usize::try_from(len).unwrap_or(usize::MAX);
+
+            // A length of zero means up to the end of the file.
+            let mut ilock = self.link().inode().write().await?;
+            let end = match len {
+                0 => ilock.content().len(),
+                len => offset.saturating_add(len),
+            };
+            ilock.content_mut().compact(offset, end);
+        }
+
         Ok(())
     }
 
+    // Growing the file leaves a hole, so no blocks need to be stored.
     async fn allocate(&mut self, offset: u64, len: u64) -> Result<(), Error> {
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::badf());
         }
 
         let offset: usize = offset.try_into().map_err(|_| Error::invalid_argument())?;
         let len: usize = len.try_into().map_err(|_| Error::invalid_argument())?;
-        offset
+        let end = offset
             .checked_add(len)
             .ok_or_else(Error::invalid_argument)?;
+
+        if end as u64 > self.max_size() {
+            return Err(Error::file_too_big());
+        }
+
+        let device = self.link().inode().id().device();
+        let mut ilock = self.link().inode().write().await?;
+        if end > ilock.content().len() {
+            resize(&device, ilock.content_mut(), end)?;
+        }
+
         Ok(())
     }
 
@@ -179,30 +490,23 @@ impl WasiFile for OpenFile {
         atime: Option<SystemTimeSpec>,
         mtime: Option<SystemTimeSpec>,
     ) -> Result<(), Error> {
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::access());
         }
 
-        self.link.inode.data.write().await.set_times(atime, mtime)
+        self.link().clone().set_times(atime, mtime).await
     }
 
     async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
-        if !self.read {
-            return Err(Error::io()); // FIXME: errorno
-        }
-
-        let mut total = 0;
-
-        let mut olock = self.state.write().await;
-        let ilock = self.link.inode.data.read().await;
-        for buf in bufs {
-            let len = min(buf.len(), ilock.content.len() - olock.pos);
-            buf[..len].copy_from_slice(&ilock.content[olock.pos..][..len]);
-            total += len as u64;
-            olock.pos += len;
+        if !self.can_read() {
+            return Err(Error::badf());
         }
 
-        Ok(total)
+        let mut olock = self.state().write().await;
+        let len = self.read_at(olock.pos(), bufs).await?;
+        let pos = olock.pos() + len;
+        olock.set_pos(pos);
+        Ok(len as u64)
     }
 
     async fn read_vectored_at<'a>(
@@ -210,91 +514,96 @@ impl WasiFile for OpenFile {
         bufs: &mut [IoSliceMut<'a>],
         offset: u64,
     ) -> Result<u64, Error> {
-        if !self.read {
-            return Err(Error::io()); // FIXME: errorno
-        }
-
-        let mut position: usize = offset.try_into().map_err(|_| Error::invalid_argument())?;
-        let mut total = 0;
-
-        let data = &self.link.inode.data.read().await.content[..];
-        for buf in bufs {
-            let len = min(buf.len(), data.len() - position);
-            buf[..len].copy_from_slice(&data[position..][..len]);
-            total += len as u64;
-            position += len;
+        if !self.can_read() {
+            return Err(Error::badf());
         }
 
-        Ok(total)
+        let pos = offset.try_into().map_err(|_| Error::invalid_argument())?;
+        Ok(self.read_at(pos, bufs).await? as u64)
     }
 
     async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::badf());
         }
 
+        let max = self.max_size();
+        let device = self.link().inode().id().device();
         let mut total = 0;
 
-        let mut olock = self.state.write().await;
-        let mut ilock = self.link.inode.data.write().await;
+        let mut olock = self.state().write().await;
+        let mut ilock = self.link().inode().write().await?;
         for buf in bufs {
-            let pos = match olock.flags.contains(FdFlags::APPEND) {
-                true => ilock.content.len(),
-                false => olock.pos,
+            let pos = match olock.flags().contains(FdFlags::APPEND) {
+                true => ilock.content().len(),
+                false => olock.pos(),
             };
 
-            if pos + buf.len() > ilock.content.len() {
-                ilock.content.resize(pos + buf.len(), 0);
-            }
-
-            ilock.content[pos..][..buf.len()].copy_from_slice(buf);
-            total += buf.len() as u64;
+            // Report a short write if some bytes made it in before a failure.
+            let old = ilock.content().len();
+            let len = match copy_in(&device, ilock.content_mut(), pos, buf, max) {
+                Ok(len) => len,
+                Err(..) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+            self.link().changed(min(old, pos), pos + len);
+            total += len as u64;
+            olock.set_pos(pos + len);
 
-            if !olock.flags.contains(FdFlags::APPEND) {
-                olock.pos += buf.len();
+            if len < buf.len() {
+                break;
             }
         }
 
         Ok(total)
     }
 
-    // FIXME: we need to decide on a behavior for O_APPEND. WASI doesn't
-    // specify a behavior. POSIX defines one behavior. Linux has a different
-    // one. See: https://linux.die.net/man/2/pwrite
+    // As POSIX specifies, positional writes ignore `FdFlags::APPEND`: they
+    // write at `offset` and leave the position untouched. Linux instead
+    // appends; see https://linux.die.net/man/2/pwrite. Sequential writes
+    // with the flag set always append and leave the position at the end.
     async fn write_vectored_at<'a>(
         &mut self,
         bufs: &[IoSlice<'a>],
         offset: u64,
     ) -> Result<u64, Error> {
-        if !self.write {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_write() {
+            return Err(Error::badf());
         }
 
         let mut pos: usize = offset.try_into().map_err(|_| Error::invalid_argument())?;
+        let max = self.max_size();
+        let device = self.link().inode().id().device();
         let mut total = 0;
 
-        let mut ilock = self.link.inode.data.write().await;
+        let mut ilock = self.link().inode().write().await?;
         for buf in bufs {
-            if pos + buf.len() > ilock.content.len() {
-                ilock.content.resize(pos + buf.len(), 0);
-            }
+            let old = ilock.content().len();
+            let len = match copy_in(&device, ilock.content_mut(), pos, buf, max) {
+                Ok(len) => len,
+                Err(..) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+            self.link().changed(min(old, pos), pos + len);
+            total += len as u64;
+            pos += len;
 
-            ilock.content[pos..][..buf.len()].copy_from_slice(buf);
-            total += buf.len() as u64;
-            pos += buf.len();
+            if len < buf.len() {
+                break;
+            }
         }
 
         Ok(total)
     }
 
     async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
-        let mut olock = self.state.write().await;
-        let ilock = self.link.inode.data.read().await;
+        let mut olock = self.state().write().await;
+        let ilock = self.link().inode().read().await?;
 
         let cur = match pos {
-            SeekFrom::Current(_) => i64::try_from(olock.pos),
+            SeekFrom::Current(_) => i64::try_from(olock.pos()),
             SeekFrom::Start(_) => Ok(0),
-            SeekFrom::End(_) => i64::try_from(ilock.content.len()),
+            SeekFrom::End(_) => i64::try_from(ilock.content().len()),
         }
         .map_err(|e| Error::invalid_argument().context(e))?;
 
@@ -305,37 +614,31 @@ impl WasiFile for OpenFile {
         }
         .map_err(|e| Error::invalid_argument().context(e))?;
 
-        let pos = usize::try_from(cur + off).map_err(|e| Error::invalid_argument().context(e))?;
-        olock.pos = pos;
+        let pos = cur.checked_add(off).ok_or_else(Error::invalid_argument)?;
+        let pos = usize::try_from(pos).map_err(|e| Error::invalid_argument().context(e))?;
+        olock.set_pos(pos);
 
         Ok(pos as u64)
     }
 
     async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
-        if !self.read {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_read() {
+            return Err(Error::badf());
         }
 
-        let mut total = 0;
-
-        let olock = self.state.read().await;
-        let ilock = self.link.inode.data.read().await;
-        let len = min(buf.len(), ilock.content.len() - olock.pos);
-        buf[..len].copy_from_slice(&ilock.content[olock.pos..][..len]);
-        total += len as u64;
-
-        Ok(total)
+        let pos = self.state().read().await.pos();
+        Ok(self.read_at(pos, &mut [IoSliceMut::new(buf)]).await? as u64)
     }
 
     async fn num_ready_bytes(&self) -> Result<u64, Error> {
-        if !self.read {
-            return Err(Error::io()); // FIXME: errorno
+        if !self.can_read() {
+            return Err(Error::badf());
         }
 
-        let olock = self.state.read().await;
-        let ilock = self.link.inode.data.read().await;
-        let len = min(ilock.content.len(), olock.pos);
-        let len = ilock.content.len() - len;
+        let olock = self.state().read().await;
+        let ilock = self.link().inode().read().await?;
+        let len = min(ilock.content().len(), olock.pos());
+        let len = ilock.content().len() - len;
         Ok(len as u64)
     }
 
@@ -0,0 +1,159 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use tokio::sync::Notify;
+
+/// The kind of an advisory lock on a byte range
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    /// Any number of owners may hold shared locks on the same bytes.
+    Shared,
+
+    /// No other owner may hold any lock on the same bytes.
+    Exclusive,
+}
+
+/// The holder of advisory locks
+///
+/// Each open handle is its own owner. The host can create owners of its own
+/// to lock a file alongside the guest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Owner(u64);
+
+impl Owner {
+    /// Creates an owner distinct from all others.
+    pub fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for Owner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Held {
+    owner: Owner,
+    range: Range<u64>,
+    kind: LockKind,
+}
+
+/// The advisory locks held on a file
+///
+/// As with `fcntl` locks, an owner locking bytes it already holds replaces
+/// its lock on them, and unlocking part of a range splits it.
+#[derive(Default)]
+pub(crate) struct Locks {
+    held: Mutex<Vec<Held>>,
+    notify: Notify,
+}
+
+fn overlaps(lhs: &Range<u64>, rhs: &Range<u64>) -> bool {
+    lhs.start < rhs.end && rhs.start < lhs.end
+}
+
+impl Locks {
+    /// Takes the lock if no other owner holds a conflicting one.
+    pub fn try_lock(&self, owner: Owner, range: Range<u64>, kind: LockKind) -> bool {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let conflict = held.iter().any(|h| {
+            h.owner != owner
+                && overlaps(&h.range, &range)
+                && (h.kind == LockKind::Exclusive || kind == LockKind::Exclusive)
+        });
+        if conflict {
+            return false;
+        }
+
+        Self::remove(&mut held, owner, &range);
+        held.push(Held { owner, range, kind });
+
+        // Downgrading an exclusive lock may let shared waiters in.
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Waits until no other owner holds a conflicting lock, then takes it.
+    pub async fn lock(&self, owner: Owner, range: Range<u64>, kind: LockKind) {
+        loop {
+            // Registered before trying, so a release in between is not lost.
+            let notified = self.notify.notified();
+            if self.try_lock(owner, range.clone(), kind) {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Releases the locks of `owner` on `range`.
+    pub fn unlock(&self, owner: Owner, range: Range<u64>) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        Self::remove(&mut held, owner, &range);
+        self.notify.notify_waiters();
+    }
+
+    // Cuts `range` out of the locks of `owner`, keeping what is left.
+    fn remove(held: &mut Vec<Held>, owner: Owner, range: &Range<u64>) {
+        let mut kept = Vec::new();
+
+        held.retain(|h| {
+            if h.owner != owner || !overlaps(&h.range, range) {
+                return true;
+            }
+
+            if h.range.start < range.start {
+                let range = h.range.start..range.start;
+                kept.push(Held { range, ..*h });
+            }
+
+            if range.end < h.range.end {
+                let range = range.end..h.range.end;
+                kept.push(Held { range, ..*h });
+            }
+
+            false
+        });
+
+        held.extend(kept);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn ranges() {
+        let locks = Locks::default();
+        let (a, b) = (Owner::new(), Owner::new());
+
+        // Shared locks overlap, but exclusive ones conflict with anything.
+        assert!(locks.try_lock(a, 0..10, LockKind::Shared));
+        assert!(locks.try_lock(b, 5..15, LockKind::Shared));
+        assert!(!locks.try_lock(b, 0..1, LockKind::Exclusive));
+        assert!(locks.try_lock(b, 10..15, LockKind::Exclusive));
+
+        // Unlocking the middle of a range keeps both ends.
+        locks.unlock(a, 2..8);
+        assert!(locks.try_lock(b, 2..8, LockKind::Exclusive));
+        assert!(!locks.try_lock(b, 1..3, LockKind::Exclusive));
+        assert!(!locks.try_lock(b, 7..9, LockKind::Exclusive));
+
+        // Relocking replaces the kind held.
+        assert!(locks.try_lock(b, 0..u64::MAX, LockKind::Shared));
+        assert!(locks.try_lock(a, 0..u64::MAX, LockKind::Shared));
+        assert!(!locks.try_lock(a, 20..21, LockKind::Exclusive));
+
+        // A waiter gets the lock once the conflict is released.
+        tokio::join!(locks.lock(a, 20..21, LockKind::Exclusive), async {
+            tokio::task::yield_now().await;
+            locks.unlock(b, 0..u64::MAX);
+        });
+        assert!(!locks.try_lock(b, 20..21, LockKind::Shared));
+    }
+}
@@ -0,0 +1,334 @@
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::IoSliceMut;
+use std::sync::Arc;
+
+/// The number of bytes in each block of a [`Sparse`] file
+pub const BLOCK_SIZE: usize = 4096;
+
+/// The content of a regular file, kept as fixed-size blocks
+///
+/// Only blocks which were written are stored. The others, the holes, read
+/// as zeros, so growing a file or writing far past its end costs nothing
+/// for the bytes in between. Clones share their blocks until either side
+/// writes to one.
+#[derive(Clone, Default)]
+pub struct Sparse {
+    len: usize,
+    blocks: BTreeMap<usize, Arc<[u8; BLOCK_SIZE]>>,
+}
+
+impl fmt::Debug for Sparse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sparse")
+            .field("len", &self.len)
+            .field("blocks", &self.blocks.len())
+            .finish()
+    }
+}
+
+impl Sparse {
+    /// The length of the content in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of blocks stored, which excludes the holes.
+    pub fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Sets the length, dropping or zeroing the bytes cut off.
+    pub fn resize(&mut self, len: usize) {
+        if len < self.len {
+            self.blocks.split_off(&len.div_ceil(BLOCK_SIZE));
+
+            // Zero the cut tail of the last block, in case the file grows again.
+            if let Some(block) = self.blocks.get_mut(&(len / BLOCK_SIZE)) {
+                Arc::make_mut(block)[len % BLOCK_SIZE..].fill(0);
+            }
+        }
+
+        self.len = len;
+    }
+
+    /// Copies the content at `pos` into `buf`, returning the bytes copied.
+    ///
+    /// Positions past the end read nothing.
+    pub fn read(&self, pos: usize, buf: &mut [u8]) -> usize {
+        let len = min(buf.len(), self.len.saturating_sub(pos));
+
+        let mut done = 0;
+        while done < len {
+            let at = pos + done;
+            let off = at % BLOCK_SIZE;
+            let n = min(len - done, BLOCK_SIZE - off);
+            let dst = &mut buf[done..][..n];
+
+            match self.blocks.get(&(at / BLOCK_SIZE)) {
+                Some(block) => dst.copy_from_slice(&block[off..][..n]),
+                None => dst.fill(0),
+            }
+
+            done += n;
+        }
+
+        len
+    }
+
+    /// Takes up to `len` bytes of the content at `pos`, sharing its blocks.
+    ///
+    /// Positions past the end take nothing.
+    pub fn slice(&self, pos: usize, len: usize) -> Slice {
+        let len = min(len, self.len.saturating_sub(pos));
+        let first = pos / BLOCK_SIZE;
+        let last = (pos + len).div_ceil(BLOCK_SIZE);
+        let blocks = self
+            .blocks
+            .range(first..last)
+            .map(|(i, block)| (*i, block.clone()))
+            .collect();
+
+        Slice {
+            pos,
+            data: Sparse {
+                len: pos + len,
+                blocks,
+            },
+        }
+    }
+
+    /// Copies `buf` into the content at `pos`, growing it as needed.
+    pub fn write(&mut self, pos: usize, buf: &[u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = pos + done;
+            let off = at % BLOCK_SIZE;
+            let n = min(buf.len() - done, BLOCK_SIZE - off);
+
+            let block = self
+                .blocks
+                .entry(at / BLOCK_SIZE)
+                .or_insert_with(|| Arc::new([0; BLOCK_SIZE]));
+            Arc::make_mut(block)[off..][..n].copy_from_slice(&buf[done..][..n]);
+
+            done += n;
+        }
+
+        self.len = self.len.max(pos + buf.len());
+    }
+
+    /// Drops the blocks overlapping `start..end` which hold only zeros.
+    pub fn compact(&mut self, start: usize, end: usize) {
+        let first = start / BLOCK_SIZE;
+        let last = end.div_ceil(BLOCK_SIZE);
+        if first >= last {
+            return;
+        }
+
+        let zeros: Vec<usize> = self
+            .blocks
+            .range(first..last)
+            .filter(|(_, block)| block.iter().all(|b| *b == 0))
+            .map(|(i, _)| *i)
+            .collect();
+
+        for i in zeros {
+            self.blocks.remove(&i);
+        }
+    }
+
+    /// Copies the whole content, holes included.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut out = vec![0; self.len];
+        self.read(0, &mut out);
+        out
+    }
+
+    /// Reads the whole content, holes included, from the start.
+    pub fn reader(&self) -> Reader<'_> {
+        Reader { data: self, pos: 0 }
+    }
+}
+
+impl From<&[u8]> for Sparse {
+    fn from(data: &[u8]) -> Self {
+        let mut sparse = Self::default();
+        sparse.write(0, data);
+        sparse
+    }
+}
+
+impl From<Vec<u8>> for Sparse {
+    fn from(data: Vec<u8>) -> Self {
+        data[..].into()
+    }
+}
+
+// Holes equal stored blocks of zeros.
+impl PartialEq for Sparse {
+    fn eq(&self, other: &Self) -> bool {
+        let zero = |block: &[u8; BLOCK_SIZE]| block.iter().all(|b| *b == 0);
+        let covered = |lhs: &Self, rhs: &Self| {
+            lhs.blocks.iter().all(|(i, block)| match rhs.blocks.get(i) {
+                Some(other) => block == other,
+                None => zero(block),
+            })
+        };
+
+        self.len == other.len && covered(self, other) && covered(other, self)
+    }
+}
+
+impl Eq for Sparse {}
+
+impl PartialEq<[u8]> for Sparse {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.len == other.len() && self.to_vec() == other
+    }
+}
+
+impl PartialEq<&[u8]> for Sparse {
+    fn eq(&self, other: &&[u8]) -> bool {
+        *self == **other
+    }
+}
+
+impl<const N: usize> PartialEq<&[u8; N]> for Sparse {
+    fn eq(&self, other: &&[u8; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+impl PartialEq<Vec<u8>> for Sparse {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        *self == other[..]
+    }
+}
+
+/// A range of the content of a [`Sparse`] file
+///
+/// Taking a slice only clones the handles of its blocks, so the bytes can
+/// be copied out once the file is unlocked. Writes to the file meanwhile
+/// copy the blocks they change, leaving the slice as it was taken.
+#[derive(Clone, Debug)]
+pub struct Slice {
+    pos: usize,
+    data: Sparse,
+}
+
+impl Slice {
+    /// The length of the slice in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len - self.pos
+    }
+
+    /// Whether the slice is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the slice into `bufs` in order, returning the bytes copied.
+    pub fn copy_to(&self, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        let mut pos = self.pos;
+        for buf in bufs {
+            pos += self.data.read(pos, buf);
+        }
+
+        pos - self.pos
+    }
+}
+
+/// A reader over the content of a [`Sparse`] file
+pub struct Reader<'a> {
+    data: &'a Sparse,
+    pos: usize,
+}
+
+impl std::io::Read for Reader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.data.read(self.pos, buf);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn holes() {
+        let mut data = Sparse::default();
+
+        // Writing far past the end stores only the block written.
+        data.write(1 << 40, b"abc");
+        assert_eq!(data.len(), (1 << 40) + 3);
+        assert_eq!(data.blocks(), 1);
+
+        let mut buf = [1u8; 8];
+        assert_eq!(data.read((1 << 40) - 5, &mut buf), 8);
+        assert_eq!(&buf, b"\0\0\0\0\0abc");
+        assert_eq!(data.read(data.len(), &mut buf), 0);
+
+        // Shrinking zeroes the cut bytes, which then read as zeros again.
+        data.resize((1 << 40) + 1);
+        data.resize((1 << 40) + 3);
+        assert_eq!(data.read(1 << 40, &mut buf), 3);
+        assert_eq!(&buf[..3], b"a\0\0");
+
+        // Blocks holding only zeros can be dropped.
+        data.write(1 << 40, b"\0");
+        data.compact(0, data.len());
+        assert_eq!(data.blocks(), 0);
+        assert_eq!(data.len(), (1 << 40) + 3);
+
+        // Spanning writes fill each block they touch.
+        let mut data = Sparse::from(vec![7u8; BLOCK_SIZE * 2 + 1]);
+        assert_eq!(data.blocks(), 3);
+        data.resize(BLOCK_SIZE);
+        assert_eq!(data.blocks(), 1);
+        assert_eq!(data, vec![7u8; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn clones() {
+        let data = Sparse::from(vec![7u8; BLOCK_SIZE * 2]);
+        let mut copy = data.clone();
+        copy.write(0, b"abc");
+
+        // Only the block written is copied.
+        assert!(!Arc::ptr_eq(&data.blocks[&0], &copy.blocks[&0]));
+        assert!(Arc::ptr_eq(&data.blocks[&1], &copy.blocks[&1]));
+        assert_eq!(data, vec![7u8; BLOCK_SIZE * 2]);
+        assert_eq!(copy.to_vec()[..4], *b"abc\x07");
+    }
+
+    #[test]
+    fn slices() {
+        let mut data = Sparse::default();
+        data.write(BLOCK_SIZE - 2, b"abcd");
+        data.resize(BLOCK_SIZE * 4);
+
+        // Slices keep the blocks they overlap, and nothing past the end.
+        let slice = data.slice(BLOCK_SIZE - 4, 8);
+        assert_eq!(slice.len(), 8);
+        assert_eq!(slice.data.blocks(), 2);
+        assert!(data.slice(data.len(), 8).is_empty());
+        assert_eq!(data.slice(data.len() - 3, 8).len(), 3);
+
+        // Later writes leave the slice as it was taken.
+        data.write(BLOCK_SIZE - 4, b"xy");
+        let (mut a, mut b) = ([1u8; 3], [1u8; 8]);
+        let n = slice.copy_to(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)]);
+        assert_eq!(n, 8);
+        assert_eq!(&a, b"\0\0a");
+        assert_eq!(&b, b"bcd\0\0\x01\x01\x01");
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use cap_fs_ext::{DirExt, SystemTimeSpec};
+use wasi_common::Error;
+use wasmtime_vfs_dir::{walk_with, Directory, Links};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_memory::Node;
+
+fn spec(time: SystemTime) -> Option<SystemTimeSpec> {
+    Some(SystemTimeSpec::Absolute(
+        cap_std::time::SystemTime::from_std(time),
+    ))
+}
+
+/// Writes the tree rooted at `node` out to the host directory `host`.
+///
+/// Directories and regular files are written with their content and access
+/// and modification times, and symbolic links as host links, replacing any
+/// host files in the way. Other kinds of node, such as sockets and keys, are
+/// left out, as is the root itself, whose children land directly in `host`.
+/// Entries whose [`walk`] path fails `filter` are left out along with
+/// everything beneath them.
+///
+/// [`walk`]: wasmtime_vfs_dir::walk
+pub async fn export_to_host(
+    node: Arc<dyn Node>,
+    host: &cap_std::fs::Dir,
+    filter: impl Fn(&str) -> bool,
+) -> Result<(), Error> {
+    export_to_host_with(node, host, filter, Links::Preserve).await
+}
+
+/// Writes the tree rooted at `node` out like [`export_to_host`], treating
+/// symbolic links as `links` says.
+pub async fn export_to_host_with(
+    node: Arc<dyn Node>,
+    host: &cap_std::fs::Dir,
+    filter: impl Fn(&str) -> bool,
+    links: Links,
+) -> Result<(), Error> {
+    let mut pruned: Vec<String> = Vec::new();
+    let mut dirs = Vec::new();
+
+    for entry in walk_with(node, links).await?.into_iter().skip(1) {
+        if pruned
+            .iter()
+            .any(|p| entry.path.starts_with(&format!("{p}/")))
+        {
+            continue;
+        }
+
+        if !filter(&entry.path) {
+            pruned.push(entry.path);
+            continue;
+        }
+
+        let path = Path::new(entry.path.trim_start_matches('/'));
+        if let Some(target) = entry.node.read_link() {
+            match host.symlink_metadata(path) {
+                Ok(meta) if meta.is_dir() => host.remove_dir_all(path)?,
+                Ok(_) => host.remove_file(path)?,
+                Err(_) => (),
+            }
+
+            DirExt::symlink(host, target, path)?;
+            continue;
+        }
+
+        let node = entry.node.to_any();
+        let node = match node.downcast::<Directory>() {
+            Ok(dir) => {
+                if !host.is_dir(path) {
+                    host.create_dir(path)?;
+                }
+
+                // Writing the children changes the times, so set them last.
+                let ilock = dir.inode().data().read().await;
+                dirs.push((path.to_owned(), ilock.access_time(), ilock.modify_time()));
+                continue;
+            }
+            Err(node) => node,
+        };
+
+        if let Ok(file) = node.downcast::<File>() {
+            let ilock = file.inode().data().read().await;
+            host.write(path, ilock.content().to_vec())?;
+            host.set_times(path, spec(ilock.access_time()), spec(ilock.modify_time()))?;
+        }
+    }
+
+    for (path, access, modify) in dirs.into_iter().rev() {
+        host.set_times(path, spec(access), spec(modify))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use cap_std::ambient_authority;
+    use wasi_common::file::FdFlags;
+    use wasi_common::SystemTimeSpec;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn export() {
+        let root = Directory::root(Ledger::new(), None);
+        let sub = Directory::new(root.clone(), None);
+        root.attach("sub", sub.clone()).await.unwrap();
+        sub.attach("file", File::with_data(sub.clone(), "abc"))
+            .await
+            .unwrap();
+        root.attach("skip", Directory::new(root.clone(), None))
+            .await
+            .unwrap();
+        root.attach("skip/file", File::with_data(root.clone(), "x"))
+            .await
+            .unwrap();
+
+        let then = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let file = sub.get("file").await.unwrap();
+        let time = || {
+            Some(SystemTimeSpec::Absolute(
+                cap_std::time::SystemTime::from_std(then),
+            ))
+        };
+        let mut open = file
+            .open_file("", false, false, true, FdFlags::empty())
+            .await;
+        open.as_mut()
+            .unwrap()
+            .set_times(time(), time())
+            .await
+            .unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let host = cap_std::fs::Dir::open_ambient_dir(tmp.path(), ambient_authority()).unwrap();
+        export_to_host(root, &host, |path| path != "/skip")
+            .await
+            .unwrap();
+
+        let path = tmp.path().join("sub/file");
+        assert_eq!(std::fs::read(&path).unwrap(), b"abc");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(modified, then);
+        assert!(!tmp.path().join("skip").exists());
+    }
+}
@@ -0,0 +1,389 @@
+use std::any::Any;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+
+use wasi_cap_std_sync::dir::Dir;
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{FdFlags, FileType, Filestat, OFlags};
+use wasi_common::{Error, SystemTimeSpec, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Node, Open, Parent};
+
+pub use cap_std;
+pub use export::{export_to_host, export_to_host_with};
+
+mod export;
+
+/// A host directory grafted into a virtual tree
+///
+/// The directory is its own device. Everything beneath it is passed through
+/// to the host by `cap-std`, so paths below it cannot escape it. Only its
+/// own `..` leads back into the virtual tree; from its subdirectories, `..`
+/// stops at the host directory.
+pub struct HostDir {
+    parent: Parent,
+    id: Arc<InodeId>,
+    dir: cap_std::fs::Dir,
+}
+
+impl HostDir {
+    pub fn new(parent: Arc<dyn Node>, dir: cap_std::fs::Dir) -> Arc<Self> {
+        let id = parent.id().device().ledger().create_device().create_inode();
+        Arc::new(Self {
+            parent: Arc::downgrade(&parent).into(),
+            id,
+            dir,
+        })
+    }
+
+    fn open(
+        self: Arc<Self>,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<OpenHostDir, Error> {
+        let dir = Dir::from_cap_std(self.dir.try_clone()?);
+        Ok(OpenHostDir(Open::new(self, read, write, flags), dir))
+    }
+}
+
+#[async_trait::async_trait]
+impl Node for HostDir {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.parent.set(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::Directory
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.id.clone()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Ok(Box::new(self.open(false, false, FdFlags::empty())?))
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        _dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if write {
+            return Err(Error::is_dir());
+        }
+
+        Ok(Box::new(self.open(read, write, flags)?))
+    }
+}
+
+struct OpenHostDir(Open<HostDir>, Dir);
+
+impl Deref for OpenHostDir {
+    type Target = Open<HostDir>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl OpenHostDir {
+    // The virtual directory containing this one, for paths leaving it.
+    async fn prev(&self) -> Result<Box<dyn WasiDir>, Error> {
+        match self.link().parent() {
+            Some(parent) => parent.open_dir().await,
+            None => self.link().clone().open_dir().await,
+        }
+    }
+}
+
+// The host directory behind `dir`: a graft, or one opened below it, which
+// `cap-std` passes through as is.
+fn host(dir: &dyn WasiDir) -> Option<&Dir> {
+    let any = dir.as_any();
+    match any.downcast_ref::<OpenHostDir>() {
+        Some(dir) => Some(&dir.1),
+        None => any.downcast_ref::<Dir>(),
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for OpenHostDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => {
+                let odir = oflags.contains(OFlags::DIRECTORY);
+                let prev = self.link().parent().unwrap_or_else(|| self.link().clone());
+                prev.open_file(path, odir, read, write, flags).await
+            }
+            ("..", rhs) => {
+                let prev = self.prev().await?;
+                prev.open_file(follow, rhs, oflags, read, write, flags)
+                    .await
+            }
+            ("." | "", "") => {
+                self.link()
+                    .clone()
+                    .open_file(path, true, read, write, flags)
+                    .await
+            }
+            _ => {
+                self.1
+                    .open_file(follow, path, oflags, read, write, flags)
+                    .await
+            }
+        }
+    }
+
+    async fn open_dir(&self, follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => self.prev().await,
+            ("..", rhs) => self.prev().await?.open_dir(follow, rhs).await,
+            (".", "") => self.link().clone().open_dir().await,
+            _ => self.1.open_dir(follow, path).await,
+        }
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        self.1.create_dir(path).await
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.1.readdir(cursor).await
+    }
+
+    async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+        self.1.symlink(old_path, new_path).await
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        self.1.remove_dir(path).await
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        self.1.unlink_file(path).await
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.1.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        // Report the virtual identity, so that the graft is seen as a device.
+        Ok(Filestat {
+            device_id: **self.link().id.device(),
+            inode: **self.link().id,
+            ..self.1.get_filestat().await?
+        })
+    }
+
+    async fn get_path_filestat(&self, path: &str, follow: bool) -> Result<Filestat, Error> {
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => self.prev().await?.get_filestat().await,
+            ("..", rhs) => self.prev().await?.get_path_filestat(rhs, follow).await,
+            ("." | "", "") => self.get_filestat().await,
+            _ => self.1.get_path_filestat(path, follow).await,
+        }
+    }
+
+    async fn rename(
+        &self,
+        src_path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        // Host files can only be moved between host directories.
+        match host(dest_dir) {
+            Some(dest) => self.1.rename(src_path, dest, dest_path).await,
+            None => Err(Error::cross_device()),
+        }
+    }
+
+    async fn hard_link(
+        &self,
+        src_path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        match host(target_dir) {
+            Some(target) => self.1.hard_link(src_path, target, target_path).await,
+            None => Err(Error::cross_device()),
+        }
+    }
+
+    async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+        follow: bool,
+    ) -> Result<(), Error> {
+        self.1.set_times(path, atime, mtime, follow).await
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenHostDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::Directory)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        WasiDir::get_filestat(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoSlice, IoSliceMut};
+
+    use cap_std::ambient_authority;
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn passthrough() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("hello"), "abc").unwrap();
+
+        let host = cap_std::fs::Dir::open_ambient_dir(tmp.path(), ambient_authority()).unwrap();
+        let root = Directory::root(Ledger::new(), None);
+        let data = HostDir::new(root.clone(), host);
+        root.attach("data", data.clone()).await.unwrap();
+        let dir = root.clone().open_dir().await.unwrap();
+
+        // Host files can be read through the virtual tree.
+        let mut file = dir
+            .open_file(
+                false,
+                "data/hello",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n as usize], b"abc");
+
+        // Files created through the virtual tree land on the host.
+        let mut file = dir
+            .open_file(
+                false,
+                "data/new",
+                OFlags::CREATE,
+                false,
+                true,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        file.write_vectored(&[IoSlice::new(b"xyz")]).await.unwrap();
+        assert_eq!(std::fs::read(tmp.path().join("new")).unwrap(), b"xyz");
+
+        // The graft is a device of its own, and `..` leads back out of it.
+        let stat = dir.get_path_filestat("data", true).await.unwrap();
+        assert_eq!(stat.device_id, **data.id().device());
+        assert_ne!(stat.device_id, **root.id().device());
+        let sub = dir.open_dir(false, "data").await.unwrap();
+        let stat = sub.get_path_filestat("..", true).await.unwrap();
+        assert_eq!(stat.inode, **root.id());
+        assert!(sub.open_dir(false, "../data/hello").await.is_err());
+
+        // Nothing beneath the graft escapes the host directory.
+        sub.create_dir("sub").await.unwrap();
+        let sub = sub.open_dir(false, "sub").await.unwrap();
+        assert!(sub.open_dir(false, "../..").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cross_device() {
+        use rustix::io::Errno;
+
+        let errno = |e: Error| {
+            let e = e.downcast_ref::<std::io::Error>()?.raw_os_error();
+            e.map(Errno::from_raw_os_error)
+        };
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("hello"), "abc").unwrap();
+        let host = cap_std::fs::Dir::open_ambient_dir(tmp.path(), ambient_authority()).unwrap();
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("data", HostDir::new(root.clone(), host))
+            .await
+            .unwrap();
+        root.attach(
+            "file",
+            wasmtime_vfs_file::File::with_data(root.clone(), "x"),
+        )
+        .await
+        .unwrap();
+
+        // Neither side can move or link files into the other.
+        let dir = root.clone().open_dir().await.unwrap();
+        let data = dir.open_dir(false, "data").await.unwrap();
+        let e = data.rename("hello", &*dir, "hello").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+        let e = data.hard_link("hello", &*dir, "hello").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+        let e = dir.rename("file", &*data, "file").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::XDEV));
+
+        // Within the host directory, they can.
+        data.rename("hello", &*data, "moved").await.unwrap();
+        data.hard_link("moved", &*data, "linked").await.unwrap();
+        assert_eq!(std::fs::read(tmp.path().join("linked")).unwrap(), b"abc");
+
+        // So can directories opened below it, which are host directories too.
+        data.create_dir("sub").await.unwrap();
+        let sub = data.open_dir(false, "sub").await.unwrap();
+        data.rename("moved", &*sub, "moved").await.unwrap();
+        data.hard_link("linked", &*sub, "linked").await.unwrap();
+        for path in ["sub/moved", "sub/linked"] {
+            assert_eq!(std::fs::read(tmp.path().join(path)).unwrap(), b"abc");
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+/// An attestation of the keys generated inside the keep
+///
+/// The embedder supplies this to prove, typically with a quote from the
+/// TEE, that a key pair was generated inside this keep.
+#[async_trait::async_trait]
+pub trait Attester: Send + Sync {
+    /// Returns a document binding the public key `public`, encoded as its
+    /// `share` file, and the `nonce` of the guest to the keep.
+    async fn attest(&self, public: &[u8], nonce: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+struct Evidence {
+    public: Vec<u8>,
+    attester: Arc<dyn Attester>,
+}
+
+/// A socket attesting a key pair generated inside the keep
+///
+/// A nonce is written in any number of writes, and may be empty. Reading at
+/// offset `u64::MAX` then returns the attestation document binding the nonce
+/// and the public key of the key pair to the keep, and starts a new nonce.
+pub struct Attest(Link<Evidence>);
+
+#[async_trait::async_trait]
+impl Node for Attest {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenAttest {
+            _root: self.root(),
+            link: self,
+            nonce: Vec::new(),
+            output: None,
+        }))
+    }
+}
+
+impl Attest {
+    pub fn new(parent: Arc<dyn Node>, public: &[u8], attester: Arc<dyn Attester>) -> Arc<Self> {
+        let evidence = Evidence {
+            public: public.to_vec(),
+            attester,
+        };
+
+        Arc::new(Self(Link::new(&parent, evidence)))
+    }
+}
+
+struct OpenAttest {
+    _root: Arc<dyn Node>,
+    link: Arc<Attest>,
+    nonce: Vec<u8>,
+
+    // The document for the nonce, kept until read in full.
+    output: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenAttest {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        // Writing after a document which was never read starts over.
+        if self.output.take().is_some() {
+            self.nonce.clear();
+        }
+
+        let mut total = 0;
+
+        for buf in bufs {
+            self.nonce.extend_from_slice(buf);
+            total += buf.len();
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        if offset != u64::MAX {
+            return Err(Error::invalid_argument());
+        }
+
+        // Asking the attester again could give another document, so keep it.
+        let output = match self.output.take() {
+            Some(output) => output,
+            None => {
+                let ilock = self.link.0.inode().data().read().await;
+                let evidence = ilock.content();
+                let attester = evidence.attester.clone();
+                let public = evidence.public.clone();
+                drop(ilock);
+
+                attester.attest(&public, &self.nonce).await?
+            }
+        };
+
+        // Copy the document into the buffer.
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), output.len() - total);
+            buf[..len].copy_from_slice(&output[total..][..len]);
+            total += len;
+        }
+
+        // Detect truncation, keeping the document for a larger read.
+        if total < output.len() {
+            self.output = Some(output);
+            return Err(Error::too_big());
+        }
+
+        self.nonce.clear();
+        Ok(total as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use aes_gcm::aead::generic_array::typenum::Unsigned;
+use aes_gcm::aead::{Aead, Nonce, Payload};
+use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
+use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+/// Whether a [`Cipher`] socket encrypts or decrypts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Encrypt,
+    Decrypt,
+}
+
+/// The domain of messages written to a [`Cipher`] socket
+pub(crate) const GUEST: &str = "";
+
+/// A key which can encrypt and decrypt messages for a [`Cipher`] socket
+///
+/// Each message is bound to a `domain`, as associated data or an OAEP
+/// label, so ciphertext of one domain never opens in another.
+pub(crate) trait Crypt {
+    fn seal(&self, domain: &str, message: &[u8]) -> Result<Vec<u8>, Error>;
+    fn open(&self, domain: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+// Ciphertext of an AEAD key is the random nonce followed by the sealed message.
+impl<A: Aead> Crypt for A {
+    fn seal(&self, domain: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = A::generate_nonce(&mut rand::thread_rng());
+        let payload = Payload {
+            msg: message,
+            aad: domain.as_bytes(),
+        };
+        let sealed = self
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::too_big())?;
+
+        let mut output = nonce.to_vec();
+        output.extend_from_slice(&sealed);
+        Ok(output)
+    }
+
+    fn open(&self, domain: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let size = A::NonceSize::USIZE;
+        if ciphertext.len() < size {
+            return Err(ErrorKind::Ilseq.into());
+        }
+
+        let (nonce, sealed) = ciphertext.split_at(size);
+        let nonce = Nonce::<A>::from_slice(nonce);
+        let payload = Payload {
+            msg: sealed,
+            aad: domain.as_bytes(),
+        };
+        self.decrypt(nonce, payload)
+            .map_err(|_| ErrorKind::Ilseq.into())
+    }
+}
+
+struct Key {
+    mode: Mode,
+    key: Option<Arc<dyn Crypt + Send + Sync>>,
+}
+
+/// A socket encrypting or decrypting messages with a key
+///
+/// The message is written in any number of writes. Reading at offset
+/// `u64::MAX` then returns the result and starts a new message. For an AEAD
+/// key the ciphertext is the random nonce followed by the sealed message,
+/// with no associated data. A ciphertext which fails to decrypt is reported
+/// as `EILSEQ`.
+pub struct Cipher(Link<Key>);
+
+#[async_trait::async_trait]
+impl Node for Cipher {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn revoke(&self) {
+        self.0.inode().data().write().await.content_mut().key = None;
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenCipher {
+            _root: self.root(),
+            link: self,
+            input: Vec::new(),
+            output: None,
+        }))
+    }
+}
+
+impl Cipher {
+    pub(crate) fn new(
+        parent: Arc<dyn Node>,
+        mode: Mode,
+        key: Arc<dyn Crypt + Send + Sync>,
+    ) -> Arc<Self> {
+        let key = Key {
+            mode,
+            key: Some(key),
+        };
+
+        Arc::new(Self(Link::new(&parent, key)))
+    }
+
+    /// Passes `input` through the key, bound to `domain`.
+    pub(crate) async fn apply(&self, domain: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let ilock = self.0.inode().data().read().await;
+        let key = ilock.content().key.as_ref().ok_or_else(Error::badf)?;
+
+        match ilock.content().mode {
+            Mode::Encrypt => key.seal(domain, input),
+            Mode::Decrypt => key.open(domain, input),
+        }
+    }
+}
+
+struct OpenCipher {
+    _root: Arc<dyn Node>,
+    link: Arc<Cipher>,
+    input: Vec<u8>,
+
+    // The result of the message, kept until read in full.
+    output: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenCipher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        // Writing after a result which was never read starts over.
+        if self.output.take().is_some() {
+            self.input.clear();
+        }
+
+        let mut total = 0;
+
+        for buf in bufs {
+            self.input.extend_from_slice(buf);
+            total += buf.len();
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        if offset != u64::MAX {
+            return Err(Error::invalid_argument());
+        }
+
+        // A fresh nonce each time would change the result, so keep it.
+        let output = match self.output.take() {
+            Some(output) => output,
+            None => self.link.apply(GUEST, &self.input).await?,
+        };
+
+        // Copy the result into the buffer.
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), output.len() - total);
+            buf[..len].copy_from_slice(&output[total..][..len]);
+            total += len;
+        }
+
+        // Detect truncation, keeping the result for a larger read.
+        if total < output.len() {
+            self.output = Some(output);
+            return Err(Error::too_big());
+        }
+
+        self.input.clear();
+        Ok(total as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
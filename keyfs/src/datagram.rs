@@ -0,0 +1,84 @@
+// The datagram handling shared by the sockets of this crate.
+//
+// Each read returns one whole datagram, or fails with `E2BIG` and keeps it
+// for a larger read. Peeking copies what fits without consuming, as
+// `MSG_PEEK` does, and sockets report the bytes of every pending datagram
+// as ready. Reads never wait, but sockets only report being readable with
+// a datagram pending, so that guests can poll instead of spinning. Those
+// whose queue is shared by all handles wait until a key is queued; those
+// which queue replies per handle can only be filled by the handle itself,
+// so while empty they never become readable and a poll runs to its timeout.
+
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, IoSliceMut};
+use std::pin::pin;
+
+use tokio::sync::Notify;
+use uuid::Uuid;
+use wasi_common::{Error, ErrorExt};
+use wasmtime_vfs_memory::{interruptible, Inode};
+
+/// The length of a hyphenated UUID naming a key
+pub(crate) const UUID: usize = 36;
+
+/// The error for reading a socket with nothing pending.
+pub(crate) fn empty() -> Error {
+    IoError::from(IoErrorKind::WouldBlock).into()
+}
+
+/// Copies the datagram `bytes` into `bufs`, failing if it does not fit.
+pub(crate) fn recv(bytes: &[u8], bufs: &mut [IoSliceMut<'_>]) -> Result<u64, Error> {
+    let mut total = 0;
+
+    for buf in bufs {
+        let len = std::cmp::min(buf.len(), bytes.len() - total);
+        buf[..len].copy_from_slice(&bytes[total..][..len]);
+        total += len;
+    }
+
+    if total < bytes.len() {
+        return Err(Error::too_big());
+    }
+
+    Ok(total as u64)
+}
+
+/// Copies as much of the datagram `bytes` as fits into `buf`.
+pub(crate) fn peek(bytes: &[u8], buf: &mut [u8]) -> u64 {
+    let len = std::cmp::min(buf.len(), bytes.len());
+    buf[..len].copy_from_slice(&bytes[..len]);
+    len as u64
+}
+
+/// Peeks at the name of the next key, if any.
+pub(crate) fn peek_uuid(next: Option<&Uuid>, buf: &mut [u8]) -> Result<u64, Error> {
+    let uuid = next.ok_or_else(empty)?;
+    Ok(peek(uuid.to_string().as_bytes(), buf))
+}
+
+/// The bytes ready as the names of `count` keys.
+pub(crate) fn ready_uuids(count: usize) -> u64 {
+    (count * UUID) as u64
+}
+
+/// Waits forever unless the queue of a handle is `ready`.
+pub(crate) async fn pending(ready: bool) -> Result<(), Error> {
+    if !ready {
+        std::future::pending::<()>().await;
+    }
+
+    Ok(())
+}
+
+/// Waits until a key is queued in `inode`, which `notify` announces.
+pub(crate) async fn queued(inode: &Inode<Vec<Uuid>>, notify: &Notify) -> Result<(), Error> {
+    loop {
+        let mut notified = pin!(notify.notified());
+        notified.as_mut().enable();
+
+        if !inode.read().await?.content().is_empty() {
+            return Ok(());
+        }
+
+        interruptible(&inode.id().device(), notified).await?;
+    }
+}
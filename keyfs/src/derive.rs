@@ -0,0 +1,240 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use hkdf::Hkdf;
+use sha2::{Sha256, Sha384, Sha512};
+use uuid::Uuid;
+use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SiFlags};
+use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::datagram;
+use crate::generate::{attach_symmetric, key_size};
+use crate::Hooks;
+use crate::{A256GCM, C20P, HS256, HS384, HS512};
+
+struct Secret {
+    algorithm: &'static [u8],
+    material: Zeroizing<Vec<u8>>,
+    hooks: Hooks,
+}
+
+/// A socket deriving new keys from a symmetric key with HKDF
+///
+/// Each write is one request: the algorithm of the new key, the length of
+/// the salt as a big-endian `u32`, the salt and then the info. Each read
+/// returns the UUID of a derived key, in the order of the requests. HMAC
+/// keys derive with their own hash and cipher keys with SHA-256. The key
+/// derived from is never exposed.
+pub struct Derive(Link<Secret>);
+
+#[async_trait::async_trait]
+impl Node for Derive {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn revoke(&self) {
+        self.0
+            .inode()
+            .data()
+            .write()
+            .await
+            .content_mut()
+            .material
+            .zeroize();
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenDerive {
+            _root: self.root(),
+            link: self,
+            derived: Vec::new(),
+        }))
+    }
+}
+
+impl Derive {
+    /// Creates the socket for the symmetric key `material` of `algorithm`.
+    pub(crate) fn new(
+        parent: Arc<dyn Node>,
+        algorithm: &'static [u8],
+        material: &[u8],
+        hooks: Hooks,
+    ) -> Arc<Self> {
+        let secret = Secret {
+            algorithm,
+            material: Zeroizing::new(material.to_vec()),
+            hooks,
+        };
+
+        Arc::new(Self(Link::new(&parent, secret)))
+    }
+
+    async fn derive(&self, request: &[u8]) -> Result<Uuid, Error> {
+        if request.len() < 8 {
+            return Err(Error::invalid_argument());
+        }
+
+        let (algorithm, rest) = request.split_at(4);
+        let (len, rest) = rest.split_at(4);
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        if rest.len() < len {
+            return Err(Error::invalid_argument());
+        }
+
+        let (salt, info) = rest.split_at(len);
+        let size = key_size(algorithm).ok_or(ErrorKind::Ilseq)?;
+        let mut okm = Zeroizing::new(vec![0u8; size]);
+
+        let ilock = self.0.inode().data().read().await;
+        let secret = ilock.content();
+        if secret.material.is_empty() {
+            return Err(Error::badf());
+        }
+
+        let expanded = match secret.algorithm {
+            HS256 | A256GCM | C20P => {
+                Hkdf::<Sha256>::new(Some(salt), &secret.material).expand(info, &mut okm)
+            }
+            HS384 => Hkdf::<Sha384>::new(Some(salt), &secret.material).expand(info, &mut okm),
+            HS512 => Hkdf::<Sha512>::new(Some(salt), &secret.material).expand(info, &mut okm),
+            _ => return Err(Error::io()),
+        };
+        expanded.map_err(|_| Error::invalid_argument())?;
+        let hooks = secret.hooks.clone();
+        drop(ilock);
+
+        // The new key is a sibling of the key it was derived from.
+        let keys = self
+            .parent()
+            .and_then(|key| key.parent())
+            .ok_or_else(Error::io)?
+            .to_any()
+            .downcast::<Directory>()
+            .map_err(|_| Error::io())?;
+
+        let uuid = Uuid::new_v4();
+        attach_symmetric(&keys, &hooks, uuid, algorithm, &okm, false).await
+    }
+}
+
+struct OpenDerive {
+    _root: Arc<dyn Node>,
+    link: Arc<Derive>,
+
+    // The keys derived and not yet read, oldest first.
+    derived: Vec<Uuid>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenDerive {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let uuid = self.derived.first().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(uuid.to_string().as_bytes(), bufs)?;
+        self.derived.remove(0);
+        Ok(n)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        datagram::peek_uuid(self.derived.first(), buf)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(datagram::ready_uuids(self.derived.len()))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let request: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        let uuid = self.link.derive(&request).await?;
+        self.derived.push(uuid);
+        Ok(request.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        datagram::pending(!self.derived.is_empty()).await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
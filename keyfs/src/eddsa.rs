@@ -0,0 +1,131 @@
+use digest::consts::U64;
+use digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Update};
+use ed25519_dalek::Signer as _;
+use sha2::{Digest, Sha512};
+use signature::rand_core::{CryptoRng, RngCore};
+use signature::{DigestVerifier, RandomizedDigestSigner};
+use wasi_common::{Error, ErrorExt};
+use zeroize::Zeroizing;
+
+/// The whole message an Ed25519 key signs, in place of a digest
+///
+/// Ed25519 hashes the message itself, so the `sign` and `verify` sockets
+/// keep all of it. It finalizes to its SHA-512 hash, the digest a
+/// [`Policy`](crate::Policy) sees for Ed25519 keys.
+#[derive(Clone, Default)]
+pub(crate) struct Message(Vec<u8>);
+
+impl HashMarker for Message {}
+
+impl OutputSizeUser for Message {
+    type OutputSize = U64;
+}
+
+impl Update for Message {
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+}
+
+impl FixedOutput for Message {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&Sha512::digest(&self.0));
+    }
+}
+
+/// An Ed25519 signature, `R` and then `S`
+#[derive(Clone, Debug)]
+pub(crate) struct Signature([u8; 64]);
+
+impl AsRef<[u8]> for Signature {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl signature::Signature for Signature {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, signature::Error> {
+        let bytes = bytes.try_into().map_err(|_| signature::Error::new())?;
+        Ok(Self(bytes))
+    }
+}
+
+/// An Ed25519 private key, whose material is its 32-byte seed
+pub(crate) struct Ed25519(ed25519_dalek::SigningKey);
+
+impl Ed25519 {
+    pub(crate) fn random() -> Self {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(&mut *seed);
+        Self(ed25519_dalek::SigningKey::from_bytes(&seed))
+    }
+
+    pub(crate) fn from_seed(seed: &[u8]) -> Result<Self, Error> {
+        let seed: &[u8; 32] = seed
+            .try_into()
+            .map_err(|_| Error::illegal_byte_sequence())?;
+        Ok(Self(ed25519_dalek::SigningKey::from_bytes(seed)))
+    }
+
+    // Decodes the key from PKCS#8, as RFC 8410 lays it out.
+    pub(crate) fn from_pkcs8_der(der: &[u8]) -> Result<Self, Error> {
+        use ed25519_dalek::pkcs8::DecodePrivateKey;
+
+        let key = ed25519_dalek::SigningKey::from_pkcs8_der(der);
+        Ok(Self(key.map_err(|_| Error::illegal_byte_sequence())?))
+    }
+
+    pub(crate) fn seed(&self) -> Vec<u8> {
+        self.0.to_bytes().to_vec()
+    }
+
+    pub(crate) fn public(&self) -> Ed25519Public {
+        Ed25519Public(self.0.verifying_key())
+    }
+}
+
+// Signing is deterministic, so the generator goes unused.
+impl RandomizedDigestSigner<Message, Signature> for Ed25519 {
+    fn try_sign_digest_with_rng(
+        &self,
+        _rng: impl CryptoRng + RngCore,
+        message: Message,
+    ) -> Result<Signature, signature::Error> {
+        Ok(Signature(self.0.sign(&message.0).to_bytes()))
+    }
+}
+
+/// An Ed25519 public key, shared as its 32-byte encoding
+pub(crate) struct Ed25519Public(ed25519_dalek::VerifyingKey);
+
+impl Ed25519Public {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes: &[u8; 32] = bytes
+            .try_into()
+            .map_err(|_| Error::illegal_byte_sequence())?;
+        let key = ed25519_dalek::VerifyingKey::from_bytes(bytes);
+        Ok(Self(key.map_err(|_| Error::illegal_byte_sequence())?))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        self.0.as_bytes()
+    }
+
+    // Encodes the key as SubjectPublicKeyInfo, as RFC 8410 lays it out.
+    pub(crate) fn to_public_key_der(&self) -> Result<Vec<u8>, Error> {
+        use ed25519_dalek::pkcs8::EncodePublicKey;
+
+        let der = self.0.to_public_key_der().map_err(|_| Error::io())?;
+        Ok(der.as_bytes().to_vec())
+    }
+}
+
+// Strict verification refuses small order keys and malleable signatures.
+impl DigestVerifier<Message, Signature> for Ed25519Public {
+    fn verify_digest(&self, message: Message, sig: &Signature) -> Result<(), signature::Error> {
+        let sig = ed25519_dalek::Signature::from_bytes(&sig.0);
+        self.0
+            .verify_strict(&message.0, &sig)
+            .map_err(|_| signature::Error::new())
+    }
+}
@@ -1,7 +1,10 @@
 use std::any::Any;
 use std::io::{IoSlice, IoSliceMut};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
 use digest::generic_array::ArrayLength;
 use digest::Digest;
 use ecdsa::elliptic_curve::ops::{Invert, Reduce};
@@ -9,20 +12,41 @@ use ecdsa::elliptic_curve::subtle::CtOption;
 use ecdsa::elliptic_curve::{ProjectiveArithmetic, Scalar};
 use ecdsa::hazmat::SignPrimitive;
 use ecdsa::{PrimeCurve, SignatureSize};
+use hmac::Hmac;
+use rand::RngCore;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{AssociatedOid, DecodePrivateKey, EncodePrivateKey};
 use rsa::PublicKeyParts;
 use sha2::{Sha256, Sha384, Sha512};
 use signature::{DigestVerifier, RandomizedDigestSigner, Signature};
+use tokio::sync::Notify;
 use uuid::Uuid;
 use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SiFlags};
 use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
 use wasmtime_vfs_dir::Directory;
 use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node};
-
-use crate::share::Share;
+use wasmtime_vfs_memory::{Link, Node};
+use zeroize::Zeroizing;
+
+use crate::attest::Attest;
+use crate::cipher::{Cipher, Mode};
+use crate::datagram;
+use crate::derive::Derive;
+use crate::eddsa::{Ed25519, Ed25519Public, Message};
+use crate::mac::Mac;
+use crate::pke;
+use crate::policy::State;
+use crate::revoke::Revoke;
+use crate::seal::Seal;
+use crate::share;
 use crate::sign::Sign;
+use crate::store;
 use crate::verify::Verify;
-use crate::{ES256, ES256K, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+use crate::{Hooks, Record};
+use crate::{
+    A256GCM, C20P, EDDSA, ES256, ES256K, ES384, HS256, HS384, HS512, PS256, PS384, PS512, RS256,
+    RS384, RS512,
+};
 
 type Rs256 = rsa::pkcs1v15::SigningKey<Sha256>;
 type Rs384 = rsa::pkcs1v15::SigningKey<Sha384>;
@@ -92,6 +116,12 @@ impl GenerateKey for Es384 {
     }
 }
 
+impl GenerateKey for Ed25519 {
+    fn generate() -> Result<Self, Error> {
+        Ok(Ed25519::random())
+    }
+}
+
 trait ToPublic {
     type Public;
 
@@ -127,6 +157,121 @@ where
     }
 }
 
+impl ToPublic for Ed25519 {
+    type Public = Ed25519Public;
+
+    fn to_public(&self) -> Self::Public {
+        self.public()
+    }
+}
+
+/// Conversion of a private key to and from its key material
+trait Export: Sized {
+    fn export(&self) -> Result<Vec<u8>, Error>;
+    fn import(material: &[u8]) -> Result<Self, Error>;
+}
+
+impl<D: Digest> Export for rsa::pkcs1v15::SigningKey<D> {
+    fn export(&self) -> Result<Vec<u8>, Error> {
+        let der = self.to_pkcs8_der().map_err(|_| Error::io())?;
+        Ok(der.as_bytes().to_vec())
+    }
+
+    fn import(material: &[u8]) -> Result<Self, Error> {
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(material);
+        Ok(key.map_err(|_| Error::illegal_byte_sequence())?.into())
+    }
+}
+
+impl<D: Digest> Export for rsa::pss::BlindedSigningKey<D> {
+    fn export(&self) -> Result<Vec<u8>, Error> {
+        let der = self.to_pkcs8_der().map_err(|_| Error::io())?;
+        Ok(der.as_bytes().to_vec())
+    }
+
+    fn import(material: &[u8]) -> Result<Self, Error> {
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(material);
+        Ok(key.map_err(|_| Error::illegal_byte_sequence())?.into())
+    }
+}
+
+impl<C: ecdsa::elliptic_curve::Curve> Export for ecdsa::SigningKey<C>
+where
+    C: PrimeCurve + ProjectiveArithmetic,
+    Scalar<C>: Invert<Output = CtOption<Scalar<C>>> + Reduce<C::UInt> + SignPrimitive<C>,
+    SignatureSize<C>: ArrayLength<u8>,
+{
+    fn export(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.to_bytes().to_vec())
+    }
+
+    fn import(material: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes(material).map_err(|_| Error::illegal_byte_sequence())
+    }
+}
+
+impl Export for Ed25519 {
+    fn export(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.seed())
+    }
+
+    fn import(material: &[u8]) -> Result<Self, Error> {
+        Self::from_seed(material)
+    }
+}
+
+/// Decoding of a private key from the DER its owner provisions it in
+trait Decode: Sized {
+    fn decode(der: &[u8]) -> Result<Self, Error>;
+}
+
+// Decodes an RSA private key from PKCS#8 or PKCS#1.
+fn decode_rsa(der: &[u8]) -> Result<rsa::RsaPrivateKey, Error> {
+    rsa::RsaPrivateKey::from_pkcs8_der(der)
+        .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_der(der))
+        .map_err(|_| Error::illegal_byte_sequence())
+}
+
+impl<D: Digest> Decode for rsa::pkcs1v15::SigningKey<D> {
+    fn decode(der: &[u8]) -> Result<Self, Error> {
+        Ok(decode_rsa(der)?.into())
+    }
+}
+
+impl<D: Digest> Decode for rsa::pss::BlindedSigningKey<D> {
+    fn decode(der: &[u8]) -> Result<Self, Error> {
+        Ok(decode_rsa(der)?.into())
+    }
+}
+
+// Decodes an elliptic curve private key from PKCS#8 or SEC1, whose curve
+// must be `C` if named.
+impl<C: ecdsa::elliptic_curve::Curve> Decode for ecdsa::SigningKey<C>
+where
+    C: PrimeCurve + ProjectiveArithmetic + AssociatedOid,
+    Scalar<C>: Invert<Output = CtOption<Scalar<C>>> + Reduce<C::UInt> + SignPrimitive<C>,
+    SignatureSize<C>: ArrayLength<u8>,
+    Self: DecodePrivateKey,
+{
+    fn decode(der: &[u8]) -> Result<Self, Error> {
+        if let Ok(key) = Self::from_pkcs8_der(der) {
+            return Ok(key);
+        }
+
+        let key = sec1::EcPrivateKey::try_from(der).map_err(|_| Error::illegal_byte_sequence())?;
+        match key.parameters.and_then(|p| p.named_curve()) {
+            Some(curve) if curve != C::OID => Err(Error::illegal_byte_sequence()),
+            _ => Self::from_bytes(key.private_key).map_err(|_| Error::illegal_byte_sequence()),
+        }
+    }
+}
+
+impl Decode for Ed25519 {
+    fn decode(der: &[u8]) -> Result<Self, Error> {
+        Self::from_pkcs8_der(der)
+    }
+}
+
 trait Encoder<T> {
     fn encode(&self, arg: T) -> Result<Vec<u8>, Error>;
 }
@@ -209,7 +354,15 @@ impl Encoder<()> for ecdsa::VerifyingKey<p384::NistP384> {
     }
 }
 
-pub struct Generate(Link<Vec<Uuid>>);
+impl Encoder<()> for Ed25519Public {
+    fn encode(&self, _: ()) -> Result<Vec<u8>, Error> {
+        let mut out = EDDSA.to_vec();
+        out.extend_from_slice(self.as_bytes());
+        Ok(out)
+    }
+}
+
+pub struct Generate(Link<Vec<Uuid>>, Notify, Hooks);
 
 #[async_trait::async_trait]
 impl Node for Generate {
@@ -218,7 +371,11 @@ impl Node for Generate {
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.0.parent.upgrade()
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -226,7 +383,7 @@ impl Node for Generate {
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.0.inode.id.clone()
+        self.0.inode().id()
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -261,27 +418,18 @@ impl Node for Generate {
 }
 
 impl Generate {
-    pub fn new(parent: Arc<dyn Node>) -> Arc<Self> {
-        let id = parent.id().device().create_inode();
-
-        let inode = Inode {
-            data: Data::from(Vec::new()).into(),
-            id,
-        };
-
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+    pub(crate) fn new(parent: Arc<dyn Node>, hooks: Hooks) -> Arc<Self> {
+        let link = Link::new(&parent, Vec::new());
+        Arc::new(Self(link, Notify::new(), hooks))
     }
 
-    async fn add<T, U, D, S>(self: &Arc<Generate>) -> Result<Uuid, Error>
+    async fn add<T, U, D, S>(self: &Arc<Generate>, algorithm: &'static [u8]) -> Result<Uuid, Error>
     where
         T: Send + Sync + 'static,
         U: Send + Sync + 'static,
         D: Send + Sync + 'static,
         S: Send + Sync + 'static,
-        T: RandomizedDigestSigner<D, S> + GenerateKey + ToPublic<Public = U>,
+        T: RandomizedDigestSigner<D, S> + GenerateKey + Export + ToPublic<Public = U>,
         U: DigestVerifier<D, S> + Encoder<()>,
         D: Digest + Clone,
         S: Signature,
@@ -293,21 +441,334 @@ impl Generate {
             .downcast::<Directory>()
             .map_err(|_| Error::io())?;
 
-        let secret = T::generate()?;
-        let public = secret.to_public();
-        let shared = public.encode(())?;
-        let uuid = uuid::Uuid::new_v4();
+        let uuid = Uuid::new_v4();
+        attach_signing(&parent, &self.2, uuid, algorithm, T::generate()?, true).await
+    }
+
+    async fn add_symmetric(self: &Arc<Generate>, algorithm: &'static [u8]) -> Result<Uuid, Error> {
+        let parent = self
+            .parent()
+            .ok_or_else(Error::io)?
+            .to_any()
+            .downcast::<Directory>()
+            .map_err(|_| Error::io())?;
+
+        let mut material = vec![0u8; key_size(algorithm).ok_or_else(Error::io)?];
+        rand::thread_rng().fill_bytes(&mut material);
+        let uuid = Uuid::new_v4();
+        attach_symmetric(&parent, &self.2, uuid, algorithm, &material, true).await
+    }
+}
+
+/// Attaches a directory named `uuid` for the private key `secret` to `keys`,
+/// once the store of `hooks` has persisted it
+///
+/// Only a key `generated` inside the keep can be attested.
+async fn attach_signing<T, U, D, S>(
+    keys: &Arc<Directory>,
+    hooks: &Hooks,
+    uuid: Uuid,
+    algorithm: &'static [u8],
+    secret: T,
+    generated: bool,
+) -> Result<Uuid, Error>
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+    D: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    T: RandomizedDigestSigner<D, S> + Export + ToPublic<Public = U>,
+    U: DigestVerifier<D, S> + Encoder<()>,
+    D: Digest + Clone,
+    S: Signature,
+{
+    let public = secret.to_public();
+    let shared = public.encode(())?;
+    let material = Zeroizing::new(secret.export()?);
+
+    let d = Directory::new(keys.clone(), None);
+    d.attach("verify", Verify::new(d.clone(), public)).await?;
+    pke::attach(&d, &shared, Some(&material)).await?;
+    if let (true, Some(attester)) = (generated, &hooks.attester) {
+        let attest = Attest::new(d.clone(), &shared, attester.clone());
+        d.attach("attest", attest).await?;
+    }
+    let size = <D as Digest>::output_size();
+    let usage = hooks.usages.get(&hooks.policy, &shared, size)?;
+    share::attach(&d, shared).await?;
+    d.attach("policy", State::new(d.clone(), usage.clone()))
+        .await?;
+    d.attach("sign", Sign::new(d.clone(), secret, usage))
+        .await?;
+    d.attach("seal", Seal::new(d.clone(), algorithm, &material))
+        .await?;
+    d.attach("revoke", Revoke::new(d.clone(), hooks, uuid))
+        .await?;
+    store::save(hooks, uuid, algorithm, &material, generated).await?;
+    keys.attach(&uuid.to_string(), d).await?;
+
+    Ok(uuid)
+}
+
+/// Attaches a directory for the key of `record`, of any algorithm, to `keys`,
+/// once the store of `hooks` has persisted it
+pub(crate) async fn import(
+    keys: &Arc<Directory>,
+    hooks: &Hooks,
+    record: &Record,
+) -> Result<Uuid, Error> {
+    let (uuid, generated) = (record.uuid, record.generated);
+    let (algorithm, material) = (&record.algorithm[..], &record.material[..]);
+    match algorithm {
+        RS256 => {
+            attach_signing::<_, _, Sha256, _>(
+                keys,
+                hooks,
+                uuid,
+                RS256,
+                Rs256::import(material)?,
+                generated,
+            )
+            .await
+        }
+        RS384 => {
+            attach_signing::<_, _, Sha384, _>(
+                keys,
+                hooks,
+                uuid,
+                RS384,
+                Rs384::import(material)?,
+                generated,
+            )
+            .await
+        }
+        RS512 => {
+            attach_signing::<_, _, Sha512, _>(
+                keys,
+                hooks,
+                uuid,
+                RS512,
+                Rs512::import(material)?,
+                generated,
+            )
+            .await
+        }
+        PS256 => {
+            attach_signing::<_, _, Sha256, _>(
+                keys,
+                hooks,
+                uuid,
+                PS256,
+                Ps256::import(material)?,
+                generated,
+            )
+            .await
+        }
+        PS384 => {
+            attach_signing::<_, _, Sha384, _>(
+                keys,
+                hooks,
+                uuid,
+                PS384,
+                Ps384::import(material)?,
+                generated,
+            )
+            .await
+        }
+        PS512 => {
+            attach_signing::<_, _, Sha512, _>(
+                keys,
+                hooks,
+                uuid,
+                PS512,
+                Ps512::import(material)?,
+                generated,
+            )
+            .await
+        }
+        ES256K => {
+            attach_signing::<_, _, Sha256, _>(
+                keys,
+                hooks,
+                uuid,
+                ES256K,
+                Es256k::import(material)?,
+                generated,
+            )
+            .await
+        }
+        ES256 => {
+            attach_signing::<_, _, Sha256, _>(
+                keys,
+                hooks,
+                uuid,
+                ES256,
+                Es256::import(material)?,
+                generated,
+            )
+            .await
+        }
+        ES384 => {
+            attach_signing::<_, _, Sha384, _>(
+                keys,
+                hooks,
+                uuid,
+                ES384,
+                Es384::import(material)?,
+                generated,
+            )
+            .await
+        }
+        EDDSA => {
+            attach_signing::<_, _, Message, _>(
+                keys,
+                hooks,
+                uuid,
+                EDDSA,
+                Ed25519::import(material)?,
+                generated,
+            )
+            .await
+        }
+        _ => attach_symmetric(keys, hooks, uuid, algorithm, material, generated).await,
+    }
+}
 
-        let d = Directory::new(parent.clone(), None);
-        d.attach("verify", Verify::new(d.clone(), public)).await?;
-        d.attach("share", Share::new(d.clone(), shared)).await?;
-        d.attach("sign", Sign::new(d.clone(), secret)).await?;
-        parent.attach(&uuid.to_string(), d).await?;
+/// Attaches a directory for the DER encoded private key `der` of any
+/// algorithm to `keys`, or for the raw key material of a symmetric one
+pub(crate) async fn import_der(
+    keys: &Arc<Directory>,
+    hooks: &Hooks,
+    algorithm: &[u8],
+    der: &[u8],
+) -> Result<Uuid, Error> {
+    let uuid = Uuid::new_v4();
+    match algorithm {
+        RS256 => {
+            attach_signing::<_, _, Sha256, _>(keys, hooks, uuid, RS256, Rs256::decode(der)?, false)
+                .await
+        }
+        RS384 => {
+            attach_signing::<_, _, Sha384, _>(keys, hooks, uuid, RS384, Rs384::decode(der)?, false)
+                .await
+        }
+        RS512 => {
+            attach_signing::<_, _, Sha512, _>(keys, hooks, uuid, RS512, Rs512::decode(der)?, false)
+                .await
+        }
+        PS256 => {
+            attach_signing::<_, _, Sha256, _>(keys, hooks, uuid, PS256, Ps256::decode(der)?, false)
+                .await
+        }
+        PS384 => {
+            attach_signing::<_, _, Sha384, _>(keys, hooks, uuid, PS384, Ps384::decode(der)?, false)
+                .await
+        }
+        PS512 => {
+            attach_signing::<_, _, Sha512, _>(keys, hooks, uuid, PS512, Ps512::decode(der)?, false)
+                .await
+        }
+        ES256K => {
+            attach_signing::<_, _, Sha256, _>(
+                keys,
+                hooks,
+                uuid,
+                ES256K,
+                Es256k::decode(der)?,
+                false,
+            )
+            .await
+        }
+        ES256 => {
+            attach_signing::<_, _, Sha256, _>(keys, hooks, uuid, ES256, Es256::decode(der)?, false)
+                .await
+        }
+        ES384 => {
+            attach_signing::<_, _, Sha384, _>(keys, hooks, uuid, ES384, Es384::decode(der)?, false)
+                .await
+        }
+        EDDSA => {
+            let key = Ed25519::decode(der)?;
+            attach_signing::<_, _, Message, _>(keys, hooks, uuid, EDDSA, key, false).await
+        }
+        _ => attach_symmetric(keys, hooks, uuid, algorithm, der, false).await,
+    }
+}
 
-        Ok(uuid)
+/// The size of the key material of a symmetric algorithm
+pub(crate) fn key_size(algorithm: &[u8]) -> Option<usize> {
+    match algorithm {
+        A256GCM | C20P | HS256 => Some(32),
+        HS384 => Some(48),
+        HS512 => Some(64),
+        _ => None,
     }
 }
 
+/// Attaches a directory named `uuid` for the symmetric key `material` to
+/// `keys`, once the store of `hooks` has persisted it
+pub(crate) async fn attach_symmetric(
+    keys: &Arc<Directory>,
+    hooks: &Hooks,
+    uuid: Uuid,
+    algorithm: &[u8],
+    material: &[u8],
+    generated: bool,
+) -> Result<Uuid, Error> {
+    let d = Directory::new(keys.clone(), None);
+
+    match algorithm {
+        A256GCM => add_cipher::<Aes256Gcm>(&d, hooks, A256GCM, material).await?,
+        C20P => add_cipher::<ChaCha20Poly1305>(&d, hooks, C20P, material).await?,
+        HS256 => add_mac::<Hmac<Sha256>>(&d, hooks, HS256, material).await?,
+        HS384 => add_mac::<Hmac<Sha384>>(&d, hooks, HS384, material).await?,
+        HS512 => add_mac::<Hmac<Sha512>>(&d, hooks, HS512, material).await?,
+        _ => return Err(ErrorKind::Ilseq.into()),
+    }
+
+    d.attach("seal", Seal::new(d.clone(), algorithm, material))
+        .await?;
+    d.attach("revoke", Revoke::new(d.clone(), hooks, uuid))
+        .await?;
+
+    store::save(hooks, uuid, algorithm, material, generated).await?;
+    keys.attach(&uuid.to_string(), d).await?;
+    Ok(uuid)
+}
+
+async fn add_cipher<A>(
+    d: &Arc<Directory>,
+    hooks: &Hooks,
+    algorithm: &'static [u8],
+    material: &[u8],
+) -> Result<(), Error>
+where
+    A: Aead + KeyInit + Send + Sync + 'static,
+{
+    let key = Arc::new(A::new_from_slice(material).map_err(|_| Error::invalid_argument())?);
+    let encrypt = Cipher::new(d.clone(), Mode::Encrypt, key.clone());
+    d.attach("encrypt", encrypt).await?;
+    d.attach("decrypt", Cipher::new(d.clone(), Mode::Decrypt, key))
+        .await?;
+    let derive = Derive::new(d.clone(), algorithm, material, hooks.live());
+    d.attach("derive", derive).await
+}
+
+async fn add_mac<M>(
+    d: &Arc<Directory>,
+    hooks: &Hooks,
+    algorithm: &'static [u8],
+    material: &[u8],
+) -> Result<(), Error>
+where
+    M: hmac::Mac + KeyInit + Clone + Send + Sync + 'static,
+{
+    let key = <M as KeyInit>::new_from_slice(material).map_err(|_| Error::invalid_argument())?;
+    d.attach("mac", Mac::new(d.clone(), key)).await?;
+    let derive = Derive::new(d.clone(), algorithm, material, hooks.live());
+    d.attach("derive", derive).await
+}
+
 struct OpenGenerate {
     _root: Arc<dyn Node>,
     link: Arc<Generate>,
@@ -324,17 +785,17 @@ impl WasiFile for OpenGenerate {
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.0.inode.data.read().await;
+        let ilock = self.link.0.inode().data().read().await;
 
         Ok(Filestat {
-            device_id: **self.link.0.inode.id.device(),
-            inode: **self.link.0.inode.id,
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
             filetype: FileType::SocketDgram,
-            nlink: Arc::strong_count(&self.link.0.inode) as u64,
+            nlink: self.link.0.inode().id().links(),
             size: 0,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
         })
     }
 
@@ -351,29 +812,23 @@ impl WasiFile for OpenGenerate {
         self.write_vectored(bufs).await
     }
 
+    // The most recent key is read first.
     async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
-        let mut ilock = self.link.0.inode.data.write().await;
-
-        if let Some(uuid) = ilock.content.pop() {
-            let name = uuid.to_string();
-            let bytes = name.as_bytes();
-            let mut total = 0;
-
-            for buf in bufs {
-                let len = std::cmp::min(buf.len(), bytes.len() - total);
-                buf[..len].copy_from_slice(&bytes[total..][..len]);
-                total += len;
-            }
-
-            if total < bytes.len() {
-                ilock.content.push(uuid);
-                return Err(Error::too_big());
-            }
+        let mut ilock = self.link.0.inode().data().write().await;
+        let uuid = ilock.content().last().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(uuid.to_string().as_bytes(), bufs)?;
+        ilock.content_mut().pop();
+        Ok(n)
+    }
 
-            return Ok(total as u64);
-        }
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+        datagram::peek_uuid(ilock.content().last(), buf)
+    }
 
-        Err(std::io::Error::from(std::io::ErrorKind::WouldBlock).into())
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+        Ok(datagram::ready_uuids(ilock.content().len()))
     }
 
     async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
@@ -382,24 +837,38 @@ impl WasiFile for OpenGenerate {
         }
 
         let uuid = match bufs[0].as_ref() {
-            RS256 => self.link.add::<Rs256, _, _, _>().await?,
-            RS384 => self.link.add::<Rs384, _, _, _>().await?,
-            RS512 => self.link.add::<Rs512, _, _, _>().await?,
-            PS256 => self.link.add::<Ps256, _, _, _>().await?,
-            PS384 => self.link.add::<Ps384, _, _, _>().await?,
-            PS512 => self.link.add::<Ps512, _, _, _>().await?,
-            ES256K => self.link.add::<Es256k, _, Sha256, _>().await?,
-            ES256 => self.link.add::<Es256, _, Sha256, _>().await?,
-            ES384 => self.link.add::<Es384, _, Sha384, _>().await?,
+            RS256 => self.link.add::<Rs256, _, _, _>(RS256).await?,
+            RS384 => self.link.add::<Rs384, _, _, _>(RS384).await?,
+            RS512 => self.link.add::<Rs512, _, _, _>(RS512).await?,
+            PS256 => self.link.add::<Ps256, _, _, _>(PS256).await?,
+            PS384 => self.link.add::<Ps384, _, _, _>(PS384).await?,
+            PS512 => self.link.add::<Ps512, _, _, _>(PS512).await?,
+            ES256K => self.link.add::<Es256k, _, Sha256, _>(ES256K).await?,
+            ES256 => self.link.add::<Es256, _, Sha256, _>(ES256).await?,
+            ES384 => self.link.add::<Es384, _, Sha384, _>(ES384).await?,
+            EDDSA => self.link.add::<Ed25519, _, Message, _>(EDDSA).await?,
+            A256GCM => self.link.add_symmetric(A256GCM).await?,
+            C20P => self.link.add_symmetric(C20P).await?,
+            HS256 => self.link.add_symmetric(HS256).await?,
+            HS384 => self.link.add_symmetric(HS384).await?,
+            HS512 => self.link.add_symmetric(HS512).await?,
             _ => return Err(ErrorKind::Ilseq.into()),
         };
 
-        self.link.0.inode.data.write().await.content.push(uuid);
+        self.link
+            .0
+            .inode()
+            .data()
+            .write()
+            .await
+            .content_mut()
+            .push(uuid);
+        self.link.1.notify_waiters();
         Ok(4)
     }
 
     async fn readable(&self) -> Result<(), Error> {
-        Ok(())
+        datagram::queued(self.link.0.inode(), &self.link.1).await
     }
 
     async fn writable(&self) -> Result<(), Error> {
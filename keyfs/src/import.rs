@@ -0,0 +1,174 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use uuid::Uuid;
+use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+use crate::datagram;
+use crate::generate::import_der;
+use crate::Hooks;
+
+/// A socket importing keys managed outside the keep
+///
+/// Each write is an algorithm followed by a private key, encoded as PKCS#8
+/// DER, as PKCS#1 DER for RSA or as SEC1 DER for elliptic curves, or by the
+/// raw key material of a symmetric algorithm. The key gets the same subtree
+/// as one from `generate`. Each read returns the UUID of an imported key, in
+/// the order of the writes. A key which fails to decode is reported as
+/// `EILSEQ`.
+pub struct Import(Link<()>, Hooks);
+
+#[async_trait::async_trait]
+impl Node for Import {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenImport {
+            _root: self.root(),
+            link: self,
+            imported: Vec::new(),
+        }))
+    }
+}
+
+impl Import {
+    pub(crate) fn new(parent: Arc<dyn Node>, hooks: Hooks) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, ()), hooks))
+    }
+}
+
+struct OpenImport {
+    _root: Arc<dyn Node>,
+    link: Arc<Import>,
+
+    // The keys imported and not yet read, oldest first.
+    imported: Vec<Uuid>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenImport {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let uuid = self.imported.first().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(uuid.to_string().as_bytes(), bufs)?;
+        self.imported.remove(0);
+        Ok(n)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        datagram::peek_uuid(self.imported.first(), buf)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(datagram::ready_uuids(self.imported.len()))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let request: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        if request.len() < 4 {
+            return Err(Error::invalid_argument());
+        }
+
+        let keys = self
+            .link
+            .parent()
+            .ok_or_else(Error::io)?
+            .to_any()
+            .downcast::<Directory>()
+            .map_err(|_| Error::io())?;
+
+        let (algorithm, der) = request.split_at(4);
+        self.imported
+            .push(import_der(&keys, &self.link.1, algorithm, der).await?);
+        Ok(request.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        datagram::pending(!self.imported.is_empty()).await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
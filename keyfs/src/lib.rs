@@ -1,18 +1,37 @@
 use std::sync::Arc;
 
 use generate::Generate;
+use import::Import;
+use policy::Usages;
+use seal::Unseal;
 use trust::Trust;
 
 use wasi_common::Error;
 use wasmtime_vfs_dir::Directory;
 use wasmtime_vfs_memory::Node;
 
+mod attest;
+mod cipher;
+mod datagram;
+mod derive;
+mod eddsa;
 mod generate;
+mod import;
+mod mac;
+mod pke;
+mod policy;
+mod revoke;
+mod seal;
 mod share;
 mod sign;
+mod store;
 mod trust;
 mod verify;
 
+pub use attest::Attester;
+pub use policy::{Hash, Policy};
+pub use store::{Record, Store};
+
 pub const RS256: &[u8] = b"\x00\x00\x00\x00";
 pub const RS384: &[u8] = b"\x00\x00\x00\x01";
 pub const RS512: &[u8] = b"\x00\x00\x00\x02";
@@ -23,11 +42,90 @@ pub const ES256K: &[u8] = b"\x00\x00\x00\x06";
 pub const ES256: &[u8] = b"\x00\x00\x00\x07";
 pub const ES384: &[u8] = b"\x00\x00\x00\x08";
 pub const ES512: &[u8] = b"\x00\x00\x00\x09";
+pub const EDDSA: &[u8] = b"\x00\x00\x00\x0a";
+pub const A256GCM: &[u8] = b"\x00\x00\x01\x00";
+pub const C20P: &[u8] = b"\x00\x00\x01\x01";
+pub const HS256: &[u8] = b"\x00\x00\x02\x00";
+pub const HS384: &[u8] = b"\x00\x00\x02\x01";
+pub const HS512: &[u8] = b"\x00\x00\x02\x02";
 
 pub async fn new(parent: Arc<dyn Node>) -> Result<Arc<dyn Node>, Error> {
-    let dir = Directory::device(parent, None);
-    dir.attach("generate", Generate::new(dir.clone())).await?;
+    Builder::default().build(parent).await
+}
+
+/// What the embedder plugs into a keyfs
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    store: Option<Arc<dyn Store>>,
+    attester: Option<Arc<dyn Attester>>,
+    policy: Policy,
+    usages: Usages,
+
+    // Whether keys come from the store, so are not saved to it again.
+    restored: bool,
+}
+
+impl Hooks {
+    /// The hooks of keys which sockets of this key create later, which are
+    /// new and so are saved even when this key was restored.
+    fn live(&self) -> Self {
+        Self {
+            restored: false,
+            ..self.clone()
+        }
+    }
+}
+
+/// A builder of a keyfs with the hooks of its embedder
+#[derive(Default)]
+pub struct Builder(Hooks);
+
+impl Builder {
+    /// Persists keys in `store`, restoring those it has persisted.
+    pub fn store(mut self, store: Arc<dyn Store>) -> Self {
+        self.0.store = Some(store);
+        self
+    }
+
+    /// Attests keys generated inside the keep with `attester`.
+    pub fn attester(mut self, attester: Arc<dyn Attester>) -> Self {
+        self.0.attester = Some(attester);
+        self
+    }
+
+    /// Limits the use of each signing key created by `policy`.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.0.policy = policy;
+        self
+    }
+
+    /// Creates the keyfs, restoring the keys of the store under their UUIDs.
+    pub async fn build(self, parent: Arc<dyn Node>) -> Result<Arc<dyn Node>, Error> {
+        let dir = attach(Directory::device(parent, None), &self.0).await?;
+
+        if let Some(store) = &self.0.store {
+            let hooks = Hooks {
+                restored: true,
+                ..self.0.clone()
+            };
+
+            for record in store.load().await? {
+                generate::import(&dir, &hooks, &record).await?;
+            }
+        }
+
+        Ok(dir)
+    }
+}
+
+async fn attach(dir: Arc<Directory>, hooks: &Hooks) -> Result<Arc<Directory>, Error> {
+    let generate = Generate::new(dir.clone(), hooks.clone());
+    dir.attach("generate", generate).await?;
     dir.attach("trust", Trust::new(dir.clone())).await?;
+    dir.attach("unseal", Unseal::new(dir.clone(), hooks.clone()))
+        .await?;
+    dir.attach("import", Import::new(dir.clone(), hooks.clone()))
+        .await?;
     Ok(dir)
 }
 
@@ -38,16 +136,13 @@ mod test {
     use signature::{Signature, Signer, Verifier};
     use uuid::Uuid;
     use wasi_common::file::{FdFlags, OFlags};
-    use wasi_common::{Error, ErrorKind, WasiDir, WasiFile};
+    use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
     use wasmtime_vfs_ledger::Ledger;
 
     use super::*;
 
     async fn root(ledger: Arc<Ledger>) -> Result<Arc<dyn Node>, Error> {
-        let dir = Directory::root(ledger, None);
-        dir.attach("generate", Generate::new(dir.clone())).await?;
-        dir.attach("trust", Trust::new(dir.clone())).await?;
-        Ok(dir)
+        Ok(attach(Directory::root(ledger, None), &Hooks::default()).await?)
     }
 
     async fn open_file(
@@ -122,6 +217,87 @@ mod test {
         let vkey = p256::ecdsa::VerifyingKey::from(pubkey);
         let sig = p256::ecdsa::Signature::from_bytes(&signature).unwrap();
         vkey.verify(b"foo", &sig).unwrap();
+
+        // Reading the signature starts a new message.
+        write(&mut *sign, &[b"bar"], false).await.unwrap();
+        let signature: [u8; 64] = read(&mut *sign, true).await;
+        let sig = p256::ecdsa::Signature::from_bytes(&signature).unwrap();
+        vkey.verify(b"bar", &sig).unwrap();
+
+        // An empty write at the end discards the message so far.
+        write(&mut *sign, &[b"junk"], false).await.unwrap();
+        write(&mut *sign, &[], true).await.unwrap();
+        write(&mut *sign, &[b"baz"], false).await.unwrap();
+
+        // A truncated read keeps the signature for a larger one.
+        let mut short = [0u8; 32];
+        let mut slice = [IoSliceMut::new(&mut short)];
+        sign.read_vectored_at(&mut slice, u64::MAX)
+            .await
+            .unwrap_err();
+        let signature: [u8; 64] = read(&mut *sign, true).await;
+        assert_eq!(&short, &signature[..32]);
+        let sig = p256::ecdsa::Signature::from_bytes(&signature).unwrap();
+        vkey.verify(b"baz", &sig).unwrap();
+    }
+
+    #[tokio::test]
+    async fn formats() {
+        use base64ct::{Base64UrlUnpadded, Encoding};
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        use rsa::pkcs8::DecodePublicKey;
+        use rsa::PublicKeyParts;
+
+        async fn share(keys: &dyn WasiDir, path: &str) -> Vec<u8> {
+            let mut file = open_file(keys, path, true, false).await;
+            let mut buf = vec![0; file.num_ready_bytes().await.unwrap() as usize];
+            let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+            assert_eq!(n.unwrap() as usize, buf.len());
+            buf
+        }
+
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Elliptic curve keys are shared as SubjectPublicKeyInfo and JWK too.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+
+        let blob = share(&*keys, &format!("{uuid}/share")).await;
+        let der = share(&*keys, &format!("{uuid}/share.der")).await;
+        let point = p256::PublicKey::from_public_key_der(&der)
+            .unwrap()
+            .to_encoded_point(false);
+        assert_eq!(point.as_bytes(), &blob[4..]);
+
+        let jwk = share(&*keys, &format!("{uuid}/share.jwk")).await;
+        let x = Base64UrlUnpadded::encode_string(point.x().unwrap());
+        let y = Base64UrlUnpadded::encode_string(point.y().unwrap());
+        let expected = format!(r#"{{"kty":"EC","alg":"ES256","crv":"P-256","x":"{x}","y":"{y}"}}"#);
+        assert_eq!(String::from_utf8(jwk).unwrap(), expected);
+
+        // So are RSA keys, which any odd modulus stands in for here.
+        let mut n = vec![0xc5; 256];
+        n[255] |= 1;
+        let e = [1, 0, 1];
+        let mut trust = open_file(&*keys, "trust", true, true).await;
+        let (el, nl) = (3u32.to_be_bytes(), 256u32.to_be_bytes());
+        write(&mut *trust, &[RS256, &el, &e, &nl, &n], false)
+            .await
+            .unwrap();
+        let uuid: [u8; 36] = read(&mut *trust, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+
+        let der = share(&*keys, &format!("{uuid}/share.der")).await;
+        let public = rsa::RsaPublicKey::from_public_key_der(&der).unwrap();
+        assert_eq!(public.n().to_bytes_be(), n);
+        assert_eq!(public.e().to_bytes_be(), e);
+
+        let jwk = share(&*keys, &format!("{uuid}/share.jwk")).await;
+        let n = Base64UrlUnpadded::encode_string(&n);
+        let expected = format!(r#"{{"kty":"RSA","alg":"RS256","n":"{n}","e":"AQAB"}}"#);
+        assert_eq!(String::from_utf8(jwk).unwrap(), expected);
     }
 
     #[tokio::test]
@@ -168,6 +344,369 @@ mod test {
             std::mem::discriminant(&ErrorKind::Ilseq),
             std::mem::discriminant(&error.downcast::<ErrorKind>().unwrap())
         );
+
+        // Each verification starts a new message, and an empty write at the
+        // end discards the message so far.
+        sig[0] -= 1;
+        write(&mut *verify, &[b"junk"], false).await.unwrap();
+        write(&mut *verify, &[], true).await.unwrap();
+        write(&mut *verify, &[b"foo"], false).await.unwrap();
+        write(&mut *verify, &[&sig], true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn import() {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        use p256::pkcs8::{AssociatedOid, EncodePrivateKey};
+        use sec1::der::Encode;
+
+        let sk = p256::SecretKey::random(&mut rand::thread_rng());
+        let ep = sk.public_key().to_encoded_point(false);
+        let pkcs8 = sk.to_pkcs8_der().unwrap();
+        let sec1 = |curve| {
+            let key = sec1::EcPrivateKey {
+                private_key: &sk.to_be_bytes(),
+                parameters: Some(sec1::EcParameters::NamedCurve(curve)),
+                public_key: None,
+            };
+            key.to_vec().unwrap()
+        };
+
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+        let mut import = open_file(&*keys, "import", true, true).await;
+
+        // The key can be imported in either encoding, and then shared.
+        for der in [pkcs8.as_bytes(), &sec1(p256::NistP256::OID)] {
+            write(&mut *import, &[ES256, der], false).await.unwrap();
+            let uuid: [u8; 36] = read(&mut *import, false).await;
+            let uuid = std::str::from_utf8(&uuid).unwrap();
+
+            let mut share = open_file(&*keys, &format!("{uuid}/share"), true, false).await;
+            let pubkey: [u8; 69] = read(&mut *share, false).await;
+            assert_eq!(&pubkey[..4], ES256);
+            assert_eq!(&pubkey[4..], ep.as_bytes());
+            open_file(&*keys, &format!("{uuid}/sign"), true, true).await;
+        }
+
+        // Keys of another curve, or not keys at all, fail to decode.
+        for der in [&sec1(p384::NistP384::OID)[..], b"foo"] {
+            let error = write(&mut *import, &[ES256, der], false).await.unwrap_err();
+            assert!(matches!(
+                error.downcast::<ErrorKind>().unwrap(),
+                ErrorKind::Ilseq
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn eddsa() {
+        use base64ct::{Base64UrlUnpadded, Encoding};
+        use ed25519_dalek::pkcs8::EncodePrivateKey;
+
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Generate a key and share it: the algorithm, then the 32-byte key.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[EDDSA], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+        let mut share = open_file(&*keys, &format!("{uuid}/share"), true, false).await;
+        let shared: [u8; 4 + 32] = read(&mut *share, false).await;
+        assert_eq!(&shared[..4], EDDSA);
+        let public = ed25519_dalek::VerifyingKey::from_bytes(&shared[4..].try_into().unwrap());
+        let public = public.unwrap();
+
+        // It shares the same key as SubjectPublicKeyInfo and as a JWK.
+        let mut share = open_file(&*keys, &format!("{uuid}/share.der"), true, false).await;
+        let der: [u8; 12 + 32] = read(&mut *share, false).await;
+        assert_eq!(&der[12..], &shared[4..]);
+        let mut share = open_file(&*keys, &format!("{uuid}/share.jwk"), true, false).await;
+        let x = Base64UrlUnpadded::encode_string(&shared[4..]);
+        let expected = format!(r#"{{"kty":"OKP","alg":"EdDSA","crv":"Ed25519","x":"{x}"}}"#);
+        let mut jwk = vec![0u8; expected.len()];
+        let n = share.read_vectored(&mut [IoSliceMut::new(&mut jwk)]).await;
+        assert_eq!(n.unwrap(), expected.len() as u64);
+        assert_eq!(String::from_utf8(jwk).unwrap(), expected);
+
+        // Sign a message, which verifies as pure Ed25519.
+        let mut sign = open_file(&*keys, &format!("{uuid}/sign"), true, true).await;
+        write(&mut *sign, &[b"foo", b"bar"], false).await.unwrap();
+        let mut signature: [u8; 64] = read(&mut *sign, true).await;
+        let sig = ed25519_dalek::Signature::from_bytes(&signature);
+        public.verify_strict(b"foobar", &sig).unwrap();
+
+        // The verify socket of the key and of a trusted copy accept it.
+        let mut trust = open_file(&*keys, "trust", true, true).await;
+        write(&mut *trust, &[&shared], false).await.unwrap();
+        let trusted: [u8; 36] = read(&mut *trust, false).await;
+        let trusted = std::str::from_utf8(&trusted).unwrap();
+        for uuid in [uuid, trusted] {
+            let mut verify = open_file(&*keys, &format!("{uuid}/verify"), false, true).await;
+            write(&mut *verify, &[b"foobar"], false).await.unwrap();
+            write(&mut *verify, &[&signature], true).await.unwrap();
+        }
+
+        // A bad signature fails.
+        signature[0] ^= 1;
+        let mut verify = open_file(&*keys, &format!("{trusted}/verify"), false, true).await;
+        write(&mut *verify, &[b"foobar"], false).await.unwrap();
+        let error = write(&mut *verify, &[&signature], true).await.unwrap_err();
+        assert!(matches!(
+            error.downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Ilseq
+        ));
+
+        // A key imported from PKCS#8 shares its public key.
+        let sk = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let pkcs8 = sk.to_pkcs8_der().unwrap();
+        let mut import = open_file(&*keys, "import", true, true).await;
+        write(&mut *import, &[EDDSA, pkcs8.as_bytes()], false)
+            .await
+            .unwrap();
+        let uuid: [u8; 36] = read(&mut *import, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+        let mut share = open_file(&*keys, &format!("{uuid}/share"), true, false).await;
+        let shared: [u8; 4 + 32] = read(&mut *share, false).await;
+        assert_eq!(&shared[4..], sk.verifying_key().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn cipher() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        for algorithm in [A256GCM, C20P] {
+            // Generate a key.
+            let mut generate = open_file(&*keys, "generate", true, true).await;
+            write(&mut *generate, &[algorithm], false).await.unwrap();
+            let uuid: [u8; 36] = read(&mut *generate, false).await;
+            let uuid: Uuid = std::str::from_utf8(&uuid).unwrap().parse().unwrap();
+
+            // Encrypt a message: a 12-byte nonce, then the sealed message and tag.
+            let mut encrypt = open_file(&*keys, &format!("{uuid}/encrypt"), true, true).await;
+            write(&mut *encrypt, &[b"foo", b"bar"], false)
+                .await
+                .unwrap();
+            let mut sealed: [u8; 12 + 6 + 16] = read(&mut *encrypt, true).await;
+
+            // Decrypt it.
+            let mut decrypt = open_file(&*keys, &format!("{uuid}/decrypt"), true, true).await;
+            write(&mut *decrypt, &[&sealed], false).await.unwrap();
+            let opened: [u8; 6] = read(&mut *decrypt, true).await;
+            assert_eq!(&opened, b"foobar");
+
+            // Check that a tampered ciphertext fails.
+            sealed[12] ^= 1;
+            write(&mut *decrypt, &[&sealed], false).await.unwrap();
+            let mut buf = [0u8; 6];
+            let mut slice = [IoSliceMut::new(&mut buf)];
+            let error = decrypt.read_vectored_at(&mut slice, u64::MAX).await;
+            assert!(matches!(
+                error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+                ErrorKind::Ilseq
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn pke() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Generate an elliptic curve key and an RSA key.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let ec: [u8; 36] = read(&mut *generate, false).await;
+        let ec = std::str::from_utf8(&ec).unwrap();
+        write(&mut *generate, &[RS256], false).await.unwrap();
+        let rsa: [u8; 36] = read(&mut *generate, false).await;
+        let rsa = std::str::from_utf8(&rsa).unwrap();
+
+        // ECIES: an ephemeral point, then the sealed message and tag.
+        let mut encrypt = open_file(&*keys, &format!("{ec}/encrypt"), true, true).await;
+        write(&mut *encrypt, &[b"foo", b"bar"], false)
+            .await
+            .unwrap();
+        let mut sealed: [u8; 65 + 6 + 16] = read(&mut *encrypt, true).await;
+
+        let mut decrypt = open_file(&*keys, &format!("{ec}/decrypt"), true, true).await;
+        write(&mut *decrypt, &[&sealed], false).await.unwrap();
+        let opened: [u8; 6] = read(&mut *decrypt, true).await;
+        assert_eq!(&opened, b"foobar");
+
+        // A tampered ciphertext fails.
+        sealed[70] ^= 1;
+        write(&mut *decrypt, &[&sealed], false).await.unwrap();
+        let mut buf = [0u8; 6];
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        let error = decrypt.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Ilseq
+        ));
+
+        // RSA-OAEP: one block of the modulus size.
+        let mut encrypt = open_file(&*keys, &format!("{rsa}/encrypt"), true, true).await;
+        write(&mut *encrypt, &[b"foobar"], false).await.unwrap();
+        let sealed: [u8; 256] = read(&mut *encrypt, true).await;
+
+        let mut decrypt = open_file(&*keys, &format!("{rsa}/decrypt"), true, true).await;
+        write(&mut *decrypt, &[&sealed], false).await.unwrap();
+        let opened: [u8; 6] = read(&mut *decrypt, true).await;
+        assert_eq!(&opened, b"foobar");
+
+        // A trusted public key can only encrypt, for its owner to decrypt.
+        let mut share = open_file(&*keys, &format!("{ec}/share"), true, false).await;
+        let pubkey: [u8; 69] = read(&mut *share, false).await;
+        let mut trust = open_file(&*keys, "trust", true, true).await;
+        write(&mut *trust, &[&pubkey], false).await.unwrap();
+        let trusted: [u8; 36] = read(&mut *trust, false).await;
+        let trusted = std::str::from_utf8(&trusted).unwrap();
+
+        let path = format!("{trusted}/decrypt");
+        let flags = FdFlags::empty();
+        let decrypt = keys.open_file(false, &path, OFlags::empty(), true, true, flags);
+        decrypt.await.err().unwrap();
+
+        let mut encrypt = open_file(&*keys, &format!("{trusted}/encrypt"), true, true).await;
+        write(&mut *encrypt, &[b"foobar"], false).await.unwrap();
+        let sealed: [u8; 65 + 6 + 16] = read(&mut *encrypt, true).await;
+
+        let mut decrypt = open_file(&*keys, &format!("{ec}/decrypt"), true, true).await;
+        write(&mut *decrypt, &[&sealed], false).await.unwrap();
+        let opened: [u8; 6] = read(&mut *decrypt, true).await;
+        assert_eq!(&opened, b"foobar");
+    }
+
+    #[tokio::test]
+    async fn mac() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Generate a key.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[HS256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid: Uuid = std::str::from_utf8(&uuid).unwrap().parse().unwrap();
+
+        // The tag of a message does not depend on how it was written.
+        let mut mac = open_file(&*keys, &format!("{uuid}/mac"), true, true).await;
+        write(&mut *mac, &[b"foo", b"bar"], false).await.unwrap();
+        let tag: [u8; 32] = read(&mut *mac, true).await;
+        write(&mut *mac, &[b"foobar"], false).await.unwrap();
+        assert_eq!(read::<32>(&mut *mac, true).await, tag);
+
+        // Derive a key twice, then with another salt.
+        let mut derive = open_file(&*keys, &format!("{uuid}/derive"), true, true).await;
+        let len = 4u32.to_be_bytes();
+        for salt in [b"salt", b"salt", b"SALT"] {
+            write(&mut *derive, &[HS256, &len, salt, b"info"], false)
+                .await
+                .unwrap();
+        }
+
+        let mut tags = Vec::new();
+        for _ in 0..3 {
+            let uuid: [u8; 36] = read(&mut *derive, false).await;
+            let uuid = std::str::from_utf8(&uuid).unwrap();
+            let mut mac = open_file(&*keys, &format!("{uuid}/mac"), true, true).await;
+            write(&mut *mac, &[b"foobar"], false).await.unwrap();
+            tags.push(read::<32>(&mut *mac, true).await);
+        }
+
+        // Check that derivation is deterministic, and depends on the salt.
+        assert_eq!(tags[0], tags[1]);
+        assert_ne!(tags[0], tags[2]);
+        assert_ne!(tags[0], tag);
+
+        // Unknown algorithms are refused.
+        let error = write(&mut *derive, &[b"\xff\xff\xff\xff", &len, b"salt"], false).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Ilseq
+        ));
+
+        // Cipher keys derive keys too, without exposing their material.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[A256GCM], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+
+        let mut derive = open_file(&*keys, &format!("{uuid}/derive"), true, true).await;
+        let mut tags = Vec::new();
+        for _ in 0..2 {
+            write(&mut *derive, &[HS256, &len, b"salt", b"session"], false)
+                .await
+                .unwrap();
+            let uuid: [u8; 36] = read(&mut *derive, false).await;
+            let uuid = std::str::from_utf8(&uuid).unwrap();
+            let mut mac = open_file(&*keys, &format!("{uuid}/mac"), true, true).await;
+            write(&mut *mac, &[b"foobar"], false).await.unwrap();
+            tags.push(read::<32>(&mut *mac, true).await);
+        }
+        assert_eq!(tags[0], tags[1]);
+    }
+
+    #[tokio::test]
+    async fn seal() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Generate a signing key and a key encryption key.
+        let mut uuids = Vec::new();
+        for algorithm in [ES256, A256GCM] {
+            let mut generate = open_file(&*keys, "generate", true, true).await;
+            write(&mut *generate, &[algorithm], false).await.unwrap();
+            let uuid: [u8; 36] = read(&mut *generate, false).await;
+            uuids.push(uuid);
+        }
+        let [key, kek] = [0, 1].map(|i| std::str::from_utf8(&uuids[i]).unwrap());
+
+        // Seal the signing key: the nonce, the sealed algorithm and scalar, then the tag.
+        let mut seal = open_file(&*keys, &format!("{key}/seal"), true, true).await;
+        write(&mut *seal, &[kek.as_bytes()], false).await.unwrap();
+        let mut sealed: [u8; 12 + 4 + 32 + 16] = read(&mut *seal, false).await;
+
+        // The decrypt socket of the key encryption key refuses sealed keys.
+        let mut decrypt = open_file(&*keys, &format!("{kek}/decrypt"), true, true).await;
+        write(&mut *decrypt, &[&sealed], false).await.unwrap();
+        let mut buf = [0u8; 4 + 32];
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        let error = decrypt.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Ilseq
+        ));
+
+        // Unseal it as a new key with the same public key.
+        let mut unseal = open_file(&*keys, "unseal", true, true).await;
+        write(&mut *unseal, &[kek.as_bytes(), &sealed], false)
+            .await
+            .unwrap();
+        let copy: [u8; 36] = read(&mut *unseal, false).await;
+        let copy = std::str::from_utf8(&copy).unwrap();
+        assert_ne!(copy, key);
+
+        let mut share = open_file(&*keys, &format!("{key}/share"), true, false).await;
+        let original: [u8; 69] = read(&mut *share, false).await;
+        let mut share = open_file(&*keys, &format!("{copy}/share"), true, false).await;
+        assert_eq!(read::<69>(&mut *share, false).await, original);
+
+        // Check that a tampered key fails to unseal.
+        sealed[12] ^= 1;
+        let error = write(&mut *unseal, &[kek.as_bytes(), &sealed], false).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Ilseq
+        ));
+
+        // A key pair seals with ECIES: the point, the sealed key, then the tag.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let pair: [u8; 36] = read(&mut *generate, false).await;
+        write(&mut *seal, &[&pair], false).await.unwrap();
+        let sealed: [u8; 65 + 4 + 32 + 16] = read(&mut *seal, false).await;
+        write(&mut *unseal, &[&pair, &sealed], false).await.unwrap();
+        let copy: [u8; 36] = read(&mut *unseal, false).await;
+        let copy = std::str::from_utf8(&copy).unwrap();
+        let mut share = open_file(&*keys, &format!("{copy}/share"), true, false).await;
+        assert_eq!(read::<69>(&mut *share, false).await, original);
     }
 
     #[tokio::test]
@@ -190,7 +729,13 @@ mod test {
             .find(|x| &uuid.as_hyphenated().to_string() == x)
             .unwrap();
 
-        // Remove the key.
+        // Remove the key, which must be emptied first.
+        let names = [
+            "sign", "verify", "encrypt", "decrypt", "seal", "policy", "revoke",
+        ];
+        for name in names.iter().chain(&["share", "share.der", "share.jwk"]) {
+            keys.unlink_file(&format!("{uuid}/{name}")).await.unwrap();
+        }
         keys.remove_dir(&uuid.to_string()).await.unwrap();
 
         // Ensure the key does not appear in the directory listing.
@@ -202,4 +747,381 @@ mod test {
             .any(|x| uuid.as_hyphenated().to_string() == x);
         assert!(!found);
     }
+
+    #[tokio::test]
+    async fn revoke() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Generate a signing key and a MAC key.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let es256: [u8; 36] = read(&mut *generate, false).await;
+        let es256 = std::str::from_utf8(&es256).unwrap();
+        write(&mut *generate, &[HS256], false).await.unwrap();
+        let hs256: [u8; 36] = read(&mut *generate, false).await;
+        let hs256 = std::str::from_utf8(&hs256).unwrap();
+
+        // Removing the sign socket stops a handle which is still open.
+        let mut sign = open_file(&*keys, &format!("{es256}/sign"), true, true).await;
+        write(&mut *sign, &[b"foo"], false).await.unwrap();
+        keys.unlink_file(&format!("{es256}/sign")).await.unwrap();
+        let mut signature = [0u8; 64];
+        let mut slice = [IoSliceMut::new(&mut signature)];
+        let error = sign.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Badf
+        ));
+
+        // Revoking a key stops the handles to all of its sockets.
+        let mut mac = open_file(&*keys, &format!("{hs256}/mac"), true, true).await;
+        let mut seal = open_file(&*keys, &format!("{hs256}/seal"), true, true).await;
+        write(&mut *mac, &[b"foo"], false).await.unwrap();
+        let mut revoke = open_file(&*keys, &format!("{hs256}/revoke"), false, true).await;
+        write(&mut *revoke, &[b"1"], false).await.unwrap();
+        let error = write(&mut *mac, &[b"bar"], false).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Badf
+        ));
+        let error = write(&mut *seal, &[hs256.as_bytes()], false).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Badf
+        ));
+
+        // New handles cannot be opened.
+        let path = format!("{hs256}/mac");
+        let error = keys
+            .open_file(false, &path, OFlags::empty(), true, true, FdFlags::empty())
+            .await;
+        assert!(matches!(
+            error.err().unwrap().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Badf
+        ));
+    }
+
+    #[tokio::test]
+    async fn remove_all() {
+        let root = attach(Directory::root(Ledger::new(), None), &Hooks::default());
+        let root = root.await.unwrap();
+        let keys = root.clone().open_dir().await.unwrap();
+
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+
+        // Removing the key directory from the host revokes the key below it.
+        let mut sign = open_file(&*keys, &format!("{uuid}/sign"), true, true).await;
+        write(&mut *sign, &[b"foo"], false).await.unwrap();
+        root.remove_all(uuid).await.unwrap();
+        let mut signature = [0u8; 64];
+        let mut slice = [IoSliceMut::new(&mut signature)];
+        let error = sign.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Badf
+        ));
+    }
+
+    #[tokio::test]
+    async fn ready() {
+        let keys = root(Ledger::new()).await.unwrap().open_dir().await.unwrap();
+
+        // Nothing is pending at first.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        assert_eq!(generate.num_ready_bytes().await.unwrap(), 0);
+        let mut buf = [0u8; 36];
+        generate.peek(&mut buf).await.unwrap_err();
+
+        // Each pending key is ready as its name.
+        write(&mut *generate, &[HS256], false).await.unwrap();
+        write(&mut *generate, &[HS256], false).await.unwrap();
+        assert_eq!(generate.num_ready_bytes().await.unwrap(), 72);
+
+        // Peeking does not consume, and a short peek truncates.
+        let mut short = [0u8; 8];
+        assert_eq!(generate.peek(&mut short).await.unwrap(), 8);
+        assert_eq!(generate.peek(&mut buf).await.unwrap(), 36);
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        assert_eq!(uuid, buf);
+        assert_eq!(&short, &uuid[..8]);
+        assert_eq!(generate.num_ready_bytes().await.unwrap(), 36);
+
+        // The share socket reports its whole blob.
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let key: [u8; 36] = read(&mut *generate, false).await;
+        let key = std::str::from_utf8(&key).unwrap();
+        let share = open_file(&*keys, &format!("{key}/share"), true, false).await;
+        assert_eq!(share.num_ready_bytes().await.unwrap(), 69);
+    }
+
+    #[tokio::test]
+    async fn store() {
+        #[derive(Default)]
+        struct Memory(std::sync::Mutex<Vec<Record>>, bool);
+
+        #[async_trait::async_trait]
+        impl Store for Memory {
+            async fn save(&self, record: Record) -> Result<(), Error> {
+                if self.1 {
+                    return Err(Error::io());
+                }
+
+                self.0.lock().unwrap().push(record);
+                Ok(())
+            }
+
+            async fn remove(&self, uuid: Uuid) {
+                self.0.lock().unwrap().retain(|record| record.uuid != uuid);
+            }
+
+            async fn load(&self) -> Result<Vec<Record>, Error> {
+                Ok(self.0.lock().unwrap().clone())
+            }
+        }
+
+        let store = Arc::new(Memory::default());
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default()
+            .store(store.clone())
+            .build(parent)
+            .await
+            .unwrap();
+        let keys = keys.open_dir().await.unwrap();
+
+        // Generated and imported keys are saved.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let ec: [u8; 36] = read(&mut *generate, false).await;
+        let ec = std::str::from_utf8(&ec).unwrap().to_string();
+        let mut share = open_file(&*keys, &format!("{ec}/share"), true, false).await;
+        let pubkey: [u8; 69] = read(&mut *share, false).await;
+
+        let mut import = open_file(&*keys, "import", true, true).await;
+        write(&mut *import, &[HS256, &[7u8; 32]], false)
+            .await
+            .unwrap();
+        let hs: [u8; 36] = read(&mut *import, false).await;
+        let hs = std::str::from_utf8(&hs).unwrap().to_string();
+        assert_eq!(store.0.lock().unwrap().len(), 2);
+
+        // A restarted keyfs restores them under the same UUIDs.
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default()
+            .store(store.clone())
+            .build(parent)
+            .await
+            .unwrap();
+        let keys = keys.open_dir().await.unwrap();
+        let mut share = open_file(&*keys, &format!("{ec}/share"), true, false).await;
+        let restored: [u8; 69] = read(&mut *share, false).await;
+        assert_eq!(restored, pubkey);
+        open_file(&*keys, &format!("{hs}/mac"), true, true).await;
+        assert_eq!(store.0.lock().unwrap().len(), 2);
+
+        // A revoked key is removed from the store, so is not restored.
+        let mut revoke = open_file(&*keys, &format!("{hs}/revoke"), false, true).await;
+        write(&mut *revoke, &[b"1"], false).await.unwrap();
+        assert_eq!(store.0.lock().unwrap().len(), 1);
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default()
+            .store(store.clone())
+            .build(parent)
+            .await
+            .unwrap();
+        let keys = keys.open_dir().await.unwrap();
+        open_file(&*keys, &format!("{ec}/share"), true, false).await;
+        let error = keys
+            .open_file(
+                false,
+                &format!("{hs}/mac"),
+                OFlags::empty(),
+                true,
+                true,
+                FdFlags::empty(),
+            )
+            .await;
+        assert!(matches!(
+            error.err().unwrap().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Noent
+        ));
+
+        // A key which cannot be saved is not created.
+        let failing = Arc::new(Memory(Default::default(), true));
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default()
+            .store(failing)
+            .build(parent)
+            .await
+            .unwrap();
+        let keys = keys.open_dir().await.unwrap();
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[HS256], false).await.unwrap_err();
+        let names = keys.readdir(0.into()).await.unwrap().count();
+        assert_eq!(names, 2 + 4);
+    }
+
+    #[tokio::test]
+    async fn attest() {
+        struct Concat;
+
+        #[async_trait::async_trait]
+        impl Attester for Concat {
+            async fn attest(&self, public: &[u8], nonce: &[u8]) -> Result<Vec<u8>, Error> {
+                Ok([public, nonce].concat())
+            }
+        }
+
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default().attester(Arc::new(Concat)).build(parent);
+        let keys = keys.await.unwrap().open_dir().await.unwrap();
+
+        // A generated key is attested along with the nonce.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+        let mut share = open_file(&*keys, &format!("{uuid}/share"), true, false).await;
+        let pubkey: [u8; 69] = read(&mut *share, false).await;
+
+        let mut attest = open_file(&*keys, &format!("{uuid}/attest"), true, true).await;
+        write(&mut *attest, &[b"nonce"], false).await.unwrap();
+        let document: [u8; 69 + 5] = read(&mut *attest, true).await;
+        assert_eq!(&document[..69], &pubkey);
+        assert_eq!(&document[69..], b"nonce");
+
+        // An imported key was not generated in the keep.
+        let sk = p256::SecretKey::random(&mut rand::thread_rng());
+        let mut import = open_file(&*keys, "import", true, true).await;
+        write(&mut *import, &[ES256, &sk.to_sec1_der().unwrap()], false)
+            .await
+            .unwrap();
+        let uuid: [u8; 36] = read(&mut *import, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+        let path = format!("{uuid}/attest");
+        let flags = FdFlags::empty();
+        let attest = keys.open_file(false, &path, OFlags::empty(), true, true, flags);
+        attest.await.err().unwrap();
+    }
+
+    #[tokio::test]
+    async fn policy() {
+        let policy = Policy {
+            max_signatures: Some(1),
+            expires: None,
+            digests: Some(vec![Hash::Sha256]),
+        };
+
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default().policy(policy.clone()).build(parent);
+        let keys = keys.await.unwrap().open_dir().await.unwrap();
+
+        // A key may only be created with an allowed digest.
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        let error = write(&mut *generate, &[ES384], false).await.unwrap_err();
+        assert!(matches!(
+            error.downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Perm
+        ));
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+
+        // Signing fails once the signatures are used up.
+        let mut sign = open_file(&*keys, &format!("{uuid}/sign"), true, true).await;
+        write(&mut *sign, &[b"foo"], false).await.unwrap();
+        let _: [u8; 64] = read(&mut *sign, true).await;
+        let mut buf = [0u8; 64];
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        let error = sign.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Perm
+        ));
+
+        // A copy of the key, sealed and unsealed again, shares the counter.
+        write(&mut *generate, &[A256GCM], false).await.unwrap();
+        let kek: [u8; 36] = read(&mut *generate, false).await;
+        let mut seal = open_file(&*keys, &format!("{uuid}/seal"), true, true).await;
+        write(&mut *seal, &[&kek], false).await.unwrap();
+        let sealed: [u8; 12 + 4 + 32 + 16] = read(&mut *seal, false).await;
+        let mut unseal = open_file(&*keys, "unseal", true, true).await;
+        write(&mut *unseal, &[&kek, &sealed], false).await.unwrap();
+        let copy: [u8; 36] = read(&mut *unseal, false).await;
+        let copy = std::str::from_utf8(&copy).unwrap();
+        let mut sign = open_file(&*keys, &format!("{copy}/sign"), true, true).await;
+        write(&mut *sign, &[b"foo"], false).await.unwrap();
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        let error = sign.read_vectored_at(&mut slice, u64::MAX).await;
+        assert!(matches!(
+            error.unwrap_err().downcast::<ErrorKind>().unwrap(),
+            ErrorKind::Perm
+        ));
+
+        // The policy file shows the state.
+        let mut state = open_file(&*keys, &format!("{uuid}/policy"), true, false).await;
+        let expected = r#"{"digest":"SHA-256","signatures":1,"max_signatures":1,"expires":null}"#;
+        let mut buf = vec![0u8; expected.len()];
+        let n = state.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!(n.unwrap(), expected.len() as u64);
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        // An expired key cannot sign at all.
+        let policy = Policy {
+            expires: Some(std::time::SystemTime::UNIX_EPOCH),
+            ..Default::default()
+        };
+        let parent = Directory::root(Ledger::new(), None);
+        let keys = Builder::default().policy(policy).build(parent);
+        let keys = keys.await.unwrap().open_dir().await.unwrap();
+        let mut generate = open_file(&*keys, "generate", true, true).await;
+        write(&mut *generate, &[ES256], false).await.unwrap();
+        let uuid: [u8; 36] = read(&mut *generate, false).await;
+        let uuid = std::str::from_utf8(&uuid).unwrap();
+        let mut sign = open_file(&*keys, &format!("{uuid}/sign"), true, true).await;
+        let mut slice = [IoSliceMut::new(&mut buf)];
+        sign.read_vectored_at(&mut slice, u64::MAX)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn queued() {
+        let root = root(Ledger::new()).await.unwrap();
+        let device = root.id().device();
+        let keys = root.open_dir().await.unwrap();
+
+        // A reader waits until another handle queues a key.
+        let reader = open_file(&*keys, "generate", true, true).await;
+        let mut writer = open_file(&*keys, "generate", true, true).await;
+        let (ready, _) = tokio::join!(reader.readable(), async {
+            write(&mut *writer, &[HS256], false).await.unwrap();
+        });
+        ready.unwrap();
+        reader.readable().await.unwrap();
+
+        // A handle queueing its own replies is readable only with one pending.
+        let mut import = open_file(&*keys, "import", true, true).await;
+        let ready = tokio::select! {
+            biased;
+            ready = import.readable() => Some(ready),
+            _ = std::future::ready(()) => None,
+        };
+        assert!(ready.is_none());
+        let key = [7u8; 32];
+        write(&mut *import, &[HS256, &key], false).await.unwrap();
+        import.readable().await.unwrap();
+
+        // Waiting stops when the host cancels IO on the device.
+        let trust = open_file(&*keys, "trust", true, true).await;
+        device.cancel();
+        let err = trust.readable().await.unwrap_err();
+        assert_eq!(
+            err.downcast::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::Interrupted
+        );
+        device.resume();
+    }
 }
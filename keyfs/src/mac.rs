@@ -0,0 +1,190 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+/// A socket authenticating messages with an HMAC key
+///
+/// The message is written in any number of writes. Reading at offset
+/// `u64::MAX` then returns the tag and starts a new message.
+pub struct Mac<M>(Link<Option<M>>);
+
+#[async_trait::async_trait]
+impl<M: hmac::Mac + Clone + Send + Sync + 'static> Node for Mac<M> {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn revoke(&self) {
+        *self.0.inode().data().write().await.content_mut() = None;
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        let state = self.0.inode().data().read().await.content().clone();
+        state.as_ref().ok_or_else(Error::badf)?;
+
+        Ok(Box::new(OpenMac {
+            _root: self.root(),
+            link: self,
+            state,
+            output: None,
+        }))
+    }
+}
+
+impl<M> Mac<M> {
+    pub fn new(parent: Arc<dyn Node>, key: M) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, Some(key))))
+    }
+}
+
+struct OpenMac<M> {
+    _root: Arc<dyn Node>,
+    link: Arc<Mac<M>>,
+
+    // The state of the message, dropped once the key is revoked.
+    state: Option<M>,
+
+    // The tag of the message, kept until read in full.
+    output: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl<M: hmac::Mac + Clone + Send + Sync + 'static> WasiFile for OpenMac<M> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        // Writing after a tag which was never read starts over.
+        self.output = None;
+
+        if self.link.0.inode().data().read().await.content().is_none() {
+            self.state = None;
+        }
+
+        let state = self.state.as_mut().ok_or_else(Error::badf)?;
+        let mut total = 0;
+
+        for buf in bufs {
+            state.update(buf);
+            total += buf.len();
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        if offset != u64::MAX {
+            return Err(Error::invalid_argument());
+        }
+
+        // Finish the message, resetting the state for the next one.
+        let output = match self.output.take() {
+            Some(output) => output,
+            None => {
+                let key = self.link.0.inode().data().read().await.content().clone();
+                let state = std::mem::replace(&mut self.state, key);
+                state
+                    .ok_or_else(Error::badf)?
+                    .finalize()
+                    .into_bytes()
+                    .to_vec()
+            }
+        };
+
+        // Copy the tag into the buffer.
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), output.len() - total);
+            buf[..len].copy_from_slice(&output[total..][..len]);
+            total += len;
+        }
+
+        // Detect truncation, keeping the tag for a larger read.
+        if total < output.len() {
+            self.output = Some(output);
+            return Err(Error::too_big());
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use aes_gcm::aead::generic_array::typenum::Unsigned;
+use aes_gcm::aead::{Aead, KeyInit, Nonce, Payload};
+use aes_gcm::Aes256Gcm;
+use ecdsa::elliptic_curve::ecdh::{diffie_hellman, EphemeralSecret, SharedSecret};
+use ecdsa::elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use ecdsa::elliptic_curve::{AffinePoint, Curve, FieldSize, ProjectiveArithmetic};
+use ecdsa::elliptic_curve::{PublicKey, SecretKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{PaddingScheme, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use wasi_common::{Error, ErrorExt, ErrorKind};
+use wasmtime_vfs_dir::Directory;
+
+use crate::cipher::{Cipher, Crypt, Mode};
+use crate::trust::Decoder;
+use crate::{EDDSA, ES256, ES256K, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+
+const INFO: &[u8] = b"wasmtime-vfs-keyfs ecies";
+
+/// An RSA key pair encrypting with OAEP and SHA-256
+pub(crate) struct Oaep {
+    public: RsaPublicKey,
+    secret: Option<RsaPrivateKey>,
+}
+
+impl Crypt for Oaep {
+    fn seal(&self, domain: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
+        use rsa::PublicKey;
+
+        let padding = PaddingScheme::new_oaep_with_label::<Sha256, _>(domain);
+        let sealed = self
+            .public
+            .encrypt(&mut rand::thread_rng(), padding, message);
+        sealed.map_err(|_| Error::too_big())
+    }
+
+    fn open(&self, domain: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let secret = self.secret.as_ref().ok_or_else(Error::perm)?;
+        let padding = PaddingScheme::new_oaep_with_label::<Sha256, _>(domain);
+        let opened = secret.decrypt_blinded(&mut rand::thread_rng(), padding, ciphertext);
+        opened.map_err(|_| ErrorKind::Ilseq.into())
+    }
+}
+
+/// An elliptic curve key pair encrypting with ECIES
+///
+/// Ciphertext is an ephemeral public key, as an uncompressed SEC1 point,
+/// followed by the message sealed with AES-256-GCM. The AES key comes from
+/// HKDF-SHA256 over the shared secret, salted with the ephemeral key, and is
+/// only used once, so the nonce is zero. The domain is the associated data.
+pub(crate) struct Ecies<C: Curve + ProjectiveArithmetic> {
+    public: PublicKey<C>,
+    secret: Option<SecretKey<C>>,
+}
+
+// Derives the single use AES key of a message.
+fn cipher<C: Curve>(shared: SharedSecret<C>, ephemeral: &[u8]) -> Result<Aes256Gcm, Error> {
+    let mut key = [0u8; 32];
+    let hkdf = shared.extract::<Sha256>(Some(ephemeral));
+    hkdf.expand(INFO, &mut key).map_err(|_| Error::io())?;
+    Aes256Gcm::new_from_slice(&key).map_err(|_| Error::io())
+}
+
+impl<C> Crypt for Ecies<C>
+where
+    C: Curve + ProjectiveArithmetic,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldSize<C>: ModulusSize,
+{
+    fn seal(&self, domain: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let ephemeral = EphemeralSecret::<C>::random(&mut rand::thread_rng());
+        let point = ephemeral.public_key().to_encoded_point(false);
+        let shared = ephemeral.diffie_hellman(&self.public);
+
+        let nonce = Nonce::<Aes256Gcm>::default();
+        let payload = Payload {
+            msg: message,
+            aad: domain.as_bytes(),
+        };
+        let sealed = cipher(shared, point.as_bytes())?
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::too_big())?;
+
+        let mut output = point.as_bytes().to_vec();
+        output.extend_from_slice(&sealed);
+        Ok(output)
+    }
+
+    fn open(&self, domain: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let secret = self.secret.as_ref().ok_or_else(Error::perm)?;
+
+        let size = 1 + 2 * FieldSize::<C>::USIZE;
+        if ciphertext.len() < size {
+            return Err(ErrorKind::Ilseq.into());
+        }
+
+        let (point, sealed) = ciphertext.split_at(size);
+        let ephemeral = PublicKey::<C>::from_sec1_bytes(point);
+        let ephemeral = ephemeral.map_err(|_| Error::from(ErrorKind::Ilseq))?;
+        let shared = diffie_hellman(secret.to_nonzero_scalar(), ephemeral.as_affine());
+
+        let nonce = Nonce::<Aes256Gcm>::default();
+        let payload = Payload {
+            msg: sealed,
+            aad: domain.as_bytes(),
+        };
+        cipher(shared, point)?
+            .decrypt(&nonce, payload)
+            .map_err(|_| ErrorKind::Ilseq.into())
+    }
+}
+
+// Decodes an elliptic curve key pair from its shared point and raw scalar.
+fn ecies<C>(point: &[u8], material: Option<&[u8]>) -> Result<Ecies<C>, Error>
+where
+    C: Curve + ProjectiveArithmetic,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldSize<C>: ModulusSize,
+{
+    let public = PublicKey::from_sec1_bytes(point).map_err(|_| Error::illegal_byte_sequence())?;
+    let secret = material
+        .map(SecretKey::from_be_bytes)
+        .transpose()
+        .map_err(|_| Error::illegal_byte_sequence())?;
+
+    Ok(Ecies { public, secret })
+}
+
+async fn add<A: Crypt + Send + Sync + 'static>(
+    d: &Arc<Directory>,
+    key: A,
+    secret: bool,
+) -> Result<(), Error> {
+    let key = Arc::new(key);
+    let encrypt = Cipher::new(d.clone(), Mode::Encrypt, key.clone());
+    d.attach("encrypt", encrypt).await?;
+
+    if secret {
+        let decrypt = Cipher::new(d.clone(), Mode::Decrypt, key);
+        d.attach("decrypt", decrypt).await?;
+    }
+
+    Ok(())
+}
+
+/// Attaches the `encrypt` socket of the shared public key `blob` to `d`,
+/// and the `decrypt` socket if the private key `material` is known
+pub(crate) async fn attach(
+    d: &Arc<Directory>,
+    blob: &[u8],
+    material: Option<&[u8]>,
+) -> Result<(), Error> {
+    if blob.len() < 4 {
+        return Err(Error::illegal_byte_sequence());
+    }
+
+    let (algorithm, key) = blob.split_at(4);
+    let secret = material.is_some();
+
+    match algorithm {
+        RS256 | RS384 | RS512 | PS256 | PS384 | PS512 => {
+            let private = material
+                .map(RsaPrivateKey::from_pkcs8_der)
+                .transpose()
+                .map_err(|_| Error::illegal_byte_sequence())?;
+            let public = RsaPublicKey::decode(key)?;
+            let key = Oaep {
+                public,
+                secret: private,
+            };
+            add(d, key, secret).await
+        }
+
+        ES256K => add(d, ecies::<k256::Secp256k1>(key, material)?, secret).await,
+        ES256 => add(d, ecies::<p256::NistP256>(key, material)?, secret).await,
+        ES384 => add(d, ecies::<p384::NistP384>(key, material)?, secret).await,
+
+        // Ed25519 keys only sign.
+        EDDSA => Ok(()),
+        _ => Err(Error::illegal_byte_sequence()),
+    }
+}
@@ -0,0 +1,267 @@
+use std::any::Any;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::IoSliceMut;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::SystemTime;
+
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+use crate::datagram;
+
+/// A digest a signing key may hash messages with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Hash {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    fn name(self) -> &'static str {
+        match self {
+            Hash::Sha256 => "SHA-256",
+            Hash::Sha384 => "SHA-384",
+            Hash::Sha512 => "SHA-512",
+        }
+    }
+}
+
+/// Limits on the use of each signing key
+///
+/// Signing fails with `EPERM` once a key has made `max_signatures` or after
+/// `expires`, and a key whose digest is not in `digests` cannot be created.
+/// Copies of a key, such as one sealed and unsealed again, share a counter,
+/// but counters start over when a store restores a key.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    pub max_signatures: Option<u64>,
+    pub expires: Option<SystemTime>,
+    pub digests: Option<Vec<Hash>>,
+}
+
+/// The policy of a signing key and its use so far
+pub(crate) struct Usage {
+    policy: Policy,
+    hash: Hash,
+    signatures: AtomicU64,
+}
+
+impl Usage {
+    /// Applies `policy` to a new key hashing with a digest of `size` bytes.
+    pub(crate) fn new(policy: &Policy, size: usize) -> Result<Arc<Self>, Error> {
+        let hash = match size {
+            32 => Hash::Sha256,
+            48 => Hash::Sha384,
+            64 => Hash::Sha512,
+            _ => return Err(Error::io()),
+        };
+
+        if let Some(digests) = &policy.digests {
+            if !digests.contains(&hash) {
+                return Err(Error::perm());
+            }
+        }
+
+        Ok(Arc::new(Self {
+            policy: policy.clone(),
+            hash,
+            signatures: AtomicU64::new(0),
+        }))
+    }
+
+    /// Counts a signature, if the policy allows another.
+    pub(crate) fn sign(&self) -> Result<(), Error> {
+        if let Some(expires) = self.policy.expires {
+            if SystemTime::now() >= expires {
+                return Err(Error::perm());
+            }
+        }
+
+        let max = self.policy.max_signatures.unwrap_or(u64::MAX);
+        self.signatures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .map_err(|_| Error::perm())?;
+
+        Ok(())
+    }
+
+    // Encodes the policy and the counters as JSON.
+    fn encode(&self) -> Vec<u8> {
+        let number = |n: Option<u64>| n.map_or("null".into(), |n| n.to_string());
+        let expires = self
+            .policy
+            .expires
+            .map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default());
+
+        format!(
+            r#"{{"digest":"{}","signatures":{},"max_signatures":{},"expires":{}}}"#,
+            self.hash.name(),
+            self.signatures.load(Ordering::SeqCst),
+            number(self.policy.max_signatures),
+            number(expires.map(|d| d.as_secs())),
+        )
+        .into_bytes()
+    }
+}
+
+/// The counted use of each signing key, by its shared public key
+///
+/// Only keys limited to `max_signatures` are counted here, for as long as
+/// the keyfs lives, so removing a key does not reset the count of a copy.
+#[derive(Clone, Default)]
+pub(crate) struct Usages(Arc<Mutex<HashMap<Vec<u8>, Arc<Usage>>>>);
+
+impl Usages {
+    /// Finds the use of the key sharing `public`, hashing with a digest of
+    /// `size` bytes, applying `policy` to a key not seen before.
+    pub(crate) fn get(
+        &self,
+        policy: &Policy,
+        public: &[u8],
+        size: usize,
+    ) -> Result<Arc<Usage>, Error> {
+        let usage = Usage::new(policy, size)?;
+        if policy.max_signatures.is_none() {
+            return Ok(usage);
+        }
+
+        let mut lock = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(lock.entry(public.to_vec()).or_insert(usage).clone())
+    }
+}
+
+/// A socket sharing the policy of a signing key
+///
+/// Each read returns the digest of the key, the signatures made so far and
+/// the limits of its [`Policy`], with the expiry in seconds since the Unix
+/// epoch, as a JSON object. Limits which are not set are `null`.
+pub struct State(Link<Arc<Usage>>);
+
+#[async_trait::async_trait]
+impl Node for State {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || write || !flags.is_empty() {
+            return Err(Error::perm());
+        }
+
+        Ok(Box::new(OpenState {
+            _root: self.root(),
+            link: self,
+        }))
+    }
+}
+
+impl State {
+    pub(crate) fn new(parent: Arc<dyn Node>, usage: Arc<Usage>) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, usage)))
+    }
+}
+
+struct OpenState {
+    _root: Arc<dyn Node>,
+    link: Arc<State>,
+}
+
+impl OpenState {
+    async fn encode(&self) -> Vec<u8> {
+        self.link.0.inode().data().read().await.content().encode()
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenState {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: ilock.content().encode().len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let state = self.encode().await;
+
+        if state.len() > bufs.iter().map(|x| x.len()).sum() {
+            return Err(Error::too_big());
+        }
+
+        let mut total = 0;
+
+        for buf in bufs {
+            let len = min(buf.len(), state.len() - total);
+            buf[..len].copy_from_slice(&state[total..][..len]);
+            total += len;
+        }
+
+        Ok(total.try_into()?)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        Ok(datagram::peek(&self.encode().await, buf))
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.encode().await.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::any::Any;
+use std::io::IoSlice;
+use std::sync::{Arc, Weak};
+
+use uuid::Uuid;
+use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+use crate::{Hooks, Store};
+
+/// A socket revoking the key of its directory
+///
+/// Any write zeroizes the secrets of the key at once, even while handles to
+/// its sockets are still open, and those handles then fail with `EBADF`.
+/// Removing a socket of the key revokes that socket alone in the same way.
+/// Revoking the key, or removing this socket as removing the key does, also
+/// removes the key from the store.
+pub struct Revoke(Link<()>, Option<Arc<dyn Store>>, Uuid);
+
+#[async_trait::async_trait]
+impl Node for Revoke {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn revoke(&self) {
+        if let Some(store) = &self.1 {
+            store.remove(self.2).await;
+        }
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if read || !write || !flags.is_empty() {
+            return Err(Error::perm());
+        }
+
+        Ok(Box::new(OpenRevoke {
+            _root: self.root(),
+            link: self,
+        }))
+    }
+}
+
+impl Revoke {
+    pub(crate) fn new(parent: Arc<dyn Node>, hooks: &Hooks, uuid: Uuid) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, ()), hooks.store.clone(), uuid))
+    }
+}
+
+struct OpenRevoke {
+    _root: Arc<dyn Node>,
+    link: Arc<Revoke>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenRevoke {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let key = self
+            .link
+            .parent()
+            .ok_or_else(Error::io)?
+            .to_any()
+            .downcast::<Directory>()
+            .map_err(|_| Error::io())?;
+
+        // Revoking under the directory lock would block lookups on sockets.
+        let ilock = key.inode().data().read().await;
+        let sockets: Vec<_> = ilock.content().values().cloned().collect();
+        drop(ilock);
+
+        for socket in sockets {
+            socket.revoke().await;
+        }
+
+        Ok(bufs.iter().map(|buf| buf.len() as u64).sum())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
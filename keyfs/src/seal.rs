@@ -0,0 +1,369 @@
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Weak};
+
+use uuid::Uuid;
+use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::cipher::Cipher;
+use crate::datagram::{self, UUID};
+use crate::generate::import;
+use crate::{Hooks, Record};
+
+// The domain of sealed keys, which the `decrypt` socket refuses to open.
+const SEALED: &str = "keyfs seal";
+
+// Passes `input` through the `socket` of the key named by `kek`, in the
+// domain of sealed keys.
+async fn wrap(
+    keys: &Arc<Directory>,
+    kek: &[u8],
+    socket: &str,
+    input: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let kek = std::str::from_utf8(kek).map_err(|_| Error::invalid_argument())?;
+    let kek: Uuid = kek.parse().map_err(|_| Error::invalid_argument())?;
+    let path = format!("{}/{socket}", kek.as_hyphenated());
+
+    let cipher = keys.get(&path).await?.to_any();
+    let cipher = cipher
+        .downcast::<Cipher>()
+        .map_err(|_| Error::invalid_argument())?;
+    cipher.apply(SEALED, input).await
+}
+
+fn keys(node: Option<Arc<dyn Node>>) -> Result<Arc<Directory>, Error> {
+    node.ok_or_else(Error::io)?
+        .to_any()
+        .downcast::<Directory>()
+        .map_err(|_| Error::io())
+}
+
+/// A socket exporting a key wrapped under another key
+///
+/// Writing the UUID of a key with an `encrypt` socket, the key encryption
+/// key, makes the next read return this key sealed under it. The plaintext
+/// key never leaves the file system: sealed keys are bound to a domain of
+/// their own, so the `decrypt` socket of the key encryption key refuses them.
+pub struct Seal(Link<Zeroizing<Vec<u8>>>);
+
+#[async_trait::async_trait]
+impl Node for Seal {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn revoke(&self) {
+        self.0.inode().data().write().await.content_mut().zeroize();
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenSeal {
+            _root: self.root(),
+            link: self,
+            output: None,
+        }))
+    }
+}
+
+impl Seal {
+    /// Creates the socket for the key `material` of `algorithm`.
+    pub fn new(parent: Arc<dyn Node>, algorithm: &[u8], material: &[u8]) -> Arc<Self> {
+        let mut plaintext = Zeroizing::new(algorithm.to_vec());
+        plaintext.extend_from_slice(material);
+        Arc::new(Self(Link::new(&parent, plaintext)))
+    }
+}
+
+struct OpenSeal {
+    _root: Arc<dyn Node>,
+    link: Arc<Seal>,
+
+    // The sealed key, kept until read in full.
+    output: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenSeal {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let output = self.output.as_ref().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(output, bufs)?;
+        self.output = None;
+        Ok(n)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        let output = self.output.as_ref().ok_or_else(datagram::empty)?;
+        Ok(datagram::peek(output, buf))
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.output.as_ref().map_or(0, |o| o.len() as u64))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let kek: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        if kek.len() != UUID {
+            return Err(Error::invalid_argument());
+        }
+
+        let keys = keys(self.link.parent().and_then(|key| key.parent()))?;
+        let plaintext = self.link.0.inode().data().read().await.content().clone();
+        if plaintext.is_empty() {
+            return Err(Error::badf());
+        }
+
+        self.output = Some(wrap(&keys, &kek, "encrypt", &plaintext).await?);
+        Ok(kek.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        datagram::pending(self.output.is_some()).await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A socket importing keys exported by [`Seal`]
+///
+/// Each write is the UUID of the key encryption key followed by a sealed
+/// key. Each read returns the UUID of an imported key, in the order of the
+/// writes. A sealed key which fails to open is reported as `EILSEQ`.
+pub struct Unseal(Link<()>, Hooks);
+
+#[async_trait::async_trait]
+impl Node for Unseal {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketDgram
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !read || !write {
+            return Err(Error::perm());
+        }
+
+        if !flags.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenUnseal {
+            _root: self.root(),
+            link: self,
+            unsealed: Vec::new(),
+        }))
+    }
+}
+
+impl Unseal {
+    pub(crate) fn new(parent: Arc<dyn Node>, hooks: Hooks) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, ()), hooks))
+    }
+}
+
+struct OpenUnseal {
+    _root: Arc<dyn Node>,
+    link: Arc<Unseal>,
+
+    // The keys imported and not yet read, oldest first.
+    unsealed: Vec<Uuid>,
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenUnseal {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
+            filetype: FileType::SocketDgram,
+            nlink: self.link.0.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let uuid = self.unsealed.first().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(uuid.to_string().as_bytes(), bufs)?;
+        self.unsealed.remove(0);
+        Ok(n)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        datagram::peek_uuid(self.unsealed.first(), buf)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(datagram::ready_uuids(self.unsealed.len()))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let request: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        if request.len() < UUID {
+            return Err(Error::invalid_argument());
+        }
+
+        let (kek, sealed) = request.split_at(UUID);
+        let keys = keys(self.link.parent())?;
+        let plaintext = wrap(&keys, kek, "decrypt", sealed).await?;
+        if plaintext.len() < 4 {
+            return Err(Error::illegal_byte_sequence());
+        }
+
+        let (algorithm, material) = plaintext.split_at(4);
+        let record = Record {
+            uuid: Uuid::new_v4(),
+            algorithm: algorithm.to_vec(),
+            material: material.to_vec(),
+            generated: false,
+        };
+        let uuid = import(&keys, &self.link.1, &record).await?;
+        self.unsealed.push(uuid);
+        Ok(request.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        datagram::pending(!self.unsealed.is_empty()).await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -1,13 +1,27 @@
 use std::any::Any;
 use std::cmp::min;
 use std::io::IoSliceMut;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ecdsa::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{BigUint, RsaPublicKey};
 use wasi_common::file::{FdFlags, FileType, Filestat};
 use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
 use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node, Open, State};
+use wasmtime_vfs_memory::{Link, Node, Open};
 
+use crate::datagram;
+use crate::eddsa::Ed25519Public;
+use crate::{EDDSA, ES256, ES256K, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+
+/// A socket sharing a public key
+///
+/// Each read returns the whole key. A key directory shares its key as the
+/// algorithm followed by the crate's own encoding in `share`, as
+/// SubjectPublicKeyInfo DER in `share.der` and as a JWK in `share.jwk`.
 pub struct Share(Link<Vec<u8>>);
 
 #[async_trait::async_trait]
@@ -17,7 +31,11 @@ impl Node for Share {
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.0.parent.upgrade()
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -25,7 +43,7 @@ impl Node for Share {
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.0.inode.id.clone()
+        self.0.inode().id()
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -48,30 +66,130 @@ impl Node for Share {
             return Err(Error::perm());
         }
 
-        Ok(Box::new(OpenShare(Open {
-            root: self.root(),
-            link: self,
-            state: State::from(flags).into(),
-            write,
-            read,
-        })))
+        Ok(Box::new(OpenShare(Open::new(self, read, write, flags))))
     }
 }
 
 impl Share {
     pub fn new(parent: Arc<dyn Node>, data: impl Into<Vec<u8>>) -> Arc<Self> {
-        let id = parent.id().device().create_inode();
+        Arc::new(Self(Link::new(&parent, data.into())))
+    }
+}
 
-        let inode = Inode {
-            data: Data::from(data.into()).into(),
-            id,
-        };
+/// Attaches the sockets sharing the public key `blob` in each format to `d`
+pub(crate) async fn attach(d: &Arc<Directory>, blob: Vec<u8>) -> Result<(), Error> {
+    let (der, jwk) = formats(&blob)?;
+    d.attach("share", Share::new(d.clone(), blob)).await?;
+    d.attach("share.der", Share::new(d.clone(), der)).await?;
+    d.attach("share.jwk", Share::new(d.clone(), jwk)).await?;
+    Ok(())
+}
 
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+// Encodes the public key `blob` as SubjectPublicKeyInfo DER and as a JWK.
+fn formats(blob: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let invalid = |_| Error::illegal_byte_sequence();
+    let (algorithm, key) = blob
+        .split_at_checked(4)
+        .ok_or_else(Error::illegal_byte_sequence)?;
+
+    let (der, point, alg, crv) = match algorithm {
+        ES256K => {
+            let key = k256::PublicKey::from_sec1_bytes(key).map_err(invalid)?;
+            let point = key.to_encoded_point(false);
+            (
+                key.to_public_key_der(),
+                point.as_bytes().to_vec(),
+                "ES256K",
+                "secp256k1",
+            )
+        }
+        ES256 => {
+            let key = p256::PublicKey::from_sec1_bytes(key).map_err(invalid)?;
+            let point = key.to_encoded_point(false);
+            (
+                key.to_public_key_der(),
+                point.as_bytes().to_vec(),
+                "ES256",
+                "P-256",
+            )
+        }
+        ES384 => {
+            let key = p384::PublicKey::from_sec1_bytes(key).map_err(invalid)?;
+            let point = key.to_encoded_point(false);
+            (
+                key.to_public_key_der(),
+                point.as_bytes().to_vec(),
+                "ES384",
+                "P-384",
+            )
+        }
+        EDDSA => return eddsa_formats(key),
+        _ => return rsa_formats(algorithm, key),
+    };
+
+    // An uncompressed point is a tag byte and then both coordinates.
+    let (x, y) = point[1..].split_at(point.len() / 2);
+    let jwk = format!(
+        r#"{{"kty":"EC","alg":"{}","crv":"{}","x":"{}","y":"{}"}}"#,
+        alg,
+        crv,
+        Base64UrlUnpadded::encode_string(x),
+        Base64UrlUnpadded::encode_string(y),
+    );
+
+    let der = der.map_err(|_| Error::io())?;
+    Ok((der.as_bytes().to_vec(), jwk.into_bytes()))
+}
+
+// Encodes the Ed25519 public key `key` as RFC 8410 and RFC 8037 lay it out.
+fn eddsa_formats(key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let der = Ed25519Public::from_bytes(key)?.to_public_key_der()?;
+    let jwk = format!(
+        r#"{{"kty":"OKP","alg":"EdDSA","crv":"Ed25519","x":"{}"}}"#,
+        Base64UrlUnpadded::encode_string(key),
+    );
+
+    Ok((der, jwk.into_bytes()))
+}
+
+// Encodes the RSA public key `key`, the exponent and then the modulus, each
+// after its length.
+fn rsa_formats(algorithm: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let alg = match algorithm {
+        RS256 => "RS256",
+        RS384 => "RS384",
+        RS512 => "RS512",
+        PS256 => "PS256",
+        PS384 => "PS384",
+        PS512 => "PS512",
+        _ => return Err(Error::illegal_byte_sequence()),
+    };
+
+    let mut fields = [&[][..]; 2];
+    let mut rest = key;
+    for field in &mut fields {
+        let (len, tail) = rest
+            .split_at_checked(4)
+            .ok_or_else(Error::illegal_byte_sequence)?;
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        (*field, rest) = tail
+            .split_at_checked(len)
+            .ok_or_else(Error::illegal_byte_sequence)?;
     }
+
+    let [e, n] = fields;
+    let (bn, be) = (BigUint::from_bytes_be(n), BigUint::from_bytes_be(e));
+    let public = RsaPublicKey::new(bn, be).map_err(|_| Error::illegal_byte_sequence())?;
+    let der = public.to_public_key_der().map_err(|_| Error::io())?;
+
+    let jwk = format!(
+        r#"{{"kty":"RSA","alg":"{}","n":"{}","e":"{}"}}"#,
+        alg,
+        Base64UrlUnpadded::encode_string(n),
+        Base64UrlUnpadded::encode_string(e),
+    );
+
+    Ok((der.as_bytes().to_vec(), jwk.into_bytes()))
 }
 
 struct OpenShare(Open<Share>);
@@ -87,41 +205,46 @@ impl WasiFile for OpenShare {
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.0.link.0.inode.data.read().await;
+        let ilock = self.0.link().0.inode().data().read().await;
 
         Ok(Filestat {
-            device_id: **self.0.link.0.inode.id.device(),
-            inode: **self.0.link.0.inode.id,
+            device_id: **self.0.link().0.inode().id().device(),
+            inode: **self.0.link().0.inode().id(),
             filetype: FileType::SocketDgram,
-            nlink: Arc::strong_count(&self.0.link.0.inode) as u64,
-            size: ilock.content.len() as u64,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
+            nlink: self.0.link().0.inode().id().links(),
+            size: ilock.content().len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
         })
     }
 
     async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
-        let ilock = self.0.link.0.inode.data.read().await;
+        let ilock = self.0.link().0.inode().data().read().await;
 
-        if ilock.content.len() > bufs.iter().map(|x| x.len()).sum() {
+        if ilock.content().len() > bufs.iter().map(|x| x.len()).sum() {
             return Err(Error::too_big());
         }
 
         let mut total = 0;
 
         for buf in bufs {
-            let len = min(buf.len(), ilock.content.len() - total);
-            buf[..len].copy_from_slice(&ilock.content[total..][..len]);
+            let len = min(buf.len(), ilock.content().len() - total);
+            buf[..len].copy_from_slice(&ilock.content()[total..][..len]);
             total += len;
         }
 
         Ok(total.try_into()?)
     }
 
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        let ilock = self.0.link().0.inode().data().read().await;
+        Ok(datagram::peek(ilock.content(), buf))
+    }
+
     async fn num_ready_bytes(&self) -> Result<u64, Error> {
-        let ilock = self.0.link.0.inode.data.read().await;
-        Ok(ilock.content.len() as u64)
+        let ilock = self.0.link().0.inode().data().read().await;
+        Ok(ilock.content().len() as u64)
     }
 
     async fn readable(&self) -> Result<(), Error> {
@@ -1,20 +1,30 @@
 use std::any::Any;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use digest::Digest;
 use signature::{RandomizedDigestSigner, Signature};
 use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
 use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
 use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node};
+use wasmtime_vfs_memory::{Link, Node};
+
+use crate::policy::Usage;
 
 struct SigningKey<K, D, S> {
     ignore: PhantomData<S>,
     digest: PhantomData<D>,
-    public: Arc<K>,
+    public: Option<Arc<K>>,
+    usage: Arc<Usage>,
 }
 
+/// A socket signing messages with a private key
+///
+/// The message is written in any number of writes. Reading at offset
+/// `u64::MAX` then returns the signature and, once it is read in full,
+/// starts a new message, so one handle signs any number of messages. An
+/// empty write at offset `u64::MAX` discards the message written so far.
+/// Each signature counts against the [`Policy`](crate::Policy) of the key.
 pub struct Sign<K, D, S>(Link<SigningKey<K, D, S>>);
 
 #[async_trait::async_trait]
@@ -29,7 +39,11 @@ where
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.0.parent.upgrade()
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -37,7 +51,12 @@ where
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.0.inode.id.clone()
+        self.0.inode().id()
+    }
+
+    // Dropping the key zeroizes it.
+    async fn revoke(&self) {
+        self.0.inode().data().write().await.content_mut().public = None;
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -68,29 +87,25 @@ where
             _root: self.root(),
             link: self,
             hash: D::new(),
+            output: None,
         }))
     }
 }
 
 impl<K, D, S> Sign<K, D, S> {
-    pub fn new(parent: Arc<dyn Node>, key: impl Into<Arc<K>>) -> Arc<Self> {
-        let id = parent.id().device().create_inode();
-
+    pub(crate) fn new(
+        parent: Arc<dyn Node>,
+        key: impl Into<Arc<K>>,
+        usage: Arc<Usage>,
+    ) -> Arc<Self> {
         let key = SigningKey {
             ignore: PhantomData,
             digest: PhantomData,
-            public: key.into(),
+            public: Some(key.into()),
+            usage,
         };
 
-        let inode = Inode {
-            data: Data::from(key).into(),
-            id,
-        };
-
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+        Arc::new(Self(Link::new(&parent, key)))
     }
 }
 
@@ -98,6 +113,9 @@ struct OpenSign<K, D, S> {
     _root: Arc<dyn Node>,
     link: Arc<Sign<K, D, S>>,
     hash: D,
+
+    // The signature of the message, kept until read in full.
+    output: Option<Vec<u8>>,
 }
 
 #[async_trait::async_trait]
@@ -116,17 +134,17 @@ where
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.0.inode.data.read().await;
+        let ilock = self.link.0.inode().data().read().await;
 
         Ok(Filestat {
-            device_id: **self.link.0.inode.id.device(),
-            inode: **self.link.0.inode.id,
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
             filetype: FileType::SocketDgram,
-            nlink: Arc::strong_count(&self.link.0.inode) as u64,
+            nlink: self.link.0.inode().id().links(),
             size: 0,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
         })
     }
 
@@ -139,6 +157,11 @@ where
     }
 
     async fn write_vectored<'a>(&mut self, bufs: &[std::io::IoSlice<'a>]) -> Result<u64, Error> {
+        // Writing after a signature which was never read starts over.
+        if self.output.take().is_some() {
+            self.hash = D::new();
+        }
+
         let mut total = 0;
 
         for buf in bufs {
@@ -158,12 +181,19 @@ where
             return Err(Error::invalid_argument());
         }
 
-        // Sign the hash.
-        let ilock = self.link.0.inode.data.read().await;
-        let hash = self.hash.clone();
-        let rng = rand::thread_rng();
-        let sig = ilock.content.public.sign_digest_with_rng(rng, hash);
-        let sig = sig.as_bytes();
+        // Signing is randomized, so keep the signature for a larger read.
+        let sig = match self.output.take() {
+            Some(sig) => sig,
+            None => {
+                let ilock = self.link.0.inode().data().read().await;
+                let key = ilock.content().public.as_ref().ok_or_else(Error::badf)?;
+                ilock.content().usage.sign()?;
+                let hash = self.hash.clone();
+                let rng = rand::thread_rng();
+                let sig = key.sign_digest_with_rng(rng, hash);
+                sig.as_bytes().to_vec()
+            }
+        };
 
         // Copy the signature into the buffer.
         let mut total = 0;
@@ -175,12 +205,28 @@ where
 
         // Detect signature truncation.
         if total < sig.len() {
+            self.output = Some(sig);
             return Err(Error::too_big());
         }
 
+        self.hash = D::new();
         Ok(total as u64)
     }
 
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[std::io::IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        if offset != u64::MAX || bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::invalid_argument());
+        }
+
+        self.output = None;
+        self.hash = D::new();
+        Ok(0)
+    }
+
     async fn readable(&self) -> Result<(), Error> {
         Ok(())
     }
@@ -0,0 +1,61 @@
+use uuid::Uuid;
+use wasi_common::Error;
+
+use crate::Hooks;
+
+/// A key as persisted by a [`Store`]
+///
+/// This is the UUID naming the directory of the key, its algorithm, its key
+/// material, in the form `seal` exports it in, and whether `generate` created
+/// it inside the keep.
+#[derive(Clone)]
+pub struct Record {
+    pub uuid: Uuid,
+    pub algorithm: Vec<u8>,
+    pub material: Vec<u8>,
+    pub generated: bool,
+}
+
+/// A backend persisting keys across restarts of the keep
+///
+/// Every key generated, imported, unsealed or derived is saved before it
+/// appears in the tree, and a failure to save fails its creation. The key
+/// material is secret, so a store outside the keep must seal it, for example
+/// by encrypting it with a key only the keep can obtain. A key the guest
+/// revokes, or removes along with its `revoke` socket, is removed from the
+/// store as well.
+#[async_trait::async_trait]
+pub trait Store: Send + Sync {
+    /// Persists `record`.
+    async fn save(&self, record: Record) -> Result<(), Error>;
+
+    /// Forgets the record of the key `uuid`, if there is one.
+    async fn remove(&self, uuid: Uuid);
+
+    /// Returns every record persisted, to restore when the keep restarts.
+    async fn load(&self) -> Result<Vec<Record>, Error>;
+}
+
+/// Persists a new key in the store of `hooks`, if there is one and the key
+/// is not being restored from it.
+pub(crate) async fn save(
+    hooks: &Hooks,
+    uuid: Uuid,
+    algorithm: &[u8],
+    material: &[u8],
+    generated: bool,
+) -> Result<(), Error> {
+    match &hooks.store {
+        Some(_) if hooks.restored => Ok(()),
+        Some(store) => {
+            let record = Record {
+                uuid,
+                algorithm: algorithm.to_vec(),
+                material: material.to_vec(),
+                generated,
+            };
+            store.save(record).await
+        }
+        None => Ok(()),
+    }
+}
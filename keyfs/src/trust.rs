@@ -1,6 +1,6 @@
 use std::any::Any;
 use std::io::{IoSlice, IoSliceMut};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use digest::Digest;
 
@@ -12,16 +12,20 @@ use ecdsa::PrimeCurve;
 use rsa::PublicKeyParts;
 use sha2::{Sha256, Sha384, Sha512};
 use signature::{DigestVerifier, Signature};
+use tokio::sync::Notify;
 use uuid::Uuid;
 use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SiFlags};
 use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
 use wasmtime_vfs_dir::Directory;
 use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node};
+use wasmtime_vfs_memory::{Link, Node};
 
-use crate::share::Share;
+use crate::datagram;
+use crate::eddsa::{Ed25519Public, Message};
+use crate::pke;
+use crate::share;
 use crate::verify::Verify;
-use crate::{ES256, ES256K, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
+use crate::{EDDSA, ES256, ES256K, ES384, PS256, PS384, PS512, RS256, RS384, RS512};
 
 type Rs256 = rsa::pkcs1v15::VerifyingKey<Sha256>;
 type Rs384 = rsa::pkcs1v15::VerifyingKey<Sha384>;
@@ -33,7 +37,8 @@ type Es256k = ecdsa::VerifyingKey<k256::Secp256k1>;
 type Es256 = ecdsa::VerifyingKey<p256::NistP256>;
 type Es384 = ecdsa::VerifyingKey<p384::NistP384>;
 
-trait Decoder: Sized {
+/// Decoding of a public key from the encoding `share` gives it
+pub(crate) trait Decoder: Sized {
     fn decode(data: &[u8]) -> Result<Self, Error>;
 }
 
@@ -119,7 +124,13 @@ where
     }
 }
 
-pub struct Trust(Link<Vec<Uuid>>);
+impl Decoder for Ed25519Public {
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes(data)
+    }
+}
+
+pub struct Trust(Link<Vec<Uuid>>, Notify);
 
 #[async_trait::async_trait]
 impl Node for Trust {
@@ -128,7 +139,11 @@ impl Node for Trust {
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.0.parent.upgrade()
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -136,7 +151,7 @@ impl Node for Trust {
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.0.inode.id.clone()
+        self.0.inode().id()
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -172,17 +187,7 @@ impl Node for Trust {
 
 impl Trust {
     pub fn new(parent: Arc<dyn Node>) -> Arc<Self> {
-        let id = parent.id().device().create_inode();
-
-        let inode = Inode {
-            data: Data::from(Vec::new()).into(),
-            id,
-        };
-
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+        Arc::new(Self(Link::new(&parent, Vec::new()), Notify::new()))
     }
 
     async fn add<T, D, S>(self: &Arc<Trust>, bytes: &[u8]) -> Result<Uuid, Error>
@@ -206,7 +211,8 @@ impl Trust {
 
         let d = Directory::new(parent.clone(), None);
         d.attach("verify", Verify::new(d.clone(), public)).await?;
-        d.attach("share", Share::new(d.clone(), bytes)).await?;
+        pke::attach(&d, bytes, None).await?;
+        share::attach(&d, bytes.to_vec()).await?;
         parent.attach(&uuid.to_string(), d).await?;
 
         Ok(uuid)
@@ -229,17 +235,17 @@ impl WasiFile for OpenTrust {
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.0.inode.data.read().await;
+        let ilock = self.link.0.inode().data().read().await;
 
         Ok(Filestat {
-            device_id: **self.link.0.inode.id.device(),
-            inode: **self.link.0.inode.id,
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
             filetype: FileType::SocketDgram,
-            nlink: Arc::strong_count(&self.link.0.inode) as u64,
+            nlink: self.link.0.inode().id().links(),
             size: 0,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
         })
     }
 
@@ -256,29 +262,23 @@ impl WasiFile for OpenTrust {
         self.write_vectored(bufs).await
     }
 
+    // The most recent key is read first.
     async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
-        let mut ilock = self.link.0.inode.data.write().await;
-
-        if let Some(uuid) = ilock.content.pop() {
-            let name = uuid.to_string();
-            let bytes = name.as_bytes();
-            let mut total = 0;
-
-            for buf in bufs {
-                let len = std::cmp::min(buf.len(), bytes.len() - total);
-                buf[..len].copy_from_slice(&bytes[total..][..len]);
-                total += len;
-            }
-
-            if total < bytes.len() {
-                ilock.content.push(uuid);
-                return Err(Error::too_big());
-            }
+        let mut ilock = self.link.0.inode().data().write().await;
+        let uuid = ilock.content().last().ok_or_else(datagram::empty)?;
+        let n = datagram::recv(uuid.to_string().as_bytes(), bufs)?;
+        ilock.content_mut().pop();
+        Ok(n)
+    }
 
-            return Ok(total as u64);
-        }
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+        datagram::peek_uuid(ilock.content().last(), buf)
+    }
 
-        Err(std::io::Error::from(std::io::ErrorKind::WouldBlock).into())
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        let ilock = self.link.0.inode().data().read().await;
+        Ok(datagram::ready_uuids(ilock.content().len()))
     }
 
     async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
@@ -299,10 +299,19 @@ impl WasiFile for OpenTrust {
                     ES256K => self.link.add::<Es256k, Sha256, _>(&all).await?,
                     ES256 => self.link.add::<Es256, Sha256, _>(&all).await?,
                     ES384 => self.link.add::<Es384, Sha384, _>(&all).await?,
+                    EDDSA => self.link.add::<Ed25519Public, Message, _>(&all).await?,
                     _ => return Err(ErrorKind::Ilseq.into()),
                 };
 
-                self.link.0.inode.data.write().await.content.push(uuid);
+                self.link
+                    .0
+                    .inode()
+                    .data()
+                    .write()
+                    .await
+                    .content_mut()
+                    .push(uuid);
+                self.link.1.notify_waiters();
                 Ok(all.len() as u64)
             }
 
@@ -311,7 +320,7 @@ impl WasiFile for OpenTrust {
     }
 
     async fn readable(&self) -> Result<(), Error> {
-        Ok(())
+        datagram::queued(self.link.0.inode(), &self.link.1).await
     }
 
     async fn writable(&self) -> Result<(), Error> {
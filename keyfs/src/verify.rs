@@ -1,14 +1,14 @@
 use std::any::Any;
 use std::io::IoSlice;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use digest::Digest;
 use signature::{DigestVerifier, Signature};
 use wasi_common::file::{FdFlags, FileType, Filestat, SiFlags};
 use wasi_common::{Error, ErrorExt, ErrorKind, WasiDir, WasiFile};
 use wasmtime_vfs_ledger::InodeId;
-use wasmtime_vfs_memory::{Data, Inode, Link, Node};
+use wasmtime_vfs_memory::{Link, Node};
 
 struct VerifyingKey<K, D, S> {
     ignore: PhantomData<S>,
@@ -16,6 +16,12 @@ struct VerifyingKey<K, D, S> {
     public: Arc<K>,
 }
 
+/// A socket verifying signatures of messages with a public key
+///
+/// The message is written in any number of writes. Writing its signature at
+/// offset `u64::MAX` then verifies it, reporting a bad signature as
+/// `EILSEQ`, and starts a new message either way. An empty write at offset
+/// `u64::MAX` discards the message written so far.
 pub struct Verify<K, D, S>(Link<VerifyingKey<K, D, S>>);
 
 #[async_trait::async_trait]
@@ -30,7 +36,11 @@ where
     }
 
     fn parent(&self) -> Option<Arc<dyn Node>> {
-        self.0.parent.upgrade()
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
     }
 
     fn filetype(&self) -> FileType {
@@ -38,7 +48,7 @@ where
     }
 
     fn id(&self) -> Arc<InodeId> {
-        self.0.inode.id.clone()
+        self.0.inode().id()
     }
 
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
@@ -75,23 +85,13 @@ where
 
 impl<K, D, S> Verify<K, D, S> {
     pub fn new(parent: Arc<dyn Node>, key: impl Into<Arc<K>>) -> Arc<Self> {
-        let id = parent.id().device().create_inode();
-
         let key = VerifyingKey {
             ignore: PhantomData,
             digest: PhantomData,
             public: key.into(),
         };
 
-        let inode = Inode {
-            data: Data::from(key).into(),
-            id,
-        };
-
-        Arc::new(Self(Link {
-            parent: Arc::downgrade(&parent),
-            inode: inode.into(),
-        }))
+        Arc::new(Self(Link::new(&parent, key)))
     }
 }
 
@@ -117,17 +117,17 @@ where
     }
 
     async fn get_filestat(&mut self) -> Result<Filestat, Error> {
-        let ilock = self.link.0.inode.data.read().await;
+        let ilock = self.link.0.inode().data().read().await;
 
         Ok(Filestat {
-            device_id: **self.link.0.inode.id.device(),
-            inode: **self.link.0.inode.id,
+            device_id: **self.link.0.inode().id().device(),
+            inode: **self.link.0.inode().id(),
             filetype: FileType::SocketDgram,
-            nlink: Arc::strong_count(&self.link.0.inode) as u64,
+            nlink: self.link.0.inode().id().links(),
             size: 0,
-            atim: Some(ilock.access),
-            mtim: Some(ilock.modify),
-            ctim: Some(ilock.create),
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
         })
     }
 
@@ -159,15 +159,20 @@ where
             return Err(Error::invalid_argument());
         }
 
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            self.hash = D::new();
+            return Ok(0);
+        }
+
         if bufs.len() != 1 {
             return Err(Error::invalid_argument());
         }
 
-        let hash = self.hash.clone();
         let sig = S::from_bytes(bufs[0].as_ref()).map_err(|_| Error::invalid_argument())?;
+        let hash = std::mem::replace(&mut self.hash, D::new());
 
-        let ilock = self.link.0.inode.data.read().await;
-        match ilock.content.public.verify_digest(hash, &sig) {
+        let ilock = self.link.0.inode().data().read().await;
+        match ilock.content().public.verify_digest(hash, &sig) {
             Ok(()) => Ok(bufs[0].len() as u64),
             Err(_) => Err(ErrorKind::Ilseq.into()),
         }
@@ -0,0 +1,38 @@
+//! Measures inode allocation while threads create and free inodes at once.
+//!
+//! This is the baseline a sharded or lock-free id allocator has to beat on
+//! several cores before it replaces the single lock of the ledger.
+//!
+//! Run with `cargo bench -p wasmtime-vfs-ledger`.
+
+use std::time::Instant;
+
+use wasmtime_vfs_ledger::Ledger;
+
+const ROUNDS: usize = 100_000;
+
+fn main() {
+    for threads in [1, 2, 4, 8] {
+        let device = Ledger::new().create_device();
+
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    // Keep a window of live inodes, freeing the oldest.
+                    let mut held = std::collections::VecDeque::new();
+                    for _ in 0..ROUNDS {
+                        held.push_back(device.clone().create_inode());
+                        if held.len() > 64 {
+                            held.pop_front();
+                        }
+                    }
+                });
+            }
+        });
+
+        let elapsed = start.elapsed();
+        let ops = (threads * ROUNDS) as f64 / elapsed.as_secs_f64();
+        println!("{threads} threads: {elapsed:?} ({ops:.0} allocations/s)");
+    }
+}
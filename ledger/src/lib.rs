@@ -1,90 +1,377 @@
-use std::collections::BTreeSet;
+use std::collections::BTreeMap;
 use std::ops::{Deref, Range};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Notify;
+
+/// Free identifiers, as disjoint ranges below the contiguous range
+#[derive(Default)]
+struct Free {
+    // The ranges, from start to end.
+    ranges: BTreeMap<u64, u64>,
+
+    // The number of identifiers in `ranges`.
+    count: u64,
+
+    // The start of the contiguous range of free identifiers.
+    next: u64,
+}
 
 /// A potentially infinite stream of unique `u64` ids.
 ///
 /// You can call `.next()` to allocate a new identifier. This will return
 /// `None` if the stream is exhausted. However, unused identifiers can be
 /// returned to the stream with `.free()` and will be reused.
+///
+/// Freed identifiers are kept as merged ranges, so the free set grows with
+/// fragmentation rather than with the identifiers freed, and are merged
+/// back into the contiguous range once they reach it. When reuse is
+/// deferred, they are only reused once the fresh ones run out.
+///
+/// All of this sits behind one mutex. Sharded or lock-free allocation is
+/// deferred until `benches/churn.rs` shows that mutex limiting several
+/// cores; free sets sharded per thread did not beat it on one.
 struct Reusable {
-    // A set of all free, discontiguous identifiers.
-    free: BTreeSet<u64>,
+    free: Mutex<Free>,
+
+    // The range identifiers are allocated from.
+    start: u64,
+    end: u64,
 
-    // A set of all free, contiguous identifiers.
-    next: Range<u64>,
+    defer: bool,
 }
 
 impl Default for Reusable {
     fn default() -> Self {
-        Reusable {
-            free: BTreeSet::new(),
-            next: 0..u64::MAX,
-        }
+        Self::new(0..u64::MAX, false)
     }
 }
 
-impl Iterator for Reusable {
-    type Item = u64;
+impl Reusable {
+    fn new(range: Range<u64>, defer: bool) -> Self {
+        let free = Free {
+            next: range.start,
+            ..Default::default()
+        };
+
+        Self {
+            free: Mutex::new(free),
+            start: range.start,
+            end: range.end.max(range.start),
+            defer,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Free> {
+        self.free.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn next(&self) -> Option<u64> {
+        let mut free = self.lock();
+        if self.defer {
+            if let Some(id) = self.fresh(&mut free) {
+                return Some(id);
+            }
+        }
+
+        // Try to reuse the lowest free identifier.
+        if let Some((start, end)) = free.ranges.pop_first() {
+            if start + 1 < end {
+                free.ranges.insert(start + 1, end);
+            }
+
+            free.count -= 1;
+            return Some(start);
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // Try to reuse an identifier from the discontiguous set.
         // Fall back to allocating from the contiguous range.
-        self.free.pop_first().or_else(|| self.next.next())
+        self.fresh(&mut free)
     }
-}
 
-impl Reusable {
-    fn free(&mut self, id: u64) {
-        // Detect double-free conditions.
-        debug_assert!(id < self.next.start);
-        debug_assert!(!self.free.contains(&id));
+    // Allocates from the contiguous range.
+    fn fresh(&self, free: &mut Free) -> Option<u64> {
+        let id = free.next;
+        (id < self.end).then(|| {
+            free.next += 1;
+            id
+        })
+    }
+
+    // The number of identifiers allocated and not freed.
+    fn live(&self) -> u64 {
+        let free = self.lock();
+        (free.next - self.start).saturating_sub(free.count)
+    }
+
+    fn stats(&self) -> Stats {
+        let allocated = self.live();
+        Stats {
+            allocated,
+            free: (self.end - self.start).saturating_sub(allocated),
+        }
+    }
 
-        // Insert the freed id into the discontiguous set.
-        self.free.insert(id);
+    fn free(&self, id: u64) {
+        let mut free = self.lock();
 
-        // Attempt to move the discontiguous set into the contiguous range.
-        while self.next.start > 0 {
-            let prev = self.next.start - 1;
-            if !self.free.remove(&prev) {
-                break;
+        // Detect double-free conditions.
+        debug_assert!(id < free.next);
+        debug_assert!(free
+            .ranges
+            .range(..=id)
+            .next_back()
+            .is_none_or(|(_, e)| id >= *e));
+
+        // Insert the freed id, merging it with its neighbors.
+        let mut start = id;
+        let mut end = id + 1;
+        if let Some((&s, &e)) = free.ranges.range(..id).next_back() {
+            if e == id {
+                free.ranges.remove(&s);
+                start = s;
             }
+        }
+        if let Some(e) = free.ranges.remove(&end) {
+            end = e;
+        }
+
+        // Move the range back into the contiguous range if it reaches it,
+        // unless that would hand the last identifiers out again at once.
+        if end == free.next && !self.defer {
+            free.next = start;
+            free.count -= end - start - 1;
+        } else {
+            free.ranges.insert(start, end);
+            free.count += 1;
+        }
+    }
+}
+
+/// A source of the current time for the timestamps of a device
+///
+/// A clock returning a fixed time makes generated trees reproducible.
+pub type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
+/// Allocation counters of a ledger or a device
+///
+/// Like [`DeviceId::live_inodes`], the counts are approximate while
+/// identifiers are being allocated or freed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The identifiers allocated and not yet freed
+    pub allocated: u64,
+
+    /// The identifiers which can still be allocated
+    pub free: u64,
+}
+
+/// The usage of a device, as `statvfs` reports it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Statvfs {
+    /// The id of the device, as in `f_fsid`
+    pub device: u64,
+
+    /// The inodes allocated, and those free under the quota
+    pub inodes: Stats,
+
+    /// The bytes held by regular files
+    pub bytes: u64,
 
-            self.next.start = prev;
+    /// The bytes free under the quota
+    pub free_bytes: u64,
+}
+
+/// How a ledger assigns the ids of devices and inodes
+///
+/// Offset ranges keep the ids apart from those of the host, as when mixing
+/// in passthrough mounts. By default, a freed id is handed out again at
+/// once; deferring reuse keeps ids stable across recreations for as long as
+/// fresh ones last. Either way, [`InodeId::generation`] tells reused inode
+/// ids apart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ids {
+    /// The range device ids are allocated from
+    pub devices: Range<u64>,
+
+    /// The range the inode ids of each device are allocated from
+    pub inodes: Range<u64>,
+
+    /// Whether freed ids wait until the fresh ones run out
+    pub defer_reuse: bool,
+}
+
+impl Default for Ids {
+    fn default() -> Self {
+        Self {
+            devices: 0..u64::MAX,
+            inodes: 0..u64::MAX,
+            defer_reuse: false,
         }
     }
 }
 
+/// Builds a [`Ledger`], as [`Ledger::builder`] starts it.
+#[derive(Default)]
+pub struct LedgerBuilder {
+    clock: Option<Clock>,
+    ids: Ids,
+}
+
+impl LedgerBuilder {
+    /// Start each device of the ledger with `clock`.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Assign ids as `ids` says.
+    pub fn ids(mut self, ids: Ids) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Create the ledger.
+    pub fn build(self) -> Arc<Ledger> {
+        Arc::new(Ledger {
+            devices: Reusable::new(self.ids.devices.clone(), self.ids.defer_reuse),
+            clock: self.clock,
+            ids: self.ids,
+        })
+    }
+}
+
 /// A ledger of filesystem devices.
-pub struct Ledger(Mutex<Reusable>);
+pub struct Ledger {
+    devices: Reusable,
+
+    // The clock each new device starts with.
+    clock: Option<Clock>,
+
+    // How ids are assigned to devices and their inodes.
+    ids: Ids,
+}
 
 impl Ledger {
     /// Create a new ledger.
     pub fn new() -> Arc<Ledger> {
-        Arc::new(Ledger(Default::default()))
+        Self::builder().build()
+    }
+
+    /// Start building a ledger with a clock or id ranges of its own.
+    pub fn builder() -> LedgerBuilder {
+        LedgerBuilder::default()
+    }
+
+    /// Get the counts of devices allocated from this ledger.
+    pub fn stats(&self) -> Stats {
+        self.devices.stats()
     }
 
     /// Allocate a new device.
     pub fn create_device(self: Arc<Self>) -> Arc<DeviceId> {
-        let id = self.0.lock().unwrap().next().expect("out of devices");
+        let id = self.devices.next().expect("out of devices");
         Arc::new(DeviceId {
             id,
-            inodes: Default::default(),
+            inodes: Reusable::new(self.ids.inodes.clone(), self.ids.defer_reuse),
+            generation: 0.into(),
+            max_file_size: u64::MAX.into(),
+            max_bytes: u64::MAX.into(),
+            bytes: 0.into(),
+            max_inodes: u64::MAX.into(),
+            strict: false.into(),
+            coarse: false.into(),
+            insensitive: false.into(),
+            cancelled: false.into(),
+            deadline: Mutex::default(),
+            interrupt: Notify::new(),
+            credentials: Mutex::default(),
+            clock: Mutex::new(self.clock.clone()),
             devices: self,
         })
     }
 }
 
+/// How a device handles operations whose POSIX semantics are ambiguous.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Keep the historical behavior of this crate.
+    #[default]
+    Permissive,
+
+    /// Fail with the error POSIX gives, or refuse when it gives none.
+    Strict,
+}
+
+/// The precision of the timestamps of a device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// Keep timestamps as precise as the host clock.
+    #[default]
+    Nanosecond,
+
+    /// Truncate timestamps to whole seconds, as for reproducible trees.
+    Second,
+}
+
+impl Granularity {
+    /// Truncate `time` to this granularity.
+    pub fn truncate(self, time: SystemTime) -> SystemTime {
+        match (self, time.duration_since(UNIX_EPOCH)) {
+            (Granularity::Second, Ok(since)) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+            _ => time,
+        }
+    }
+}
+
+/// How a device matches the names of directory entries.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// Match names byte for byte, as POSIX does.
+    #[default]
+    Exact,
+
+    /// Match names regardless of case and Unicode normalization form, as
+    /// Windows does, while keeping the names as they were created.
+    Insensitive,
+}
+
+/// The user and group a guest accesses a device as
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    /// The user id
+    pub uid: u32,
+
+    /// The group id
+    pub gid: u32,
+}
+
 /// A filesystem device identifier.
 pub struct DeviceId {
     devices: Arc<Ledger>,
-    inodes: Mutex<Reusable>,
+    inodes: Reusable,
+    generation: AtomicU64,
+    max_file_size: AtomicU64,
+    max_bytes: AtomicU64,
+    bytes: AtomicU64,
+    max_inodes: AtomicU64,
+    strict: AtomicBool,
+    coarse: AtomicBool,
+    insensitive: AtomicBool,
+    cancelled: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+    interrupt: Notify,
+    credentials: Mutex<Option<Credentials>>,
+    clock: Mutex<Option<Clock>>,
     id: u64,
 }
 
 impl Drop for DeviceId {
     fn drop(&mut self) {
-        self.devices.0.lock().unwrap().free(self.id);
+        self.devices.devices.free(self.id);
     }
 }
 
@@ -109,10 +396,266 @@ impl DeviceId {
         self.devices.clone()
     }
 
+    /// Get the maximum size of a regular file on this device.
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size.load(Ordering::Relaxed)
+    }
+
+    /// Set the maximum size of a regular file on this device.
+    ///
+    /// The limit applies to future writes; existing files are untouched.
+    pub fn set_max_file_size(&self, size: u64) {
+        self.max_file_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Get the quota of bytes held by regular files on this device.
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Set the quota of bytes held by regular files on this device.
+    ///
+    /// Writes growing files past the quota fail with `ENOSPC`. Lowering the
+    /// quota below the bytes already used only stops further growth.
+    pub fn set_max_bytes(&self, bytes: u64) {
+        self.max_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Get the bytes held by regular files on this device.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    /// Count `bytes` more as used, unless that would exceed the quota.
+    pub fn reserve(&self, bytes: u64) -> bool {
+        if bytes == 0 {
+            return true;
+        }
+
+        let max = self.max_bytes();
+        self.bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|used| *used <= max)
+            })
+            .is_ok()
+    }
+
+    /// Count `bytes` more as used, even past the quota, as when the host
+    /// creates a file.
+    pub fn charge(&self, bytes: u64) {
+        let _ = self
+            .bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_add(bytes))
+            });
+    }
+
+    /// Count `bytes` fewer as used.
+    pub fn release(&self, bytes: u64) {
+        let _ = self
+            .bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+
+    /// Get the quota of inodes on this device.
+    pub fn max_inodes(&self) -> u64 {
+        self.max_inodes.load(Ordering::Relaxed)
+    }
+
+    /// Set the quota of inodes on this device.
+    ///
+    /// Guests creating nodes past the quota fail with `ENOSPC`; the host can
+    /// still create them. Like [`DeviceId::live_inodes`], the check is
+    /// approximate while inodes are being created or freed.
+    pub fn set_max_inodes(&self, inodes: u64) {
+        self.max_inodes.store(inodes, Ordering::Relaxed);
+    }
+
+    /// Whether another inode fits in the quota of this device.
+    pub fn inode_room(&self) -> bool {
+        self.live_inodes() < self.max_inodes()
+    }
+
+    /// Get the strictness of this device.
+    pub fn strictness(&self) -> Strictness {
+        match self.strict.load(Ordering::Relaxed) {
+            true => Strictness::Strict,
+            false => Strictness::Permissive,
+        }
+    }
+
+    /// Set the strictness of this device.
+    pub fn set_strictness(&self, strictness: Strictness) {
+        let strict = strictness == Strictness::Strict;
+        self.strict.store(strict, Ordering::Relaxed);
+    }
+
+    /// Get the timestamp granularity of this device.
+    pub fn granularity(&self) -> Granularity {
+        match self.coarse.load(Ordering::Relaxed) {
+            true => Granularity::Second,
+            false => Granularity::Nanosecond,
+        }
+    }
+
+    /// Set the timestamp granularity of this device.
+    ///
+    /// Timestamps already set keep their precision. A change time set
+    /// within the current second is kept, so it never moves backwards.
+    pub fn set_granularity(&self, granularity: Granularity) {
+        let coarse = granularity == Granularity::Second;
+        self.coarse.store(coarse, Ordering::Relaxed);
+    }
+
+    /// Get the name collation of this device.
+    pub fn collation(&self) -> Collation {
+        match self.insensitive.load(Ordering::Relaxed) {
+            true => Collation::Insensitive,
+            false => Collation::Exact,
+        }
+    }
+
+    /// Set the name collation of this device.
+    ///
+    /// Set it before populating the device: entries which already collide
+    /// under the new collation stay, but only one of them can be looked up.
+    pub fn set_collation(&self, collation: Collation) {
+        let insensitive = collation == Collation::Insensitive;
+        self.insensitive.store(insensitive, Ordering::Relaxed);
+    }
+
+    /// Get the clock of this device, if it does not use the host clock.
+    pub fn clock(&self) -> Option<Clock> {
+        self.clock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Set the clock of this device, or `None` for the host clock.
+    ///
+    /// Timestamps already set are kept.
+    pub fn set_clock(&self, clock: Option<Clock>) {
+        *self.clock.lock().unwrap_or_else(PoisonError::into_inner) = clock;
+    }
+
+    /// The current time by the clock of this device.
+    pub fn now(&self) -> SystemTime {
+        match self.clock() {
+            Some(clock) => clock(),
+            None => SystemTime::now(),
+        }
+    }
+
+    /// Get the credentials the guest accesses this device with, if any.
+    pub fn credentials(&self) -> Option<Credentials> {
+        *self
+            .credentials
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Set the credentials the guest accesses this device with.
+    ///
+    /// While set, nodes with permissions are checked against them. Without
+    /// credentials, the default, permissions are kept but not enforced.
+    pub fn set_credentials(&self, credentials: Option<Credentials>) {
+        *self
+            .credentials
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = credentials;
+    }
+
+    /// Cancel IO on this device.
+    ///
+    /// Guest operations fail with `EINTR` when they next take a lock, and
+    /// those waiting, as on a FIFO or a file lock, are woken to fail. They
+    /// keep failing until [`DeviceId::resume`] is called.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.interrupt.notify_waiters();
+    }
+
+    /// Let IO on this device proceed again after [`DeviceId::cancel`].
+    pub fn resume(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// Get the deadline for IO on this device, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Set the deadline for IO on this device.
+    ///
+    /// Past the deadline, guest operations fail as if IO was cancelled. The
+    /// deadline is checked as operations take locks and as waits wake up;
+    /// no timer wakes a wait at the deadline, so hosts wanting that should
+    /// call [`DeviceId::cancel`] from a timer of their own.
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner) = deadline;
+    }
+
+    /// Whether IO on this device is cancelled or past its deadline.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self.deadline().is_some_and(|d| d <= Instant::now())
+    }
+
+    /// Wait until IO on this device is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.interrupt.notified();
+            if self.cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Get the number of inodes allocated on this device and not yet freed.
+    ///
+    /// While inodes are being created or freed, the count is approximate.
+    pub fn live_inodes(&self) -> u64 {
+        self.inodes.live()
+    }
+
+    /// Get the counts of inodes allocated on this device.
+    ///
+    /// The free inodes are capped by the quota of the device.
+    pub fn stats(&self) -> Stats {
+        let stats = self.inodes.stats();
+        let room = self.max_inodes().saturating_sub(stats.allocated);
+        Stats {
+            free: std::cmp::min(stats.free, room),
+            ..stats
+        }
+    }
+
+    /// Get the usage of this device, as `statvfs` reports it.
+    pub fn statvfs(&self) -> Statvfs {
+        let bytes = self.bytes();
+        Statvfs {
+            device: self.id,
+            inodes: self.stats(),
+            bytes,
+            free_bytes: self.max_bytes().saturating_sub(bytes),
+        }
+    }
+
     /// Allocate a new inode.
     pub fn create_inode(self: Arc<Self>) -> Arc<InodeId> {
-        let id = self.inodes.lock().unwrap().next().expect("out of inodes");
-        Arc::new(InodeId { id, device: self })
+        let id = self.inodes.next().expect("out of inodes");
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        Arc::new(InodeId {
+            id,
+            generation,
+            device: self,
+            links: AtomicU64::new(0),
+        })
     }
 }
 
@@ -120,11 +663,13 @@ impl DeviceId {
 pub struct InodeId {
     device: Arc<DeviceId>,
     id: u64,
+    generation: u64,
+    links: AtomicU64,
 }
 
 impl Drop for InodeId {
     fn drop(&mut self) {
-        self.device.inodes.lock().unwrap().free(self.id);
+        self.device.inodes.free(self.id);
     }
 }
 
@@ -144,15 +689,41 @@ impl PartialEq for InodeId {
 }
 
 impl InodeId {
+    /// Get the generation of this inode.
+    ///
+    /// Each inode created on a device has a later generation than those
+    /// before it, so the pair of id and generation is never repeated on
+    /// the device even when the id is.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     /// Get a reference to the device.
     pub fn device(&self) -> Arc<DeviceId> {
         self.device.clone()
     }
+
+    /// Get the number of directory entries naming this inode.
+    pub fn links(&self) -> u64 {
+        self.links.load(Ordering::SeqCst)
+    }
+
+    /// Count a new directory entry naming this inode.
+    pub fn link(&self) {
+        self.links.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Count the removal of a directory entry naming this inode.
+    pub fn unlink(&self) {
+        let _ = self
+            .links
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::Ledger;
+    use crate::{Ledger, Reusable, Stats};
 
     #[test]
     fn reuse() {
@@ -208,4 +779,169 @@ mod test {
         assert_eq!(**inode00.device(), 0);
         assert_eq!(**inode00, 0);
     }
+
+    #[test]
+    fn compaction() {
+        let ids = Reusable::default();
+        let all: Vec<_> = (0..8).map(|_| ids.next().unwrap()).collect();
+        assert_eq!(all, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // Freed ids merge into ranges, lowest reused first.
+        for id in [1, 2, 5, 3] {
+            ids.free(id);
+        }
+        assert_eq!(ids.lock().ranges.len(), 2);
+        assert_eq!(ids.next(), Some(1));
+        ids.free(1);
+
+        // Freeing the top id folds every adjacent range back into the counter.
+        ids.free(7);
+        ids.free(6);
+        ids.free(4);
+        assert!(ids.lock().ranges.is_empty());
+        ids.free(0);
+        assert_eq!(ids.next(), Some(0));
+        assert_eq!(ids.next(), Some(1));
+    }
+
+    #[test]
+    fn churn() {
+        use std::collections::BTreeSet;
+
+        let device = Ledger::new().create_device();
+
+        let held: Vec<_> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut held = Vec::new();
+                        for i in 0..1000 {
+                            held.push(device.clone().create_inode());
+                            if i % 3 == 0 {
+                                held.swap_remove(i % held.len());
+                            }
+                        }
+                        held
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .flat_map(|t| t.join().unwrap())
+                .collect()
+        });
+
+        // No id is held twice at once.
+        let ids: BTreeSet<_> = held.iter().map(|i| ***i).collect();
+        assert_eq!(ids.len(), held.len());
+
+        // Once everything is freed, all ids are back in the contiguous range.
+        assert_eq!(device.live_inodes(), held.len() as u64);
+        drop(held);
+        assert_eq!(device.live_inodes(), 0);
+        assert!(device.inodes.lock().ranges.is_empty());
+        assert_eq!(**device.clone().create_inode(), 0);
+    }
+
+    #[test]
+    fn ids() {
+        use crate::Ids;
+
+        // Reused ids are told apart by their generation.
+        let device = Ledger::new().create_device();
+        let first = device.clone().create_inode();
+        let (id, generation) = (**first, first.generation());
+        drop(first);
+        let second = device.clone().create_inode();
+        assert_eq!(**second, id);
+        assert!(second.generation() > generation);
+
+        let ledger = Ledger::builder()
+            .ids(Ids {
+                devices: 100..102,
+                inodes: 1000..1003,
+                defer_reuse: true,
+            })
+            .build();
+        let device = ledger.clone().create_device();
+        assert_eq!(**device, 100);
+        assert_eq!(device.stats().free, 3);
+
+        // Freed ids wait until the fresh ones run out.
+        let inodes: Vec<_> = (0..2).map(|_| device.clone().create_inode()).collect();
+        assert_eq!(**inodes[0], 1000);
+        drop(inodes);
+        assert_eq!(device.live_inodes(), 0);
+        let inodes: Vec<_> = (0..3).map(|_| device.clone().create_inode()).collect();
+        let ids: Vec<_> = inodes.iter().map(|i| ***i).collect();
+        assert_eq!(ids[0], 1002);
+        assert!(ids[1..].contains(&1000) && ids[1..].contains(&1001));
+        assert_eq!(device.stats().free, 0);
+
+        // Devices stay in their range too.
+        drop(device);
+        assert_eq!(**ledger.clone().create_device(), 101);
+        assert_eq!(ledger.stats().free, 1);
+
+        // A clock and id ranges apply together.
+        let device = Ledger::builder()
+            .clock(std::sync::Arc::new(|| std::time::UNIX_EPOCH))
+            .ids(Ids {
+                devices: 7..8,
+                ..Ids::default()
+            })
+            .build()
+            .create_device();
+        assert_eq!(**device, 7);
+        assert_eq!(device.now(), std::time::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn bytes() {
+        let device = Ledger::new().create_device();
+        device.set_max_bytes(10);
+        assert!(device.reserve(6));
+        assert!(!device.reserve(5));
+        assert!(device.reserve(4));
+        assert_eq!(device.bytes(), 10);
+
+        // The host may exceed the quota, and releasing never underflows.
+        device.charge(5);
+        assert_eq!(device.bytes(), 15);
+        device.release(20);
+        assert_eq!(device.bytes(), 0);
+    }
+
+    #[test]
+    fn stats() {
+        let ledger = Ledger::new();
+        let device = ledger.clone().create_device();
+        let inodes: Vec<_> = (0..3).map(|_| device.clone().create_inode()).collect();
+
+        let stats = Stats {
+            allocated: 3,
+            free: u64::MAX - 3,
+        };
+        assert_eq!(device.stats(), stats);
+        assert_eq!(ledger.stats().allocated, 1);
+
+        device.set_max_inodes(5);
+        assert_eq!(device.stats().free, 2);
+        assert!(device.inode_room());
+        device.set_max_inodes(3);
+        assert!(!device.inode_room());
+
+        device.set_max_bytes(10);
+        device.charge(4);
+        let statvfs = device.statvfs();
+        assert_eq!(statvfs.device, **device);
+        assert_eq!(statvfs.inodes, device.stats());
+        assert_eq!((statvfs.bytes, statvfs.free_bytes), (4, 6));
+
+        drop(inodes);
+        assert_eq!(device.stats().allocated, 0);
+        drop(device);
+        assert_eq!(ledger.stats().allocated, 0);
+    }
 }
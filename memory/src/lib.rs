@@ -1,19 +1,118 @@
-use std::sync::Weak;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::{PoisonError, RwLock as SyncRwLock, Weak};
+use std::task::Poll;
 use std::time::SystemTime;
 use std::{any::Any, sync::Arc};
 
-use tokio::sync::RwLock;
-use wasi_common::file::{FdFlags, FileType};
-use wasi_common::{Error, SystemTimeSpec, WasiDir, WasiFile};
-use wasmtime_vfs_ledger::InodeId;
+use rustix::io::Errno;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::{Credentials, DeviceId, InodeId};
+
+pub use watch::{Event, Watch, Watchers, MAX_QUEUED_EVENTS};
+
+mod watch;
 
 #[async_trait::async_trait]
 pub trait Node: 'static + Any + Send + Sync {
     fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
     fn parent(&self) -> Option<Arc<dyn Node>>;
+
+    /// Points the node at a new parent, as when it is renamed into another directory.
+    fn set_parent(&self, parent: Weak<dyn Node>);
     fn filetype(&self) -> FileType;
     fn id(&self) -> Arc<InodeId>;
 
+    /// The target of a symbolic link, or `None` for any other node.
+    fn read_link(&self) -> Option<String> {
+        None
+    }
+
+    /// The owner and mode of the node, or `None` if it has none to enforce.
+    async fn permissions(&self) -> Option<Permissions> {
+        None
+    }
+
+    /// Sets the owner and mode of the node.
+    async fn set_permissions(&self, _permissions: Permissions) -> Result<(), Error> {
+        Err(Error::not_supported())
+    }
+
+    /// The value of the extended attribute `name`, failing with `ENODATA`
+    /// if it is not set.
+    async fn xattr(&self, _name: &str) -> Result<Vec<u8>, Error> {
+        Err(Error::not_supported())
+    }
+
+    /// The names of the extended attributes set, in order.
+    async fn xattrs(&self) -> Result<Vec<String>, Error> {
+        Err(Error::not_supported())
+    }
+
+    /// Sets the extended attribute `name` to `value`.
+    async fn set_xattr(&self, _name: &str, _value: Vec<u8>) -> Result<(), Error> {
+        Err(Error::not_supported())
+    }
+
+    /// Removes the extended attribute `name`, failing with `ENODATA` if it
+    /// is not set.
+    async fn remove_xattr(&self, _name: &str) -> Result<(), Error> {
+        Err(Error::not_supported())
+    }
+
+    /// Discards any secret held by the node, once the last directory entry
+    /// naming it is removed, so handles still open to it stop working.
+    async fn revoke(&self) {}
+
+    /// Starts watching the node for changes, or `None` if it cannot be
+    /// watched.
+    fn watch(&self) -> Option<Watch> {
+        None
+    }
+
+    /// Copies the node under `parent` for a snapshot, or `None` if it cannot
+    /// be copied. Copies share what they can with the original.
+    async fn snapshot(&self, _parent: Arc<dyn Node>) -> Option<Arc<dyn Node>> {
+        None
+    }
+
+    /// The attributes of the node, as a handle to it would report them.
+    ///
+    /// The default opens a handle to ask. Nodes override it to stat
+    /// without one.
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let flags = FdFlags::empty();
+        let mut file = self.open_file("", false, false, false, flags).await?;
+        file.get_filestat().await
+    }
+
+    /// Sets the access and modification times of the node.
+    ///
+    /// The default opens a handle to set them. Nodes override it to set
+    /// them without one.
+    async fn set_times(
+        self: Arc<Self>,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        let flags = FdFlags::empty();
+        let mut file = self.open_file("", false, false, false, flags).await?;
+        file.set_times(atime, mtime).await
+    }
+
+    /// Looks up the entry `name` of a directory, `.` and `..` included, for
+    /// [`walk_path`].
+    ///
+    /// Returns `None` for nodes which cannot be walked by name, such as
+    /// files, and directories which resolve their paths themselves.
+    async fn child(self: Arc<Self>, _name: &str) -> Option<Result<Arc<dyn Node>, Error>> {
+        None
+    }
+
     async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error>;
 
     async fn open_file(
@@ -39,35 +138,496 @@ pub trait Node: 'static + Any + Send + Sync {
     }
 }
 
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for wasi_common::Error {}
+}
+
+/// The semver-stable surface for writing custom file systems
+///
+/// Custom nodes implement [`Node`](api::Node) and keep their parent in a
+/// [`Parent`](api::Parent). The other items of this crate, such as
+/// [`Link`] and [`Open`], are building blocks of the workspace's own
+/// nodes, reached through their methods; their layout may change in any
+/// release.
+pub mod api {
+    pub use crate::{
+        access, interruptible, normalize, walk_path, walk_path_as_guest, ErrnoExt, Node, Parent,
+        Permissions, MAX_LINKS,
+    };
+    pub use wasmtime_vfs_ledger::{
+        Clock, Collation, Credentials, DeviceId, Granularity, InodeId, Ledger, Strictness,
+    };
+}
+
+/// Constructors for errors which [`wasi_common::ErrorExt`] does not provide
+///
+/// This trait is sealed; it is only implemented for [`Error`].
+pub trait ErrnoExt: sealed::Sealed {
+    fn file_too_big() -> Self;
+    fn is_dir() -> Self;
+    fn not_empty() -> Self;
+    fn would_block() -> Self;
+    fn broken_pipe() -> Self;
+    fn busy() -> Self;
+    fn access() -> Self;
+    fn no_space() -> Self;
+
+    /// The host cancelled IO on the device; see [`DeviceId::cancel`].
+    fn interrupted() -> Self;
+
+    /// The operation would move a node between devices.
+    ///
+    /// WASI has no mapping for `EXDEV`, so guests see `ENOTSUP`. Host
+    /// callers can still find the `EXDEV` [`std::io::Error`] by downcasting.
+    fn cross_device() -> Self;
+
+    /// A path crossed more than [`MAX_LINKS`] symbolic links, or a link
+    /// was opened without following it.
+    fn too_many_links() -> Self;
+
+    /// An extended attribute is not set.
+    ///
+    /// WASI has no mapping for `ENODATA`, so guests see `ENOENT`. Host
+    /// callers can still find the `ENODATA` [`std::io::Error`] by downcasting.
+    fn no_data() -> Self;
+
+    /// The operation would change a read-only tree.
+    ///
+    /// WASI has no mapping for `EROFS`, so guests see `EPERM`. Host callers
+    /// can still find the `EROFS` [`std::io::Error`] by downcasting.
+    fn read_only() -> Self;
+}
+
+impl ErrnoExt for Error {
+    fn file_too_big() -> Self {
+        std::io::Error::from(Errno::FBIG).into()
+    }
+
+    fn is_dir() -> Self {
+        std::io::Error::from(Errno::ISDIR).into()
+    }
+
+    fn not_empty() -> Self {
+        std::io::Error::from(Errno::NOTEMPTY).into()
+    }
+
+    fn would_block() -> Self {
+        std::io::Error::from(Errno::AGAIN).into()
+    }
+
+    fn broken_pipe() -> Self {
+        std::io::Error::from(Errno::PIPE).into()
+    }
+
+    fn busy() -> Self {
+        std::io::Error::from(Errno::BUSY).into()
+    }
+
+    fn access() -> Self {
+        std::io::Error::from(Errno::ACCESS).into()
+    }
+
+    fn no_space() -> Self {
+        std::io::Error::from(Errno::NOSPC).into()
+    }
+
+    fn interrupted() -> Self {
+        std::io::Error::from(Errno::INTR).into()
+    }
+
+    fn cross_device() -> Self {
+        Error::not_supported().context(std::io::Error::from(Errno::XDEV))
+    }
+
+    fn too_many_links() -> Self {
+        std::io::Error::from(Errno::LOOP).into()
+    }
+
+    fn no_data() -> Self {
+        Error::not_found().context(std::io::Error::from(Errno::NODATA))
+    }
+
+    fn read_only() -> Self {
+        Error::perm().context(std::io::Error::from(Errno::ROFS))
+    }
+}
+
+/// The timestamps, permissions and content of an inode
 pub struct Data<T> {
-    pub create: SystemTime,
-    pub access: SystemTime,
-    pub modify: SystemTime,
-    pub content: T,
+    pub(crate) create: SystemTime,
+    pub(crate) access: SystemTime,
+    pub(crate) modify: SystemTime,
+    pub(crate) change: SystemTime,
+    pub(crate) content: T,
+
+    /// The owner and mode of the inode
+    pub(crate) permissions: Permissions,
+
+    /// The extended attributes of the inode, by name
+    pub(crate) xattrs: BTreeMap<String, Vec<u8>>,
+
+    /// The device of the inode, which sets the precision of the timestamps
+    pub(crate) device: Arc<DeviceId>,
+}
+
+/// The owner and permission bits of a node
+///
+/// Only the `rwx` bits of `mode` for owner, group and others are checked.
+/// The owner of a node is checked by its class alone, as on Linux, and
+/// uid 0 passes every check.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    /// The permission bits, as in `0o755`
+    pub mode: u32,
+
+    /// The user id of the owner
+    pub uid: u32,
+
+    /// The group id of the owner
+    pub gid: u32,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            mode: 0o777,
+            uid: 0,
+            gid: 0,
+        }
+    }
+}
+
+impl Permissions {
+    /// Whether `credentials` may read, write and search or execute, as asked.
+    pub fn allows(&self, credentials: Credentials, read: bool, write: bool, exec: bool) -> bool {
+        if credentials.uid == 0 {
+            return true;
+        }
+
+        let shift = if credentials.uid == self.uid {
+            6
+        } else if credentials.gid == self.gid {
+            3
+        } else {
+            0
+        };
+
+        let want = (read as u32) << 2 | (write as u32) << 1 | exec as u32;
+        (self.mode >> shift) & want == want
+    }
 }
 
+/// An inode: its data behind a lock, its id and its watches
 pub struct Inode<T> {
-    pub data: RwLock<Data<T>>,
-    pub id: Arc<InodeId>,
+    pub(crate) data: RwLock<Data<T>>,
+    pub(crate) id: Arc<InodeId>,
+
+    /// The watches on the inode, told of each change made to it
+    pub(crate) watchers: Watchers,
+}
+
+impl<T> Inode<T> {
+    /// Locks the data for reading, failing with `EINTR` if IO on the device
+    /// is cancelled first.
+    pub async fn read(&self) -> Result<RwLockReadGuard<'_, Data<T>>, Error> {
+        interruptible(&self.id.device(), self.data.read()).await
+    }
+
+    /// Locks the data for writing, failing with `EINTR` if IO on the device
+    /// is cancelled first.
+    pub async fn write(&self) -> Result<RwLockWriteGuard<'_, Data<T>>, Error> {
+        interruptible(&self.id.device(), self.data.write()).await
+    }
+
+    /// The data of the inode, behind its lock
+    pub fn data(&self) -> &RwLock<Data<T>> {
+        &self.data
+    }
+
+    /// The id of the inode
+    pub fn id(&self) -> Arc<InodeId> {
+        self.id.clone()
+    }
+
+    /// The watches on the inode, told of each change made to it
+    pub fn watchers(&self) -> &Watchers {
+        &self.watchers
+    }
+}
+
+/// Runs `future`, failing with `EINTR` if IO on `device` is cancelled first.
+pub async fn interruptible<F: Future>(device: &DeviceId, future: F) -> Result<F::Output, Error> {
+    if device.is_cancelled() {
+        return Err(Error::interrupted());
+    }
+
+    let mut future = pin!(future);
+    let mut cancelled = pin!(device.cancelled());
+    poll_fn(|cx| {
+        if cancelled.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(Error::interrupted()));
+        }
+
+        future.as_mut().poll(cx).map(Ok)
+    })
+    .await
+}
+
+/// Normalizes a relative path as POSIX resolves it.
+///
+/// Repeated and trailing separators count as one and `.` segments are
+/// dropped, but for a last one. Empty paths fail with `ENOENT` and absolute
+/// ones with `EINVAL`.
+pub fn normalize(path: &str) -> Result<Cow<'_, str>, Error> {
+    if path.is_empty() {
+        return Err(Error::not_found());
+    }
+
+    if path.starts_with('/') {
+        return Err(Error::invalid_argument());
+    }
+
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let last = segments.pop().unwrap_or(".");
+    segments.retain(|s| *s != ".");
+    segments.push(last);
+
+    match segments.join("/") {
+        normal if normal == path => Ok(Cow::Borrowed(path)),
+        normal => Ok(Cow::Owned(normal)),
+    }
+}
+
+/// Fails with `EACCES` unless the guest may access `node` as asked.
+///
+/// The guest is the credentials of the device of `node`; without any, every
+/// access is allowed.
+pub async fn access(node: &dyn Node, read: bool, write: bool, exec: bool) -> Result<(), Error> {
+    let credentials = match node.id().device().credentials() {
+        Some(credentials) => credentials,
+        None => return Ok(()),
+    };
+
+    match node.permissions().await {
+        Some(perms) if !perms.allows(credentials, read, write, exec) => Err(Error::access()),
+        _ => Ok(()),
+    }
+}
+
+/// The most symbolic links followed while resolving a path, as on Linux
+pub const MAX_LINKS: usize = 40;
+
+/// Walks all but the last segment of the [`normalize`]d `path` from `dir`.
+///
+/// Returns the node reached and the rest of the path. The rest is the last
+/// segment, unless the walk stopped early at a node which cannot be walked
+/// by [`Node::child`]; the directory opened from that node resolves it.
+/// Segments are walked in a loop, so long paths cost no stack.
+///
+/// Symbolic links on the way are followed: relative targets from the
+/// directory holding the link and absolute ones from the root of its tree.
+/// The last segment is left to the caller, even if it is a link.
+pub async fn walk_path(
+    dir: Arc<dyn Node>,
+    path: &str,
+) -> Result<(Arc<dyn Node>, Cow<'_, str>), Error> {
+    walk(dir, path, false).await
+}
+
+/// Walks `path` as [`walk_path`] does, on behalf of the guest.
+///
+/// Each directory searched on the way must allow the credentials of its
+/// device to search it, or the walk fails with `EACCES`.
+pub async fn walk_path_as_guest(
+    dir: Arc<dyn Node>,
+    path: &str,
+) -> Result<(Arc<dyn Node>, Cow<'_, str>), Error> {
+    walk(dir, path, true).await
 }
 
+async fn walk(
+    dir: Arc<dyn Node>,
+    path: &str,
+    search: bool,
+) -> Result<(Arc<dyn Node>, Cow<'_, str>), Error> {
+    let mut node = dir;
+    let mut rest = Cow::Borrowed(path);
+    let mut links = 0;
+
+    while let Some(i) = rest.find('/') {
+        if search {
+            access(&*node, false, false, true).await?;
+        }
+
+        let child = match node.clone().child(&rest[..i]).await {
+            Some(child) => child?,
+            None => break,
+        };
+
+        let target = match child.read_link() {
+            Some(target) => target,
+            None => {
+                node = child;
+                rest = match rest {
+                    Cow::Borrowed(rest) => Cow::Borrowed(&rest[i + 1..]),
+                    Cow::Owned(mut rest) => {
+                        rest.drain(..=i);
+                        Cow::Owned(rest)
+                    }
+                };
+                continue;
+            }
+        };
+
+        links += 1;
+        if links > MAX_LINKS {
+            return Err(Error::too_many_links());
+        }
+
+        if target.starts_with('/') {
+            while let Some(parent) = node.parent() {
+                node = parent;
+            }
+        }
+
+        let target = match target.trim_start_matches('/') {
+            "" => Cow::Borrowed("."),
+            target => normalize(target)?,
+        };
+        rest = Cow::Owned(format!("{target}/{}", &rest[i + 1..]));
+    }
+
+    Ok((node, rest))
+}
+
+/// A weak reference to the parent of a node, which changes on rename
+pub struct Parent(SyncRwLock<Weak<dyn Node>>);
+
+impl From<Weak<dyn Node>> for Parent {
+    fn from(parent: Weak<dyn Node>) -> Self {
+        Self(parent.into())
+    }
+}
+
+impl Parent {
+    pub fn upgrade(&self) -> Option<Arc<dyn Node>> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .upgrade()
+    }
+
+    pub fn set(&self, parent: Weak<dyn Node>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = parent;
+    }
+}
+
+/// The parent and inode of a node
 pub struct Link<T> {
-    pub parent: Weak<dyn Node>,
-    pub inode: Arc<Inode<T>>,
+    pub(crate) parent: Parent,
+    pub(crate) inode: Arc<Inode<T>>,
 }
 
+impl<T> Link<T> {
+    /// Creates a link to a new inode on the device of `parent`.
+    pub fn new(parent: &Arc<dyn Node>, content: T) -> Self {
+        let id = parent.id().device().create_inode();
+        let inode = Inode {
+            data: Data::new(content, id.device()).into(),
+            id,
+            watchers: Watchers::default(),
+        };
+
+        Self {
+            parent: Arc::downgrade(parent).into(),
+            inode: inode.into(),
+        }
+    }
+
+    /// Creates a link to the new inode `id` with default content, as for the
+    /// root of a device.
+    pub fn with_id(parent: Weak<dyn Node>, id: Arc<InodeId>) -> Self
+    where
+        T: Default,
+    {
+        Self {
+            parent: parent.into(),
+            inode: Arc::new(id.into()),
+        }
+    }
+
+    /// The parent of the node, unless it is gone
+    pub fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.parent.upgrade()
+    }
+
+    /// Points the node at a new parent, as when it is renamed.
+    pub fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.parent.set(parent)
+    }
+
+    /// The inode of the node
+    pub fn inode(&self) -> &Arc<Inode<T>> {
+        &self.inode
+    }
+}
+
+/// The state of an open handle to a node
 pub struct Open<T> {
-    pub root: Arc<dyn Node>,
-    pub link: Arc<T>,
+    pub(crate) root: Arc<dyn Node>,
+    pub(crate) link: Arc<T>,
 
-    pub state: RwLock<State>,
-    pub write: bool,
-    pub read: bool,
+    pub(crate) state: RwLock<State>,
+    pub(crate) write: bool,
+    pub(crate) read: bool,
 }
 
+impl<T: Node> Open<T> {
+    /// Opens `link`, keeping its root alive for as long as the handle.
+    pub fn new(link: Arc<T>, read: bool, write: bool, flags: FdFlags) -> Self {
+        Self {
+            root: link.root(),
+            link,
+            state: State::from(flags).into(),
+            write,
+            read,
+        }
+    }
+}
+
+impl<T> Open<T> {
+    /// The root of the tree, kept alive for as long as the handle
+    pub fn root(&self) -> &Arc<dyn Node> {
+        &self.root
+    }
+
+    /// The node the handle is open on
+    pub fn link(&self) -> &Arc<T> {
+        &self.link
+    }
+
+    /// The flags and position of the handle, behind their lock
+    pub fn state(&self) -> &RwLock<State> {
+        &self.state
+    }
+
+    /// Whether the handle was opened for reading
+    pub fn can_read(&self) -> bool {
+        self.read
+    }
+
+    /// Whether the handle was opened for writing
+    pub fn can_write(&self) -> bool {
+        self.write
+    }
+}
+
+/// The flags and position of an open handle
 pub struct State {
-    pub flags: FdFlags,
-    pub pos: usize,
+    pub(crate) flags: FdFlags,
+    pub(crate) pos: usize,
 }
 
 impl Default for State {
@@ -83,33 +643,163 @@ impl From<FdFlags> for State {
     }
 }
 
-impl<T> From<T> for Data<T> {
-    fn from(content: T) -> Self {
-        let now = SystemTime::now();
+impl State {
+    /// The flags of the handle
+    pub fn flags(&self) -> FdFlags {
+        self.flags
+    }
+
+    /// Replaces the flags of the handle.
+    pub fn set_flags(&mut self, flags: FdFlags) {
+        self.flags = flags;
+    }
+
+    /// The position reads and writes without an offset start at
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Moves the handle to `pos`.
+    pub fn set_pos(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+}
+
+impl<T: Default> From<Arc<InodeId>> for Inode<T> {
+    fn from(id: Arc<InodeId>) -> Self {
+        let data = Data::new(T::default(), id.device()).into();
+        let watchers = Watchers::default();
+        Self { data, id, watchers }
+    }
+}
+
+impl<T> Data<T> {
+    /// Creates the data of a new inode on `device`.
+    pub fn new(content: T, device: Arc<DeviceId>) -> Self {
+        let now = device.granularity().truncate(device.now());
 
         Self {
             create: now,
             access: now,
             modify: now,
+            change: now,
             content,
+            permissions: Permissions::default(),
+            xattrs: BTreeMap::new(),
+            device,
         }
     }
-}
 
-impl<T: Default> Default for Data<T> {
-    fn default() -> Self {
-        T::default().into()
+    /// The content of the inode
+    pub fn content(&self) -> &T {
+        &self.content
     }
-}
 
-impl<T: Default> From<Arc<InodeId>> for Inode<T> {
-    fn from(id: Arc<InodeId>) -> Self {
-        let data = Data::default().into();
-        Self { data, id }
+    /// The content of the inode, to change it
+    pub fn content_mut(&mut self) -> &mut T {
+        &mut self.content
+    }
+
+    /// The time the inode was created
+    pub fn create_time(&self) -> SystemTime {
+        self.create
+    }
+
+    /// The time the content was last read
+    pub fn access_time(&self) -> SystemTime {
+        self.access
+    }
+
+    /// The time the content was last modified
+    pub fn modify_time(&self) -> SystemTime {
+        self.modify
+    }
+
+    /// The time the content or metadata was last changed
+    pub fn change_time(&self) -> SystemTime {
+        self.change
+    }
+
+    /// Sets the modification time alone, as when unpacking an archive.
+    pub fn set_modify_time(&mut self, time: SystemTime) {
+        self.modify = self.truncate(time);
+    }
+
+    /// The owner and mode of the inode
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    /// The extended attributes of the inode, by name
+    pub fn xattrs(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.xattrs
+    }
+
+    /// The device of the inode, which sets the precision of the timestamps
+    pub fn device(&self) -> &Arc<DeviceId> {
+        &self.device
+    }
+
+    /// The current time, at the granularity of the device.
+    ///
+    /// The time never precedes the change time, even if the clock of the device was
+    /// set back, so that the change time never moves backwards.
+    pub fn now(&self) -> SystemTime {
+        self.truncate(self.device.now()).max(self.change)
+    }
+
+    fn truncate(&self, time: SystemTime) -> SystemTime {
+        self.device.granularity().truncate(time)
+    }
+
+    /// Takes the timestamps, permissions and extended attributes of
+    /// `other`, as for a copy.
+    pub fn copy_metadata<U>(&mut self, other: &Data<U>) {
+        self.create = other.create;
+        self.access = other.access;
+        self.modify = other.modify;
+        self.change = other.change;
+        self.permissions = other.permissions;
+        self.xattrs = other.xattrs.clone();
+    }
+
+    /// Set the owner and mode, which is itself a metadata change.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+        self.change = self.now();
+    }
+
+    /// The value of the extended attribute `name`, failing with `ENODATA`
+    /// if it is not set.
+    pub fn xattr(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.xattrs.get(name).cloned().ok_or_else(Error::no_data)
+    }
+
+    /// Sets the extended attribute `name`, which is a metadata change.
+    pub fn set_xattr(&mut self, name: &str, value: Vec<u8>) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        self.xattrs.insert(name.into(), value);
+        self.change = self.now();
+        Ok(())
+    }
+
+    /// Removes the extended attribute `name`, which is a metadata change.
+    pub fn remove_xattr(&mut self, name: &str) -> Result<(), Error> {
+        self.xattrs.remove(name).ok_or_else(Error::no_data)?;
+        self.change = self.now();
+        Ok(())
+    }
+
+    /// Mark the content as modified, as when a directory entry is added or removed.
+    pub fn touch(&mut self) {
+        let now = self.now();
+        self.modify = now;
+        self.change = now;
     }
-}
 
-impl<T> Data<T> {
     // Update the timestamps of this inode.
     pub fn set_times(
         &mut self,
@@ -121,8 +811,8 @@ impl<T> Data<T> {
 
         // If either input wants the current time, get it.
         let now = match (&atime, &mtime) {
-            (Some(SystemTimeSpec::SymbolicNow), _) => Some(SystemTime::now()),
-            (_, Some(SystemTimeSpec::SymbolicNow)) => Some(SystemTime::now()),
+            (Some(SystemTimeSpec::SymbolicNow), _) => Some(self.now()),
+            (_, Some(SystemTimeSpec::SymbolicNow)) => Some(self.now()),
             _ => None,
         };
 
@@ -130,7 +820,7 @@ impl<T> Data<T> {
         if let Some(atime) = atime {
             self.access = match atime {
                 SystemTimeSpec::SymbolicNow => now.unwrap(),
-                SystemTimeSpec::Absolute(time) => time.into_std(),
+                SystemTimeSpec::Absolute(time) => self.truncate(time.into_std()),
             };
         }
 
@@ -138,10 +828,37 @@ impl<T> Data<T> {
         if let Some(mtime) = mtime {
             self.modify = match mtime {
                 SystemTimeSpec::SymbolicNow => now.unwrap(),
-                SystemTimeSpec::Absolute(time) => time.into_std(),
+                SystemTimeSpec::Absolute(time) => self.truncate(time.into_std()),
             };
         }
 
+        // Changing the timestamps is itself a metadata change.
+        self.change = now.unwrap_or_else(|| self.now());
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normal() {
+        for (path, normal) in [
+            ("foo", "foo"),
+            ("foo//bar", "foo/bar"),
+            ("./foo/", "foo"),
+            ("foo/./bar", "foo/bar"),
+            ("foo/.", "foo/."),
+            ("./", "."),
+            ("foo/../bar//", "foo/../bar"),
+        ] {
+            assert_eq!(normalize(path).unwrap(), normal, "{path}");
+        }
+
+        let enoent = normalize("").unwrap_err().downcast::<std::io::Error>();
+        assert_eq!(enoent.unwrap().kind(), std::io::ErrorKind::NotFound);
+        normalize("/foo").unwrap_err();
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+use tokio::sync::Notify;
+
+/// How many events a watch queues before it overflows, as inotify's
+/// `max_queued_events` bounds it
+pub const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// A change to a watched node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The entry `name` was created in the directory, or linked or moved
+    /// into it from another directory.
+    Create(String),
+
+    /// The file was written to or resized.
+    Write,
+
+    /// The entry `name` was removed from the directory, or moved into
+    /// another directory.
+    Remove(String),
+
+    /// The entry `from` was renamed to `to` within the directory.
+    Rename { from: String, to: String },
+
+    /// Events were dropped because the watch was not read in time.
+    Overflow,
+}
+
+#[derive(Default)]
+struct Queue {
+    events: Mutex<VecDeque<Event>>,
+    notify: Notify,
+}
+
+/// The watches on an inode
+///
+/// Sending an event costs nothing while the inode is not watched.
+#[derive(Default)]
+pub struct Watchers(Mutex<Vec<Weak<Queue>>>);
+
+impl Watchers {
+    /// Starts watching the inode. Events sent from now on are queued until
+    /// the watch is dropped.
+    pub fn subscribe(&self) -> Watch {
+        let queue = Arc::<Queue>::default();
+        let mut queues = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        queues.push(Arc::downgrade(&queue));
+        Watch(queue)
+    }
+
+    /// Queues `event` on every watch of the inode.
+    pub fn send(&self, event: Event) {
+        let mut queues = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        queues.retain(|queue| {
+            let queue = match queue.upgrade() {
+                Some(queue) => queue,
+                None => return false,
+            };
+
+            // As with inotify, an event repeating the last one queued is
+            // merged into it, and a full queue ends with a single overflow.
+            let mut events = queue.events.lock().unwrap_or_else(PoisonError::into_inner);
+            match events.len() {
+                _ if events.back() == Some(&event) => {}
+                len if len < MAX_QUEUED_EVENTS => events.push_back(event.clone()),
+                MAX_QUEUED_EVENTS => events.push_back(Event::Overflow),
+                _ => {}
+            }
+
+            drop(events);
+            queue.notify.notify_waiters();
+            true
+        });
+    }
+}
+
+/// A subscription to the events of an inode
+pub struct Watch(Arc<Queue>);
+
+impl Watch {
+    /// Takes the next event, if one is queued.
+    pub fn try_next(&self) -> Option<Event> {
+        let mut events = self.0.events.lock().unwrap_or_else(PoisonError::into_inner);
+        events.pop_front()
+    }
+
+    /// Takes the next event without removing it.
+    pub fn peek(&self) -> Option<Event> {
+        let events = self.0.events.lock().unwrap_or_else(PoisonError::into_inner);
+        events.front().cloned()
+    }
+
+    /// Waits until an event is queued.
+    pub async fn ready(&self) {
+        loop {
+            // Registered before checking, so an event in between is not lost.
+            let notified = self.0.notify.notified();
+            if self.peek().is_some() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Waits for the next event and takes it.
+    pub async fn next(&self) -> Event {
+        loop {
+            self.ready().await;
+            if let Some(event) = self.try_next() {
+                return event;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overflow() {
+        let watchers = Watchers::default();
+        watchers.send(Event::Write);
+
+        // Only events sent since subscribing are queued, and repeats merge.
+        let watch = watchers.subscribe();
+        watchers.send(Event::Write);
+        watchers.send(Event::Write);
+        watchers.send(Event::Create("a".into()));
+        assert_eq!(watch.try_next(), Some(Event::Write));
+        assert_eq!(watch.try_next(), Some(Event::Create("a".into())));
+        assert_eq!(watch.try_next(), None);
+
+        for i in 0..MAX_QUEUED_EVENTS + 2 {
+            watchers.send(Event::Remove(i.to_string()));
+        }
+
+        for i in 0..MAX_QUEUED_EVENTS {
+            assert_eq!(watch.try_next(), Some(Event::Remove(i.to_string())));
+        }
+        assert_eq!(watch.try_next(), Some(Event::Overflow));
+        assert_eq!(watch.try_next(), None);
+
+        // Dropped watches are forgotten.
+        drop(watch);
+        watchers.send(Event::Write);
+        assert!(watchers.0.lock().unwrap().is_empty());
+    }
+}
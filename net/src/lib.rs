@@ -0,0 +1,356 @@
+//! Host network connections for WASI virtual file system trees
+
+use std::any::Any;
+use std::sync::{Arc, Weak};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{FdFlags, FileType, Filestat, OFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node, Open};
+use wasmtime_vfs_stream::Stream;
+
+/// A connection to a host, as returned by a [`Dialer`]
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+/// The embedder's policy for connecting guests to hosts
+#[async_trait::async_trait]
+pub trait Dialer: Send + Sync {
+    /// Connects to `port` on `host`, a name or an IP address.
+    ///
+    /// Connections the guest may not make should fail with `EACCES`.
+    async fn dial(&self, host: &str, port: u16) -> Result<Box<dyn Connection>, Error>;
+}
+
+/// A directory of TCP connections
+///
+/// Opening `host:port` in it dials `host` with the [`Dialer`] of the
+/// embedder, and returns a stream socket on the connection. IPv6 addresses
+/// are written in brackets, as in `[::1]:80`. Each open makes a connection
+/// of its own, and nothing is listed.
+pub struct Tcp(Link<Arc<dyn Dialer>>);
+
+#[async_trait::async_trait]
+impl Node for Tcp {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::Directory
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.inode().id()
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        let inode = self.0.inode();
+        let ilock = inode.read().await?;
+
+        Ok(Filestat {
+            device_id: **inode.id().device(),
+            inode: **inode.id(),
+            filetype: FileType::Directory,
+            nlink: inode.id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Ok(Box::new(OpenTcp(Open::new(
+            self,
+            false,
+            false,
+            FdFlags::empty(),
+        ))))
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        _dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        // As on Linux, directories cannot be opened for writing.
+        if write {
+            return Err(Error::is_dir());
+        }
+
+        Ok(Box::new(OpenTcp(Open::new(self, read, write, flags))))
+    }
+}
+
+impl Tcp {
+    pub fn new(parent: Arc<dyn Node>, dialer: Arc<dyn Dialer>) -> Arc<Self> {
+        Arc::new(Self(Link::new(&parent, dialer)))
+    }
+}
+
+/// Creates a `net` device holding a `tcp` directory dialing with `dialer`.
+pub async fn new(parent: Arc<dyn Node>, dialer: Arc<dyn Dialer>) -> Result<Arc<Directory>, Error> {
+    let net = Directory::device(parent, None);
+    net.attach("tcp", Tcp::new(net.clone(), dialer)).await?;
+    Ok(net)
+}
+
+// Splits `host:port` into its host and port.
+fn address(name: &str) -> Result<(&str, u16), Error> {
+    let (host, port) = name.rsplit_once(':').ok_or_else(Error::not_found)?;
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.strip_suffix(']').ok_or_else(Error::not_found)?,
+        None => host,
+    };
+
+    let port = port.parse().map_err(|_| Error::not_found())?;
+    if host.is_empty() || host.contains('/') {
+        return Err(Error::not_found());
+    }
+
+    Ok((host, port))
+}
+
+struct OpenTcp(Open<Tcp>);
+
+impl OpenTcp {
+    // The directory holding this one, for paths leaving it.
+    fn prev(&self) -> Arc<dyn Node> {
+        self.0
+            .link()
+            .parent()
+            .unwrap_or_else(|| self.0.link().clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for OpenTcp {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let odir = oflags.contains(OFlags::DIRECTORY);
+
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => self.prev().open_file(path, odir, read, write, flags).await,
+            ("..", rhs) => {
+                let prev = self.prev().open_dir().await?;
+                prev.open_file(follow, rhs, oflags, read, write, flags)
+                    .await
+            }
+            ("." | "", "") => {
+                let link = self.0.link().clone();
+                link.open_file(path, true, read, write, flags).await
+            }
+            (_, "") => {
+                let (host, port) = address(path)?;
+                if odir {
+                    return Err(Error::not_dir());
+                }
+
+                let dialer = self
+                    .0
+                    .link()
+                    .0
+                    .inode()
+                    .data()
+                    .read()
+                    .await
+                    .content()
+                    .clone();
+                let connection = dialer.dial(host, port).await?;
+                let stream = Stream::new(self.0.link().clone(), connection);
+                stream.open_file(path, false, read, write, flags).await
+            }
+            _ => Err(Error::not_dir()),
+        }
+    }
+
+    async fn open_dir(&self, follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => self.prev().open_dir().await,
+            ("..", rhs) => self.prev().open_dir().await?.open_dir(follow, rhs).await,
+            ("." | "", "") => self.0.link().clone().open_dir().await,
+            _ => Err(Error::not_dir()),
+        }
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let prev = self.prev();
+        let entries = [(".", **self.0.link().id()), ("..", **prev.id())];
+
+        let skip = u64::from(cursor).try_into().unwrap_or(usize::MAX);
+        let entries = entries.into_iter().enumerate().skip(skip);
+        let entries = entries.map(|(i, (name, inode))| {
+            Ok(ReaddirEntity {
+                next: (i as u64 + 1).into(),
+                inode,
+                name: name.into(),
+                filetype: FileType::Directory,
+            })
+        });
+
+        Ok(Box::new(entries.collect::<Vec<_>>().into_iter()))
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.link().clone().filestat().await
+    }
+
+    async fn get_path_filestat(&self, path: &str, follow: bool) -> Result<Filestat, Error> {
+        match path.split_once('/').unwrap_or((path, "")) {
+            ("..", "") => self.prev().filestat().await,
+            ("..", rhs) => {
+                let prev = self.prev().open_dir().await?;
+                prev.get_path_filestat(rhs, follow).await
+            }
+            ("." | "", "") => self.get_filestat().await,
+
+            // Connections only exist once opened.
+            _ => Err(Error::not_found()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenTcp {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::Directory)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.0.state().read().await.flags())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        WasiDir::get_filestat(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoSlice, IoSliceMut};
+    use std::sync::Mutex;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    // Dials `localhost:7` alone, handing the host end to the test.
+    #[derive(Default)]
+    struct Echo(Mutex<Vec<(String, DuplexStream)>>);
+
+    #[async_trait::async_trait]
+    impl Dialer for Echo {
+        async fn dial(&self, host: &str, port: u16) -> Result<Box<dyn Connection>, Error> {
+            if (host, port) != ("localhost", 7) && host != "::1" {
+                return Err(Error::access());
+            }
+
+            let (guest, peer) = tokio::io::duplex(64);
+            self.0
+                .lock()
+                .unwrap()
+                .push((format!("{host}:{port}"), peer));
+            Ok(Box::new(guest))
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp() {
+        let echo = Arc::new(Echo::default());
+        let root = Directory::root(Ledger::new(), None);
+        let net = new(root.clone(), echo.clone()).await.unwrap();
+        root.attach("net", net).await.unwrap();
+
+        let dir = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut file = dir
+            .open_file(
+                false,
+                "net/tcp/localhost:7",
+                OFlags::empty(),
+                true,
+                true,
+                flags,
+            )
+            .await
+            .unwrap();
+        assert_eq!(file.get_filetype().await.unwrap(), FileType::SocketStream);
+        let (name, mut peer) = echo.0.lock().unwrap().pop().unwrap();
+        assert_eq!(name, "localhost:7");
+
+        // Bytes flow both ways over the connection.
+        file.write_vectored(&[IoSlice::new(b"ping")]).await.unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        peer.write_all(b"pong").await.unwrap();
+        let n = file.read_vectored(&mut [IoSliceMut::new(&mut buf)]).await;
+        assert_eq!(&buf[..n.unwrap() as usize], b"pong");
+
+        // IPv6 addresses are bracketed.
+        dir.open_file(
+            false,
+            "net/tcp/[::1]:80",
+            OFlags::empty(),
+            true,
+            true,
+            flags,
+        )
+        .await
+        .unwrap();
+        assert_eq!(echo.0.lock().unwrap().pop().unwrap().0, "::1:80");
+
+        // The dialer refuses other hosts, and other names are not found.
+        let tcp = dir.open_dir(false, "net/tcp").await.unwrap();
+        let open = |path| tcp.open_file(false, path, OFlags::empty(), true, true, flags);
+        let kind = |e: Error| e.downcast::<std::io::Error>().unwrap().kind();
+        let error = open("example.com:80").await.err().unwrap();
+        assert_eq!(kind(error), std::io::ErrorKind::PermissionDenied);
+        for path in ["localhost", "localhost:http", "[::1:80"] {
+            let error = open(path).await.err().unwrap();
+            assert_eq!(kind(error), std::io::ErrorKind::NotFound);
+        }
+
+        // Only the single dot entries are listed.
+        let names = tcp.readdir(0.into()).await.unwrap();
+        let names: Vec<_> = names.map(|e| e.unwrap().name).collect();
+        assert_eq!(names, [".", ".."]);
+        let stat = tcp.get_path_filestat("..", false).await.unwrap();
+        assert_eq!(stat.filetype, FileType::Directory);
+    }
+}
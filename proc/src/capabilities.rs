@@ -0,0 +1,107 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use wasi_common::Error;
+use wasmtime_vfs_dir::Directory;
+
+use crate::Generated;
+
+/// The name of the file written by [`capabilities`]
+pub const CAPABILITIES: &str = ".vfs-capabilities";
+
+/// The features of a tree, as advertised to guests by [`capabilities`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub symlinks: bool,
+    pub rename: bool,
+    pub locks: bool,
+    pub watches: bool,
+
+    /// The names of the keyfs algorithms available, such as `ES256`
+    pub algorithms: Vec<String>,
+}
+
+impl Default for Capabilities {
+    /// The features of a plain in-memory tree.
+    fn default() -> Self {
+        Self {
+            symlinks: true,
+            rename: true,
+            locks: false,
+            watches: false,
+            algorithms: Vec::new(),
+        }
+    }
+}
+
+impl Capabilities {
+    /// Renders the capabilities in the format guests read.
+    ///
+    /// The first line is `version 1`. Each following line is a feature name
+    /// and its value, separated by a space: `yes` or `no` for flags and a
+    /// space-separated list for `keyfs`. Later versions only add lines, so
+    /// guests should skip the names they do not know.
+    pub fn render(&self) -> String {
+        let flag = |on| if on { "yes" } else { "no" };
+
+        let mut out = String::from("version 1\n");
+        writeln!(out, "symlinks {}", flag(self.symlinks)).unwrap();
+        writeln!(out, "rename {}", flag(self.rename)).unwrap();
+        writeln!(out, "locks {}", flag(self.locks)).unwrap();
+        writeln!(out, "watches {}", flag(self.watches)).unwrap();
+        writeln!(out, "keyfs {}", self.algorithms.join(" ")).unwrap();
+        out
+    }
+}
+
+/// Attaches a read-only [`CAPABILITIES`] file describing `caps` to `root`.
+///
+/// This lets guests adapt to the tree up front rather than probing each
+/// operation for failure.
+pub async fn capabilities(root: &Arc<Directory>, caps: &Capabilities) -> Result<(), Error> {
+    let content = caps.render().into_bytes();
+    let file = Generated::new(root.clone(), move || std::future::ready(content.clone()));
+    root.attach(CAPABILITIES, file).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::IoSliceMut;
+
+    use wasi_common::file::{FdFlags, OFlags};
+    use wasmtime_vfs_ledger::Ledger;
+    use wasmtime_vfs_memory::Node;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn capabilities() {
+        let root = Directory::root(Ledger::new(), None);
+        let caps = Capabilities {
+            algorithms: vec!["ES256".into(), "ES384".into()],
+            ..Default::default()
+        };
+        super::capabilities(&root, &caps).await.unwrap();
+
+        let dir = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut file = dir
+            .open_file(false, CAPABILITIES, OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n as usize]).unwrap(),
+            "version 1\nsymlinks yes\nrename yes\nlocks no\nwatches no\nkeyfs ES256 ES384\n"
+        );
+
+        // Guests cannot change what they are told.
+        let open = dir.open_file(false, CAPABILITIES, OFlags::empty(), false, true, flags);
+        assert!(open.await.is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::any::Any;
+use std::cmp::min;
+use std::future::Future;
+use std::io::{IoSliceMut, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{Link, Node};
+
+type Render = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Vec<u8>> + Send>> + Send + Sync>;
+
+/// A read-only file whose content is rendered each time it is opened
+///
+/// Each open handle sees the content as it was when it was opened.
+pub struct Generated {
+    link: Link<()>,
+    render: Render,
+}
+
+#[async_trait::async_trait]
+impl Node for Generated {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.link.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.link.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::RegularFile
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.link.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        _read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if write || !flags.is_empty() {
+            return Err(Error::perm());
+        }
+
+        let content = (self.render)().await;
+        Ok(Box::new(OpenGenerated {
+            _root: self.root(),
+            link: self,
+            content,
+            pos: 0,
+        }))
+    }
+}
+
+impl Generated {
+    /// Creates a file whose content `render` makes on each open.
+    pub fn new<F, T>(parent: Arc<dyn Node>, render: F) -> Arc<Self>
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Future<Output = Vec<u8>> + Send + 'static,
+    {
+        Arc::new(Self {
+            link: Link::new(&parent, ()),
+            render: Arc::new(move || Box::pin(render())),
+        })
+    }
+}
+
+struct OpenGenerated {
+    _root: Arc<dyn Node>,
+    link: Arc<Generated>,
+    content: Vec<u8>,
+    pos: usize,
+}
+
+impl OpenGenerated {
+    fn copy_out(&self, mut pos: usize, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        let mut total = 0;
+
+        for buf in bufs {
+            let data = self.content.get(pos..).unwrap_or_default();
+            let len = min(buf.len(), data.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            total += len;
+            pos += len;
+        }
+
+        total
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenGenerated {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.link.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.link.inode().id().device(),
+            inode: **self.link.link.inode().id(),
+            filetype: FileType::RegularFile,
+            nlink: self.link.link.inode().id().links(),
+            size: self.content.len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let total = self.copy_out(self.pos, bufs);
+        self.pos += total;
+        Ok(total as u64)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        let pos = offset.try_into().map_err(|_| Error::invalid_argument())?;
+        Ok(self.copy_out(pos, bufs) as u64)
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let cur = match pos {
+            SeekFrom::Current(_) => i64::try_from(self.pos),
+            SeekFrom::Start(_) => Ok(0),
+            SeekFrom::End(_) => i64::try_from(self.content.len()),
+        }
+        .map_err(|e| Error::invalid_argument().context(e))?;
+
+        let off = match pos {
+            SeekFrom::Current(off) => Ok(off),
+            SeekFrom::Start(off) => i64::try_from(off),
+            SeekFrom::End(off) => Ok(off),
+        }
+        .map_err(|e| Error::invalid_argument().context(e))?;
+
+        let pos = cur.checked_add(off).ok_or_else(Error::invalid_argument)?;
+        self.pos = usize::try_from(pos).map_err(|e| Error::invalid_argument().context(e))?;
+        Ok(self.pos as u64)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.content.len().saturating_sub(self.pos) as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Weak};
+use std::time::SystemTime;
+
+use wasi_common::file::FileType;
+use wasi_common::Error;
+use wasmtime_vfs_dir::{walk, Directory};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_ledger::DeviceId;
+use wasmtime_vfs_memory::Node;
+
+pub use capabilities::{capabilities, Capabilities, CAPABILITIES};
+pub use generated::Generated;
+
+mod capabilities;
+mod generated;
+
+/// Creates a device of read-only files describing the tree rooted at `target`.
+///
+/// - `mounts` lists each device in the tree as `<path> <device>` lines.
+/// - `usage` lists each device as `<device> <inodes> <bytes>` lines.
+/// - `statvfs` lists each device in the tree as `<path> <device> <inodes>
+///   <free> <bytes> <free bytes>` lines, counting every inode allocated on
+///   the device, even those no longer reachable but still open, and the
+///   bytes left under its quota.
+/// - `uptime` holds the seconds since the device was created, by its clock,
+///   and the idle seconds, which are unknown and so zero, as on Linux.
+///
+/// The files are rendered anew each time they are opened. The open file
+/// table of a guest lives in its `WasiCtx`, out of reach of the file system,
+/// so hosts wanting to expose it attach their own [`Generated`] file.
+pub async fn new(parent: Arc<dyn Node>, target: &Arc<dyn Node>) -> Result<Arc<Directory>, Error> {
+    let dir = Directory::device(parent, None);
+
+    // The target usually contains this directory, so keep it weakly.
+    let weak = Arc::downgrade(target);
+    let mounts = Generated::new(dir.clone(), move || mounts(weak.clone()));
+    dir.attach("mounts", mounts).await?;
+
+    let weak = Arc::downgrade(target);
+    let usage = Generated::new(dir.clone(), move || usage(weak.clone()));
+    dir.attach("usage", usage).await?;
+
+    let weak = Arc::downgrade(target);
+    let statvfs = Generated::new(dir.clone(), move || statvfs(weak.clone()));
+    dir.attach("statvfs", statvfs).await?;
+
+    let device = dir.id().device();
+    let start = device.now();
+    let uptime = Generated::new(dir.clone(), move || uptime(device.clone(), start));
+    dir.attach("uptime", uptime).await?;
+
+    Ok(dir)
+}
+
+// Finds the path and device of each mount in the tree rooted at `target`.
+async fn mountpoints(target: Weak<dyn Node>) -> Vec<(String, Arc<DeviceId>)> {
+    let entries = match target.upgrade() {
+        Some(target) => walk(target).await.unwrap_or_default(),
+        None => return Vec::new(),
+    };
+
+    let mut out = Vec::new();
+    for entry in entries {
+        let device = entry.node.id().device();
+        let mounted = match entry.node.parent() {
+            Some(parent) => parent.id().device() != device,
+            None => true,
+        };
+
+        if entry.depth == 0 || (mounted && entry.node.filetype() == FileType::Directory) {
+            out.push((entry.path, device));
+        }
+    }
+
+    out
+}
+
+async fn mounts(target: Weak<dyn Node>) -> Vec<u8> {
+    let mut out = String::new();
+    for (path, device) in mountpoints(target).await {
+        writeln!(out, "{path} {}", **device).unwrap();
+    }
+
+    out.into_bytes()
+}
+
+async fn statvfs(target: Weak<dyn Node>) -> Vec<u8> {
+    let mut out = String::new();
+    for (path, device) in mountpoints(target).await {
+        let usage = device.statvfs();
+        writeln!(
+            out,
+            "{path} {} {} {} {} {}",
+            usage.device, usage.inodes.allocated, usage.inodes.free, usage.bytes, usage.free_bytes
+        )
+        .unwrap();
+    }
+
+    out.into_bytes()
+}
+
+async fn usage(target: Weak<dyn Node>) -> Vec<u8> {
+    let entries = match target.upgrade() {
+        Some(target) => walk(target).await.unwrap_or_default(),
+        None => return Vec::new(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut devices = BTreeMap::<u64, (u64, u64)>::new();
+    for entry in entries {
+        // Count each node once, however many paths reach it.
+        if !seen.insert(Arc::as_ptr(&entry.node) as *const () as usize) {
+            continue;
+        }
+
+        let (inodes, bytes) = devices.entry(**entry.node.id().device()).or_default();
+        *inodes += 1;
+
+        if let Ok(file) = entry.node.to_any().downcast::<File>() {
+            *bytes += file.inode().data().read().await.content().len() as u64;
+        }
+    }
+
+    let mut out = String::new();
+    for (device, (inodes, bytes)) in devices {
+        writeln!(out, "{device} {inodes} {bytes}").unwrap();
+    }
+
+    out.into_bytes()
+}
+
+async fn uptime(device: Arc<DeviceId>, start: SystemTime) -> Vec<u8> {
+    let up = device.now().duration_since(start).unwrap_or_default();
+    format!("{}.{:02} 0.00\n", up.as_secs(), up.subsec_millis() / 10).into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::IoSliceMut;
+
+    use wasi_common::file::{FdFlags, OFlags};
+    use wasi_common::WasiDir;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    async fn read(dir: &dyn WasiDir, path: &str) -> String {
+        let mut file = dir
+            .open_file(false, path, OFlags::empty(), true, false, FdFlags::empty())
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        String::from_utf8(buf[..n as usize].to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn proc() {
+        let root = Directory::root(Ledger::new(), None);
+        let target: Arc<dyn Node> = root.clone();
+        let proc = super::new(root.clone(), &target).await.unwrap();
+        root.attach("proc", proc.clone()).await.unwrap();
+        let file = File::with_data(root.clone(), "abc");
+        root.attach("file", file.clone()).await.unwrap();
+
+        let dir = root.clone().open_dir().await.unwrap();
+        let (r, p) = (**root.id().device(), **proc.id().device());
+        assert_eq!(
+            read(&*dir, "proc/mounts").await,
+            format!("/ {r}\n/proc {p}\n")
+        );
+        assert_eq!(
+            read(&*dir, "proc/usage").await,
+            format!("{r} 2 3\n{p} 5 0\n")
+        );
+
+        // Inodes still held count, though no path reaches them.
+        dir.unlink_file("file").await.unwrap();
+        let free = u64::MAX - 2;
+        assert_eq!(
+            read(&*dir, "proc/statvfs").await,
+            format!(
+                "/ {r} 2 {free} 3 {}\n/proc {p} 5 {} 0 {}\n",
+                u64::MAX - 3,
+                free - 3,
+                u64::MAX
+            )
+        );
+        drop(file);
+
+        // The files are read-only.
+        let flags = FdFlags::empty();
+        let open = dir.open_file(false, "proc/usage", OFlags::empty(), false, true, flags);
+        assert!(open.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn uptime() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let ledger = Ledger::builder().clock(Arc::new(|| UNIX_EPOCH)).build();
+        let root = Directory::root(ledger, None);
+        let target: Arc<dyn Node> = root.clone();
+        let proc = super::new(root.clone(), &target).await.unwrap();
+        root.attach("proc", proc.clone()).await.unwrap();
+
+        let later = UNIX_EPOCH + Duration::from_millis(62_250);
+        proc.id().device().set_clock(Some(Arc::new(move || later)));
+        let dir = root.clone().open_dir().await.unwrap();
+        assert_eq!(read(&*dir, "proc/uptime").await, "62.25 0.00\n");
+    }
+}
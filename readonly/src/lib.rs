@@ -0,0 +1,470 @@
+//! Read-only views of WASI virtual file system trees
+
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{Advice, FdFlags, FileType, Filestat, OFlags, RiFlags, RoFlags};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Node, Permissions};
+
+/// A node whose whole tree can be read but not changed
+///
+/// Reads pass through to the wrapped node; anything that would create,
+/// remove, write or otherwise change a node below it fails with `EROFS`.
+/// The wrapper itself is still an entry of its parent, so wrap a node on a
+/// device of its own, as for a mount, to keep guests from renaming it away.
+pub struct ReadOnly(Arc<dyn Node>);
+
+impl ReadOnly {
+    /// Wraps `node` and everything below it.
+    pub fn new(node: Arc<dyn Node>) -> Arc<Self> {
+        Arc::new(Self(node))
+    }
+}
+
+#[async_trait::async_trait]
+impl Node for ReadOnly {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.0.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.0.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        self.0.filetype()
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.0.id()
+    }
+
+    fn read_link(&self) -> Option<String> {
+        self.0.read_link()
+    }
+
+    async fn permissions(&self) -> Option<Permissions> {
+        self.0.permissions().await
+    }
+
+    async fn set_permissions(&self, _permissions: Permissions) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn xattr(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.0.xattr(name).await
+    }
+
+    async fn xattrs(&self) -> Result<Vec<String>, Error> {
+        self.0.xattrs().await
+    }
+
+    async fn set_xattr(&self, _name: &str, _value: Vec<u8>) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn remove_xattr(&self, _name: &str) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn snapshot(&self, parent: Arc<dyn Node>) -> Option<Arc<dyn Node>> {
+        let copy: Arc<dyn Node> = Self::new(self.0.snapshot(parent).await?);
+        Some(copy)
+    }
+
+    async fn filestat(self: Arc<Self>) -> Result<Filestat, Error> {
+        self.0.clone().filestat().await
+    }
+
+    async fn set_times(
+        self: Arc<Self>,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    // The children are not walked, so that paths below this node are all
+    // resolved by the read-only directory opened from it.
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Ok(Box::new(ReadOnlyDir::new(self.0.clone().open_dir().await?)))
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if write {
+            return Err(Error::read_only());
+        }
+
+        let file = self.0.clone().open_file(path, dir, read, false, flags);
+        Ok(Box::new(ReadOnlyFile::new(file.await?)))
+    }
+}
+
+/// A directory handle refusing every change below it
+///
+/// Files and directories opened from it are read-only in turn.
+pub struct ReadOnlyDir(Box<dyn WasiDir>);
+
+impl ReadOnlyDir {
+    /// Wraps the directory handle `dir`.
+    pub fn new(dir: Box<dyn WasiDir>) -> Self {
+        Self(dir)
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if write || oflags.contains(OFlags::TRUNCATE) {
+            return Err(Error::read_only());
+        }
+
+        // As on Linux, creating a file which already exists does not write.
+        let create = oflags.contains(OFlags::CREATE);
+        let exclusive = oflags.contains(OFlags::EXCLUSIVE);
+        let oflags = oflags & !(OFlags::CREATE | OFlags::EXCLUSIVE);
+        let file = self.0.open_file(follow, path, oflags, read, false, flags);
+        match file.await {
+            Ok(..) if create && exclusive => Err(Error::exist()),
+            Ok(file) => Ok(Box::new(ReadOnlyFile::new(file))),
+            Err(..) if create => Err(Error::read_only()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn open_dir(&self, follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        Ok(Box::new(Self::new(self.0.open_dir(follow, path).await?)))
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(&self, path: &str, follow: bool) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow).await
+    }
+
+    async fn rename(
+        &self,
+        _src_path: &str,
+        _dest_dir: &dyn WasiDir,
+        _dest_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn hard_link(
+        &self,
+        _src_path: &str,
+        _target_dir: &dyn WasiDir,
+        _target_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow: bool,
+    ) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+}
+
+/// A file handle which can be read but not written
+pub struct ReadOnlyFile(Box<dyn WasiFile>);
+
+impl ReadOnlyFile {
+    /// Wraps the file handle `file`.
+    pub fn new(file: Box<dyn WasiFile>) -> Self {
+        Self(file)
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for ReadOnlyFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        self.0.get_filetype().await
+    }
+
+    fn isatty(&mut self) -> bool {
+        self.0.isatty()
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        self.0.sock_recv(bufs, flags).await
+    }
+
+    async fn datasync(&mut self) -> Result<(), Error> {
+        self.0.datasync().await
+    }
+
+    async fn sync(&mut self) -> Result<(), Error> {
+        self.0.sync().await
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.0.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.0.set_fdflags(flags).await
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn set_filestat_size(&mut self, _size: u64) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn advise(&mut self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.0.advise(offset, len, advice).await
+    }
+
+    async fn allocate(&mut self, _offset: u64, _len: u64) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn set_times(
+        &mut self,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.0.read_vectored(bufs).await
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.0.read_vectored_at(bufs, offset).await
+    }
+
+    async fn write_vectored<'a>(&mut self, _bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Err(Error::read_only())
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        _bufs: &[IoSlice<'a>],
+        _offset: u64,
+    ) -> Result<u64, Error> {
+        Err(Error::read_only())
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.0.seek(pos).await
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.0.peek(buf).await
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.0.num_ready_bytes().await
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.0.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Err(Error::read_only())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rustix::io::Errno;
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_file::File;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    fn errno(e: Error) -> Option<Errno> {
+        let e = e
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.raw_os_error());
+        e.map(Errno::from_raw_os_error)
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let config = Directory::device(root.clone(), Some(Arc::new(File::new)));
+        let sub = Directory::new(config.clone(), None);
+        config.attach("sub", sub.clone()).await.unwrap();
+        sub.attach("file", File::with_data(sub.clone(), "abc"))
+            .await
+            .unwrap();
+        root.attach("config", ReadOnly::new(config.clone()))
+            .await
+            .unwrap();
+
+        // Reads pass through, however deep.
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        let mut file = open
+            .open_file(
+                false,
+                "config/sub/file",
+                OFlags::empty(),
+                true,
+                false,
+                flags,
+            )
+            .await
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let bufs = &mut [IoSliceMut::new(&mut buf)];
+        assert_eq!(file.read_vectored(bufs).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        let stat = open.get_path_filestat("config/sub/file", false).await;
+        assert_eq!(stat.unwrap().size, 3);
+
+        // Handles opened for reading still cannot write.
+        let e = file.write_vectored(&[IoSlice::new(b"x")]).await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::ROFS));
+        let e = file.set_filestat_size(0).await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::ROFS));
+
+        // Nothing below the wrapper can be changed.
+        let e = open
+            .open_file(false, "config/sub/file", OFlags::empty(), true, true, flags)
+            .await;
+        assert_eq!(errno(e.err().unwrap()), Some(Errno::ROFS));
+        let e = open
+            .open_file(
+                false,
+                "config/sub/file",
+                OFlags::TRUNCATE,
+                true,
+                false,
+                flags,
+            )
+            .await;
+        assert_eq!(errno(e.err().unwrap()), Some(Errno::ROFS));
+        let e = open
+            .open_file(false, "config/new", OFlags::CREATE, true, false, flags)
+            .await;
+        assert_eq!(errno(e.err().unwrap()), Some(Errno::ROFS));
+        let e = open.create_dir("config/dir").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::ROFS));
+        let e = open.unlink_file("config/sub/file").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::ROFS));
+        let e = open.set_times("config/sub", None, None, false).await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::ROFS));
+        let dir = open.open_dir(false, "config").await.unwrap();
+        let e = dir.rename("sub", &*dir, "moved").await.unwrap_err();
+        assert_eq!(errno(e), Some(Errno::ROFS));
+        assert!(sub.get("file").await.is_ok());
+
+        // Extended attributes can be read, but not changed.
+        config.set_xattr("user.tag", b"v".to_vec()).await.unwrap();
+        let wrapper = root.get("config").await.unwrap();
+        assert_eq!(wrapper.xattr("user.tag").await.unwrap(), b"v");
+        assert_eq!(wrapper.xattrs().await.unwrap(), ["user.tag"]);
+        let e = wrapper.set_xattr("user.tag", Vec::new()).await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::ROFS));
+        let e = wrapper.remove_xattr("user.tag").await;
+        assert_eq!(errno(e.unwrap_err()), Some(Errno::ROFS));
+
+        // Opening an existing file with `O_CREAT` only reads it.
+        let sub = dir.open_dir(false, "sub").await.unwrap();
+        sub.open_file(false, "file", OFlags::CREATE, true, false, flags)
+            .await
+            .unwrap();
+        let e = sub
+            .open_file(
+                false,
+                "file",
+                OFlags::CREATE | OFlags::EXCLUSIVE,
+                true,
+                false,
+                flags,
+            )
+            .await;
+        assert_eq!(errno(e.err().unwrap()), None);
+
+        // The host can still change the tree.
+        config
+            .attach("new", File::with_data(config.clone(), "xyz"))
+            .await
+            .unwrap();
+        assert!(dir.get_path_filestat("new", false).await.is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use tokio::runtime::Handle;
+use wasi_common::file::{FdFlags, OFlags};
+use wasi_common::{Error, ErrorKind, WasiDir, WasiFile};
+use wasmtime_vfs_memory::Node;
+
+// Converts an error of the async APIs to one of std.
+fn convert(error: Error) -> io::Error {
+    let error = match error.downcast::<io::Error>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+
+    let kind = match error.downcast_ref::<ErrorKind>() {
+        Some(ErrorKind::Perm) => io::ErrorKind::PermissionDenied,
+        Some(ErrorKind::Notdir) => io::ErrorKind::NotADirectory,
+        Some(ErrorKind::Notsup) => io::ErrorKind::Unsupported,
+        Some(ErrorKind::Ilseq) => io::ErrorKind::InvalidData,
+        _ => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, error)
+}
+
+/// A directory of the tree, driven from blocking code
+///
+/// Every method blocks on the async API using the runtime `handle`. They
+/// must not be called from inside that runtime, which would panic.
+pub struct BlockingDir {
+    dir: Box<dyn WasiDir>,
+    handle: Handle,
+}
+
+impl BlockingDir {
+    /// Opens `node` as a directory.
+    pub fn new(node: Arc<dyn Node>, handle: Handle) -> io::Result<Self> {
+        let dir = handle.block_on(node.open_dir()).map_err(convert)?;
+        Ok(Self { dir, handle })
+    }
+
+    /// Opens the file at `path`, creating it first if `create` is set.
+    pub fn open(
+        &self,
+        path: &str,
+        read: bool,
+        write: bool,
+        create: bool,
+    ) -> io::Result<BlockingFile> {
+        let oflags = match create {
+            true => OFlags::CREATE,
+            false => OFlags::empty(),
+        };
+
+        let open = self
+            .dir
+            .open_file(false, path, oflags, read, write, FdFlags::empty());
+        let file = self.handle.block_on(open).map_err(convert)?;
+
+        Ok(BlockingFile {
+            file,
+            handle: self.handle.clone(),
+        })
+    }
+
+    /// Opens the directory at `path`.
+    pub fn open_dir(&self, path: &str) -> io::Result<BlockingDir> {
+        let dir = self.dir.open_dir(false, path);
+        let dir = self.handle.block_on(dir).map_err(convert)?;
+
+        Ok(BlockingDir {
+            dir,
+            handle: self.handle.clone(),
+        })
+    }
+
+    /// Lists the names in this directory, without `.` and `..`.
+    pub fn read_dir(&self) -> io::Result<Vec<String>> {
+        let entries = self.handle.block_on(self.dir.readdir(0.into()));
+        let mut names = Vec::new();
+        for entry in entries.map_err(convert)? {
+            let name = entry.map_err(convert)?.name;
+            if name != "." && name != ".." {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    pub fn create_dir(&self, path: &str) -> io::Result<()> {
+        let create = self.dir.create_dir(path);
+        self.handle.block_on(create).map_err(convert)
+    }
+
+    pub fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let remove = self.dir.remove_dir(path);
+        self.handle.block_on(remove).map_err(convert)
+    }
+
+    pub fn remove_file(&self, path: &str) -> io::Result<()> {
+        let unlink = self.dir.unlink_file(path);
+        self.handle.block_on(unlink).map_err(convert)
+    }
+
+    pub fn rename(&self, from: &str, to: &BlockingDir, to_path: &str) -> io::Result<()> {
+        let rename = self.dir.rename(from, &*to.dir, to_path);
+        self.handle.block_on(rename).map_err(convert)
+    }
+}
+
+/// An open file of the tree, driven from blocking code
+///
+/// See [`BlockingDir`] for the constraints on the runtime handle.
+pub struct BlockingFile {
+    file: Box<dyn WasiFile>,
+    handle: Handle,
+}
+
+impl BlockingFile {
+    /// The underlying async file.
+    pub fn into_inner(self) -> Box<dyn WasiFile> {
+        self.file
+    }
+}
+
+impl Read for BlockingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_vectored(&mut [IoSliceMut::new(buf)])
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let read = self.file.read_vectored(bufs);
+        let n = self.handle.block_on(read).map_err(convert)?;
+        Ok(n as usize)
+    }
+}
+
+impl Write for BlockingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let write = self.file.write_vectored(bufs);
+        let n = self.handle.block_on(write).map_err(convert)?;
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for BlockingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let seek = self.file.seek(pos);
+        self.handle.block_on(seek).map_err(convert)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_file::File;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[test]
+    fn blocking() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let dir = BlockingDir::new(root, runtime.handle().clone()).unwrap();
+
+        dir.create_dir("sub").unwrap();
+        let mut file = dir.open("sub/file", true, true, true).unwrap();
+        file.write_all(b"hello world").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "world");
+
+        let sub = dir.open_dir("sub").unwrap();
+        assert_eq!(sub.read_dir().unwrap(), ["file"]);
+        sub.rename("file", &dir, "moved").unwrap();
+        assert_eq!(sub.read_dir().unwrap(), Vec::<String>::new());
+
+        let e = dir.open("missing", true, false, false).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        dir.remove_file("moved").unwrap();
+    }
+}
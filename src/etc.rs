@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use wasi_common::{Error, ErrorExt};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_file::File;
+
+/// The user a guest runs as, for [`etc`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    pub shell: String,
+}
+
+impl Default for User {
+    fn default() -> Self {
+        Self {
+            name: "user".into(),
+            uid: 1000,
+            gid: 1000,
+            home: "/".into(),
+            shell: "/bin/sh".into(),
+        }
+    }
+}
+
+impl User {
+    // Fields containing these would corrupt the files' line format.
+    fn check(&self) -> Result<(), Error> {
+        for field in [&self.name, &self.home, &self.shell] {
+            if field.contains([':', '\n']) {
+                return Err(Error::invalid_argument());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn passwd(&self) -> String {
+        let Self {
+            name,
+            uid,
+            gid,
+            home,
+            shell,
+        } = self;
+
+        let mut passwd = String::new();
+        if *uid != 0 && name != "root" {
+            passwd.push_str("root:x:0:0:root:/:/bin/sh\n");
+        }
+
+        passwd + &format!("{name}:x:{uid}:{gid}:{name}:{home}:{shell}\n")
+    }
+
+    fn group(&self) -> String {
+        let Self { name, gid, .. } = self;
+
+        let mut group = String::new();
+        if *gid != 0 && name != "root" {
+            group.push_str("root:x:0:\n");
+        }
+
+        group + &format!("{name}:x:{gid}:{name}\n")
+    }
+}
+
+/// Writes minimal `/etc/passwd`, `/etc/group` and `/etc/nsswitch.conf` files.
+///
+/// This lets software which looks up the current user work in a guest. The
+/// `etc` directory is created under `root` if missing. It is an error for any
+/// of the files to exist already, or for the user's name, home or shell to
+/// contain `:` or a newline. The built-in `root` entries are left out when
+/// the user would clash with them.
+pub async fn etc(root: &Arc<Directory>, user: &User) -> Result<(), Error> {
+    user.check()?;
+
+    let etc = match root.get("etc").await {
+        Ok(etc) => etc
+            .to_any()
+            .downcast::<Directory>()
+            .map_err(|_| Error::not_dir())?,
+        Err(..) => {
+            let etc = Directory::new(root.clone(), root.factory());
+            root.attach("etc", etc.clone()).await?;
+            etc
+        }
+    };
+
+    let files = [
+        ("passwd", user.passwd()),
+        ("group", user.group()),
+        ("nsswitch.conf", "passwd: files\ngroup: files\n".into()),
+    ];
+
+    for (name, _) in &files {
+        if etc.get(name).await.is_ok() {
+            return Err(Error::exist());
+        }
+    }
+
+    for (name, data) in files {
+        etc.attach(name, File::with_data(etc.clone(), data)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_ledger::Ledger;
+
+    #[tokio::test]
+    async fn etc() {
+        let root = Directory::root(Ledger::new(), None);
+        let user = User {
+            name: "enarx".into(),
+            uid: 42,
+            gid: 7,
+            ..Default::default()
+        };
+
+        super::etc(&root, &user).await.unwrap();
+        super::etc(&root, &user).await.unwrap_err();
+
+        let node = root.get("etc/passwd").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let data = file.inode().data().read().await.content().to_vec();
+        let passwd = String::from_utf8(data).unwrap();
+        assert!(passwd.contains("\nenarx:x:42:7:enarx:/:/bin/sh\n"));
+
+        let node = root.get("etc/group").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let data = file.inode().data().read().await.content().to_vec();
+        assert!(String::from_utf8(data).unwrap().contains("enarx:x:7:enarx"));
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let root = Directory::root(Ledger::new(), None);
+        for user in [
+            User {
+                name: "a:b".into(),
+                ..Default::default()
+            },
+            User {
+                shell: "/bin/sh\nevil::0:0::/:".into(),
+                ..Default::default()
+            },
+        ] {
+            super::etc(&root, &user).await.unwrap_err();
+        }
+        assert!(root.get("etc/passwd").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn root() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let user = User {
+            name: "root".into(),
+            uid: 0,
+            gid: 0,
+            ..Default::default()
+        };
+        super::etc(&root, &user).await.unwrap();
+
+        let node = root.get("etc/passwd").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let data = file.inode().data().read().await.content().to_vec();
+        assert_eq!(data, b"root:x:0:0:root:/:/bin/sh\n");
+
+        // The directory lets guests create files like its parent does.
+        let node = root.get("etc").await.unwrap();
+        let etc = node.to_any().downcast::<Directory>().unwrap();
+        assert!(etc.factory().is_some());
+    }
+}
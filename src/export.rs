@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use wasi_common::file::FileType;
+use wasi_common::Error;
+use wasmtime_vfs_dir::{walk_with, Links};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_memory::Node;
+
+use crate::{Redaction, Redactor};
+
+/// A node of a tree exported by [`export`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exported {
+    /// The path of the node, as yielded by [`walk`]
+    ///
+    /// [`walk`]: wasmtime_vfs_dir::walk
+    pub path: String,
+
+    /// The type of the node
+    pub filetype: FileType,
+
+    /// The content of a regular file or a replaced node
+    pub content: Option<Vec<u8>>,
+
+    /// The target of a symbolic link
+    pub link: Option<String>,
+}
+
+/// Exports the tree rooted at `node`, as decided by `redactor`.
+///
+/// Nodes are exported in [`walk`] order. An omitted directory is exported
+/// without anything below it. Symbolic links are exported as links.
+///
+/// [`walk`]: wasmtime_vfs_dir::walk
+pub async fn export(node: Arc<dyn Node>, redactor: &dyn Redactor) -> Result<Vec<Exported>, Error> {
+    export_with(node, redactor, Links::Preserve).await
+}
+
+/// Exports the tree rooted at `node` like [`export`], treating symbolic
+/// links as `links` says.
+pub async fn export_with(
+    node: Arc<dyn Node>,
+    redactor: &dyn Redactor,
+    links: Links,
+) -> Result<Vec<Exported>, Error> {
+    let mut exported = Vec::new();
+    let mut omitted: Option<String> = None;
+
+    for entry in walk_with(node, links).await? {
+        if let Some(prefix) = &omitted {
+            if prefix == "/" || entry.path.starts_with(&format!("{prefix}/")) {
+                continue;
+            }
+        }
+
+        let content = match redactor.redact(&entry.path, &*entry.node) {
+            Redaction::Omit => {
+                omitted = Some(entry.path);
+                continue;
+            }
+            Redaction::Replace(data) => Some(data),
+            Redaction::Keep => match entry.node.clone().to_any().downcast::<File>() {
+                Ok(file) => Some(file.inode().data().read().await.content().to_vec()),
+                Err(..) => None,
+            },
+        };
+
+        exported.push(Exported {
+            path: entry.path,
+            filetype: entry.node.filetype(),
+            content,
+            link: entry.node.read_link(),
+        });
+    }
+
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use crate::Patterns;
+
+    #[tokio::test]
+    async fn export() {
+        let root = Directory::root(Ledger::new(), None);
+        let keys = Directory::new(root.clone(), None);
+        let etc = Directory::new(root.clone(), None);
+        root.attach("keys", keys.clone()).await.unwrap();
+        root.attach("etc", etc.clone()).await.unwrap();
+        keys.attach("secret", File::with_data(keys.clone(), "s3cr3t"))
+            .await
+            .unwrap();
+        etc.attach("key.pem", File::with_data(etc.clone(), "PRIVATE"))
+            .await
+            .unwrap();
+        etc.attach("passwd", File::with_data(etc.clone(), "root"))
+            .await
+            .unwrap();
+
+        let patterns = Patterns::new()
+            .omit("/keys")
+            .replace("**/*.pem", "REDACTED");
+        let exported = super::export(root, &patterns).await.unwrap();
+
+        let exported: Vec<_> = exported
+            .iter()
+            .map(|e| (&e.path[..], e.content.as_deref()))
+            .collect();
+        let expected: [(&str, Option<&[u8]>); 4] = [
+            ("/", None),
+            ("/etc", None),
+            ("/etc/key.pem", Some(b"REDACTED")),
+            ("/etc/passwd", Some(b"root")),
+        ];
+        assert_eq!(exported, expected);
+    }
+}
@@ -0,0 +1,15 @@
+mod blocking;
+mod etc;
+mod export;
+mod redact;
+mod root;
+mod stats;
+mod validate;
+
+pub use blocking::{BlockingDir, BlockingFile};
+pub use etc::{etc, User};
+pub use export::{export, export_with, Exported};
+pub use redact::{Patterns, Redaction, Redactor};
+pub use root::{standard_root, standard_root_with, Layout};
+pub use stats::{stats, TreeStats};
+pub use validate::{validate, Policy, Violation};
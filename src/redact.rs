@@ -0,0 +1,151 @@
+use wasmtime_vfs_memory::Node;
+
+/// What an export does with a node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Redaction {
+    /// Export the node as it is.
+    Keep,
+
+    /// Leave the node, and anything below it, out of the export.
+    Omit,
+
+    /// Export the node with its content replaced.
+    Replace(Vec<u8>),
+}
+
+/// A host hook deciding how each node of a tree is exported
+///
+/// Paths are absolute from the root of the export, as yielded by
+/// [`walk`](wasmtime_vfs_dir::walk).
+pub trait Redactor: Send + Sync {
+    fn redact(&self, path: &str, node: &dyn Node) -> Redaction;
+}
+
+impl<F: Fn(&str, &dyn Node) -> Redaction + Send + Sync> Redactor for F {
+    fn redact(&self, path: &str, node: &dyn Node) -> Redaction {
+        self(path, node)
+    }
+}
+
+/// A [`Redactor`] matching paths against glob patterns
+///
+/// In a pattern, `*` matches within one path segment and `**` matches any
+/// number of whole segments. The first matching pattern wins; paths matching
+/// none are kept.
+#[derive(Clone, Debug, Default)]
+pub struct Patterns(Vec<(String, Redaction)>);
+
+impl Patterns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Omits paths matching `pattern`.
+    pub fn omit(mut self, pattern: impl Into<String>) -> Self {
+        self.0.push((pattern.into(), Redaction::Omit));
+        self
+    }
+
+    /// Replaces the content of paths matching `pattern`.
+    pub fn replace(mut self, pattern: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.0
+            .push((pattern.into(), Redaction::Replace(data.into())));
+        self
+    }
+}
+
+impl Redactor for Patterns {
+    fn redact(&self, path: &str, _node: &dyn Node) -> Redaction {
+        let path: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        for (pattern, redaction) in &self.0 {
+            let pattern: Vec<_> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+            if matches(&pattern, &path) {
+                return redaction.clone();
+            }
+        }
+
+        Redaction::Keep
+    }
+}
+
+fn matches(pattern: &[&str], path: &[&str]) -> bool {
+    glob(pattern, path, |p| *p == "**", |p, s| segment(p, s))
+}
+
+fn segment(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+    glob(&pattern, &name, |p| *p == '*', |p, c| p == c)
+}
+
+// Matches `items` against `pattern`, where a `star` matches any run of items.
+//
+// On a mismatch only the most recent star is retried, which is enough for
+// star-only globs and keeps matching to O(pattern * items).
+fn glob<P, T>(
+    pattern: &[P],
+    items: &[T],
+    star: impl Fn(&P) -> bool,
+    eq: impl Fn(&P, &T) -> bool,
+) -> bool {
+    let (mut p, mut i) = (0, 0);
+    let mut retry = None;
+
+    while i < items.len() {
+        if p < pattern.len() && star(&pattern[p]) {
+            p += 1;
+            retry = Some((p, i));
+        } else if p < pattern.len() && eq(&pattern[p], &items[i]) {
+            p += 1;
+            i += 1;
+        } else if let Some((rp, ri)) = retry {
+            // Let the star swallow one more item and try again.
+            (p, i) = (rp, ri + 1);
+            retry = Some((rp, ri + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(star)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    #[test]
+    fn patterns() {
+        let node = Directory::root(Ledger::new(), None);
+        let patterns = Patterns::new()
+            .omit("/keys/**")
+            .replace("**/*.pem", "REDACTED")
+            .omit("/tmp/*");
+
+        let cases = [
+            ("/keys", Redaction::Omit),
+            ("/keys/a/b", Redaction::Omit),
+            ("/etc/tls/key.pem", Redaction::Replace(b"REDACTED".to_vec())),
+            ("/key.pem", Redaction::Replace(b"REDACTED".to_vec())),
+            ("/tmp/x", Redaction::Omit),
+            ("/tmp/x/y", Redaction::Keep),
+            ("/tmp", Redaction::Keep),
+            ("/etc/passwd", Redaction::Keep),
+        ];
+
+        for (path, redaction) in cases {
+            assert_eq!(patterns.redact(path, &*node), redaction, "{path}");
+        }
+
+        // Many stars take linear rather than exponential time.
+        let name = "a".repeat(64);
+        assert!(!segment(&format!("{}b", "*a".repeat(32)), &name));
+        assert!(segment(&"*a".repeat(32), &name));
+        assert!(segment("a*b*c", "aXbYbc"));
+        assert!(!segment("a*b*c", "aXbYb"));
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use wasi_common::Error;
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_ledger::Ledger;
+use wasmtime_vfs_memory::Node;
+
+/// The optional parts of the tree built by [`standard_root_with`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// Whether to mount the tree description of `wasmtime_vfs_proc` at `/proc`
+    pub proc: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self { proc: true }
+    }
+}
+
+/// Builds the standard Enarx tree with the default [`Layout`].
+pub async fn standard_root(ledger: Arc<Ledger>) -> Result<Arc<Directory>, Error> {
+    standard_root_with(ledger, &Layout::default()).await
+}
+
+/// Builds the standard Enarx tree.
+///
+/// The root is a tmpfs in which guests can create files. Below it, `/keys`
+/// is a keyfs device, `/dev` holds `null`, `zero`, `full` and `urandom`, and
+/// `/proc`, if the layout asks for it, describes the whole tree. Each of
+/// these is its own device, so limits set on the root do not reach them.
+pub async fn standard_root_with(
+    ledger: Arc<Ledger>,
+    layout: &Layout,
+) -> Result<Arc<Directory>, Error> {
+    let root = Directory::root(ledger, Some(Arc::new(File::new)));
+
+    let keys = wasmtime_vfs_keyfs::new(root.clone()).await?;
+    root.attach("keys", keys).await?;
+
+    let dev = Directory::device(root.clone(), None);
+    wasmtime_vfs_dev::populate(&dev).await?;
+    root.attach("dev", dev).await?;
+
+    if layout.proc {
+        let target: Arc<dyn Node> = root.clone();
+        let proc = wasmtime_vfs_proc::new(root.clone(), &target).await?;
+        root.attach("proc", proc).await?;
+    }
+
+    Ok(root)
+}
+
+#[cfg(test)]
+mod test {
+    use wasi_common::file::{FdFlags, FileType, OFlags};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn standard() {
+        let root = standard_root(Ledger::new()).await.unwrap();
+
+        let keys = root.get("keys/generate").await.unwrap();
+        assert_eq!(keys.filetype(), FileType::SocketDgram);
+        assert!(keys.id().device() != root.id().device());
+        assert_eq!(
+            root.get("dev/null").await.unwrap().filetype(),
+            FileType::CharacterDevice
+        );
+        root.get("proc/mounts").await.unwrap();
+
+        // Guests can create files in the root, but not in `/dev`.
+        let open = root.clone().open_dir().await.unwrap();
+        let flags = FdFlags::empty();
+        open.open_file(false, "file", OFlags::CREATE, true, true, flags)
+            .await
+            .unwrap();
+        open.open_file(false, "dev/file", OFlags::CREATE, true, true, flags)
+            .await
+            .err()
+            .unwrap();
+
+        let layout = Layout { proc: false };
+        let root = standard_root_with(Ledger::new(), &layout).await.unwrap();
+        root.get("proc").await.err().unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use wasi_common::file::FileType;
+use wasi_common::Error;
+use wasmtime_vfs_dir::walk;
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_memory::Node;
+
+/// A summary of the contents of a [`Node`] tree
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    counts: [u64; 9],
+
+    /// The total size of all regular file contents in bytes
+    pub bytes: u64,
+
+    /// The number of path segments to the deepest node
+    pub depth: usize,
+
+    /// The path of the deepest node
+    pub deepest: String,
+
+    /// The path and size of the largest regular file, if any
+    pub largest: Option<(String, u64)>,
+}
+
+impl TreeStats {
+    fn index(filetype: FileType) -> usize {
+        match filetype {
+            FileType::Unknown => 0,
+            FileType::BlockDevice => 1,
+            FileType::CharacterDevice => 2,
+            FileType::Directory => 3,
+            FileType::RegularFile => 4,
+            FileType::SocketDgram => 5,
+            FileType::SocketStream => 6,
+            FileType::SymbolicLink => 7,
+            FileType::Pipe => 8,
+        }
+    }
+
+    /// The number of nodes of the given type
+    pub fn count(&self, filetype: FileType) -> u64 {
+        self.counts[Self::index(filetype)]
+    }
+
+    /// The total number of nodes
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// Computes a [`TreeStats`] summary of the tree rooted at `node`.
+pub async fn stats(node: Arc<dyn Node>) -> Result<TreeStats, Error> {
+    let mut stats = TreeStats {
+        deepest: "/".into(),
+        ..Default::default()
+    };
+
+    for entry in walk(node).await? {
+        stats.counts[TreeStats::index(entry.node.filetype())] += 1;
+
+        if entry.depth > stats.depth {
+            stats.depth = entry.depth;
+            stats.deepest = entry.path.clone();
+        }
+
+        if let Ok(file) = entry.node.to_any().downcast::<File>() {
+            let size = file.inode().data().read().await.content().len() as u64;
+            stats.bytes += size;
+
+            match stats.largest {
+                Some((_, largest)) if largest >= size => (),
+                _ => stats.largest = Some((entry.path, size)),
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    #[tokio::test]
+    async fn stats() {
+        let root = Directory::root(Ledger::new(), None);
+        let foo = Directory::new(root.clone(), None);
+        let bar = Directory::new(foo.clone(), None);
+        root.attach("foo", foo.clone()).await.unwrap();
+        root.attach("a", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+        foo.attach("bar", bar.clone()).await.unwrap();
+        foo.attach("b", File::with_data(foo.clone(), "abcdef"))
+            .await
+            .unwrap();
+        bar.attach("c", File::new(bar.clone())).await.unwrap();
+
+        let stats = super::stats(root).await.unwrap();
+        assert_eq!(stats.count(FileType::Directory), 3);
+        assert_eq!(stats.count(FileType::RegularFile), 3);
+        assert_eq!(stats.count(FileType::SocketDgram), 0);
+        assert_eq!(stats.total(), 6);
+        assert_eq!(stats.bytes, 9);
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.deepest, "/foo/bar/c");
+        assert_eq!(stats.largest, Some(("/foo/b".into(), 6)));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use wasi_common::file::FileType;
+use wasi_common::Error;
+use wasmtime_vfs_dir::{walk, Directory};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_ledger::Strictness;
+use wasmtime_vfs_memory::Node;
+
+/// A device policy which a nested device must not loosen
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// The maximum size of a regular file.
+    MaxFileSize,
+
+    /// The [`Strictness`] of the device.
+    Strictness,
+
+    /// Whether removed files go to a trash.
+    Trash,
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxFileSize => f.write_str("maximum file size"),
+            Self::Strictness => f.write_str("strictness"),
+            Self::Trash => f.write_str("trash"),
+        }
+    }
+}
+
+/// A broken invariant found by [`validate`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The node's parent is not the directory that contains it.
+    Parent { path: String },
+
+    /// Two different nodes share the same device and inode ids.
+    Duplicate { path: String, other: String },
+
+    /// The directory contains itself.
+    Cycle { path: String, ancestor: String },
+
+    /// The directory is attached at more than one path.
+    Linked { path: String, other: String },
+
+    /// A node other than a directory is on a different device from its parent.
+    Device { path: String },
+
+    /// The file is larger than its device's maximum file size.
+    Oversized { path: String, size: u64, max: u64 },
+
+    /// The socket is on a permissive device, where the guest can unlink it.
+    Socket { path: String },
+
+    /// The device is looser than the device it is nested in.
+    Policy { path: String, policy: Policy },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parent { path } => write!(
+                f,
+                "`{path}` was created under a different parent than the one it is attached to"
+            ),
+            Self::Duplicate { path, other } => {
+                write!(
+                    f,
+                    "`{path}` and `{other}` are different nodes with the same inode id"
+                )
+            }
+            Self::Cycle { path, ancestor } => {
+                write!(
+                    f,
+                    "`{path}` is the same directory as its ancestor `{ancestor}`"
+                )
+            }
+            Self::Linked { path, other } => {
+                write!(f, "`{path}` is the same directory as `{other}`")
+            }
+            Self::Device { path } => write!(
+                f,
+                "`{path}` is on a different device from its parent, but only directories can be"
+            ),
+            Self::Oversized { path, size, max } => write!(
+                f,
+                "`{path}` is {size} bytes, over its device's maximum file size of {max}"
+            ),
+            Self::Socket { path } => write!(
+                f,
+                "`{path}` is a socket on a permissive device, so the guest can unlink it"
+            ),
+            Self::Policy { path, policy } => write!(
+                f,
+                "`{path}` is a device with a looser {policy} than the device it is nested in"
+            ),
+        }
+    }
+}
+
+fn addr(node: &Arc<dyn Node>) -> *const () {
+    Arc::as_ptr(node) as *const ()
+}
+
+fn parent_path(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+// The policies of the device `dir` which are looser than those of `parent`.
+fn loosened(parent: &Arc<dyn Node>, dir: &Arc<dyn Node>) -> Vec<Policy> {
+    let (outer, inner) = (parent.id().device(), dir.id().device());
+    let mut policies = Vec::new();
+
+    if inner.max_file_size() > outer.max_file_size() {
+        policies.push(Policy::MaxFileSize);
+    }
+
+    if inner.strictness() == Strictness::Permissive && outer.strictness() == Strictness::Strict {
+        policies.push(Policy::Strictness);
+    }
+
+    let trash = |node: &Arc<dyn Node>| match node.clone().to_any().downcast::<Directory>() {
+        Ok(dir) => dir.trash().is_some(),
+        Err(..) => false,
+    };
+    if trash(parent) && !trash(dir) {
+        policies.push(Policy::Trash);
+    }
+
+    policies
+}
+
+/// Checks the invariants of the tree rooted at `node`.
+///
+/// All violations found are returned; an empty list means the tree is valid.
+pub async fn validate(node: Arc<dyn Node>) -> Result<Vec<Violation>, Error> {
+    let entries = walk(node).await?;
+    let paths: HashMap<&str, &Arc<dyn Node>> =
+        entries.iter().map(|e| (&e.path[..], &e.node)).collect();
+
+    let mut violations = Vec::new();
+    let mut inodes = BTreeMap::new();
+    let mut dirs = HashMap::new();
+
+    for entry in &entries {
+        let path = &entry.path;
+        let ptr = addr(&entry.node);
+        let id = entry.node.id();
+        let device = id.device();
+
+        if entry.depth > 0 {
+            let parent = paths[parent_path(path)];
+
+            // A hard-linked file is in several directories but has a single
+            // parent, which need not be this one.
+            let directory = entry.node.filetype() == FileType::Directory;
+            match entry.node.parent() {
+                _ if !directory && id.links() > 1 => (),
+                Some(p) if addr(&p) == addr(parent) => (),
+                _ => violations.push(Violation::Parent { path: path.clone() }),
+            }
+
+            if !directory && parent.id().device() != device {
+                violations.push(Violation::Device { path: path.clone() });
+            }
+
+            if directory && parent.id().device() != device {
+                for policy in loosened(parent, &entry.node) {
+                    let path = path.clone();
+                    violations.push(Violation::Policy { path, policy });
+                }
+            }
+        }
+
+        let socket = matches!(
+            entry.node.filetype(),
+            FileType::SocketStream | FileType::SocketDgram
+        );
+        if socket && device.strictness() == Strictness::Permissive {
+            violations.push(Violation::Socket { path: path.clone() });
+        }
+
+        let (first, other) = *inodes.entry((**device, **id)).or_insert((ptr, path));
+        if first != ptr {
+            violations.push(Violation::Duplicate {
+                path: path.clone(),
+                other: other.clone(),
+            });
+        }
+
+        if entry.node.filetype() == FileType::Directory {
+            // Look for this directory among its own ancestors.
+            let mut ancestor = &path[..];
+            while ancestor != "/" {
+                ancestor = parent_path(ancestor);
+                if addr(paths[ancestor]) == ptr {
+                    let ancestor = ancestor.to_string();
+                    violations.push(Violation::Cycle {
+                        path: path.clone(),
+                        ancestor,
+                    });
+                    break;
+                }
+            }
+
+            // Paths below the first one are reported as cycles above.
+            let other = *dirs.entry(ptr).or_insert(path);
+            if other != path && !path.starts_with(&format!("{other}/")) {
+                violations.push(Violation::Linked {
+                    path: path.clone(),
+                    other: other.clone(),
+                });
+            }
+        }
+
+        if let Ok(file) = entry.node.clone().to_any().downcast::<File>() {
+            let size = file.inode().data().read().await.content().len() as u64;
+            let max = device.max_file_size();
+            if size > max {
+                let path = path.clone();
+                violations.push(Violation::Oversized { path, size, max });
+            }
+        }
+    }
+
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    #[tokio::test]
+    async fn valid() {
+        let root = Directory::root(Ledger::new(), None);
+        let foo = Directory::new(root.clone(), None);
+        let dev = Directory::device(foo.clone(), None);
+        root.attach("foo", foo.clone()).await.unwrap();
+        foo.attach("dev", dev.clone()).await.unwrap();
+        dev.attach("file", File::with_data(dev.clone(), "abc"))
+            .await
+            .unwrap();
+
+        assert_eq!(validate(root).await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn hard_link() {
+        use wasi_common::WasiDir;
+
+        let root = Directory::root(Ledger::new(), None);
+        for name in ["a", "b"] {
+            let dir = Directory::new(root.clone(), None);
+            root.attach(name, dir).await.unwrap();
+        }
+        root.attach("file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+
+        // Each name of a hard-linked file is valid, even once the file is
+        // renamed away from the directory of the first.
+        let open = root.clone().open_dir().await.unwrap();
+        let a = open.open_dir(false, "a").await.unwrap();
+        let b = open.open_dir(false, "b").await.unwrap();
+        open.hard_link("file", &*a, "link").await.unwrap();
+        assert_eq!(validate(root.clone()).await.unwrap(), []);
+        a.rename("link", &*b, "moved").await.unwrap();
+        assert_eq!(validate(root.clone()).await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let root = Directory::root(Ledger::new(), None);
+        let foo = Directory::new(root.clone(), None);
+        let dev = Directory::device(root.clone(), None);
+        root.attach("foo", foo.clone()).await.unwrap();
+        root.attach("dev", dev.clone()).await.unwrap();
+
+        // A file created on one device but attached to another.
+        foo.attach("file", File::with_data(dev.clone(), "abcd"))
+            .await
+            .unwrap();
+
+        // The same directory attached twice, once inside itself.
+        foo.attach("loop", foo.clone()).await.unwrap();
+        root.attach("bar", foo.clone()).await.unwrap();
+
+        // A file over the device's limit.
+        dev.attach("big", File::with_data(dev.clone(), "abcd"))
+            .await
+            .unwrap();
+        dev.id().device().set_max_file_size(3);
+
+        let violations = validate(root.clone()).await.unwrap();
+        let expected = [
+            Violation::Linked {
+                path: "/foo".into(),
+                other: "/bar".into(),
+            },
+            Violation::Parent {
+                path: "/bar/file".into(),
+            },
+            Violation::Device {
+                path: "/bar/file".into(),
+            },
+            Violation::Oversized {
+                path: "/bar/file".into(),
+                size: 4,
+                max: 3,
+            },
+            Violation::Oversized {
+                path: "/dev/big".into(),
+                size: 4,
+                max: 3,
+            },
+            Violation::Parent {
+                path: "/bar/loop".into(),
+            },
+            Violation::Cycle {
+                path: "/bar/loop".into(),
+                ancestor: "/bar".into(),
+            },
+        ];
+
+        for violation in &expected {
+            assert!(violations.contains(violation), "{violation}");
+        }
+
+        // Break the cycle so the tree can be dropped.
+        let mut ilock = foo.inode().data().write().await;
+        ilock.content_mut().remove("loop");
+    }
+
+    #[tokio::test]
+    async fn policy() {
+        use wasmtime_vfs_dir::Trash;
+        use wasmtime_vfs_stream::Stream;
+
+        let root = Directory::root(Ledger::new(), None);
+        let dev = Directory::device(root.clone(), None);
+        root.attach("dev", dev.clone()).await.unwrap();
+
+        // Sockets are only expected where the guest cannot unlink them.
+        let (a, _a) = tokio::io::duplex(1);
+        let (b, _b) = tokio::io::duplex(1);
+        root.attach("a", Stream::new(root.clone(), a))
+            .await
+            .unwrap();
+        dev.attach("b", Stream::new(dev.clone(), b)).await.unwrap();
+
+        let device = root.id().device();
+        device.set_strictness(Strictness::Strict);
+        device.set_max_file_size(1024);
+        root.set_trash(Some(Trash::new(1024)));
+
+        let dev = "/dev".to_string();
+        let expected = [
+            Violation::Policy {
+                path: dev.clone(),
+                policy: Policy::MaxFileSize,
+            },
+            Violation::Policy {
+                path: dev.clone(),
+                policy: Policy::Strictness,
+            },
+            Violation::Policy {
+                path: dev,
+                policy: Policy::Trash,
+            },
+            Violation::Socket {
+                path: "/dev/b".into(),
+            },
+        ];
+
+        let mut violations = validate(root).await.unwrap();
+        violations.sort_by_key(|v| v.to_string());
+        let mut expected = expected.to_vec();
+        expected.sort_by_key(|v| v.to_string());
+        assert_eq!(violations, expected);
+    }
+}
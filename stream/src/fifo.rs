@@ -0,0 +1,443 @@
+use std::any::Any;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{IoSlice, IoSliceMut};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+use tokio::sync::Notify;
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{interruptible, ErrnoExt, Link, Node};
+
+/// The number of bytes a FIFO buffers before writers wait
+pub const CAPACITY: usize = 64 * 1024;
+
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+/// A named pipe
+///
+/// Opening for reading waits for a writer and opening for writing waits for
+/// a reader, unless opened with `O_NONBLOCK`. A non-blocking open for writing
+/// with no reader fails with `EAGAIN`, since WASI cannot express `ENXIO`.
+/// Opening for both never waits.
+pub struct Fifo {
+    link: Link<()>,
+    pipe: Mutex<Pipe>,
+    notify: Notify,
+}
+
+#[async_trait::async_trait]
+impl Node for Fifo {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.link.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.link.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::Pipe
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.link.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        {
+            let mut pipe = self.lock();
+            pipe.readers += read as usize;
+            pipe.writers += write as usize;
+        }
+        self.notify.notify_waiters();
+
+        // Dropping the handle while waiting below unregisters it again.
+        let open = OpenFifo {
+            _root: self.root(),
+            link: self.clone(),
+            flags,
+            read,
+            write,
+        };
+
+        match (read, write, flags.contains(FdFlags::NONBLOCK)) {
+            (true, false, false) => self.wait(|p| (p.writers > 0).then_some(())).await?,
+            (false, true, false) => self.wait(|p| (p.readers > 0).then_some(())).await?,
+            (false, true, true) if self.lock().readers == 0 => return Err(Error::would_block()),
+            _ => (),
+        }
+
+        Ok(Box::new(open))
+    }
+}
+
+impl Fifo {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(parent: Arc<dyn Node>) -> Arc<dyn Node> {
+        Arc::new(Self {
+            link: Link::new(&parent, ()),
+            pipe: Mutex::default(),
+            notify: Notify::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pipe> {
+        self.pipe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Waits until `ready` returns a value, checking after every change,
+    // unless IO on the device is cancelled first.
+    async fn wait<T>(&self, mut ready: impl FnMut(&mut Pipe) -> Option<T>) -> Result<T, Error> {
+        let device = self.link.inode().id().device();
+        interruptible(&device, async move {
+            loop {
+                let mut notified = pin!(self.notify.notified());
+                notified.as_mut().enable();
+
+                let value = ready(&mut self.lock());
+                match value {
+                    Some(value) => return value,
+                    None => notified.await,
+                }
+            }
+        })
+        .await
+    }
+}
+
+struct OpenFifo {
+    _root: Arc<dyn Node>,
+    link: Arc<Fifo>,
+    flags: FdFlags,
+    read: bool,
+    write: bool,
+}
+
+impl Drop for OpenFifo {
+    fn drop(&mut self) {
+        {
+            let mut pipe = self.link.lock();
+            pipe.readers -= self.read as usize;
+            pipe.writers -= self.write as usize;
+        }
+        self.link.notify.notify_waiters();
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenFifo {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::Pipe)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.flags)
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        if !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        self.flags = flags;
+        Ok(())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.link.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.link.inode().id().device(),
+            inode: **self.link.link.inode().id(),
+            filetype: FileType::Pipe,
+            nlink: self.link.link.inode().id().links(),
+            size: self.link.lock().data.len() as u64,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf());
+        }
+
+        let nonblock = self.flags.contains(FdFlags::NONBLOCK);
+        let space: usize = bufs.iter().map(|b| b.len()).sum();
+
+        // With no writers left, an empty pipe is at EOF.
+        let data = self
+            .link
+            .wait(|p| match (p.data.is_empty(), p.writers) {
+                (true, 0) => Some(Ok(Vec::new())),
+                (true, _) if nonblock => Some(Err(Error::would_block())),
+                (true, _) => None,
+                (false, _) => {
+                    let len = min(space, p.data.len());
+                    Some(Ok(p.data.drain(..len).collect::<Vec<_>>()))
+                }
+            })
+            .await??;
+        self.link.notify.notify_waiters();
+
+        let mut total = 0;
+        for buf in bufs {
+            let len = min(buf.len(), data.len() - total);
+            buf[..len].copy_from_slice(&data[total..][..len]);
+            total += len;
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if !self.write {
+            return Err(Error::badf());
+        }
+
+        let nonblock = self.flags.contains(FdFlags::NONBLOCK);
+        let data: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+
+        // Blocking writes wait until everything is buffered; non-blocking
+        // writes buffer what fits.
+        let mut total = 0;
+        while total < data.len() {
+            let done = total;
+            let step = self
+                .link
+                .wait(|p| match (p.readers, CAPACITY - p.data.len()) {
+                    (0, _) => Some(Err(Error::broken_pipe())),
+                    (_, 0) if nonblock && done > 0 => Some(Ok(0)),
+                    (_, 0) if nonblock => Some(Err(Error::would_block())),
+                    (_, 0) => None,
+                    (_, space) => {
+                        let len = min(space, data.len() - done);
+                        p.data.extend(&data[done..][..len]);
+                        Some(Ok(len))
+                    }
+                })
+                .await
+                .and_then(std::convert::identity);
+
+            match step {
+                Ok(0) => break,
+                Ok(len) => total += len,
+                Err(..) if total > 0 => break,
+                Err(e) => return Err(e),
+            }
+
+            self.link.notify.notify_waiters();
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn seek(&mut self, _pos: std::io::SeekFrom) -> Result<u64, Error> {
+        Err(Error::seek_pipe())
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf());
+        }
+
+        let pipe = self.link.lock();
+        let len = min(buf.len(), pipe.data.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.data.iter()) {
+            *dst = *src;
+        }
+
+        Ok(len as u64)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.link.lock().data.len() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        if !self.read {
+            return Err(Error::badf());
+        }
+
+        let ready = |p: &mut Pipe| (!p.data.is_empty() || p.writers == 0).then_some(());
+        self.link.wait(ready).await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        if !self.write {
+            return Err(Error::badf());
+        }
+
+        let ready = |p: &mut Pipe| (p.data.len() < CAPACITY || p.readers == 0).then_some(());
+        self.link.wait(ready).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasi_common::file::OFlags;
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    async fn open(dir: &dyn WasiDir, read: bool, write: bool, flags: FdFlags) -> Box<dyn WasiFile> {
+        dir.open_file(false, "fifo", OFlags::empty(), read, write, flags)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn blocking() {
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("fifo", Fifo::new(root.clone())).await.unwrap();
+        let dir = root.open_dir().await.unwrap();
+
+        // Each side's open waits for the other.
+        let (mut reader, mut writer) = tokio::join!(
+            open(&*dir, true, false, FdFlags::empty()),
+            open(&*dir, false, true, FdFlags::empty()),
+        );
+
+        let mut buf = [0u8; 8];
+        let mut bufs = [IoSliceMut::new(&mut buf)];
+        let data = [IoSlice::new(b"ab"), IoSlice::new(b"cd")];
+        let (written, read) = tokio::join!(
+            writer.write_vectored(&data),
+            reader.read_vectored(&mut bufs),
+        );
+        assert_eq!(written.unwrap(), 4);
+        let read = read.unwrap() as usize;
+        assert_eq!(&buf[..read], &b"abcd"[..read]);
+
+        // Once the writer is gone, the rest is read and then EOF.
+        drop(writer);
+        let mut rest = Vec::new();
+        loop {
+            let n = reader
+                .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+                .await
+                .unwrap() as usize;
+            if n == 0 {
+                break;
+            }
+            rest.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(rest, &b"abcd"[read..]);
+        reader.readable().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn nonblocking() {
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("fifo", Fifo::new(root.clone())).await.unwrap();
+        let dir = root.open_dir().await.unwrap();
+
+        // Without a reader, a non-blocking open for writing fails.
+        let flags = FdFlags::NONBLOCK;
+        let e = dir
+            .open_file(false, "fifo", OFlags::empty(), false, true, flags)
+            .await
+            .err()
+            .unwrap();
+        let e = e.downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+
+        // A non-blocking reader opens at once and sees EOF with no writers.
+        let mut reader = open(&*dir, true, false, flags).await;
+        let mut buf = [0u8; 4];
+        let mut bufs = [IoSliceMut::new(&mut buf)];
+        assert_eq!(reader.read_vectored(&mut bufs).await.unwrap(), 0);
+
+        // With a writer but no data, reads would block.
+        let mut writer = open(&*dir, false, true, flags).await;
+        let e = reader.read_vectored(&mut bufs).await.unwrap_err();
+        let e = e.downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+
+        // A full pipe takes a partial write.
+        let data = vec![1u8; CAPACITY + 1];
+        let n = writer.write_vectored(&[IoSlice::new(&data)]).await.unwrap();
+        assert_eq!(n as usize, CAPACITY);
+        assert_eq!(reader.num_ready_bytes().await.unwrap() as usize, CAPACITY);
+
+        // Once the reader is gone, writes fail.
+        drop(reader);
+        let e = writer
+            .write_vectored(&[IoSlice::new(b"x")])
+            .await
+            .unwrap_err();
+        let e = e.downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("fifo", Fifo::new(root.clone())).await.unwrap();
+        let device = root.id().device();
+        let dir = root.open_dir().await.unwrap();
+
+        // Cancelling wakes an open waiting for a writer.
+        let (opened, ()) = tokio::join!(
+            dir.open_file(
+                false,
+                "fifo",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty()
+            ),
+            async {
+                tokio::task::yield_now().await;
+                device.cancel();
+            },
+        );
+        let e = opened.err().unwrap().downcast::<std::io::Error>().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::Interrupted);
+
+        // IO proceeds again once resumed.
+        device.resume();
+        let flags = FdFlags::NONBLOCK;
+        let reader = open(&*dir, true, false, flags).await;
+        let mut writer = open(&*dir, false, true, flags).await;
+        writer.write_vectored(&[IoSlice::new(b"x")]).await.unwrap();
+        assert_eq!(reader.num_ready_bytes().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+use std::future::Future;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use wasi_common::{Error, WasiFile};
+
+type Op = Pin<Box<dyn Future<Output = (Box<dyn WasiFile>, io::Result<Done>)> + Send>>;
+
+enum Done {
+    Read(Vec<u8>),
+
+    // The count and the data it was written from.
+    Write(usize, Vec<u8>),
+}
+
+fn to_io(error: Error) -> io::Error {
+    match error.downcast::<io::Error>() {
+        Ok(error) => error,
+        Err(error) => io::Error::other(error),
+    }
+}
+
+/// An open [`WasiFile`] usable as a tokio [`AsyncRead`] and [`AsyncWrite`]
+///
+/// Each poll drives at most one underlying operation. The file is moved into
+/// the operation while it runs and handed back when it completes. A write
+/// abandoned while pending still lands in the file, but its count is only
+/// returned to a later `poll_write` with the same buffer.
+pub struct FileStream {
+    file: Option<Box<dyn WasiFile>>,
+    op: Option<Op>,
+
+    // Bytes read from the file but not yet returned to the caller.
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl From<Box<dyn WasiFile>> for FileStream {
+    fn from(file: Box<dyn WasiFile>) -> Self {
+        Self {
+            file: Some(file),
+            op: None,
+            buffer: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl FileStream {
+    pub fn new(file: Box<dyn WasiFile>) -> Self {
+        file.into()
+    }
+
+    /// Returns the file, or `None` if an operation is still in flight.
+    ///
+    /// Any bytes read from the file but not yet returned are discarded.
+    pub fn into_inner(self) -> Option<Box<dyn WasiFile>> {
+        self.file
+    }
+
+    // Drives the in-flight operation, if any, to completion.
+    fn poll_op(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Done>>> {
+        let op = match self.op.as_mut() {
+            Some(op) => op,
+            None => return Poll::Ready(None),
+        };
+
+        let (file, result) = ready!(op.as_mut().poll(cx));
+        self.file = Some(file);
+        self.op = None;
+        Poll::Ready(Some(result))
+    }
+
+    fn start(&mut self, op: impl FnOnce(Box<dyn WasiFile>) -> Op) -> io::Result<()> {
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::other("file is busy"))?;
+        self.op = Some(op(file));
+        Ok(())
+    }
+
+    // Copies buffered bytes to the caller. Returns false if there are none.
+    fn drain(&mut self, buf: &mut ReadBuf<'_>) -> bool {
+        let data = &self.buffer[self.offset..];
+        if data.is_empty() {
+            return false;
+        }
+
+        let len = std::cmp::min(data.len(), buf.remaining());
+        buf.put_slice(&data[..len]);
+        self.offset += len;
+        true
+    }
+}
+
+impl AsyncRead for FileStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.drain(buf) {
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(this.poll_op(cx)) {
+                Some(Ok(Done::Read(data))) => {
+                    this.buffer = data;
+                    this.offset = 0;
+
+                    // An empty read leaves `buf` unfilled, signalling EOF.
+                    this.drain(buf);
+                    return Poll::Ready(Ok(()));
+                }
+
+                // An abandoned write finished; its caller has gone away.
+                Some(Ok(Done::Write(..))) => continue,
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+
+                None => {
+                    let len = buf.remaining();
+                    this.start(|mut file| {
+                        Box::pin(async move {
+                            let mut data = vec![0; len];
+                            let bufs = &mut [IoSliceMut::new(&mut data)];
+                            let result = file.read_vectored(bufs).await.map_err(to_io);
+                            let result = result.map(|n| {
+                                data.truncate(n as usize);
+                                Done::Read(data)
+                            });
+                            (file, result)
+                        })
+                    })?;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for FileStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            match ready!(this.poll_op(cx)) {
+                // Only credit a write to the buffer it was started from.
+                Some(Ok(Done::Write(n, data))) if data == buf => return Poll::Ready(Ok(n)),
+                Some(Ok(Done::Write(..))) => continue,
+                Some(Ok(Done::Read(data))) => {
+                    this.buffer = data;
+                    this.offset = 0;
+                }
+
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+
+                None => {
+                    let data = buf.to_vec();
+                    this.start(|mut file| {
+                        Box::pin(async move {
+                            let bufs = &[IoSlice::new(&data)];
+                            let result = file.write_vectored(bufs).await.map_err(to_io);
+                            let result = result.map(|n| Done::Write(n as usize, data));
+                            (file, result)
+                        })
+                    })?;
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match ready!(this.poll_op(cx)) {
+            Some(Ok(Done::Read(data))) => {
+                this.buffer = data;
+                this.offset = 0;
+                Poll::Ready(Ok(()))
+            }
+
+            Some(Ok(Done::Write(..))) | None => Poll::Ready(Ok(())),
+            Some(Err(e)) => Poll::Ready(Err(e)),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use wasi_common::file::{FdFlags, OFlags};
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_file::File;
+    use wasmtime_vfs_ledger::Ledger;
+    use wasmtime_vfs_memory::Node;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        let dir = root.open_dir().await.unwrap();
+
+        let flags = FdFlags::empty();
+        let file = dir
+            .open_file(false, "file", OFlags::CREATE, false, true, flags)
+            .await
+            .unwrap();
+
+        let mut writer = FileStream::new(file);
+        writer.write_all(b"hello, ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert!(writer.into_inner().is_some());
+
+        let file = dir
+            .open_file(false, "file", OFlags::empty(), true, false, flags)
+            .await
+            .unwrap();
+
+        // Read in small pieces to exercise the partial read path.
+        let mut reader = FileStream::new(file);
+        let mut piece = [0u8; 5];
+        reader.read_exact(&mut piece).await.unwrap();
+        assert_eq!(&piece, b"hello");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, ", world");
+    }
+
+    #[tokio::test]
+    async fn error() {
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("file", File::with_data(root.clone(), "abc"))
+            .await
+            .unwrap();
+        let dir = root.open_dir().await.unwrap();
+
+        // A read-only handle refuses writes.
+        let file = dir
+            .open_file(
+                false,
+                "file",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await
+            .unwrap();
+        let mut stream = FileStream::new(file);
+        assert!(stream.write_all(b"x").await.is_err());
+
+        // The stream stays usable after a failure.
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"abc");
+    }
+}
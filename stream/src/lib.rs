@@ -0,0 +1,7 @@
+mod fifo;
+mod file;
+mod node;
+
+pub use fifo::{Fifo, CAPACITY};
+pub use file::FileStream;
+pub use node::Stream;
@@ -0,0 +1,458 @@
+use std::any::Any;
+use std::future::Future;
+use std::io::{IoSlice, IoSliceMut};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use wasi_common::file::{FdFlags, FileType, Filestat, RiFlags, RoFlags, SdFlags, SiFlags};
+use wasi_common::{Error, ErrorExt, WasiDir, WasiFile};
+use wasmtime_vfs_ledger::InodeId;
+use wasmtime_vfs_memory::{ErrnoExt, Link, Node};
+
+type Reader = Mutex<BufReader<Box<dyn AsyncRead + Send + Unpin>>>;
+type Writer = Mutex<Box<dyn AsyncWrite + Send + Unpin>>;
+
+/// A stream socket node backed by host async I/O
+///
+/// All open handles share the same underlying reader and writer.
+pub struct Stream {
+    link: Link<()>,
+    reader: Option<Reader>,
+    writer: Option<Writer>,
+}
+
+#[async_trait::async_trait]
+impl Node for Stream {
+    fn to_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Node>> {
+        self.link.parent()
+    }
+
+    fn set_parent(&self, parent: Weak<dyn Node>) {
+        self.link.set_parent(parent)
+    }
+
+    fn filetype(&self) -> FileType {
+        FileType::SocketStream
+    }
+
+    fn id(&self) -> Arc<InodeId> {
+        self.link.inode().id()
+    }
+
+    async fn open_dir(self: Arc<Self>) -> Result<Box<dyn WasiDir>, Error> {
+        Err(Error::not_dir())
+    }
+
+    async fn open_file(
+        self: Arc<Self>,
+        _path: &str,
+        dir: bool,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if dir {
+            return Err(Error::not_dir());
+        }
+
+        if (read && self.reader.is_none()) || (write && self.writer.is_none()) {
+            return Err(Error::perm());
+        }
+
+        if !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        Ok(Box::new(OpenStream {
+            _root: self.root(),
+            link: self,
+            flags,
+            read,
+            write,
+        }))
+    }
+}
+
+impl Stream {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<T>(parent: Arc<dyn Node>, io: T) -> Arc<dyn Node>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(io);
+        Self::with_halves(parent, Some(Box::new(reader)), Some(Box::new(writer)))
+    }
+
+    pub fn reader(
+        parent: Arc<dyn Node>,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+    ) -> Arc<dyn Node> {
+        Self::with_halves(parent, Some(Box::new(reader)), None)
+    }
+
+    pub fn writer(
+        parent: Arc<dyn Node>,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Arc<dyn Node> {
+        Self::with_halves(parent, None, Some(Box::new(writer)))
+    }
+
+    fn with_halves(
+        parent: Arc<dyn Node>,
+        reader: Option<Box<dyn AsyncRead + Send + Unpin>>,
+        writer: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    ) -> Arc<dyn Node> {
+        Arc::new(Self {
+            link: Link::new(&parent, ()),
+            reader: reader.map(|r| BufReader::new(r).into()),
+            writer: writer.map(Mutex::new),
+        })
+    }
+}
+
+struct OpenStream {
+    _root: Arc<dyn Node>,
+    link: Arc<Stream>,
+    flags: FdFlags,
+    read: bool,
+    write: bool,
+}
+
+impl OpenStream {
+    fn reader(&self) -> Result<&Reader, Error> {
+        match (self.read, &self.link.reader) {
+            (true, Some(reader)) => Ok(reader),
+            _ => Err(Error::badf()),
+        }
+    }
+
+    fn writer(&self) -> Result<&Writer, Error> {
+        match (self.write, &self.link.writer) {
+            (true, Some(writer)) => Ok(writer),
+            _ => Err(Error::badf()),
+        }
+    }
+
+    // Fills the read buffer, returning the buffered bytes (empty on EOF).
+    //
+    // In non-blocking mode, this fails with `EAGAIN` instead of waiting.
+    async fn fill<'a>(
+        &self,
+        reader: &'a mut BufReader<Box<dyn AsyncRead + Send + Unpin>>,
+    ) -> Result<&'a [u8], Error> {
+        if !self.flags.contains(FdFlags::NONBLOCK) {
+            return Ok(reader.fill_buf().await?);
+        }
+
+        let poll = {
+            let mut fill = pin!(std::future::poll_fn(|cx| {
+                Pin::new(&mut *reader).poll_fill_buf(cx).map_ok(|b| b.len())
+            }));
+            fill.as_mut().poll(&mut Context::from_waker(Waker::noop()))
+        };
+
+        match poll {
+            Poll::Ready(result) => result?,
+            Poll::Pending => return Err(Error::would_block()),
+        };
+
+        Ok(reader.buffer())
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OpenStream {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.flags)
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        if !(flags - FdFlags::NONBLOCK).is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        self.flags = flags;
+        Ok(())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let ilock = self.link.link.inode().data().read().await;
+
+        Ok(Filestat {
+            device_id: **self.link.link.inode().id().device(),
+            inode: **self.link.link.inode().id(),
+            filetype: FileType::SocketStream,
+            nlink: self.link.link.inode().id().links(),
+            size: 0,
+            atim: Some(ilock.access_time()),
+            mtim: Some(ilock.modify_time()),
+            ctim: Some(ilock.change_time()),
+        })
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        if flags.contains(RiFlags::RECV_PEEK) {
+            let total = match bufs.first_mut() {
+                Some(buf) => self.peek(buf).await?,
+                None => 0,
+            };
+            return Ok((total, RoFlags::empty()));
+        }
+
+        let n = self.read_vectored(bufs).await?;
+        Ok((n, RoFlags::empty()))
+    }
+
+    async fn sock_send<'a>(&mut self, bufs: &[IoSlice<'a>], _flags: SiFlags) -> Result<u64, Error> {
+        self.write_vectored(bufs).await
+    }
+
+    async fn sock_shutdown(&mut self, how: SdFlags) -> Result<(), Error> {
+        if how.contains(SdFlags::WR) {
+            self.writer()?.lock().await.shutdown().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let mut reader = self.reader()?.lock().await;
+        let data = self.fill(&mut reader).await?;
+
+        let mut total = 0;
+        for buf in bufs {
+            let len = std::cmp::min(buf.len(), data.len() - total);
+            buf[..len].copy_from_slice(&data[total..][..len]);
+            total += len;
+        }
+
+        reader.consume(total);
+        Ok(total as u64)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let mut writer = self.writer()?.lock().await;
+
+        if !self.flags.contains(FdFlags::NONBLOCK) {
+            let mut total = 0;
+            for buf in bufs {
+                writer.write_all(buf).await?;
+                total += buf.len() as u64;
+            }
+
+            writer.flush().await?;
+            return Ok(total);
+        }
+
+        // In non-blocking mode, write what the writer accepts without waiting.
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut total = 0;
+        for buf in bufs {
+            match Pin::new(&mut *writer).poll_write(&mut cx, buf) {
+                Poll::Ready(Ok(len)) => {
+                    total += len;
+                    if len < buf.len() {
+                        break;
+                    }
+                }
+                Poll::Ready(Err(e)) if total == 0 => return Err(e.into()),
+                Poll::Pending if total == 0 => return Err(Error::would_block()),
+                _ => break,
+            }
+        }
+
+        if let Poll::Ready(Err(e)) = Pin::new(&mut *writer).poll_flush(&mut cx) {
+            return Err(e.into());
+        }
+
+        Ok(total as u64)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        let mut reader = self.reader()?.lock().await;
+        let data = self.fill(&mut reader).await?;
+
+        let len = std::cmp::min(buf.len(), data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len as u64)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        // A handle blocked in a read holds the lock and has nothing buffered.
+        match self.reader()?.try_lock() {
+            Ok(reader) => Ok(reader.buffer().len() as u64),
+            Err(..) => Ok(0),
+        }
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        let mut reader = self.reader()?.lock().await;
+        reader.fill_buf().await?;
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        // A writer with room accepts an empty write; a full one waits.
+        let mut writer = self.writer()?.lock().await;
+        std::future::poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, &[])).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncReadExt;
+    use wasi_common::file::OFlags;
+    use wasi_common::ErrorKind;
+    use wasmtime_vfs_dir::Directory;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    async fn open(
+        root: &Arc<Directory>,
+        node: Arc<dyn Node>,
+        read: bool,
+        write: bool,
+        flags: FdFlags,
+    ) -> Box<dyn WasiFile> {
+        root.attach("sock", node).await.unwrap();
+        let dir = root.clone().open_dir().await.unwrap();
+        dir.open_file(false, "sock", OFlags::empty(), read, write, flags)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn duplex() {
+        let (guest, mut host) = tokio::io::duplex(64);
+        let root = Directory::root(Ledger::new(), None);
+        let node = Stream::new(root.clone(), guest);
+        let mut file = open(&root, node, true, true, FdFlags::empty()).await;
+        assert_eq!(file.get_filetype().await.unwrap(), FileType::SocketStream);
+
+        // Host to guest.
+        host.write_all(b"ping").await.unwrap();
+        file.readable().await.unwrap();
+        assert_eq!(file.num_ready_bytes().await.unwrap(), 4);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(file.peek(&mut buf[..2]).await.unwrap(), 2);
+        assert_eq!(&buf[..2], b"pi");
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n as usize], b"ping");
+
+        // Guest to host.
+        file.writable().await.unwrap();
+        file.write_vectored(&[IoSlice::new(b"po"), IoSlice::new(b"ng")])
+            .await
+            .unwrap();
+        host.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Shutting down the guest's write side is EOF for the host.
+        file.sock_shutdown(SdFlags::WR).await.unwrap();
+        assert_eq!(host.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn nonblocking() {
+        let (guest, mut host) = tokio::io::duplex(64);
+        let root = Directory::root(Ledger::new(), None);
+        let node = Stream::reader(root.clone(), guest);
+        let mut file = open(&root, node, true, false, FdFlags::NONBLOCK).await;
+
+        let mut buf = [0u8; 4];
+        let err = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap_err();
+        let err = err.downcast::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+        host.write_all(b"abc").await.unwrap();
+        let n = file
+            .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n as usize], b"abc");
+
+        // A read-only stream cannot be written.
+        let err = file
+            .write_vectored(&[IoSlice::new(b"x")])
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast::<ErrorKind>().unwrap(), ErrorKind::Badf);
+    }
+
+    #[tokio::test]
+    async fn nonblocking_write() {
+        let (guest, mut host) = tokio::io::duplex(4);
+        let root = Directory::root(Ledger::new(), None);
+        let node = Stream::writer(root.clone(), guest);
+        let mut file = open(&root, node, false, true, FdFlags::NONBLOCK).await;
+
+        // Writes stop short once the pipe is full.
+        let n = file
+            .write_vectored(&[IoSlice::new(b"abc"), IoSlice::new(b"def")])
+            .await
+            .unwrap();
+        assert_eq!(n, 4);
+
+        let err = file
+            .write_vectored(&[IoSlice::new(b"g")])
+            .await
+            .unwrap_err();
+        let err = err.downcast::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+        // Draining the pipe makes the stream writable again.
+        let mut buf = [0u8; 4];
+        tokio::join!(file.writable(), host.read_exact(&mut buf))
+            .0
+            .unwrap();
+        assert_eq!(&buf, b"abcd");
+        let n = file.write_vectored(&[IoSlice::new(b"g")]).await.unwrap();
+        assert_eq!(n, 1);
+    }
+
+    #[tokio::test]
+    async fn unlink() {
+        use wasmtime_vfs_ledger::Strictness;
+
+        let (guest, _host) = tokio::io::duplex(64);
+        let root = Directory::root(Ledger::new(), None);
+        root.attach("sock", Stream::new(root.clone(), guest))
+            .await
+            .unwrap();
+        let dir = root.clone().open_dir().await.unwrap();
+
+        // Only strict devices refuse to unlink sockets.
+        root.id().device().set_strictness(Strictness::Strict);
+        dir.unlink_file("sock").await.unwrap_err();
+        root.id().device().set_strictness(Strictness::Permissive);
+        dir.unlink_file("sock").await.unwrap();
+    }
+}
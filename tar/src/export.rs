@@ -0,0 +1,114 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tar::{Builder, EntryType, Header};
+use wasi_common::Error;
+use wasmtime_vfs_dir::{walk_with, Directory, Links};
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_memory::Node;
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Writes the tree rooted at `root` to `writer` as a tar archive.
+///
+/// Directories and regular files are written with their content and
+/// modification times, in [`walk`] order, and symbolic links as links.
+/// Other kinds of node, such as sockets and keys, are left out, as is the
+/// root itself. A directory reachable by several paths is written at each
+/// of them.
+///
+/// [`walk`]: wasmtime_vfs_dir::walk
+pub async fn export(root: Arc<dyn Node>, writer: impl Write) -> Result<(), Error> {
+    export_with(root, writer, Links::Preserve).await
+}
+
+/// Writes the tree rooted at `root` like [`export`], treating symbolic
+/// links as `links` says.
+pub async fn export_with(
+    root: Arc<dyn Node>,
+    writer: impl Write,
+    links: Links,
+) -> Result<(), Error> {
+    let mut builder = Builder::new(writer);
+
+    for entry in walk_with(root, links).await?.into_iter().skip(1) {
+        let path = entry.path.trim_start_matches('/');
+        let mut header = Header::new_gnu();
+
+        if let Some(target) = entry.node.read_link() {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            builder.append_link(&mut header, path, target)?;
+            continue;
+        }
+
+        let node = entry.node.to_any();
+        let node = match node.downcast::<Directory>() {
+            Ok(dir) => {
+                let ilock = dir.inode().data().read().await;
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_mtime(seconds(ilock.modify_time()));
+                header.set_size(0);
+                builder.append_data(&mut header, format!("{path}/"), &[][..])?;
+                continue;
+            }
+            Err(node) => node,
+        };
+
+        if let Ok(file) = node.downcast::<File>() {
+            let ilock = file.inode().data().read().await;
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_mtime(seconds(ilock.modify_time()));
+            header.set_size(ilock.content().len() as u64);
+            builder.append_data(&mut header, path, ilock.content().reader())?;
+        }
+    }
+
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let root = Directory::root(Ledger::new(), None);
+        let sub = Directory::new(root.clone(), None);
+        root.attach("sub", sub.clone()).await.unwrap();
+        sub.attach("file", File::with_data(sub.clone(), "abc"))
+            .await
+            .unwrap();
+        root.attach("empty", File::with_data(root.clone(), ""))
+            .await
+            .unwrap();
+
+        let mut tar = Vec::new();
+        export(root, &mut tar).await.unwrap();
+
+        let mut archive = tar::Archive::new(&tar[..]);
+        let paths: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(paths, ["empty", "sub/", "sub/file"]);
+
+        // Importing the archive restores the tree.
+        let copy = Directory::root(Ledger::new(), None);
+        crate::import(&copy, &tar[..]).await.unwrap();
+        let node = copy.get("sub/file").await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        assert_eq!(*file.inode().data().read().await.content(), b"abc");
+        assert!(copy.get("empty").await.is_ok());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use flate2::read::GzDecoder;
+use tar::{Archive, EntryType};
+use wasi_common::file::FileType;
+use wasi_common::{Error, ErrorExt};
+use wasmtime_vfs_dir::{Directory, Metadata, TreeEntry};
+use wasmtime_vfs_file::File;
+
+struct Item {
+    path: Vec<String>,
+    dir: bool,
+    mtime: SystemTime,
+    data: Vec<u8>,
+}
+
+// Reads the supported entries of an archive, before any await point.
+fn items(reader: impl Read) -> Result<Vec<Item>, Error> {
+    let mut items = Vec::new();
+
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        let dir = match entry.header().entry_type() {
+            EntryType::Directory => true,
+            EntryType::Regular | EntryType::Continuous => false,
+            _ => continue,
+        };
+
+        let mut path = Vec::new();
+        for seg in entry
+            .path()?
+            .to_str()
+            .ok_or_else(Error::illegal_byte_sequence)?
+            .split('/')
+        {
+            match seg {
+                "" | "." => continue,
+                ".." => return Err(Error::invalid_argument()),
+                seg => path.push(seg.to_string()),
+            }
+        }
+
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        items.push(Item {
+            path,
+            dir,
+            mtime,
+            data,
+        });
+    }
+
+    Ok(items)
+}
+
+// Gets the directory at `path` below `root`, creating any that are missing.
+async fn mkdirs(root: &Arc<Directory>, path: &[String]) -> Result<Arc<Directory>, Error> {
+    let mut dir = root.clone();
+
+    for name in path {
+        dir = match dir.get(name).await {
+            Ok(node) => node
+                .to_any()
+                .downcast::<Directory>()
+                .map_err(|_| Error::not_dir())?,
+            Err(..) => {
+                let child = Directory::new(dir.clone(), dir.factory());
+                dir.attach(name, child.clone()).await?;
+                child
+            }
+        };
+    }
+
+    Ok(dir)
+}
+
+/// Unpacks a tar archive into `root`.
+///
+/// Directories and regular files are created with their archived content
+/// and modification times. Existing directories are merged into and existing
+/// files replaced, as when applying layers in order. Other kinds of entry,
+/// such as links and devices, are skipped. Entries climbing out of `root`
+/// with `..` fail with `EINVAL`.
+///
+/// The archive is read in full before the tree is touched, so `reader` may
+/// block without stalling other tasks partway through.
+pub async fn import(root: &Arc<Directory>, reader: impl Read) -> Result<(), Error> {
+    let mut mtimes = Vec::new();
+
+    for item in items(reader)? {
+        let (name, parent) = match item.path.split_last() {
+            Some(split) => split,
+            None if item.dir => {
+                mtimes.push((root.clone(), item.mtime));
+                continue;
+            }
+            None => return Err(Error::invalid_argument()),
+        };
+
+        let parent = mkdirs(root, parent).await?;
+        if item.dir {
+            let dir = mkdirs(&parent, std::slice::from_ref(name)).await?;
+            mtimes.push((dir, item.mtime));
+            continue;
+        }
+
+        let file = File::with_data(parent.clone(), item.data);
+        let file = file.to_any().downcast::<File>().unwrap();
+        file.inode()
+            .data()
+            .write()
+            .await
+            .set_modify_time(item.mtime);
+
+        // A later entry for the same path replaces the earlier one.
+        if let Ok(node) = parent.get(name).await {
+            if node.filetype() == FileType::Directory {
+                return Err(Error::exist());
+            }
+
+            let mut ilock = parent.inode().data().write().await;
+            if let Some(node) = ilock.content_mut().remove(name) {
+                node.id().unlink();
+            }
+        }
+
+        parent.attach(name, file).await?;
+    }
+
+    // Adding entries touches their directories, so set those times last.
+    for (dir, mtime) in mtimes {
+        dir.inode().data().write().await.set_modify_time(mtime);
+    }
+
+    Ok(())
+}
+
+/// Unpacks a gzip-compressed tar archive into `root`, as with [`import`].
+pub async fn import_gz(root: &Arc<Directory>, reader: impl Read) -> Result<(), Error> {
+    import(root, GzDecoder::new(reader)).await
+}
+
+/// Reads a tar archive as entries for [`TreeBuilder::entries`].
+///
+/// Entries are read as by [`import`], keeping their modification times. A
+/// later entry for the path of an earlier file takes its place, so that
+/// the builder sees each file once.
+///
+/// [`TreeBuilder::entries`]: wasmtime_vfs_dir::TreeBuilder::entries
+pub fn entries(reader: impl Read) -> Result<Vec<TreeEntry>, Error> {
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    let mut files = BTreeMap::new();
+
+    for item in items(reader)? {
+        if item.path.is_empty() {
+            continue;
+        }
+
+        let entry = TreeEntry {
+            path: item.path.join("/"),
+            data: (!item.dir).then_some(item.data),
+            meta: Metadata {
+                mtime: Some(item.mtime),
+                ..Default::default()
+            },
+        };
+
+        match files.get(&entry.path) {
+            Some(&index) => entries[index] = entry,
+            None if entry.data.is_some() => {
+                files.insert(entry.path.clone(), entries.len());
+                entries.push(entry);
+            }
+            None => entries.push(entry),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads a gzip-compressed tar archive as with [`entries`].
+pub fn entries_gz(reader: impl Read) -> Result<Vec<TreeEntry>, Error> {
+    entries(GzDecoder::new(reader))
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tar::{Builder, Header};
+    use wasmtime_vfs_dir::TreeBuilder;
+    use wasmtime_vfs_ledger::Ledger;
+
+    use super::*;
+
+    fn archive(entries: &[(&str, Option<&str>, u64)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for (path, data, mtime) in entries {
+            let mut header = Header::new_gnu();
+            header.set_mtime(*mtime);
+            header.set_mode(0o755);
+
+            match data {
+                None => {
+                    header.set_entry_type(EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, &[][..]).unwrap();
+                }
+                Some(data) => {
+                    header.set_entry_type(EntryType::Regular);
+                    header.set_size(data.len() as u64);
+                    builder
+                        .append_data(&mut header, path, data.as_bytes())
+                        .unwrap();
+                }
+            }
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    async fn content(root: &Arc<Directory>, path: &str) -> (Vec<u8>, SystemTime) {
+        let node = root.get(path).await.unwrap();
+        let file = node.to_any().downcast::<File>().unwrap();
+        let ilock = file.inode().data().read().await;
+        (ilock.content().to_vec(), ilock.modify_time())
+    }
+
+    #[tokio::test]
+    async fn import() {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let root = Directory::root(Ledger::new(), None);
+        let tar = archive(&[
+            ("a/", None, 100),
+            ("a/b", Some("old"), 200),
+            ("./c/d", Some("deep"), 300),
+            ("a/b", Some("new"), 400),
+        ]);
+        super::import(&root, &tar[..]).await.unwrap();
+
+        assert_eq!(content(&root, "a/b").await, (b"new".to_vec(), time(400)));
+        assert_eq!(content(&root, "c/d").await, (b"deep".to_vec(), time(300)));
+
+        let node = root.get("a").await.unwrap();
+        let dir = node.to_any().downcast::<Directory>().unwrap();
+        assert_eq!(dir.inode().data().read().await.modify_time(), time(100));
+
+        // A second layer merges into the tree, compressed this time.
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut gz, &archive(&[("a/e", Some("e"), 500)])).unwrap();
+        import_gz(&root, &gz.finish().unwrap()[..]).await.unwrap();
+        assert_eq!(content(&root, "a/e").await, (b"e".to_vec(), time(500)));
+        assert_eq!(content(&root, "a/b").await.0, b"new");
+    }
+
+    #[tokio::test]
+    async fn builder() {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let tar = archive(&[
+            ("a/", None, 100),
+            ("a/b", Some("old"), 200),
+            ("./c/d", Some("deep"), 300),
+            ("a/b", Some("new"), 400),
+        ]);
+
+        let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+        TreeBuilder::new(root.clone())
+            .file("e", "e")
+            .entries(entries(&tar[..]).unwrap())
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(content(&root, "a/b").await, (b"new".to_vec(), time(400)));
+        assert_eq!(content(&root, "c/d").await, (b"deep".to_vec(), time(300)));
+        assert_eq!(content(&root, "e").await.0, b"e");
+
+        let node = root.get("a").await.unwrap();
+        let dir = node.to_any().downcast::<Directory>().unwrap();
+        assert_eq!(dir.inode().data().read().await.modify_time(), time(100));
+
+        // Compressed archives read the same.
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut gz, &tar).unwrap();
+        assert_eq!(entries_gz(&gz.finish().unwrap()[..]).unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn escape() {
+        let root = Directory::root(Ledger::new(), None);
+
+        // The tar builder refuses `..`, so write the name by hand.
+        let mut header = Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..5].copy_from_slice(b"../x\0");
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(0);
+        header.set_cksum();
+
+        let mut builder = Builder::new(Vec::new());
+        builder.append(&header, &[][..]).unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        assert!(super::import(&root, &tar[..]).await.is_err());
+        assert!(root.get("x").await.is_err());
+    }
+}
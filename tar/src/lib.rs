@@ -0,0 +1,7 @@
+//! Tar archives of WASI virtual file system trees
+
+pub use export::{export, export_with};
+pub use import::{entries, entries_gz, import, import_gz};
+
+mod export;
+mod import;
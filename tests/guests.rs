@@ -0,0 +1,114 @@
+//! Runs small WASI guests against each file system implementation.
+//!
+//! Each guest in `tests/guests/` exercises one syscall pattern and exits
+//! with the number of the first step that failed, or zero.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use tempfile::tempdir;
+use wasi_common::{I32Exit, WasiDir};
+use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_file::File;
+use wasmtime_vfs_host::cap_std::{ambient_authority, fs::Dir};
+use wasmtime_vfs_host::HostDir;
+use wasmtime_vfs_ledger::Ledger;
+use wasmtime_vfs_memory::Node;
+use wasmtime_wasi::WasiCtxBuilder;
+
+const PRELUDE: &str = include_str!("guests/prelude.wat");
+
+#[derive(Copy, Clone, Debug)]
+enum Fs {
+    /// An in-memory tree
+    Memory,
+
+    /// A host directory, as a sync preopen; the reference behavior
+    Host,
+
+    /// A host directory grafted into an in-memory tree
+    HostDir,
+}
+
+const ALL: &[Fs] = &[Fs::Memory, Fs::Host, Fs::HostDir];
+
+impl Fs {
+    async fn open(self, path: &Path) -> anyhow::Result<Box<dyn WasiDir>> {
+        let host = || Dir::open_ambient_dir(path, ambient_authority());
+
+        Ok(match self {
+            Fs::Memory => {
+                let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
+                root.open_dir().await?
+            }
+
+            Fs::Host => Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(host()?)),
+
+            Fs::HostDir => {
+                let root = Directory::root(Ledger::new(), None);
+                HostDir::new(root, host()?).open_dir().await?
+            }
+        })
+    }
+}
+
+async fn run(guest: &str, dir: Box<dyn WasiDir>) -> anyhow::Result<i32> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, format!("(module\n{PRELUDE}\n{guest})"))
+        .context("failed to compile the guest")?;
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |s| s).context("failed to link WASI")?;
+
+    let mut ctx = WasiCtxBuilder::new().build();
+    ctx.push_preopened_dir(dir, "/")
+        .context("failed to push directory")?;
+
+    let mut store = Store::new(&engine, ctx);
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .context("failed to instantiate the guest")?;
+
+    let start = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
+    let error = start
+        .call(&mut store, ())
+        .expect_err("the guest did not exit");
+    error.downcast().map(|I32Exit(code)| code)
+}
+
+async fn check(guest: &str, systems: &[Fs]) {
+    for fs in systems {
+        let tmp = tempdir().unwrap();
+        let dir = fs.open(tmp.path()).await.unwrap();
+        let code = run(guest, dir).await.unwrap();
+        assert_eq!(code, 0, "failed at step {code} on {fs:?}");
+    }
+}
+
+#[tokio::test]
+async fn append() {
+    check(include_str!("guests/append.wat"), ALL).await;
+}
+
+// Linux appends positional writes to append handles, unlike POSIX. The
+// in-memory tree follows POSIX, so the host is no reference here.
+#[tokio::test]
+async fn pwrite_append() {
+    check(include_str!("guests/pwrite_append.wat"), &[Fs::Memory]).await;
+}
+
+#[tokio::test]
+async fn pread_pwrite() {
+    check(include_str!("guests/pread_pwrite.wat"), ALL).await;
+}
+
+#[tokio::test]
+async fn readdir() {
+    check(include_str!("guests/readdir.wat"), ALL).await;
+}
+
+#[tokio::test]
+async fn unlink_open() {
+    check(include_str!("guests/unlink_open.wat"), ALL).await;
+}
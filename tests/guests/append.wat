@@ -0,0 +1,25 @@
+;; Writes to an append handle land at the end, even after seeking away.
+(data (i32.const 0x400) "f")
+(data (i32.const 0x410) "abc")
+
+(func (export "_start")
+  (local $fd i32)
+  ;; O_CREAT, FDFLAGS_APPEND
+  (local.set $fd (call $open (i32.const 3) (i32.const 0x400) (i32.const 1)
+    (i32.const 1) (i32.const 1) (i32.const 1)))
+  (drop (call $write (local.get $fd) (i32.const 0x410) (i32.const 2) (i32.const 2)))
+  (drop (call $seek (local.get $fd) (i64.const 0) (i32.const 0) (i32.const 3)))
+  (drop (call $write (local.get $fd) (i32.const 0x412) (i32.const 1) (i32.const 4)))
+
+  ;; The position is left at the end.
+  (call $assert
+    (i64.eq (call $seek (local.get $fd) (i64.const 0) (i32.const 1) (i32.const 5))
+      (i64.const 3))
+    (i32.const 6))
+
+  (call $assert
+    (i32.eq (call $pread (local.get $fd) (i32.const 0x1000) (i32.const 16) (i64.const 0)
+      (i32.const 7)) (i32.const 3))
+    (i32.const 8))
+  (call $assert (call $eq (i32.const 0x1000) (i32.const 0x410) (i32.const 3)) (i32.const 9))
+  (call $proc_exit (i32.const 0)))
@@ -0,0 +1,32 @@
+;; Positional I/O leaves the position alone and zero-fills gaps.
+(data (i32.const 0x400) "f")
+(data (i32.const 0x410) "hello")
+(data (i32.const 0x420) "\00el")
+
+(func (export "_start")
+  (local $fd i32)
+  ;; O_CREAT
+  (local.set $fd (call $open (i32.const 3) (i32.const 0x400) (i32.const 1)
+    (i32.const 1) (i32.const 0) (i32.const 1)))
+  (call $assert
+    (i32.eq (call $pwrite (local.get $fd) (i32.const 0x410) (i32.const 5) (i64.const 2)
+      (i32.const 2)) (i32.const 5))
+    (i32.const 3))
+  (call $assert
+    (i64.eq (call $seek (local.get $fd) (i64.const 0) (i32.const 1) (i32.const 4))
+      (i64.const 0))
+    (i32.const 5))
+
+  ;; The gap before the write reads as zeros.
+  (drop (call $pread (local.get $fd) (i32.const 0x1000) (i32.const 1) (i64.const 0)
+    (i32.const 6)))
+  (drop (call $pread (local.get $fd) (i32.const 0x1001) (i32.const 2) (i64.const 3)
+    (i32.const 7)))
+  (call $assert (call $eq (i32.const 0x1000) (i32.const 0x420) (i32.const 3)) (i32.const 8))
+
+  ;; Reads at the end return nothing.
+  (call $assert
+    (i32.eqz (call $pread (local.get $fd) (i32.const 0x1000) (i32.const 4) (i64.const 7)
+      (i32.const 9)))
+    (i32.const 10))
+  (call $proc_exit (i32.const 0)))
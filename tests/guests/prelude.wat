@@ -0,0 +1,89 @@
+;; Imports and helpers shared by the guests. Each guest is spliced into a
+;; module after this prelude; fd 3 is the preopened directory.
+(import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+(import "wasi_snapshot_preview1" "path_open"
+  (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "path_create_directory"
+  (func $path_create_directory (param i32 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "path_unlink_file"
+  (func $path_unlink_file (param i32 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_pwrite"
+  (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_readdir"
+  (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+
+(memory (export "memory") 1)
+
+;; An iovec and a result slot; guests keep their own data from 0x400.
+(global $iov i32 (i32.const 0x100))
+(global $out i32 (i32.const 0x110))
+
+;; Exits with `step` unless `errno` is zero.
+(func $ok (param $errno i32) (param $step i32)
+  (if (local.get $errno) (then (call $proc_exit (local.get $step)))))
+
+;; Exits with `step` unless `cond` holds.
+(func $assert (param $cond i32) (param $step i32)
+  (if (i32.eqz (local.get $cond)) (then (call $proc_exit (local.get $step)))))
+
+;; Opens `path` under `dir` for reading and writing, returning the fd.
+(func $open (param $dir i32) (param $path i32) (param $len i32) (param $oflags i32)
+  (param $fdflags i32) (param $step i32) (result i32)
+  (call $ok
+    (call $path_open (local.get $dir) (i32.const 1) (local.get $path) (local.get $len)
+      (local.get $oflags) (i64.const 0x1fffffff) (i64.const 0x1fffffff)
+      (local.get $fdflags) (global.get $out))
+    (local.get $step))
+  (i32.load (global.get $out)))
+
+(func $iovec (param $ptr i32) (param $len i32)
+  (i32.store (global.get $iov) (local.get $ptr))
+  (i32.store offset=4 (global.get $iov) (local.get $len)))
+
+(func $write (param $fd i32) (param $ptr i32) (param $len i32) (param $step i32) (result i32)
+  (call $iovec (local.get $ptr) (local.get $len))
+  (call $ok
+    (call $fd_write (local.get $fd) (global.get $iov) (i32.const 1) (global.get $out))
+    (local.get $step))
+  (i32.load (global.get $out)))
+
+(func $pwrite (param $fd i32) (param $ptr i32) (param $len i32) (param $off i64)
+  (param $step i32) (result i32)
+  (call $iovec (local.get $ptr) (local.get $len))
+  (call $ok
+    (call $fd_pwrite (local.get $fd) (global.get $iov) (i32.const 1) (local.get $off)
+      (global.get $out))
+    (local.get $step))
+  (i32.load (global.get $out)))
+
+(func $pread (param $fd i32) (param $ptr i32) (param $len i32) (param $off i64)
+  (param $step i32) (result i32)
+  (call $iovec (local.get $ptr) (local.get $len))
+  (call $ok
+    (call $fd_pread (local.get $fd) (global.get $iov) (i32.const 1) (local.get $off)
+      (global.get $out))
+    (local.get $step))
+  (i32.load (global.get $out)))
+
+;; Seeks to `off` from `whence`, returning the new position.
+(func $seek (param $fd i32) (param $off i64) (param $whence i32) (param $step i32) (result i64)
+  (call $ok
+    (call $fd_seek (local.get $fd) (local.get $off) (local.get $whence) (global.get $out))
+    (local.get $step))
+  (i64.load (global.get $out)))
+
+;; Returns whether `len` bytes at `a` and `b` are equal.
+(func $eq (param $a i32) (param $b i32) (param $len i32) (result i32)
+  (block $differ
+    (loop $next
+      (if (i32.eqz (local.get $len)) (then (return (i32.const 1))))
+      (br_if $differ
+        (i32.ne (i32.load8_u (local.get $a)) (i32.load8_u (local.get $b))))
+      (local.set $a (i32.add (local.get $a) (i32.const 1)))
+      (local.set $b (i32.add (local.get $b) (i32.const 1)))
+      (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+      (br $next)))
+  (i32.const 0))
@@ -0,0 +1,26 @@
+;; Positional writes to an append handle ignore the flag, as POSIX specifies.
+(data (i32.const 0x400) "f")
+(data (i32.const 0x410) "abX")
+(data (i32.const 0x420) "Xb")
+
+(func (export "_start")
+  (local $fd i32)
+  ;; O_CREAT, FDFLAGS_APPEND
+  (local.set $fd (call $open (i32.const 3) (i32.const 0x400) (i32.const 1)
+    (i32.const 1) (i32.const 1) (i32.const 1)))
+  (drop (call $write (local.get $fd) (i32.const 0x410) (i32.const 2) (i32.const 2)))
+  (drop (call $pwrite (local.get $fd) (i32.const 0x412) (i32.const 1) (i64.const 0)
+    (i32.const 3)))
+
+  ;; The position is untouched.
+  (call $assert
+    (i64.eq (call $seek (local.get $fd) (i64.const 0) (i32.const 1) (i32.const 4))
+      (i64.const 2))
+    (i32.const 5))
+
+  (call $assert
+    (i32.eq (call $pread (local.get $fd) (i32.const 0x1000) (i32.const 16) (i64.const 0)
+      (i32.const 6)) (i32.const 2))
+    (i32.const 7))
+  (call $assert (call $eq (i32.const 0x1000) (i32.const 0x420) (i32.const 2)) (i32.const 8))
+  (call $proc_exit (i32.const 0)))
@@ -0,0 +1,55 @@
+;; Reading a directory in small pages yields each entry exactly once.
+(data (i32.const 0x400) "d")
+(data (i32.const 0x410) "abcd")
+
+(func (export "_start")
+  (local $dir i32) (local $i i32) (local $cookie i64) (local $used i32)
+  (local $pos i32) (local $len i32) (local $count i32) (local $pages i32)
+
+  (call $ok (call $path_create_directory (i32.const 3) (i32.const 0x400) (i32.const 1))
+    (i32.const 1))
+  ;; O_DIRECTORY
+  (local.set $dir (call $open (i32.const 3) (i32.const 0x400) (i32.const 1)
+    (i32.const 2) (i32.const 0) (i32.const 2)))
+
+  ;; Create the files `a` to `d`.
+  (block $created
+    (loop $create
+      (br_if $created (i32.eq (local.get $i) (i32.const 4)))
+      (drop (call $open (local.get $dir) (i32.add (i32.const 0x410) (local.get $i))
+        (i32.const 1) (i32.const 1) (i32.const 0) (i32.const 3)))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $create)))
+
+  ;; Each page holds two of the 25-byte entries and part of a third.
+  (block $done
+    (loop $page
+      (call $ok
+        (call $fd_readdir (local.get $dir) (i32.const 0x2000) (i32.const 64)
+          (local.get $cookie) (global.get $out))
+        (i32.const 4))
+      (local.set $used (i32.load (global.get $out)))
+      (local.set $pos (i32.const 0))
+
+      ;; Count the complete entries, resuming after the last of them.
+      (block $cut
+        (loop $entry
+          (br_if $cut (i32.gt_u (i32.add (local.get $pos) (i32.const 24)) (local.get $used)))
+          (local.set $len (i32.load (i32.add (i32.const 0x2010) (local.get $pos))))
+          (br_if $cut
+            (i32.gt_u (i32.add (i32.add (local.get $pos) (i32.const 24)) (local.get $len))
+              (local.get $used)))
+          (local.set $cookie (i64.load (i32.add (i32.const 0x2000) (local.get $pos))))
+          (local.set $count (i32.add (local.get $count) (i32.const 1)))
+          (local.set $pos (i32.add (local.get $pos) (i32.add (i32.const 24) (local.get $len))))
+          (br $entry)))
+
+      ;; A short page is the last.
+      (br_if $done (i32.lt_u (local.get $used) (i32.const 64)))
+      (local.set $pages (i32.add (local.get $pages) (i32.const 1)))
+      (call $assert (i32.lt_u (local.get $pages) (i32.const 16)) (i32.const 5))
+      (br $page)))
+
+  ;; `.`, `..` and the four files.
+  (call $assert (i32.eq (local.get $count) (i32.const 6)) (i32.const 6))
+  (call $proc_exit (i32.const 0)))
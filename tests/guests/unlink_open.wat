@@ -0,0 +1,28 @@
+;; A file unlinked while open stays readable through its handle.
+(data (i32.const 0x400) "f")
+(data (i32.const 0x410) "data")
+
+(func (export "_start")
+  (local $fd i32)
+  ;; O_CREAT
+  (local.set $fd (call $open (i32.const 3) (i32.const 0x400) (i32.const 1)
+    (i32.const 1) (i32.const 0) (i32.const 1)))
+  (drop (call $write (local.get $fd) (i32.const 0x410) (i32.const 4) (i32.const 2)))
+  (call $ok (call $path_unlink_file (i32.const 3) (i32.const 0x400) (i32.const 1))
+    (i32.const 3))
+
+  (call $assert
+    (i32.eq (call $pread (local.get $fd) (i32.const 0x1000) (i32.const 16) (i64.const 0)
+      (i32.const 4)) (i32.const 4))
+    (i32.const 5))
+  (call $assert (call $eq (i32.const 0x1000) (i32.const 0x410) (i32.const 4)) (i32.const 6))
+
+  ;; The name is gone: ERRNO_NOENT.
+  (call $assert
+    (i32.eq
+      (call $path_open (i32.const 3) (i32.const 1) (i32.const 0x400) (i32.const 1)
+        (i32.const 0) (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0)
+        (global.get $out))
+      (i32.const 44))
+    (i32.const 7))
+  (call $proc_exit (i32.const 0)))
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use anyhow::Context;
 use tempfile::tempdir;
 use tokio::test;
-use wasmtime_vfs_dir::Directory;
+use wasmtime_vfs_dir::{Directory, TreeBuilder};
 use wasmtime_vfs_file::File;
 use wasmtime_vfs_ledger::Ledger;
 use wasmtime_vfs_memory::Node;
@@ -58,24 +58,15 @@ test
 a.file b.dir
 "#;
 
-    let tree = [
-        ("/file", Some(b"file")),
-        ("/dir", None),
-        ("/dir/a.file", Some(b"file")),
-        ("/dir/b.dir", None),
-    ];
-
     // Construct the tmpfs tree.
     let root = Directory::root(Ledger::new(), Some(Arc::new(File::new)));
-    for (path, data) in tree {
-        let parent = root.get(path.rsplit_once('/').unwrap().0).await.unwrap();
-        let child: Arc<dyn Node> = match data {
-            Some(data) => File::with_data(parent, *data),
-            None => Directory::new(parent, Some(Arc::new(File::new))),
-        };
-
-        root.attach(path, child).await.unwrap();
-    }
+    let root = TreeBuilder::new(root)
+        .file("file", "file")
+        .file("dir/a.file", "file")
+        .dir("dir/b.dir")
+        .build()
+        .await
+        .unwrap();
     let root = root.open_dir().await.unwrap();
 
     // Run the script and test the output.